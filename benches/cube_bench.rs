@@ -1,5 +1,10 @@
+use ble_cube::geo::bbox_around;
+use ble_cube::prelude::*;
+use ble_cube::{BleCube, BleObservation, DistanceMetric, GroupBy, Query, SyntheticConfig};
 use criterion::{criterion_group, criterion_main, Criterion};
-use ble_cube::{BleCube, BleObservation};
+
+/// Records in the realistic-workload benches
+const SYNTHETIC_RECORDS: usize = 1_000_000;
 
 fn bench_insert(c: &mut Criterion) {
     c.bench_function("insert", |b| {
@@ -12,11 +17,257 @@ fn bench_insert(c: &mut Criterion) {
                     timestamp: i as i64,
                     lat: 37.7749,
                     lon: -122.4194,
+                    receiver_id: None,
+                    floor: None,
+                    tx_power: None,
+                    quality: None,
                 });
             }
         });
     });
 }
 
-criterion_group!(benches, bench_insert);
+fn sample(n: usize) -> Vec<BleObservation> {
+    (0..n)
+        .map(|i| BleObservation {
+            rssi: -40 - (i % 60) as i8,
+            mac: [0, 0, 0, (i >> 16) as u8, (i >> 8) as u8, (i % 500) as u8],
+            timestamp: 1_700_000_000 + i as i64,
+            lat: 37.7 + (i % 1000) as f64 * 0.0001,
+            lon: -122.4 + (i / 1000) as f64 * 0.0001,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        })
+        .collect()
+}
+
+fn bench_bulk_load(c: &mut Criterion) {
+    let records = sample(100_000);
+    let mut group = c.benchmark_group("hydrate_100k");
+    group.sample_size(10);
+    group.bench_function("insert_loop", |b| {
+        b.iter(|| {
+            let mut cube = BleCube::with_capacity(records.len());
+            for obs in &records {
+                cube.insert(*obs);
+            }
+            cube
+        });
+    });
+    group.bench_function("bulk_load", |b| {
+        b.iter(|| BleCube::bulk_load(records.clone()));
+    });
+    group.finish();
+}
+
+/// Residual filters and aggregations that read only RSSI or timestamps
+fn bench_column_scans(c: &mut Criterion) {
+    let cube = BleCube::bulk_load(sample(100_000));
+    let start = 1_700_000_000;
+    let mut group = c.benchmark_group("scan_100k");
+    // Driven by the time index, RSSI checked per candidate
+    group.bench_function("time_range_rssi_filter", |b| {
+        let query = Query::new()
+            .time_between(start, start + 80_000)
+            .rssi_between(-70, -50);
+        b.iter(|| cube.execute_ids(&query).len());
+    });
+    // Driven by the RSSI index, timestamp checked per candidate
+    group.bench_function("rssi_range_time_filter", |b| {
+        let query = Query::new()
+            .rssi_between(-90, -40)
+            .time_between(start + 10_000, start + 20_000);
+        b.iter(|| cube.execute_ids(&query).len());
+    });
+    group.bench_function("rssi_histogram_by_time", |b| {
+        let query = Query::new().time_between(start, start + 80_000);
+        b.iter(|| cube.rssi_histogram(&query, 5));
+    });
+    // No RSSI index: every record's RSSI is read
+    let unindexed = BleCube::builder()
+        .without_rssi_index()
+        .bulk_load(sample(100_000));
+    group.bench_function("rssi_full_scan", |b| {
+        b.iter(|| unindexed.query_rssi_gte(-42).len());
+    });
+    group.finish();
+}
+
+/// Points straddling the antimeridian and around both poles
+fn wrapped_sample(n: usize) -> Vec<BleObservation> {
+    (0..n)
+        .map(|i| {
+            let (lat, lon) = if i % 2 == 0 {
+                let offset = (i % 1000) as f64 * 0.001;
+                (
+                    -0.5 + (i / 1000) as f64 * 0.01,
+                    if i % 4 == 0 {
+                        180.0 - offset
+                    } else {
+                        -180.0 + offset
+                    },
+                )
+            } else {
+                let lat = 89.0 + (i % 100) as f64 * 0.01;
+                (
+                    if i % 4 == 1 { lat } else { -lat },
+                    -180.0 + (i / 100) as f64 * 0.36,
+                )
+            };
+            BleObservation {
+                rssi: -60,
+                mac: [0, 0, 0, (i >> 16) as u8, (i >> 8) as u8, i as u8],
+                timestamp: i as i64,
+                lat,
+                lon,
+                receiver_id: None,
+                floor: None,
+                tx_power: None,
+                quality: None,
+            }
+        })
+        .collect()
+}
+
+/// Radius and bbox queries whose envelopes wrap the date line or a pole
+fn bench_antimeridian(c: &mut Criterion) {
+    let cube = BleCube::bulk_load(wrapped_sample(100_000));
+    let mut group = c.benchmark_group("antimeridian_100k");
+    group.bench_function("radius_across_date_line", |b| {
+        b.iter(|| cube.query_geo_radius(0.0, 180.0, 50_000.0).len());
+    });
+    group.bench_function("bbox_across_date_line", |b| {
+        b.iter(|| cube.query_geo_bbox(-0.2, 179.5, 0.2, -179.5).len());
+    });
+    group.bench_function("radius_across_date_line_vincenty", |b| {
+        b.iter(|| {
+            cube.query_geo_radius_with(0.0, 180.0, 50_000.0, DistanceMetric::Vincenty)
+                .len()
+        });
+    });
+    group.bench_function("radius_around_pole", |b| {
+        b.iter(|| cube.query_geo_radius(90.0, 0.0, 50_000.0).len());
+    });
+    group.bench_function("radius_around_south_pole", |b| {
+        b.iter(|| cube.query_geo_radius(-90.0, 0.0, 50_000.0).len());
+    });
+    group.bench_function("radius_near_pole_off_axis", |b| {
+        b.iter(|| cube.query_geo_radius(89.5, 90.0, 100_000.0).len());
+    });
+    group.bench_function("bbox_polar_cap", |b| {
+        b.iter(|| cube.query_geo_bbox(89.5, -180.0, 90.0, 180.0).len());
+    });
+    let query = Query::new().within_radius(0.0, -180.0, 50_000.0);
+    group.bench_function("query_within_radius_across_date_line", |b| {
+        b.iter(|| cube.execute_ids(&query).len());
+    });
+    group.finish();
+}
+
+/// Every query type over a skewed, clustered, day/night workload, so index
+/// and planner changes are measured on data shaped like a real deployment
+fn bench_synthetic(c: &mut Criterion) {
+    let config = SyntheticConfig::default();
+    let records = config.generate(SYNTHETIC_RECORDS);
+    let mut group = c.benchmark_group("synthetic_1m_ingest");
+    group.sample_size(10);
+    group.bench_function("bulk_load", |b| {
+        b.iter(|| BleCube::bulk_load(records.clone()));
+    });
+    group.finish();
+
+    let cube = BleCube::bulk_load(records);
+    let (lat, lon) = config.hotspot_centers()[0];
+    let noon = config.start + 12 * 3600;
+    let hour = (noon, noon + 3600);
+    let day = (config.start, config.start + 86_400);
+    // Device 0 is the most frequently seen
+    let busy_mac = config.device_mac(0, noon);
+
+    let mut group = c.benchmark_group("synthetic_1m_single");
+    group.bench_function("mac", |b| b.iter(|| cube.query_mac(busy_mac).len()));
+    group.bench_function("time_hour", |b| {
+        b.iter(|| cube.query_time_range(hour.0, hour.1).len())
+    });
+    group.bench_function("rssi_strong", |b| {
+        b.iter(|| cube.query_rssi_range(-50, -40).len())
+    });
+    group.bench_function("receiver", |b| b.iter(|| cube.query_receiver(3).len()));
+    group.bench_function("geo_radius_hotspot", |b| {
+        b.iter(|| cube.query_geo_radius(lat, lon, 100.0).len())
+    });
+    group.bench_function("geo_bbox_hotspot", |b| {
+        let (min_lat, min_lon, max_lat, max_lon) = bbox_around(lat, lon, 300.0);
+        b.iter(|| {
+            cube.query_geo_bbox(min_lat, min_lon, max_lat, max_lon)
+                .len()
+        })
+    });
+    group.bench_function("geo_polygon_hotspot", |b| {
+        let d = 0.002;
+        let square = [
+            (lat - d, lon - d),
+            (lat - d, lon + d),
+            (lat + d, lon + d),
+            (lat + d, lon - d),
+        ];
+        b.iter(|| cube.query_geo_polygon(&square).len())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("synthetic_1m_multi");
+    group.bench_function("mac_day", |b| {
+        let query = Query::new().mac(busy_mac).time_between(day.0, day.1);
+        b.iter(|| cube.execute_ids(&query).len())
+    });
+    group.bench_function("hotspot_hour_rssi", |b| {
+        let query = Query::new()
+            .within_radius(lat, lon, 150.0)
+            .time_between(hour.0, hour.1)
+            .rssi_between(-70, -40);
+        b.iter(|| cube.execute_ids(&query).len())
+    });
+    group.bench_function("query_multi", |b| {
+        b.iter(|| {
+            cube.query_multi(None, Some((-70, -40)), Some(hour), Some((lat, lon, 150.0)))
+                .len()
+        })
+    });
+    group.bench_function("query_str", |b| {
+        let query = format!(
+            "time BETWEEN {} AND {} AND rssi >= -70 WITHIN 150m OF ({lat}, {lon})",
+            hour.0, hour.1
+        );
+        b.iter(|| cube.query_str(&query).unwrap().len())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("synthetic_1m_aggregate");
+    group.bench_function("grouped_hourly_day", |b| {
+        let query = Query::new()
+            .time_between(day.0, day.1)
+            .group_by(GroupBy::TimeBucket(3600));
+        b.iter(|| cube.execute_grouped(&query).len())
+    });
+    group.bench_function("top_10_devices_day", |b| {
+        let query = Query::new().time_between(day.0, day.1);
+        b.iter(|| cube.top_k_by_observation_count(10, &query).len())
+    });
+    group.bench_function("rssi_histogram_hotspot", |b| {
+        let query = Query::new().within_radius(lat, lon, 150.0);
+        b.iter(|| cube.rssi_histogram(&query, 5).len())
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_insert,
+    bench_bulk_load,
+    bench_column_scans,
+    bench_antimeridian,
+    bench_synthetic
+);
 criterion_main!(benches);
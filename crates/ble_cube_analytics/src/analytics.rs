@@ -0,0 +1,486 @@
+//! Derived metrics computed over the indices (presence sessions, dwell time,
+//! per-device summaries, top-k rankings and trajectory anomalies).
+
+use alloc::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::{BleCube, BleObservation, MacAddr, Query, TimeUnit, Zone};
+use core::cmp::Reverse;
+
+/// Contiguous run of a device's observations inside a region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PresenceSession {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "ble_cube_core::internal::serde_octets")
+    )]
+    pub mac: [u8; 6],
+    /// Timestamp of the first observation in the session
+    pub start: i64,
+    /// Timestamp of the last observation in the session
+    pub end: i64,
+    pub observations: usize,
+}
+
+impl PresenceSession {
+    /// Session length in timestamp units; zero for a single observation
+    pub fn duration(&self) -> i64 {
+        self.end - self.start
+    }
+}
+
+/// Total time a device spent inside a region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DwellTime {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "ble_cube_core::internal::serde_octets")
+    )]
+    pub mac: [u8; 6],
+    /// Sum of session durations, in timestamp units
+    pub total: i64,
+    pub sessions: usize,
+    pub observations: usize,
+}
+
+/// Summary of one device's observations within a query slice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MacStats {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "ble_cube_core::internal::serde_octets")
+    )]
+    pub mac: [u8; 6],
+    pub observations: usize,
+    /// Strongest RSSI seen (closest to zero)
+    pub max_rssi: i8,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+/// Dwell time, presence sessions, per-device summaries and anomaly checks
+/// over a [`BleCube`]
+pub trait Analytics {
+    /// Per-MAC summary of every observation matching `filter`, sorted by MAC
+    fn mac_stats(&self, filter: &Query) -> Vec<MacStats>;
+
+    /// The `k` devices with the most observations matching `filter`, most
+    /// seen first. Ties are broken by ascending MAC.
+    fn top_k_by_observation_count(&self, k: usize, filter: &Query) -> Vec<MacStats>;
+
+    /// The `k` devices with the strongest peak RSSI matching `filter`,
+    /// strongest first. Ties are broken by ascending MAC.
+    fn top_k_by_max_rssi(&self, k: usize, filter: &Query) -> Vec<MacStats>;
+
+    /// Consecutive observations of `mac` (in time order) that imply moving
+    /// faster than `max_speed_mps`, as `(earlier_id, later_id)` pairs.
+    ///
+    /// Timestamps are read in the cube's declared unit (seconds if none).
+    /// Two sightings at different places with the same timestamp are always
+    /// flagged. A single bad fix typically shows up as two pairs: the jump
+    /// away and the jump back.
+    fn detect_anomalies<M: Into<MacAddr>>(&self, mac: M, max_speed_mps: f64)
+        -> Vec<(usize, usize)>;
+
+    /// Per-MAC dwell time inside a registered zone, sorted by MAC.
+    ///
+    /// A device's observations are walked in time order; a session is a run of
+    /// observations inside the zone with no gap larger than `max_gap`
+    /// (timestamp units). With a scan schedule set, only the scanned part of
+    /// a gap counts. An observation outside the zone ends the session.
+    /// Unknown zones yield no results.
+    fn dwell_times(
+        &self,
+        zone: &str,
+        time_range: Option<(i64, i64)>,
+        max_gap: i64,
+    ) -> Vec<DwellTime>;
+
+    /// Like [`BleCube::dwell_times`] for an ad-hoc region
+    fn dwell_times_in(
+        &self,
+        zone: &Zone,
+        time_range: Option<(i64, i64)>,
+        max_gap: i64,
+    ) -> Vec<DwellTime>;
+
+    /// Presence sessions of every device inside an ad-hoc region, ordered by
+    /// MAC then start time
+    fn presence_sessions(
+        &self,
+        zone: &Zone,
+        time_range: Option<(i64, i64)>,
+        max_gap: i64,
+    ) -> Vec<PresenceSession>;
+}
+
+impl Analytics for BleCube {
+    fn mac_stats(&self, filter: &Query) -> Vec<MacStats> {
+        let mut stats: BTreeMap<[u8; 6], MacStats> = BTreeMap::new();
+        for id in self.execute_ids(filter) {
+            let obs = &self.records()[id];
+            stats
+                .entry(obs.mac)
+                .and_modify(|s| {
+                    s.observations += 1;
+                    s.max_rssi = s.max_rssi.max(obs.rssi);
+                    s.first_seen = s.first_seen.min(obs.timestamp);
+                    s.last_seen = s.last_seen.max(obs.timestamp);
+                })
+                .or_insert(MacStats {
+                    mac: obs.mac,
+                    observations: 1,
+                    max_rssi: obs.rssi,
+                    first_seen: obs.timestamp,
+                    last_seen: obs.timestamp,
+                });
+        }
+        stats.into_values().collect()
+    }
+
+    fn top_k_by_observation_count(&self, k: usize, filter: &Query) -> Vec<MacStats> {
+        top_k_by(self.mac_stats(filter), k, |s| s.observations)
+    }
+
+    fn top_k_by_max_rssi(&self, k: usize, filter: &Query) -> Vec<MacStats> {
+        top_k_by(self.mac_stats(filter), k, |s| s.max_rssi)
+    }
+
+    fn detect_anomalies<M: Into<MacAddr>>(
+        &self,
+        mac: M,
+        max_speed_mps: f64,
+    ) -> Vec<(usize, usize)> {
+        let mut ids = self.mac_record_ids(mac).to_vec();
+        let timestamps = self.timestamps();
+        ids.sort_by_key(|&id| (timestamps[id], id));
+
+        let per_second = self.time_unit().unwrap_or(TimeUnit::Seconds).per_second() as f64;
+        ids.windows(2)
+            .filter(|pair| {
+                let (a, b) = (&self.records()[pair[0]], &self.records()[pair[1]]);
+                let meters = self.distance(a.lat, a.lon, b.lat, b.lon);
+                let seconds = (b.timestamp - a.timestamp) as f64 / per_second;
+                meters > max_speed_mps * seconds
+            })
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+
+    fn dwell_times(
+        &self,
+        zone: &str,
+        time_range: Option<(i64, i64)>,
+        max_gap: i64,
+    ) -> Vec<DwellTime> {
+        let Some(members) = self.zone_record_ids(zone) else {
+            return Vec::new();
+        };
+
+        let sessions = sessions_where(
+            self,
+            members.iter().map(|&id| &self.records()[id]),
+            |id| members.binary_search(&id).is_ok(),
+            time_range,
+            max_gap,
+        );
+        summarize(&sessions)
+    }
+
+    fn dwell_times_in(
+        &self,
+        zone: &Zone,
+        time_range: Option<(i64, i64)>,
+        max_gap: i64,
+    ) -> Vec<DwellTime> {
+        summarize(&self.presence_sessions(zone, time_range, max_gap))
+    }
+
+    fn presence_sessions(
+        &self,
+        zone: &Zone,
+        time_range: Option<(i64, i64)>,
+        max_gap: i64,
+    ) -> Vec<PresenceSession> {
+        let candidates = self
+            .locate_in_envelope(zone.envelope(self.coordinate_system()))
+            .filter(|point| {
+                zone.contains_in(self.coordinate_system(), point.coords[0], point.coords[1])
+            })
+            .map(|point| &self.records()[point.record_id]);
+
+        sessions_where(
+            self,
+            candidates,
+            |id| {
+                let obs = &self.records()[id];
+                zone.contains_in(self.coordinate_system(), obs.lat, obs.lon)
+            },
+            time_range,
+            max_gap,
+        )
+    }
+}
+
+/// Build sessions for every MAC appearing in `candidates`, using `inside`
+/// to classify each of that MAC's records
+fn sessions_where<'a, I, F>(
+    cube: &BleCube,
+    candidates: I,
+    inside: F,
+    time_range: Option<(i64, i64)>,
+    max_gap: i64,
+) -> Vec<PresenceSession>
+where
+    I: Iterator<Item = &'a BleObservation>,
+    F: Fn(usize) -> bool,
+{
+    let in_range = |ts: i64| time_range.is_none_or(|(start, end)| (start..=end).contains(&ts));
+    let macs: BTreeSet<[u8; 6]> = candidates
+        .filter(|obs| in_range(obs.timestamp))
+        .map(|obs| obs.mac)
+        .collect();
+
+    let timestamps = cube.timestamps();
+    let mut sessions = Vec::new();
+    for mac in macs {
+        let mut ids: Vec<usize> = cube
+            .mac_record_ids(mac)
+            .iter()
+            .copied()
+            .filter(|&id| in_range(timestamps[id]))
+            .collect();
+        ids.sort_by_key(|&id| (timestamps[id], id));
+
+        let mut current: Option<PresenceSession> = None;
+        for id in ids {
+            let ts = timestamps[id];
+            if !inside(id) {
+                sessions.extend(current.take());
+                continue;
+            }
+            match current.as_mut() {
+                Some(session) if cube.scanned(session.end, ts) <= max_gap => {
+                    session.end = ts;
+                    session.observations += 1;
+                }
+                _ => {
+                    sessions.extend(current.replace(PresenceSession {
+                        mac,
+                        start: ts,
+                        end: ts,
+                        observations: 1,
+                    }));
+                }
+            }
+        }
+        sessions.extend(current);
+    }
+    sessions
+}
+
+/// Keep the `k` largest entries by `key` with a bounded min-heap
+/// (O(n log k)), returned in descending key order
+fn top_k_by<K: Ord>(stats: Vec<MacStats>, k: usize, key: impl Fn(&MacStats) -> K) -> Vec<MacStats> {
+    if k == 0 {
+        return Vec::new();
+    }
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+    for (i, s) in stats.iter().enumerate() {
+        // Reverse(i): earlier (lower MAC) wins ties, since stats are MAC-sorted
+        heap.push(Reverse((key(s), Reverse(i))));
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((_, Reverse(i)))| stats[i])
+        .collect()
+}
+
+/// Fold sessions (grouped by MAC) into per-MAC dwell totals
+fn summarize(sessions: &[PresenceSession]) -> Vec<DwellTime> {
+    let mut dwell: Vec<DwellTime> = Vec::new();
+    for session in sessions {
+        match dwell.last_mut() {
+            Some(last) if last.mac == session.mac => {
+                last.total += session.duration();
+                last.sessions += 1;
+                last.observations += session.observations;
+            }
+            _ => dwell.push(DwellTime {
+                mac: session.mac,
+                total: session.duration(),
+                sessions: 1,
+                observations: session.observations,
+            }),
+        }
+    }
+    dwell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::ScanSchedule;
+
+    const INSIDE: (f64, f64) = (0.0, 0.0);
+    const OUTSIDE: (f64, f64) = (1.0, 1.0);
+
+    fn sighting(cube: &mut BleCube, mac: u8, timestamp: i64, (lat, lon): (f64, f64)) {
+        cube.insert(BleObservation {
+            rssi: -60,
+            mac: [0, 0, 0, 0, 0, mac],
+            timestamp,
+            lat,
+            lon,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+    }
+
+    fn cube_with_zone() -> BleCube {
+        let mut cube = BleCube::new();
+        cube.add_zone("shop", Zone::circle(0.0, 0.0, 100.0));
+        cube
+    }
+
+    #[test]
+    fn test_single_observation_has_zero_dwell() {
+        let mut cube = cube_with_zone();
+        sighting(&mut cube, 1, 100, INSIDE);
+
+        let dwell = cube.dwell_times("shop", None, 60);
+        assert_eq!(
+            dwell,
+            vec![DwellTime {
+                mac: [0, 0, 0, 0, 0, 1],
+                total: 0,
+                sessions: 1,
+                observations: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sessions_split_on_gap_and_exit() {
+        let mut cube = cube_with_zone();
+        // Session 1: 100..160
+        sighting(&mut cube, 1, 100, INSIDE);
+        sighting(&mut cube, 1, 130, INSIDE);
+        sighting(&mut cube, 1, 160, INSIDE);
+        // Left the zone, came back: session 2: 200..210
+        sighting(&mut cube, 1, 180, OUTSIDE);
+        sighting(&mut cube, 1, 200, INSIDE);
+        sighting(&mut cube, 1, 210, INSIDE);
+        // Gap too long: session 3 is a single observation
+        sighting(&mut cube, 1, 1000, INSIDE);
+        // Never inside: not reported
+        sighting(&mut cube, 2, 100, OUTSIDE);
+
+        let dwell = cube.dwell_times("shop", None, 60);
+        assert_eq!(dwell.len(), 1);
+        assert_eq!(dwell[0].total, 70);
+        assert_eq!(dwell[0].sessions, 3);
+        assert_eq!(dwell[0].observations, 6);
+
+        // Time range clips the observations considered
+        let dwell = cube.dwell_times("shop", Some((0, 150)), 60);
+        assert_eq!(dwell[0].total, 30);
+
+        // Ad-hoc region agrees with the registered zone
+        let adhoc = cube.dwell_times_in(&Zone::circle(0.0, 0.0, 100.0), None, 60);
+        assert_eq!(adhoc, cube.dwell_times("shop", None, 60));
+
+        assert!(cube.dwell_times("nowhere", None, 60).is_empty());
+    }
+
+    #[test]
+    fn test_sessions_skip_unscanned_time() {
+        let mut cube = BleCube::new();
+        for timestamp in [0, 100, 700, 800] {
+            sighting(&mut cube, 1, timestamp, INSIDE);
+        }
+        let everywhere = Zone::circle(0.0, 0.0, 10.0);
+        assert_eq!(cube.presence_sessions(&everywhere, None, 150).len(), 2);
+
+        // The scanner was off from 200 to 650: only 150 scanned units lie
+        // between 100 and 700, so it is one visit
+        cube.set_scan_schedule(ScanSchedule::new().window(0, 200).window(650, 1000));
+        let sessions = cube.presence_sessions(&everywhere, None, 150);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].duration(), 800);
+    }
+
+    #[test]
+    fn test_top_k_devices() {
+        let mut cube = BleCube::new();
+        // mac 1: 3 sightings, peak -70; mac 2: 1 sighting at -40;
+        // mac 3: 3 sightings, peak -50, one outside the time slice
+        for (mac, rssi, ts) in [
+            (1, -80, 10),
+            (1, -70, 20),
+            (1, -75, 30),
+            (2, -40, 10),
+            (3, -50, 10),
+            (3, -60, 20),
+            (3, -55, 500),
+        ] {
+            cube.insert(BleObservation {
+                rssi,
+                mac: [0, 0, 0, 0, 0, mac],
+                timestamp: ts,
+                lat: 0.0,
+                lon: 0.0,
+                receiver_id: None,
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+
+        let macs = |stats: Vec<MacStats>| stats.iter().map(|s| s.mac[5]).collect::<Vec<_>>();
+
+        let everything = Query::new();
+        assert_eq!(
+            macs(cube.top_k_by_observation_count(2, &everything)),
+            vec![1, 3]
+        );
+        assert_eq!(macs(cube.top_k_by_max_rssi(2, &everything)), vec![2, 3]);
+        assert!(cube.top_k_by_max_rssi(0, &everything).is_empty());
+
+        let slice = Query::new().time_between(0, 100);
+        let top = cube.top_k_by_observation_count(10, &slice);
+        assert_eq!(macs(top.clone()), vec![1, 3, 2]);
+        assert_eq!(top[1].observations, 2);
+        assert_eq!((top[1].first_seen, top[1].last_seen), (10, 20));
+    }
+
+    #[test]
+    fn test_detect_teleportation() {
+        let mut cube = BleCube::new();
+        // Walking north at ~1.1 m/s, one GPS glitch ~111 km away, then a
+        // same-instant sighting 11 m apart (MAC collision)
+        for (timestamp, lat) in [
+            (0, 0.0),
+            (10, 0.0001),
+            (20, 1.0),
+            (30, 0.0003),
+            (40, 0.0004),
+            (40, 0.0005),
+        ] {
+            sighting(&mut cube, 1, timestamp, (lat, 0.0));
+        }
+
+        assert_eq!(
+            cube.detect_anomalies([0, 0, 0, 0, 0, 1], 5.0),
+            vec![(1, 2), (2, 3), (4, 5)]
+        );
+        assert!(cube.detect_anomalies([0, 0, 0, 0, 0, 9], 5.0).is_empty());
+    }
+}
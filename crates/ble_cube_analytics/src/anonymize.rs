@@ -0,0 +1,180 @@
+//! Anonymized export for sharing datasets.
+//!
+//! An [`AnonymizationPolicy`] replaces each MAC with a keyed pseudonym (the
+//! same device always gets the same pseudonym under the same salt, and
+//! without the salt pseudonyms cannot be linked back to addresses),
+//! optionally snaps coordinates to a grid and timestamps to buckets, and
+//! drops devices seen fewer than k times, whose rare sightings are the
+//! easiest to re-identify. [`BleCube::anonymized`] applies it to a query's
+//! matches; the result can be bulk-loaded or written out through a sink.
+
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::internal::{Aes128, HashMap};
+use ble_cube_core::{BleCube, BleObservation, Query};
+
+/// How [`BleCube::anonymized`] transforms observations
+#[derive(Clone)]
+pub struct AnonymizationPolicy {
+    cipher: Aes128,
+    grid: Option<f64>,
+    time_bucket: Option<i64>,
+    min_sightings: usize,
+}
+
+impl core::fmt::Debug for AnonymizationPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // The salt stays out of logs
+        f.debug_struct("AnonymizationPolicy")
+            .field("grid", &self.grid)
+            .field("time_bucket", &self.time_bucket)
+            .field("min_sightings", &self.min_sightings)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AnonymizationPolicy {
+    /// Pseudonymize MACs under a secret 16-byte `salt` and change nothing
+    /// else. Keep the salt to give later exports matching pseudonyms, or use
+    /// a fresh random one per export so they cannot be joined.
+    pub fn new(salt: [u8; 16]) -> Self {
+        Self {
+            cipher: Aes128::new(&salt),
+            grid: None,
+            time_bucket: None,
+            min_sightings: 1,
+        }
+    }
+
+    /// Truncate lat and lon down to multiples of `cell` (degrees for WGS84,
+    /// meters in a projected coordinate system)
+    ///
+    /// # Panics
+    /// Panics if `cell` is not positive.
+    pub fn coordinate_grid(mut self, cell: f64) -> Self {
+        assert!(cell > 0.0, "grid cell must be positive");
+        self.grid = Some(cell);
+        self
+    }
+
+    /// Truncate timestamps down to multiples of `width`
+    ///
+    /// # Panics
+    /// Panics if `width` is not positive.
+    pub fn time_bucket(mut self, width: i64) -> Self {
+        assert!(width > 0, "time bucket width must be positive");
+        self.time_bucket = Some(width);
+        self
+    }
+
+    /// Drop every device with fewer than `k` exported observations
+    pub fn min_sightings(mut self, k: usize) -> Self {
+        self.min_sightings = k;
+        self
+    }
+
+    /// Pseudonym of `mac`: the salt-keyed AES-128 of the address, cut to six
+    /// octets and marked as a static random address (top bits `11`) so it
+    /// is never mistaken for a real public address
+    pub fn pseudonym(&self, mac: [u8; 6]) -> [u8; 6] {
+        let mut block = [0u8; 16];
+        block[..6].copy_from_slice(&mac);
+        let out = self.cipher.encrypt(block);
+        let mut pseudonym = [0u8; 6];
+        pseudonym.copy_from_slice(&out[..6]);
+        pseudonym[0] |= 0xC0;
+        pseudonym
+    }
+
+    fn apply(&self, obs: &BleObservation) -> BleObservation {
+        let snap = |value: f64| match self.grid {
+            Some(cell) => (value / cell).floor() * cell,
+            None => value,
+        };
+        BleObservation {
+            mac: self.pseudonym(obs.mac),
+            timestamp: match self.time_bucket {
+                Some(width) => obs.timestamp.div_euclid(width) * width,
+                None => obs.timestamp,
+            },
+            lat: snap(obs.lat),
+            lon: snap(obs.lon),
+            ..*obs
+        }
+    }
+}
+
+/// Anonymized export of a [`BleCube`]
+pub trait Anonymize {
+    /// The matches of `query` transformed by `policy`, in record ID order,
+    /// without devices below the policy's sighting threshold
+    fn anonymized(&self, query: &Query, policy: &AnonymizationPolicy) -> Vec<BleObservation>;
+}
+
+impl Anonymize for BleCube {
+    fn anonymized(&self, query: &Query, policy: &AnonymizationPolicy) -> Vec<BleObservation> {
+        let matches = self.execute(query);
+        let mut sightings: HashMap<[u8; 6], usize> = HashMap::new();
+        for obs in &matches {
+            *sightings.entry(obs.mac).or_default() += 1;
+        }
+        matches
+            .into_iter()
+            .filter(|obs| sightings[&obs.mac] >= policy.min_sightings)
+            .map(|obs| policy.apply(obs))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(mac: u8, timestamp: i64, lat: f64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -60,
+            timestamp,
+            lat,
+            lon: -122.4567,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_anonymized_export() {
+        let mut cube = BleCube::new();
+        cube.insert(obs(1, 1_000, 37.7749));
+        cube.insert(obs(2, 1_100, 37.7750));
+        cube.insert(obs(1, 1_900, 37.7751));
+        cube.insert(obs(1, 4_000, 37.7752));
+
+        let policy = AnonymizationPolicy::new([7; 16])
+            .coordinate_grid(0.01)
+            .time_bucket(3600)
+            .min_sightings(2);
+        let out = cube.anonymized(&Query::new(), &policy);
+
+        // Device 2 was seen once and is dropped
+        assert_eq!(out.len(), 3);
+        let pseudonym = policy.pseudonym([1; 6]);
+        assert!(out.iter().all(|obs| obs.mac == pseudonym));
+        assert_ne!(pseudonym, [1; 6]);
+        assert_eq!(pseudonym[0] & 0xC0, 0xC0);
+        let stamps: Vec<i64> = out.iter().map(|obs| obs.timestamp).collect();
+        assert_eq!(stamps, [0, 0, 3600]);
+        assert!((out[0].lat - 37.77).abs() < 1e-9);
+        assert!((out[0].lon - -122.46).abs() < 1e-9);
+        assert_eq!(out[0].rssi, -60);
+
+        // Pseudonyms are stable per salt and differ across salts
+        assert_eq!(
+            AnonymizationPolicy::new([7; 16]).pseudonym([1; 6]),
+            pseudonym
+        );
+        assert_ne!(
+            AnonymizationPolicy::new([8; 16]).pseudonym([1; 6]),
+            pseudonym
+        );
+        assert!(!format!("{policy:?}").contains("cipher"));
+    }
+}
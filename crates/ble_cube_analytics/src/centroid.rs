@@ -0,0 +1,167 @@
+//! RSSI-weighted position estimates.
+//!
+//! When the collector moves (a survey walk, a vehicle) or several fixed
+//! receivers hear a device, the positions a device was heard from cluster
+//! around it, closer ones with stronger signals. Weighting those positions
+//! by received power gives a cheap estimate of where the device was,
+//! without path-loss models or trilateration.
+
+use alloc::collections::BTreeMap;
+use ble_cube_core::geo::{lon_delta, normalize_longitude};
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::{BleCube, CoordinateSystem, MacAddr};
+
+/// A device's estimated position over one time bucket, see
+/// [`BleCube::estimated_positions`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PositionEstimate {
+    /// Start of the bucket
+    pub timestamp: i64,
+    pub lat: f64,
+    pub lon: f64,
+    /// Observations the estimate combines
+    pub observations: usize,
+    /// Weighted RMS distance (meters) of those observations from the
+    /// estimate; a rough uncertainty
+    pub spread_m: f64,
+}
+
+/// RSSI-weighted position estimates over a [`BleCube`]
+pub trait Positioning {
+    /// RSSI-weighted centroid of the positions `mac` was observed from, per
+    /// time bucket `bucket` timestamp units wide (aligned to multiples of
+    /// the width), in time order. Buckets without observations are skipped;
+    /// a non-positive `bucket` returns nothing.
+    ///
+    /// Each observation is weighted by its received power in milliwatts,
+    /// `10^(rssi / 10)`, so a reading 10 dB stronger counts ten times as
+    /// much. WGS84 longitudes are averaged across the antimeridian.
+    fn estimated_positions<M: Into<MacAddr>>(&self, mac: M, bucket: i64) -> Vec<PositionEstimate>;
+}
+
+impl Positioning for BleCube {
+    fn estimated_positions<M: Into<MacAddr>>(&self, mac: M, bucket: i64) -> Vec<PositionEstimate> {
+        if bucket <= 0 {
+            return Vec::new();
+        }
+        let mut buckets: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+        for &id in self.mac_record_ids(mac) {
+            let start = self.records()[id].timestamp.div_euclid(bucket) * bucket;
+            buckets.entry(start).or_default().push(id);
+        }
+        buckets
+            .into_iter()
+            .map(|(timestamp, ids)| weighted_centroid(self, timestamp, &ids))
+            .collect()
+    }
+}
+
+fn weighted_centroid(cube: &BleCube, timestamp: i64, ids: &[usize]) -> PositionEstimate {
+    let wgs84 = cube.coordinate_system() == CoordinateSystem::Wgs84;
+    let strongest = ids
+        .iter()
+        .map(|&id| cube.records()[id].rssi)
+        .max()
+        .unwrap_or(0);
+    // Longitudes relative to the first one, so a bucket straddling the
+    // antimeridian averages to a point near it rather than near 0°
+    let origin_lon = cube.records()[ids[0]].lon;
+    let offset = |lon: f64| {
+        if wgs84 {
+            lon_delta(origin_lon, lon)
+        } else {
+            lon - origin_lon
+        }
+    };
+
+    let (mut total, mut lat, mut lon) = (0.0, 0.0, 0.0);
+    let weights: Vec<f64> = ids
+        .iter()
+        .map(|&id| {
+            let obs = &cube.records()[id];
+            // Relative to the strongest reading, which keeps the
+            // weights in (0, 1] however weak the bucket is
+            let weight = 10f64.powf(f64::from(i16::from(obs.rssi) - i16::from(strongest)) / 10.0);
+            total += weight;
+            lat += weight * obs.lat;
+            lon += weight * offset(obs.lon);
+            weight
+        })
+        .collect();
+    let lat = lat / total;
+    let lon = origin_lon + lon / total;
+    let lon = if wgs84 { normalize_longitude(lon) } else { lon };
+
+    let variance = ids
+        .iter()
+        .zip(&weights)
+        .map(|(&id, weight)| {
+            let obs = &cube.records()[id];
+            weight * cube.distance(lat, lon, obs.lat, obs.lon).powi(2)
+        })
+        .sum::<f64>()
+        / total;
+    PositionEstimate {
+        timestamp,
+        lat,
+        lon,
+        observations: ids.len(),
+        spread_m: variance.sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::BleObservation;
+
+    fn heard(mac: u8, timestamp: i64, rssi: i8, lat: f64, lon: f64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi,
+            timestamp,
+            lat,
+            lon,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_centroid_leans_toward_strong_readings() {
+        let mut cube = BleCube::new();
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        // Two receivers 10 m apart; the device sits near the first, then
+        // moves to the second
+        for (timestamp, near, far) in [(0, 0.0, 10.0), (30, 0.0, 10.0), (65, 10.0, 0.0)] {
+            cube.insert(heard(1, timestamp, -50, 0.0, near));
+            cube.insert(heard(1, timestamp, -60, 0.0, far));
+        }
+        cube.insert(heard(2, 0, -50, 5.0, 5.0));
+
+        let estimates = cube.estimated_positions([1; 6], 60);
+        assert_eq!(estimates.len(), 2);
+        let first = estimates[0];
+        assert_eq!((first.timestamp, first.observations), (0, 4));
+        // Weights 1 and 0.1: 10 / 11 m toward the far receiver
+        assert!((first.lon - 10.0 / 11.0).abs() < 1e-9);
+        assert_eq!(first.lat, 0.0);
+        assert!(first.spread_m > 0.0 && first.spread_m < 5.0);
+        assert_eq!(estimates[1].timestamp, 60);
+        assert!((estimates[1].lon - 100.0 / 11.0).abs() < 1e-9);
+
+        assert!(cube.estimated_positions([3; 6], 60).is_empty());
+        assert!(cube.estimated_positions([1; 6], 0).is_empty());
+    }
+
+    #[test]
+    fn test_centroid_across_antimeridian() {
+        let mut cube = BleCube::new();
+        cube.insert(heard(1, 0, -60, 10.0, 179.9999));
+        cube.insert(heard(1, 1, -60, 10.0, -179.9999));
+        let estimate = cube.estimated_positions([1; 6], 10)[0];
+        assert!(lon_delta(estimate.lon, 180.0).abs() < 1e-9);
+        // ~11 m either side
+        assert!((estimate.spread_m - 10.96).abs() < 0.1);
+    }
+}
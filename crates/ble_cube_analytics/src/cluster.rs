@@ -0,0 +1,162 @@
+//! Density-based spatial clustering (DBSCAN) of observations, for finding
+//! hotspots such as entrances or bus stops without predefined zones.
+
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::{BleCube, Query};
+
+/// Cluster membership of one record, see [`BleCube::cluster_geo`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClusterAssignment {
+    pub record_id: usize,
+    /// Cluster index (0-based), `None` for noise
+    pub cluster: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Label {
+    Unvisited,
+    Noise,
+    Cluster(usize),
+}
+
+/// Density-based clustering of a [`BleCube`]'s observations
+pub trait Clustering {
+    /// DBSCAN over the observations matching `filter`.
+    ///
+    /// A record is a core point if at least `min_points` matching records
+    /// (itself included) lie within `eps_m` meters; clusters are the sets of
+    /// points density-reachable from a core point. Neighborhoods are radius
+    /// searches on the R-tree, so the cost is one search per record.
+    ///
+    /// Returns one assignment per matching record in record ID order.
+    /// Clusters are numbered in order of their lowest record ID.
+    fn cluster_geo(&self, eps_m: f64, min_points: usize, filter: &Query) -> Vec<ClusterAssignment>;
+}
+
+impl Clustering for BleCube {
+    fn cluster_geo(&self, eps_m: f64, min_points: usize, filter: &Query) -> Vec<ClusterAssignment> {
+        let ids = self.execute_ids(filter);
+        // Record ID -> position in `ids`, for matching records only
+        let mut slot = vec![None; self.records().len()];
+        for (i, &id) in ids.iter().enumerate() {
+            slot[id] = Some(i);
+        }
+
+        let neighbors = |i: usize| -> Vec<usize> {
+            let obs = &self.records()[ids[i]];
+            self.locate_in_envelope(
+                self.coordinate_system()
+                    .radius_envelope(obs.lat, obs.lon, eps_m),
+            )
+            .filter_map(|point| slot[point.record_id].map(|j| (j, point)))
+            .filter(|(_, point)| {
+                self.coordinate_system().distance(
+                    obs.lat,
+                    obs.lon,
+                    point.coords[0],
+                    point.coords[1],
+                ) <= eps_m
+            })
+            .map(|(j, _)| j)
+            .collect()
+        };
+
+        let mut labels = vec![Label::Unvisited; ids.len()];
+        let mut clusters = 0;
+        for i in 0..ids.len() {
+            if labels[i] != Label::Unvisited {
+                continue;
+            }
+            let seeds = neighbors(i);
+            if seeds.len() < min_points {
+                labels[i] = Label::Noise;
+                continue;
+            }
+
+            let cluster = clusters;
+            clusters += 1;
+            labels[i] = Label::Cluster(cluster);
+            let mut queue = seeds;
+            while let Some(j) = queue.pop() {
+                match labels[j] {
+                    Label::Cluster(_) => continue,
+                    // Reachable but not core: a border point
+                    Label::Noise => labels[j] = Label::Cluster(cluster),
+                    Label::Unvisited => {
+                        labels[j] = Label::Cluster(cluster);
+                        let reach = neighbors(j);
+                        if reach.len() >= min_points {
+                            queue.extend(reach);
+                        }
+                    }
+                }
+            }
+        }
+
+        ids.into_iter()
+            .zip(labels)
+            .map(|(record_id, label)| ClusterAssignment {
+                record_id,
+                cluster: match label {
+                    Label::Cluster(c) => Some(c),
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::{BleObservation, CoordinateSystem};
+
+    #[test]
+    fn test_dbscan_finds_hotspots() {
+        let mut cube = BleCube::new();
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        for (y, x, rssi) in [
+            (0.0, 0.0, -50),   // 0: entrance
+            (1.0, 0.0, -50),   // 1
+            (0.0, 1.0, -50),   // 2
+            (2.5, 0.0, -50),   // 3: border, only reaches 1
+            (50.0, 50.0, -50), // 4: bus stop
+            (50.0, 51.0, -50), // 5
+            (51.0, 50.0, -90), // 6
+            (100.0, 0.0, -50), // 7: lone sighting
+        ] {
+            cube.insert(BleObservation {
+                rssi,
+                lat: y,
+                lon: x,
+                ..Default::default()
+            });
+        }
+
+        let labels: Vec<Option<usize>> = cube
+            .cluster_geo(1.5, 3, &Query::new())
+            .iter()
+            .map(|a| a.cluster)
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(1),
+                Some(1),
+                Some(1),
+                None
+            ]
+        );
+
+        // Filtering out the weak sighting leaves the bus stop too sparse
+        let strong = cube.cluster_geo(1.5, 3, &Query::new().rssi_between(-60, 0));
+        assert_eq!(strong.len(), 7);
+        assert_eq!(strong[4].record_id, 4);
+        assert!(strong[4..].iter().all(|a| a.cluster.is_none()));
+    }
+}
@@ -0,0 +1,245 @@
+//! Survey coverage gaps.
+//!
+//! [`BleCube::coverage_report`] lays a grid of roughly square cells of a
+//! given size in meters over an area and counts the observations in each,
+//! so a survey team can see which streets and blocks have not been walked
+//! (or were walked too briefly) and export them as GeoJSON for a map.
+
+use ble_cube_core::geo::EARTH_RADIUS_M;
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::{BleCube, CoordinateSystem};
+use core::fmt::Write;
+
+/// One grid cell of a [`CoverageReport`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoverageCell {
+    /// Column, from the western edge
+    pub x: usize,
+    /// Row, from the northern edge
+    pub y: usize,
+    /// (min_lat, min_lon, max_lat, max_lon) of the cell
+    pub bbox: (f64, f64, f64, f64),
+    pub observations: usize,
+}
+
+/// Observation counts over a grid, see [`BleCube::coverage_report`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoverageReport {
+    /// (min_lat, min_lon, max_lat, max_lon) covered by the grid: the
+    /// requested box, extended south and east to whole cells
+    pub bbox: (f64, f64, f64, f64),
+    pub width: usize,
+    pub height: usize,
+    /// Cell size in degrees (or projected units): (lat, lon)
+    cell: (f64, f64),
+    /// Row-major counts from the north-west corner
+    counts: Vec<usize>,
+}
+
+impl CoverageReport {
+    /// Observation count of the cell at column `x`, row `y`
+    pub fn count(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| self.counts[y * self.width + x])
+    }
+
+    /// Cells with at least one observation
+    pub fn covered(&self) -> usize {
+        self.counts.iter().filter(|&&count| count > 0).count()
+    }
+
+    /// Fraction of cells with at least `min_observations` (1.0 for an
+    /// empty grid)
+    pub fn coverage_ratio(&self, min_observations: usize) -> f64 {
+        if self.counts.is_empty() {
+            return 1.0;
+        }
+        let covered = self
+            .counts
+            .iter()
+            .filter(|&&count| count >= min_observations)
+            .count();
+        covered as f64 / self.counts.len() as f64
+    }
+
+    /// Cells with fewer than `min_observations` observations, row-major from
+    /// the north-west corner: `1` lists only empty cells, higher values
+    /// also list sparsely covered ones
+    pub fn gaps(&self, min_observations: usize) -> Vec<CoverageCell> {
+        let (_, min_lon, max_lat, _) = self.bbox;
+        let (cell_lat, cell_lon) = self.cell;
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count < min_observations)
+            .map(|(i, &count)| {
+                let (x, y) = (i % self.width, i / self.width);
+                let north = max_lat - y as f64 * cell_lat;
+                let west = min_lon + x as f64 * cell_lon;
+                CoverageCell {
+                    x,
+                    y,
+                    bbox: (north - cell_lat, west, north, west + cell_lon),
+                    observations: count,
+                }
+            })
+            .collect()
+    }
+
+    /// [`CoverageReport::gaps`] as a GeoJSON `FeatureCollection` of cell
+    /// polygons with `x`, `y` and `observations` properties
+    pub fn gaps_geojson(&self, min_observations: usize) -> String {
+        let mut out = String::from(r#"{"type":"FeatureCollection","features":["#);
+        for cell in self.gaps(min_observations) {
+            if !out.ends_with('[') {
+                out.push(',');
+            }
+            let (south, west, north, east) = cell.bbox;
+            // GeoJSON positions are [lon, lat], rings counter-clockwise
+            let _ = write!(
+                out,
+                r#"{{"type":"Feature","geometry":{{"type":"Polygon","coordinates":[[[{west},{south}],[{east},{south}],[{east},{north}],[{west},{north}],[{west},{south}]]]}},"properties":{{"x":{},"y":{},"observations":{}}}}}"#,
+                cell.x, cell.y, cell.observations
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// Survey coverage of a [`BleCube`]
+pub trait Coverage {
+    /// Count observations on a grid of `cell_size_m` cells covering `bbox`
+    /// = (min_lat, min_lon, max_lat, max_lon), optionally only those with a
+    /// timestamp in `time_range` (inclusive). Row 0 is the northern edge.
+    ///
+    /// WGS84 cells span equal degrees, sized at the box's central latitude,
+    /// so they are only approximately square over tall boxes, and the box
+    /// must not cross the antimeridian. Projected cubes use `cell_size_m`
+    /// in their own units.
+    ///
+    /// # Panics
+    /// Panics if `cell_size_m` is not positive.
+    fn coverage_report(
+        &self,
+        bbox: (f64, f64, f64, f64),
+        cell_size_m: f64,
+        time_range: Option<(i64, i64)>,
+    ) -> CoverageReport;
+}
+
+impl Coverage for BleCube {
+    fn coverage_report(
+        &self,
+        bbox: (f64, f64, f64, f64),
+        cell_size_m: f64,
+        time_range: Option<(i64, i64)>,
+    ) -> CoverageReport {
+        assert!(cell_size_m > 0.0, "cell size must be positive");
+        let (min_lat, min_lon, max_lat, max_lon) = bbox;
+        let cell = match self.coordinate_system() {
+            CoordinateSystem::Wgs84 => {
+                let cell_lat = (cell_size_m / EARTH_RADIUS_M).to_degrees();
+                let central = ((min_lat + max_lat) / 2.0).to_radians().cos();
+                (cell_lat, cell_lat / central.max(1e-6))
+            }
+            CoordinateSystem::Projected => (cell_size_m, cell_size_m),
+        };
+        let grid = |span: f64, step: f64| {
+            if span > 0.0 {
+                (span / step).ceil() as usize
+            } else {
+                0
+            }
+        };
+        let height = grid(max_lat - min_lat, cell.0);
+        let width = grid(max_lon - min_lon, cell.1);
+        let mut report = CoverageReport {
+            bbox: (
+                max_lat - height as f64 * cell.0,
+                min_lon,
+                max_lat,
+                min_lon + width as f64 * cell.1,
+            ),
+            width,
+            height,
+            cell,
+            counts: vec![0; width * height],
+        };
+        if report.counts.is_empty() {
+            return report;
+        }
+
+        let timestamps = self.timestamps();
+        let envelope = self
+            .coordinate_system()
+            .bbox_envelope(min_lat, min_lon, max_lat, max_lon);
+        for point in self.locate_in_envelope(envelope) {
+            if let Some((start, end)) = time_range {
+                if !(start..=end).contains(&timestamps[point.record_id]) {
+                    continue;
+                }
+            }
+            let [lat, lon] = point.coords;
+            let x = (((lon - min_lon) / cell.1) as usize).min(width - 1);
+            let y = (((max_lat - lat) / cell.0) as usize).min(height - 1);
+            report.counts[y * width + x] += 1;
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::BleObservation;
+
+    #[test]
+    fn test_gaps_of_a_partly_walked_area() {
+        let mut cube = BleCube::new();
+        // Zigzag along two east-west lines in the north of a ~400 x 450 m
+        // box, once in the morning and once at noon
+        for (i, timestamp) in [(0, 1_000), (1, 40_000)] {
+            for step in 0..40 {
+                cube.insert(BleObservation {
+                    mac: [i; 6],
+                    timestamp,
+                    lat: 37.0035 - f64::from(step % 2) * 0.001,
+                    lon: -122.0 + f64::from(step) * 1.1e-4,
+                    ..Default::default()
+                });
+            }
+        }
+        let bbox = (37.0, -122.0, 37.0036, -121.9955);
+        let report = cube.coverage_report(bbox, 100.0, None);
+        assert_eq!((report.width, report.height), (4, 5));
+        // Rows 0 and 1 were walked, rows 2 to 4 were not
+        assert_eq!(report.covered(), 8);
+        assert_eq!(report.count(0, 0), Some(12));
+        assert_eq!(report.gaps(1).len(), 12);
+        assert!(report.gaps(1).iter().all(|cell| cell.y >= 2));
+        assert!((report.coverage_ratio(1) - 0.4).abs() < 1e-12);
+
+        let gap = report.gaps(1)[0];
+        let (south, west, north, east) = gap.bbox;
+        assert!((west - -122.0).abs() < 1e-12);
+        assert!((north - south - 0.000_899).abs() < 1e-6);
+        assert!(cube.query_geo_bbox(south, west, north, east).is_empty());
+
+        // Only the morning walk: every cell is sparse below 10 observations
+        let morning = cube.coverage_report(bbox, 100.0, Some((0, 10_000)));
+        assert_eq!(morning.count(0, 0), Some(6));
+        assert_eq!(morning.gaps(10).len(), 20);
+        assert_eq!(morning.gaps(1).len(), 12);
+
+        let geojson = report.gaps_geojson(1);
+        assert!(geojson.starts_with(r#"{"type":"FeatureCollection","features":[{"type":"Feature","geometry":{"type":"Polygon""#));
+        assert_eq!(geojson.matches(r#""type":"Feature""#).count(), 12);
+        assert_eq!(cube.coverage_report(bbox, 1e9, None).counts.len(), 1);
+        assert!(cube
+            .coverage_report((1.0, 1.0, 1.0, 2.0), 10.0, None)
+            .gaps(1)
+            .is_empty());
+    }
+}
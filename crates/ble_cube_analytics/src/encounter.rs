@@ -0,0 +1,244 @@
+//! Device encounter graph, for contact-network analysis.
+//!
+//! Two devices *encounter* each other when they are observed within a
+//! distance and time threshold of one another. [`BleCube::build_encounter_graph`]
+//! turns every such pair of observations into a weighted, undirected graph:
+//! nodes are devices, edges carry how often and for how long two devices
+//! were together. The graph is a plain adjacency list; export
+//! [`EncounterGraph::edges`] to a graph library for centrality or community
+//! detection.
+
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::internal::HashMap;
+use ble_cube_core::{BleCube, MacAddr};
+
+/// Co-occurrence of two devices, see [`BleCube::build_encounter_graph`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncounterEdge {
+    /// The lower of the two MACs
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "ble_cube_core::internal::serde_octets")
+    )]
+    pub a: [u8; 6],
+    /// The higher of the two MACs
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "ble_cube_core::internal::serde_octets")
+    )]
+    pub b: [u8; 6],
+    /// Pairs of observations within the distance and time thresholds
+    pub encounters: usize,
+    /// Time of the first encounter (the later observation of the pair)
+    pub first_seen: i64,
+    /// Time of the last encounter
+    pub last_seen: i64,
+    /// Total length of contact episodes in timestamp units: encounters at
+    /// most `max_dt` apart belong to one episode, whose length runs from its
+    /// first to its last encounter
+    pub duration: i64,
+}
+
+/// Undirected device graph built by [`BleCube::build_encounter_graph`]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncounterGraph {
+    nodes: Vec<[u8; 6]>,
+    edges: Vec<EncounterEdge>,
+    /// Per node (same order as `nodes`), indices into `edges`
+    adjacency: Vec<Vec<usize>>,
+}
+
+impl EncounterGraph {
+    /// Every device in the cube, sorted by MAC, including devices that never
+    /// encountered another
+    pub fn nodes(&self) -> &[[u8; 6]] {
+        &self.nodes
+    }
+
+    /// Every edge, sorted by `(a, b)`
+    pub fn edges(&self) -> &[EncounterEdge] {
+        &self.edges
+    }
+
+    /// The edge between two devices, in either order
+    pub fn edge<A: Into<MacAddr>, B: Into<MacAddr>>(&self, a: A, b: B) -> Option<&EncounterEdge> {
+        let (a, b) = ordered(a.into().0, b.into().0);
+        self.edges
+            .binary_search_by(|edge| (edge.a, edge.b).cmp(&(a, b)))
+            .ok()
+            .map(|i| &self.edges[i])
+    }
+
+    /// Devices `mac` encountered, sorted by MAC, with the connecting edge
+    pub fn neighbors<M: Into<MacAddr>>(&self, mac: M) -> Vec<([u8; 6], &EncounterEdge)> {
+        let mac = mac.into().0;
+        let Ok(node) = self.nodes.binary_search(&mac) else {
+            return Vec::new();
+        };
+        self.adjacency[node]
+            .iter()
+            .map(|&i| {
+                let edge = &self.edges[i];
+                (if edge.a == mac { edge.b } else { edge.a }, edge)
+            })
+            .collect()
+    }
+
+    /// Number of distinct devices `mac` encountered
+    pub fn degree<M: Into<MacAddr>>(&self, mac: M) -> usize {
+        let mac = mac.into().0;
+        self.nodes
+            .binary_search(&mac)
+            .map_or(0, |node| self.adjacency[node].len())
+    }
+}
+
+/// Device encounter graph of a [`BleCube`]
+pub trait Encounters {
+    /// Graph of which devices were near each other: an edge joins two MACs
+    /// with at least one pair of observations at most `max_dist_m` meters
+    /// (in the cube's coordinate system) and `max_dt` timestamp units apart.
+    ///
+    /// Observations are swept in time order, so the cost grows with the
+    /// number of observations inside each `max_dt` window rather than with
+    /// the square of the cube size. Returns an edgeless graph if `max_dt`
+    /// is negative.
+    fn build_encounter_graph(&self, max_dist_m: f64, max_dt: i64) -> EncounterGraph;
+}
+
+impl Encounters for BleCube {
+    fn build_encounter_graph(&self, max_dist_m: f64, max_dt: i64) -> EncounterGraph {
+        let mut nodes: Vec<[u8; 6]> = self.mac_postings().map(|(mac, _)| *mac).collect();
+        nodes.sort_unstable();
+
+        let mut by_time: Vec<usize> = self
+            .mac_postings()
+            .flat_map(|(_, ids)| ids)
+            .copied()
+            .collect();
+        by_time.sort_unstable_by_key(|&id| (self.records()[id].timestamp, id));
+
+        // Encounter times per device pair
+        let mut pairs: HashMap<([u8; 6], [u8; 6]), Vec<i64>> = HashMap::new();
+        if max_dt >= 0 {
+            for (i, &id) in by_time.iter().enumerate() {
+                let obs = &self.records()[id];
+                for &other_id in &by_time[i + 1..] {
+                    let other = &self.records()[other_id];
+                    if other.timestamp.saturating_sub(obs.timestamp) > max_dt {
+                        break;
+                    }
+                    if other.mac != obs.mac
+                        && self.distance(obs.lat, obs.lon, other.lat, other.lon) <= max_dist_m
+                    {
+                        pairs
+                            .entry(ordered(obs.mac, other.mac))
+                            .or_default()
+                            .push(other.timestamp);
+                    }
+                }
+            }
+        }
+
+        let mut edges: Vec<EncounterEdge> = pairs
+            .into_iter()
+            .map(|((a, b), mut times)| {
+                times.sort_unstable();
+                let duration = times
+                    .windows(2)
+                    .map(|w| w[1].saturating_sub(w[0]))
+                    .filter(|&gap| gap <= max_dt)
+                    .sum();
+                EncounterEdge {
+                    a,
+                    b,
+                    encounters: times.len(),
+                    first_seen: times[0],
+                    last_seen: times[times.len() - 1],
+                    duration,
+                }
+            })
+            .collect();
+        edges.sort_unstable_by_key(|edge| (edge.a, edge.b));
+
+        // Edge order is (a, b) order, so each list comes out sorted by
+        // neighbor: edges to lower MACs first, then edges to higher ones
+        let mut adjacency = vec![Vec::new(); nodes.len()];
+        for (i, edge) in edges.iter().enumerate() {
+            for mac in [edge.a, edge.b] {
+                if let Ok(node) = nodes.binary_search(&mac) {
+                    adjacency[node].push(i);
+                }
+            }
+        }
+        EncounterGraph {
+            nodes,
+            edges,
+            adjacency,
+        }
+    }
+}
+
+fn ordered(a: [u8; 6], b: [u8; 6]) -> ([u8; 6], [u8; 6]) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::{BleObservation, CoordinateSystem};
+
+    #[test]
+    fn test_encounter_graph() {
+        let mut cube = BleCube::new();
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        let mut sight = |mac: u8, timestamp: i64, x: f64| {
+            cube.insert(BleObservation {
+                mac: [mac; 6],
+                timestamp,
+                lon: x,
+                ..Default::default()
+            });
+        };
+        // 1 and 2 walk together for a while, then 2 meets 3 once; 4 is
+        // close to 3 but an hour later
+        sight(1, 0, 0.0);
+        sight(2, 15, 1.0);
+        sight(1, 30, 2.0);
+        sight(2, 30, 2.5);
+        sight(1, 200, 10.0);
+        sight(2, 210, 11.0);
+        sight(2, 500, 50.0);
+        sight(3, 505, 52.0);
+        sight(4, 4000, 52.0);
+        sight(1, 520, 100.0);
+
+        let graph = cube.build_encounter_graph(3.0, 20);
+        assert_eq!(graph.nodes().len(), 4);
+        assert_eq!(graph.edges().len(), 2);
+
+        let pair = graph.edge([2; 6], [1; 6]).unwrap();
+        assert_eq!((pair.a, pair.b), ([1; 6], [2; 6]));
+        // (0, 15), (15, 30), (30, 30) and (200, 210)
+        assert_eq!(pair.encounters, 4);
+        assert_eq!((pair.first_seen, pair.last_seen), (15, 210));
+        // Episodes [15, 30] and [210, 210]
+        assert_eq!(pair.duration, 15);
+
+        let neighbors: Vec<[u8; 6]> = graph.neighbors([2; 6]).iter().map(|n| n.0).collect();
+        assert_eq!(neighbors, [[1; 6], [3; 6]]);
+        assert_eq!(graph.degree([4; 6]), 0);
+        assert_eq!(graph.degree([9; 6]), 0);
+        assert!(graph.edge([3; 6], [4; 6]).is_none());
+
+        // Wider thresholds link 1 to 3 through the 520 sighting
+        assert_eq!(cube.build_encounter_graph(50.0, 20).degree([1; 6]), 2);
+        assert!(cube.build_encounter_graph(3.0, -1).edges().is_empty());
+    }
+}
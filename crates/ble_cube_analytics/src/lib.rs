@@ -0,0 +1,40 @@
+//! Analytics for `ble-cube`: dwell time and presence sessions, per-device
+//! rankings, RSSI-weighted positioning, DBSCAN clustering, survey coverage,
+//! encounter graphs, device-to-device distance series, heatmap rasters and
+//! anonymized export.
+//!
+//! Each family is an extension trait on [`BleCube`](ble_cube_core::BleCube);
+//! bring it into scope (or use the `ble-cube` prelude) to call its methods.
+//! Like the core, the crate is `no_std` + `alloc` without `std`.
+//!
+//! | Feature | Default | Module |
+//! |---------|---------|--------|
+//! | `std`   | yes     | standard library support; off = `no_std` + `alloc` |
+//! | `serde` | no      | `Serialize`/`Deserialize` for the result types |
+//! | `image` | no      | PNG export of heatmaps (`Raster::to_png`, `write_png`, implies `std`) |
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+mod analytics;
+mod anonymize;
+mod centroid;
+mod cluster;
+mod coverage;
+mod encounter;
+#[cfg(feature = "image")]
+mod png;
+mod proximity;
+mod raster;
+
+pub use analytics::{Analytics, DwellTime, MacStats, PresenceSession};
+pub use anonymize::{AnonymizationPolicy, Anonymize};
+pub use centroid::{PositionEstimate, Positioning};
+pub use cluster::{ClusterAssignment, Clustering};
+pub use coverage::{Coverage, CoverageCell, CoverageReport};
+pub use encounter::{EncounterEdge, EncounterGraph, Encounters};
+pub use proximity::{Alignment, DistanceSample, Proximity};
+pub use raster::{Raster, RasterMetric, Rasterize};
@@ -0,0 +1,152 @@
+//! Minimal PNG encoder for [`Raster`] heatmaps (RGBA, uncompressed deflate).
+
+use crate::raster::Raster;
+use ble_cube_core::internal::crc32;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Largest payload of a stored (uncompressed) deflate block
+const MAX_STORED_BLOCK: usize = 65_535;
+
+impl Raster {
+    /// Encode as an RGBA PNG, one pixel per cell. Values are scaled over
+    /// [`Raster::value_range`] onto a blue → red ramp; empty cells are
+    /// transparent.
+    pub fn to_png(&self) -> Vec<u8> {
+        let (lo, hi) = self.value_range().unwrap_or((0.0, 0.0));
+        let span = hi - lo;
+
+        // Each scanline is prefixed with filter type 0 (None)
+        let mut pixels = Vec::with_capacity(self.height() * (1 + 4 * self.width()));
+        for row in self.cells().chunks(self.width().max(1)) {
+            pixels.push(0);
+            for cell in row {
+                match cell {
+                    Some(v) => {
+                        let t = if span > 0.0 { (v - lo) / span } else { 1.0 };
+                        pixels.extend_from_slice(&heat_color(t));
+                        pixels.push(0xFF);
+                    }
+                    None => pixels.extend_from_slice(&[0, 0, 0, 0]),
+                }
+            }
+        }
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.width() as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height() as u32).to_be_bytes());
+        // Bit depth 8, color type 6 (RGBA), deflate, adaptive filtering, no interlace
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Write [`Raster::to_png`] to `path`
+    pub fn write_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_png())
+    }
+}
+
+/// Piecewise-linear blue → cyan → green → yellow → red for `t` in [0, 1]
+fn heat_color(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0) * 4.0;
+    let ramp = |x: f64| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    match t {
+        t if t < 1.0 => [0, ramp(t), 255],
+        t if t < 2.0 => [0, 255, ramp(2.0 - t)],
+        t if t < 3.0 => [ramp(t - 2.0), 255, 0],
+        t => [255, ramp(4.0 - t), 0],
+    }
+}
+
+/// `[len][type][data][crc32(type + data)]`
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Zlib stream made of stored deflate blocks (no compression)
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(2 + blocks * 5 + data.len() + 4);
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(u8::from(last));
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Adler-32 checksum of `data` (zlib stream trailer)
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65_521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest run that cannot overflow u32 before reducing
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RasterMetric, Rasterize};
+    use ble_cube_core::{BleCube, BleObservation};
+
+    #[test]
+    fn test_png_structure() {
+        let mut cube = BleCube::new();
+        cube.insert(BleObservation {
+            lat: 0.5,
+            lon: 0.5,
+            ..Default::default()
+        });
+        let raster = cube.rasterize((0.0, 0.0, 1.0, 1.0), 3, 2, RasterMetric::Density);
+        let png = raster.to_png();
+
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 3);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 2);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        assert_eq!(png[png.len() - 4..], crc32(b"IEND").to_be_bytes());
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        // IDAT holds 2 rows x (filter byte + 3 RGBA pixels), stored verbatim
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        assert_eq!(idat_len, 2 + 5 + 2 * (1 + 3 * 4) + 4);
+        let pixels = &png[41 + 7..41 + 7 + 26];
+        // The single point lands in the middle column of the southern row
+        assert_eq!(pixels[0], 0);
+        assert_eq!(pixels[13 + 1 + 4..13 + 1 + 8], [255, 0, 0, 255]);
+        assert_eq!(pixels[1..5], [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,194 @@
+//! Device-to-device distance over time, for proximity and contact analysis.
+
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::{BleCube, MacAddr};
+
+/// How a device's position is estimated at a grid time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Alignment {
+    /// Position of the closest observation in time
+    #[default]
+    Nearest,
+    /// Linear interpolation between the observations just before and after
+    /// the grid time (falls back to the nearest one at the edges)
+    Interpolated,
+}
+
+/// Distance between two devices at one grid time
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DistanceSample {
+    pub timestamp: i64,
+    /// Meters, in the cube's coordinate system
+    pub distance_m: f64,
+}
+
+/// A device's track: (timestamp, lat, lon) sorted by time, one point per
+/// timestamp (simultaneous sightings averaged)
+type Track = Vec<(i64, f64, f64)>;
+
+/// Device-to-device distance series over a [`BleCube`]
+pub trait Proximity {
+    /// Distance between two devices sampled on a common grid of timestamps
+    /// that are multiples of `bucket`, across the span both were observed.
+    ///
+    /// A device only has a position at grid time `t` if it was observed
+    /// within `bucket` of `t`; grid times where either device has no
+    /// position are skipped rather than extrapolated from stale sightings.
+    /// Returns nothing if `bucket` is not positive.
+    fn pairwise_distance_series<A: Into<MacAddr>, B: Into<MacAddr>>(
+        &self,
+        mac_a: A,
+        mac_b: B,
+        bucket: i64,
+        alignment: Alignment,
+    ) -> Vec<DistanceSample>;
+}
+
+impl Proximity for BleCube {
+    fn pairwise_distance_series<A: Into<MacAddr>, B: Into<MacAddr>>(
+        &self,
+        mac_a: A,
+        mac_b: B,
+        bucket: i64,
+        alignment: Alignment,
+    ) -> Vec<DistanceSample> {
+        let (a, b) = (track(self, mac_a.into()), track(self, mac_b.into()));
+        let (Some(first_a), Some(first_b)) = (a.first(), b.first()) else {
+            return Vec::new();
+        };
+        if bucket <= 0 {
+            return Vec::new();
+        }
+        let start = first_a.0.max(first_b.0);
+        let end = a[a.len() - 1].0.min(b[b.len() - 1].0);
+
+        // First multiple of `bucket` at or after `start`
+        let mut t = start.div_euclid(bucket) * bucket;
+        if t < start {
+            t += bucket;
+        }
+        let mut samples = Vec::new();
+        while t <= end {
+            if let (Some((lat_a, lon_a)), Some((lat_b, lon_b))) = (
+                position_at(&a, t, bucket, alignment),
+                position_at(&b, t, bucket, alignment),
+            ) {
+                samples.push(DistanceSample {
+                    timestamp: t,
+                    distance_m: self.distance(lat_a, lon_a, lat_b, lon_b),
+                });
+            }
+            let Some(next) = t.checked_add(bucket) else {
+                break;
+            };
+            t = next;
+        }
+        samples
+    }
+}
+
+fn track(cube: &BleCube, mac: MacAddr) -> Track {
+    let mut points: Vec<(i64, f64, f64)> = cube
+        .mac_record_ids(mac)
+        .iter()
+        .map(|&id| {
+            let obs = &cube.records()[id];
+            (obs.timestamp, obs.lat, obs.lon)
+        })
+        .collect();
+    points.sort_by_key(|&(timestamp, ..)| timestamp);
+    points
+        .chunk_by(|x, y| x.0 == y.0)
+        .map(|run| {
+            let n = run.len() as f64;
+            let lat = run.iter().map(|p| p.1).sum::<f64>() / n;
+            let lon = run.iter().map(|p| p.2).sum::<f64>() / n;
+            (run[0].0, lat, lon)
+        })
+        .collect()
+}
+
+/// Estimated (lat, lon) at `t`, `None` if no observation is within `max_gap`
+fn position_at(track: &Track, t: i64, max_gap: i64, alignment: Alignment) -> Option<(f64, f64)> {
+    let after = track.partition_point(|&(timestamp, ..)| timestamp < t);
+    let next = track.get(after).filter(|p| p.0 - t <= max_gap);
+    let prev = after
+        .checked_sub(1)
+        .map(|i| &track[i])
+        .filter(|p| t - p.0 <= max_gap);
+
+    match (prev, next, alignment) {
+        (_, Some(&(timestamp, lat, lon)), _) if timestamp == t => Some((lat, lon)),
+        (Some(&(t0, lat0, lon0)), Some(&(t1, lat1, lon1)), Alignment::Interpolated) => {
+            let f = (t - t0) as f64 / (t1 - t0) as f64;
+            Some((lat0 + (lat1 - lat0) * f, lon0 + (lon1 - lon0) * f))
+        }
+        (Some(p), Some(n), Alignment::Nearest) => {
+            let nearest = if t - p.0 <= n.0 - t { p } else { n };
+            Some((nearest.1, nearest.2))
+        }
+        (Some(p), None, _) | (None, Some(p), _) => Some((p.1, p.2)),
+        (None, None, _) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::{BleObservation, CoordinateSystem};
+
+    #[test]
+    fn test_distance_series_alignment() {
+        let mut cube = BleCube::new();
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        let mut sight = |mac: u8, timestamp: i64, x: f64| {
+            cube.insert(BleObservation {
+                mac: [0, 0, 0, 0, 0, mac],
+                timestamp,
+                lon: x,
+                ..Default::default()
+            });
+        };
+        // A walks east 1 m/s from x = 0; B stands at x = 100
+        for t in (0..=100).step_by(20) {
+            sight(1, t, t as f64);
+        }
+        sight(2, 5, 100.0);
+        sight(2, 60, 100.0);
+        sight(2, 95, 100.0);
+
+        let nearest = cube.pairwise_distance_series(
+            [0, 0, 0, 0, 0, 1],
+            [0, 0, 0, 0, 0, 2],
+            10,
+            Alignment::Nearest,
+        );
+        let times: Vec<i64> = nearest.iter().map(|s| s.timestamp).collect();
+        // Span [5, 95]; B only has a position within 10 of its sightings
+        assert_eq!(times, vec![10, 50, 60, 70, 90]);
+        // Ties go to the earlier sighting: A at x = 0 for t = 10
+        assert_eq!(nearest[0].distance_m, 100.0);
+        assert_eq!(nearest[1].distance_m, 60.0);
+        assert_eq!(nearest[2].distance_m, 40.0);
+
+        let interpolated = cube.pairwise_distance_series(
+            [0, 0, 0, 0, 0, 1],
+            [0, 0, 0, 0, 0, 2],
+            10,
+            Alignment::Interpolated,
+        );
+        assert_eq!(interpolated[0].distance_m, 90.0);
+        assert_eq!(interpolated[1].distance_m, 50.0);
+
+        assert!(cube
+            .pairwise_distance_series(
+                [0, 0, 0, 0, 0, 1],
+                [0, 0, 0, 0, 0, 9],
+                10,
+                Alignment::Nearest
+            )
+            .is_empty());
+    }
+}
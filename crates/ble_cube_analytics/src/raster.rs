@@ -0,0 +1,172 @@
+//! Rasterize observations onto a lat/lon grid (density or RSSI heatmaps).
+
+use ble_cube_core::internal::prelude::*;
+use ble_cube_core::BleCube;
+use rstar::AABB;
+
+/// Value computed for each raster cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RasterMetric {
+    /// Number of observations in the cell
+    #[default]
+    Density,
+    /// Mean RSSI (dBm) of the cell's observations
+    MeanRssi,
+    /// Strongest RSSI (dBm) in the cell
+    MaxRssi,
+}
+
+/// Row-major grid of cell values; row 0 is the northern edge, column 0 the
+/// western edge. Cells without observations are `None`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Raster {
+    width: usize,
+    height: usize,
+    metric: RasterMetric,
+    cells: Vec<Option<f64>>,
+}
+
+impl Raster {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn metric(&self) -> RasterMetric {
+        self.metric
+    }
+
+    /// Value of the cell at column `x`, row `y`
+    pub fn get(&self, x: usize, y: usize) -> Option<f64> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells[y * self.width + x]
+    }
+
+    /// All cells, row-major from the north-west corner
+    pub fn cells(&self) -> &[Option<f64>] {
+        &self.cells
+    }
+
+    /// (min, max) over non-empty cells, `None` if every cell is empty
+    pub fn value_range(&self) -> Option<(f64, f64)> {
+        self.cells
+            .iter()
+            .flatten()
+            .fold(None, |range, &v| match range {
+                None => Some((v, v)),
+                Some((lo, hi)) => Some((lo.min(v), hi.max(v))),
+            })
+    }
+}
+
+/// Heatmap rasterization of a [`BleCube`]
+pub trait Rasterize {
+    /// Bin every observation inside `bbox` = (min_lat, min_lon, max_lat,
+    /// max_lon) into a `width` x `height` grid of equal-degree cells.
+    /// Observations on the max edges land in the last row/column.
+    fn rasterize(
+        &self,
+        bbox: (f64, f64, f64, f64),
+        width: usize,
+        height: usize,
+        metric: RasterMetric,
+    ) -> Raster;
+}
+
+impl Rasterize for BleCube {
+    fn rasterize(
+        &self,
+        bbox: (f64, f64, f64, f64),
+        width: usize,
+        height: usize,
+        metric: RasterMetric,
+    ) -> Raster {
+        let (min_lat, min_lon, max_lat, max_lon) = bbox;
+        let mut raster = Raster {
+            width,
+            height,
+            metric,
+            cells: vec![None; width * height],
+        };
+        let lat_span = max_lat - min_lat;
+        let lon_span = max_lon - min_lon;
+        if width == 0 || height == 0 || lat_span <= 0.0 || lon_span <= 0.0 {
+            return raster;
+        }
+
+        // (count, rssi sum, rssi max) per cell
+        let mut acc = vec![(0u32, 0i64, i8::MIN); width * height];
+        let envelope = AABB::from_corners([min_lat, min_lon], [max_lat, max_lon]);
+        for point in self.locate_in_envelope(envelope) {
+            let [lat, lon] = point.coords;
+            let x = (((lon - min_lon) / lon_span * width as f64) as usize).min(width - 1);
+            let y = (((max_lat - lat) / lat_span * height as f64) as usize).min(height - 1);
+            let rssi = self.rssi_values()[point.record_id];
+
+            let cell = &mut acc[y * width + x];
+            cell.0 += 1;
+            cell.1 += i64::from(rssi);
+            cell.2 = cell.2.max(rssi);
+        }
+
+        for (value, &(count, sum, max)) in raster.cells.iter_mut().zip(&acc) {
+            if count == 0 {
+                continue;
+            }
+            *value = Some(match metric {
+                RasterMetric::Density => f64::from(count),
+                RasterMetric::MeanRssi => sum as f64 / f64::from(count),
+                RasterMetric::MaxRssi => f64::from(max),
+            });
+        }
+        raster
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::BleObservation;
+
+    #[test]
+    fn test_rasterize_density_and_rssi() {
+        let mut cube = BleCube::new();
+        // North-west cell: two sightings; south-east corner: one on the edge
+        for (lat, lon, rssi) in [(0.9, 0.1, -50), (0.8, 0.2, -70), (0.0, 1.0, -90)] {
+            cube.insert(BleObservation {
+                rssi,
+                lat,
+                lon,
+                ..Default::default()
+            });
+        }
+        // Outside the box
+        cube.insert(BleObservation {
+            lat: 5.0,
+            lon: 5.0,
+            ..Default::default()
+        });
+
+        let bbox = (0.0, 0.0, 1.0, 1.0);
+        let density = cube.rasterize(bbox, 2, 2, RasterMetric::Density);
+        assert_eq!(density.get(0, 0), Some(2.0));
+        assert_eq!(density.get(1, 1), Some(1.0));
+        assert_eq!(density.get(1, 0), None);
+        assert_eq!(density.value_range(), Some((1.0, 2.0)));
+
+        let mean = cube.rasterize(bbox, 2, 2, RasterMetric::MeanRssi);
+        assert_eq!(mean.get(0, 0), Some(-60.0));
+        let max = cube.rasterize(bbox, 2, 2, RasterMetric::MaxRssi);
+        assert_eq!(max.get(0, 0), Some(-50.0));
+
+        let empty = cube.rasterize(bbox, 0, 4, RasterMetric::Density);
+        assert!(empty.cells().is_empty());
+    }
+}
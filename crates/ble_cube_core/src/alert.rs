@@ -0,0 +1,356 @@
+//! Streaming alert rules on top of the change feed.
+//!
+//! Perimeter monitoring cares about events rather than observations: a
+//! device nobody has seen in the zone before, a device suddenly much closer
+//! to a scanner, a crowd forming. [`BleCube::subscribe_alerts`] evaluates a
+//! set of [`AlertRule`]s on every insert and sends an [`Alert`] down a
+//! channel whenever one fires. Rules keep only the state they need (seen
+//! MACs, recent RSSI readings, recent devices per zone), so evaluation does
+//! not query the cube's history.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Inserts between sweeps of per-device state that fell out of its window
+const SWEEP_EVERY: u64 = 4096;
+
+/// A condition [`BleCube::subscribe_alerts`] watches for; windows are in
+/// timestamp units
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AlertRule {
+    /// A MAC shows up inside the zone for the first time, counting records
+    /// already in the cube when subscribing
+    NewDeviceInZone { zone: String },
+    /// A device's RSSI is more than `jump_db` above a reading of the same
+    /// MAC less than `window` earlier
+    RssiSpike { jump_db: u8, window: i64 },
+    /// More than `max_devices` distinct MACs were seen inside the zone
+    /// during the last `window`, counting inserts since subscribing. Fires
+    /// once when the count goes over the limit and again only after it has
+    /// dropped back to it.
+    ZoneOccupancy {
+        zone: String,
+        window: i64,
+        max_devices: usize,
+    },
+}
+
+/// An event emitted by an [`AlertRule`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Alert {
+    /// See [`AlertRule::NewDeviceInZone`]
+    NewDeviceInZone {
+        zone: String,
+        observation: BleObservation,
+    },
+    /// See [`AlertRule::RssiSpike`]
+    RssiSpike {
+        /// The earlier, weaker reading
+        from_rssi: i8,
+        /// Time since that reading
+        elapsed: i64,
+        observation: BleObservation,
+    },
+    /// See [`AlertRule::ZoneOccupancy`]
+    ZoneOccupancy {
+        zone: String,
+        /// Distinct MACs in the zone during the window
+        devices: usize,
+        observation: BleObservation,
+    },
+}
+
+impl Alert {
+    /// The inserted observation that triggered the alert
+    pub fn observation(&self) -> &BleObservation {
+        match self {
+            Alert::NewDeviceInZone { observation, .. }
+            | Alert::RssiSpike { observation, .. }
+            | Alert::ZoneOccupancy { observation, .. } => observation,
+        }
+    }
+}
+
+/// A rule and what it remembers between inserts
+#[derive(Debug)]
+enum Detector {
+    NewDeviceInZone {
+        zone: String,
+        seen: HashSet<[u8; 6]>,
+    },
+    RssiSpike {
+        jump_db: u8,
+        window: i64,
+        /// Readings per MAC, oldest first
+        recent: HashMap<[u8; 6], VecDeque<(i64, i8)>>,
+    },
+    ZoneOccupancy {
+        zone: String,
+        window: i64,
+        max_devices: usize,
+        /// Latest sighting in the zone per MAC
+        last_seen: HashMap<[u8; 6], i64>,
+        over: bool,
+    },
+}
+
+impl Detector {
+    fn new(cube: &BleCube, rule: AlertRule) -> Self {
+        match rule {
+            AlertRule::NewDeviceInZone { zone } => Detector::NewDeviceInZone {
+                seen: cube
+                    .query_zone(&zone, None)
+                    .iter()
+                    .map(|obs| obs.mac)
+                    .collect(),
+                zone,
+            },
+            AlertRule::RssiSpike { jump_db, window } => Detector::RssiSpike {
+                jump_db,
+                window,
+                recent: HashMap::new(),
+            },
+            AlertRule::ZoneOccupancy {
+                zone,
+                window,
+                max_devices,
+            } => Detector::ZoneOccupancy {
+                zone,
+                window,
+                max_devices,
+                last_seen: HashMap::new(),
+                over: false,
+            },
+        }
+    }
+
+    fn observe(&mut self, cube: &BleCube, record_id: usize) -> Option<Alert> {
+        let obs = cube.records[record_id];
+        let in_zone = |zone: &str| {
+            cube.geofence
+                .members(zone)
+                .is_some_and(|members| members.binary_search(&record_id).is_ok())
+        };
+        match self {
+            Detector::NewDeviceInZone { zone, seen } => (in_zone(zone) && seen.insert(obs.mac))
+                .then(|| Alert::NewDeviceInZone {
+                    zone: zone.clone(),
+                    observation: obs,
+                }),
+            Detector::RssiSpike {
+                jump_db,
+                window,
+                recent,
+            } => {
+                let readings = recent.entry(obs.mac).or_default();
+                let since = obs.timestamp.saturating_sub(*window);
+                while readings.front().is_some_and(|&(ts, _)| ts <= since) {
+                    readings.pop_front();
+                }
+                let weakest = readings
+                    .iter()
+                    .filter(|&&(ts, _)| ts <= obs.timestamp)
+                    .min_by_key(|&&(_, rssi)| rssi)
+                    .copied();
+                match weakest {
+                    Some((ts, rssi))
+                        if i16::from(obs.rssi) - i16::from(rssi) > i16::from(*jump_db) =>
+                    {
+                        // Start over, so one jump raises one alert
+                        readings.clear();
+                        Some(Alert::RssiSpike {
+                            from_rssi: rssi,
+                            elapsed: obs.timestamp - ts,
+                            observation: obs,
+                        })
+                    }
+                    _ => {
+                        let at = readings.partition_point(|&(ts, _)| ts <= obs.timestamp);
+                        readings.insert(at, (obs.timestamp, obs.rssi));
+                        None
+                    }
+                }
+            }
+            Detector::ZoneOccupancy {
+                zone,
+                window,
+                max_devices,
+                last_seen,
+                over,
+            } => {
+                if !in_zone(zone) {
+                    return None;
+                }
+                let latest = last_seen.entry(obs.mac).or_insert(obs.timestamp);
+                *latest = (*latest).max(obs.timestamp);
+                let since = obs.timestamp.saturating_sub(*window);
+                last_seen.retain(|_, &mut ts| ts > since);
+                let devices = last_seen.len();
+                if devices <= *max_devices {
+                    *over = false;
+                    return None;
+                }
+                (!core::mem::replace(over, true)).then(|| Alert::ZoneOccupancy {
+                    zone: zone.clone(),
+                    devices,
+                    observation: obs,
+                })
+            }
+        }
+    }
+
+    /// Forget RSSI readings that can no longer start a spike
+    fn sweep(&mut self, now: i64) {
+        if let Detector::RssiSpike { window, recent, .. } = self {
+            let since = now.saturating_sub(*window);
+            recent.retain(|_, readings| readings.back().is_some_and(|&(ts, _)| ts > since));
+        }
+    }
+}
+
+/// Rules of one [`BleCube::subscribe_alerts`] call and their channel
+#[derive(Debug)]
+pub(crate) struct AlertSubscription {
+    detectors: Vec<Detector>,
+    tx: Sender<Alert>,
+    inserts: u64,
+}
+
+impl BleCube {
+    /// Evaluate `rules` on every subsequently inserted observation and
+    /// receive the alerts they raise, in insertion order. Drop the receiver
+    /// to unsubscribe.
+    pub fn subscribe_alerts<I>(&mut self, rules: I) -> Receiver<Alert>
+    where
+        I: IntoIterator<Item = AlertRule>,
+    {
+        let (tx, rx) = mpsc::channel();
+        let detectors = rules
+            .into_iter()
+            .map(|rule| Detector::new(self, rule))
+            .collect();
+        self.subscribers.alerts.push(AlertSubscription {
+            detectors,
+            tx,
+            inserts: 0,
+        });
+        rx
+    }
+
+    /// Run a freshly indexed record through every alert subscription
+    pub(crate) fn notify_alerts(&mut self, record_id: usize) {
+        let mut alerts = std::mem::take(&mut self.subscribers.alerts);
+        alerts.retain_mut(|sub| {
+            sub.inserts += 1;
+            if sub.inserts.is_multiple_of(SWEEP_EVERY) {
+                let now = self.records[record_id].timestamp;
+                sub.detectors.iter_mut().for_each(|d| d.sweep(now));
+            }
+            sub.detectors
+                .iter_mut()
+                .filter_map(|detector| detector.observe(self, record_id))
+                .all(|alert| sub.tx.send(alert).is_ok())
+        });
+        self.subscribers.alerts = alerts;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::Zone;
+
+    fn obs(mac: u8, timestamp: i64, lat: f64, rssi: i8) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            timestamp,
+            lat,
+            rssi,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_new_device_and_occupancy_alerts() {
+        let mut cube = BleCube::new();
+        cube.add_zone("gate", Zone::circle(0.0, 0.0, 100.0));
+        cube.insert(obs(1, 0, 0.0, -60));
+        let rx = cube.subscribe_alerts([
+            AlertRule::NewDeviceInZone {
+                zone: "gate".to_string(),
+            },
+            AlertRule::ZoneOccupancy {
+                zone: "gate".to_string(),
+                window: 60,
+                max_devices: 2,
+            },
+        ]);
+
+        cube.insert(obs(1, 10, 0.0, -60)); // known before subscribing
+        cube.insert(obs(2, 20, 1.0, -60)); // outside the zone
+        cube.insert(obs(2, 30, 0.0, -60)); // new
+        cube.insert(obs(3, 40, 0.0, -60)); // new, and a third device
+        cube.insert(obs(4, 45, 0.0, -60)); // new, still over: no second alert
+        cube.insert(obs(2, 200, 0.0, -60)); // others left the window: re-armed
+        cube.insert(obs(3, 210, 0.0, -60));
+        cube.insert(obs(4, 220, 0.0, -60));
+
+        let alerts: Vec<(char, u8, i64)> = rx
+            .try_iter()
+            .map(|alert| {
+                let kind = match alert {
+                    Alert::NewDeviceInZone { .. } => 'n',
+                    Alert::ZoneOccupancy { devices, .. } => {
+                        assert_eq!(devices, 3);
+                        'o'
+                    }
+                    Alert::RssiSpike { .. } => 's',
+                };
+                (
+                    kind,
+                    alert.observation().mac[0],
+                    alert.observation().timestamp,
+                )
+            })
+            .collect();
+        assert_eq!(
+            alerts,
+            [
+                ('n', 2, 30),
+                ('n', 3, 40),
+                ('o', 3, 40),
+                ('n', 4, 45),
+                ('o', 4, 220)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rssi_spike_alert() {
+        let mut cube = BleCube::new();
+        let rx = cube.subscribe_alerts([AlertRule::RssiSpike {
+            jump_db: 20,
+            window: 10,
+        }]);
+        cube.insert(obs(1, 0, 0.0, -85));
+        cube.insert(obs(1, 5, 0.0, -70));
+        cube.insert(obs(1, 9, 0.0, -60)); // 25 dB above the reading 9 earlier
+        cube.insert(obs(1, 10, 0.0, -55)); // history was reset by the alert
+        cube.insert(obs(2, 20, 0.0, -90));
+        cube.insert(obs(2, 30, 0.0, -50)); // 40 dB, but not less than 10 later
+
+        let alerts: Vec<Alert> = rx.try_iter().collect();
+        assert_eq!(alerts.len(), 1);
+        assert!(matches!(
+            alerts[0],
+            Alert::RssiSpike {
+                from_rssi: -85,
+                elapsed: 9,
+                ..
+            }
+        ));
+    }
+}
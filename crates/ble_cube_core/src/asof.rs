@@ -0,0 +1,88 @@
+//! "As-of" lookups: a device's observation closest in time to an instant.
+//!
+//! Aligning BLE sightings with an external event log (door badge swipes,
+//! POS transactions, alarms) needs, for each event time, the sighting of a
+//! device nearest to it, and nothing if the device was not seen around then.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::mac::MacAddr;
+
+impl BleCube {
+    /// The observation of `mac` closest in time to `timestamp`, if one lies
+    /// within `tolerance` timestamp units of it. Ties go to the earlier
+    /// observation, then to the lower record ID.
+    pub fn query_asof<M: Into<MacAddr>>(
+        &self,
+        mac: M,
+        timestamp: i64,
+        tolerance: i64,
+    ) -> Option<&BleObservation> {
+        let ids = self.mac_index.get(&mac.into().0)?;
+        self.closest_in_time(ids, timestamp, tolerance)
+    }
+
+    /// [`BleCube::query_asof`] for every device, sorted by MAC; devices with
+    /// no observation within `tolerance` are left out
+    pub fn query_asof_all(&self, timestamp: i64, tolerance: i64) -> Vec<&BleObservation> {
+        let mut matches: Vec<&BleObservation> = self
+            .mac_index
+            .values()
+            .filter_map(|ids| self.closest_in_time(ids, timestamp, tolerance))
+            .collect();
+        matches.sort_unstable_by_key(|obs| obs.mac);
+        matches
+    }
+
+    fn closest_in_time(
+        &self,
+        ids: &[usize],
+        timestamp: i64,
+        tolerance: i64,
+    ) -> Option<&BleObservation> {
+        let tolerance = u64::try_from(tolerance).ok()?;
+        ids.iter()
+            .map(|&id| (&self.records[id], id))
+            .filter(|(obs, _)| obs.timestamp.abs_diff(timestamp) <= tolerance)
+            .min_by_key(|&(obs, id)| (obs.timestamp.abs_diff(timestamp), obs.timestamp, id))
+            .map(|(obs, _)| obs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -60,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_query_asof() {
+        let mut cube = BleCube::new();
+        for (mac, ts) in [(1, 100), (1, 160), (1, 130), (2, 140), (3, 1000)] {
+            cube.insert(obs(mac, ts));
+        }
+
+        assert_eq!(cube.query_asof([1; 6], 125, 10).unwrap().timestamp, 130);
+        // 115 is as far from 100 as from 130: the earlier one wins
+        assert_eq!(cube.query_asof([1; 6], 115, 60).unwrap().timestamp, 100);
+        assert!(cube.query_asof([1; 6], 200, 30).is_none());
+        assert!(cube.query_asof([1; 6], 100, -1).is_none());
+        assert!(cube.query_asof([9; 6], 100, 1000).is_none());
+
+        let at: Vec<([u8; 6], i64)> = cube
+            .query_asof_all(150, 20)
+            .iter()
+            .map(|obs| (obs.mac, obs.timestamp))
+            .collect();
+        assert_eq!(at, [([1; 6], 160), ([2; 6], 140)]);
+    }
+}
@@ -0,0 +1,553 @@
+//! iBeacon / Eddystone advertisement decoding and beacon-identity indices.
+//!
+//! Beacons commonly rotate their MAC but keep a stable beacon identity, so
+//! observations inserted with [`BleCube::insert_advertisement`] are also
+//! indexed by iBeacon UUID (and UUID/major/minor) and Eddystone-UID.
+
+use crate::ble_cube::{insert_posting, BleCube, BleObservation, PostingIndex};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::error::CubeError;
+use crate::memory::{hash_postings_bytes, hash_table_bytes, shrink_hash_postings, ComponentMemory};
+
+const AD_TYPE_TX_POWER_LEVEL: u8 = 0x0A;
+const AD_TYPE_SERVICE_DATA_16: u8 = 0x16;
+const AD_TYPE_MANUFACTURER_DATA: u8 = 0xFF;
+const APPLE_COMPANY_ID: u16 = 0x004C;
+const EDDYSTONE_SERVICE_UUID: u16 = 0xFEAA;
+
+/// Free-space loss between 0 m and 1 m at 2.4 GHz (dB), to bring 0 m
+/// calibrations (Eddystone, TX Power Level) to the 1 m reference of
+/// [`BleObservation::tx_power`]
+const LOSS_AT_1M_DB: i8 = 41;
+
+/// Longest payload kept per record (BLE 5 extended advertising data)
+pub(crate) const MAX_ADVERTISEMENT_LEN: usize = 255;
+
+/// Apple iBeacon frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IBeacon {
+    pub uuid: [u8; 16],
+    pub major: u16,
+    pub minor: u16,
+    /// Calibrated RSSI at 1m, dBm
+    pub tx_power: i8,
+}
+
+/// Eddystone-UID frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EddystoneUid {
+    /// Calibrated RSSI at 0m, dBm
+    pub tx_power: i8,
+    pub namespace: [u8; 10],
+    pub instance: [u8; 6],
+}
+
+/// Eddystone-URL frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EddystoneUrl {
+    /// Calibrated RSSI at 0m, dBm
+    pub tx_power: i8,
+    /// Fully expanded URL
+    pub url: String,
+}
+
+/// Eddystone-TLM (unencrypted telemetry) frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EddystoneTlm {
+    pub version: u8,
+    /// Battery voltage in mV, 0 if unsupported
+    pub battery_mv: u16,
+    /// Beacon temperature in °C, `None` if unsupported
+    pub temperature_c: Option<f32>,
+    /// Advertising PDUs sent since power-up
+    pub adv_count: u32,
+    /// Time since power-up, in 0.1s units
+    pub uptime_ds: u32,
+}
+
+/// A decoded beacon frame
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BeaconFrame {
+    IBeacon(IBeacon),
+    EddystoneUid(EddystoneUid),
+    EddystoneUrl(EddystoneUrl),
+    EddystoneTlm(EddystoneTlm),
+}
+
+/// Decode every recognized beacon frame in a raw advertisement payload
+/// (a sequence of `[len][ad_type][data...]` AD structures). Malformed or
+/// unknown structures are skipped.
+pub fn parse_advertisement(payload: &[u8]) -> Vec<BeaconFrame> {
+    ad_structures(payload)
+        .filter_map(|(ad_type, data)| match ad_type {
+            AD_TYPE_MANUFACTURER_DATA => parse_ibeacon(data).map(BeaconFrame::IBeacon),
+            AD_TYPE_SERVICE_DATA_16 => parse_eddystone(data),
+            _ => None,
+        })
+        .collect()
+}
+
+/// (AD type, data) of each well-formed AD structure, stopping at the first
+/// malformed length
+pub(crate) fn ad_structures(payload: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = payload;
+    core::iter::from_fn(move || {
+        let (&len, tail) = rest.split_first()?;
+        let len = len as usize;
+        if len == 0 || len > tail.len() {
+            return None;
+        }
+        let (structure, next) = tail.split_at(len);
+        rest = next;
+        Some((structure[0], &structure[1..]))
+    })
+}
+
+/// Calibrated power at 1 m announced by an advertisement: iBeacon measured
+/// power as is, or an Eddystone UID/URL or TX Power Level value (0 m
+/// references) less 41 dB. Beacon frames win over the generic AD type.
+pub(crate) fn advertised_tx_power(payload: &[u8]) -> Option<i8> {
+    let from_frame = parse_advertisement(payload)
+        .into_iter()
+        .find_map(|frame| match frame {
+            BeaconFrame::IBeacon(b) => Some(b.tx_power),
+            BeaconFrame::EddystoneUid(EddystoneUid { tx_power, .. })
+            | BeaconFrame::EddystoneUrl(EddystoneUrl { tx_power, .. }) => {
+                Some(tx_power.saturating_sub(LOSS_AT_1M_DB))
+            }
+            BeaconFrame::EddystoneTlm(_) => None,
+        });
+    from_frame.or_else(|| {
+        ad_structures(payload).find_map(|(ad_type, data)| match (ad_type, data) {
+            (AD_TYPE_TX_POWER_LEVEL, &[level]) => Some((level as i8).saturating_sub(LOSS_AT_1M_DB)),
+            _ => None,
+        })
+    })
+}
+
+fn parse_ibeacon(data: &[u8]) -> Option<IBeacon> {
+    // company ID (LE), type 0x02, length 0x15, UUID, major, minor, tx power
+    if data.len() != 25
+        || u16::from_le_bytes([data[0], data[1]]) != APPLE_COMPANY_ID
+        || data[2] != 0x02
+        || data[3] != 0x15
+    {
+        return None;
+    }
+
+    Some(IBeacon {
+        uuid: data[4..20].try_into().ok()?,
+        major: u16::from_be_bytes([data[20], data[21]]),
+        minor: u16::from_be_bytes([data[22], data[23]]),
+        tx_power: data[24] as i8,
+    })
+}
+
+fn parse_eddystone(data: &[u8]) -> Option<BeaconFrame> {
+    if data.len() < 3 || u16::from_le_bytes([data[0], data[1]]) != EDDYSTONE_SERVICE_UUID {
+        return None;
+    }
+
+    let frame = &data[3..];
+    match data[2] {
+        0x00 if frame.len() >= 17 => Some(BeaconFrame::EddystoneUid(EddystoneUid {
+            tx_power: frame[0] as i8,
+            namespace: frame[1..11].try_into().ok()?,
+            instance: frame[11..17].try_into().ok()?,
+        })),
+        0x10 if frame.len() >= 2 => Some(BeaconFrame::EddystoneUrl(EddystoneUrl {
+            tx_power: frame[0] as i8,
+            url: decode_eddystone_url(frame[1], &frame[2..])?,
+        })),
+        0x20 if frame.len() >= 13 && frame[0] == 0x00 => {
+            let raw_temp = i16::from_be_bytes([frame[3], frame[4]]);
+            Some(BeaconFrame::EddystoneTlm(EddystoneTlm {
+                version: frame[0],
+                battery_mv: u16::from_be_bytes([frame[1], frame[2]]),
+                // 8.8 fixed point; 0x8000 means "not supported"
+                temperature_c: (raw_temp != i16::MIN).then(|| f32::from(raw_temp) / 256.0),
+                adv_count: u32::from_be_bytes(frame[5..9].try_into().ok()?),
+                uptime_ds: u32::from_be_bytes(frame[9..13].try_into().ok()?),
+            }))
+        }
+        _ => None,
+    }
+}
+
+fn decode_eddystone_url(scheme: u8, encoded: &[u8]) -> Option<String> {
+    const SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+    const EXPANSIONS: [&str; 14] = [
+        ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu",
+        ".net", ".info", ".biz", ".gov",
+    ];
+
+    let mut url = String::from(*SCHEMES.get(scheme as usize)?);
+    for &byte in encoded {
+        match byte {
+            0x00..=0x0D => url.push_str(EXPANSIONS[byte as usize]),
+            0x21..=0x7E => url.push(byte as char),
+            _ => return None,
+        }
+    }
+    Some(url)
+}
+
+/// Raw advertisements and beacon-identity postings
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BeaconIndex {
+    advertisements: HashMap<usize, Box<[u8]>>,
+    ibeacon_uuid: HashMap<[u8; 16], Vec<usize>>,
+    ibeacon: HashMap<([u8; 16], u16, u16), Vec<usize>>,
+    eddystone_uid: HashMap<([u8; 10], [u8; 6]), Vec<usize>>,
+}
+
+impl BeaconIndex {
+    /// Stored advertisement count and estimated heap bytes (see `memory.rs`)
+    pub(crate) fn memory(&self) -> ComponentMemory {
+        ComponentMemory {
+            entries: self.advertisements.len(),
+            bytes: hash_table_bytes(&self.advertisements)
+                + self.advertisements.values().map(|p| p.len()).sum::<usize>()
+                + hash_postings_bytes(&self.ibeacon_uuid)
+                + hash_postings_bytes(&self.ibeacon)
+                + hash_postings_bytes(&self.eddystone_uid),
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.advertisements.shrink_to_fit();
+        shrink_hash_postings(&mut self.ibeacon_uuid);
+        shrink_hash_postings(&mut self.ibeacon);
+        shrink_hash_postings(&mut self.eddystone_uid);
+    }
+
+    fn index(&mut self, record_id: usize, payload: &[u8]) {
+        if payload.is_empty() {
+            return;
+        }
+        let payload = &payload[..payload.len().min(MAX_ADVERTISEMENT_LEN)];
+        self.advertisements.insert(record_id, payload.into());
+
+        for frame in parse_advertisement(payload) {
+            match frame {
+                BeaconFrame::IBeacon(b) => {
+                    insert_posting(self.ibeacon_uuid.entry(b.uuid).or_default(), record_id);
+                    insert_posting(
+                        self.ibeacon.entry((b.uuid, b.major, b.minor)).or_default(),
+                        record_id,
+                    );
+                }
+                BeaconFrame::EddystoneUid(b) => {
+                    insert_posting(
+                        self.eddystone_uid
+                            .entry((b.namespace, b.instance))
+                            .or_default(),
+                        record_id,
+                    );
+                }
+                BeaconFrame::EddystoneUrl(_) | BeaconFrame::EddystoneTlm(_) => {}
+            }
+        }
+    }
+
+    /// Swap the advertisement stored with `record_id` for `payload` (none
+    /// if empty), moving its beacon-identity postings along
+    pub(crate) fn replace(&mut self, record_id: usize, payload: &[u8]) {
+        if let Some(old) = self.advertisements.remove(&record_id) {
+            for frame in parse_advertisement(&old) {
+                match frame {
+                    BeaconFrame::IBeacon(b) => {
+                        self.ibeacon_uuid.remove_id(&b.uuid, record_id);
+                        self.ibeacon
+                            .remove_id(&(b.uuid, b.major, b.minor), record_id);
+                    }
+                    BeaconFrame::EddystoneUid(b) => {
+                        self.eddystone_uid
+                            .remove_id(&(b.namespace, b.instance), record_id);
+                    }
+                    BeaconFrame::EddystoneUrl(_) | BeaconFrame::EddystoneTlm(_) => {}
+                }
+            }
+        }
+        self.index(record_id, payload);
+    }
+
+    /// Renumber record IDs after compaction (`remap[old] = new`, `None` for
+    /// removed records); the mapping is monotone, so postings stay sorted
+    #[cfg(feature = "std")]
+    pub(crate) fn remap(&mut self, remap: &[Option<usize>]) {
+        self.advertisements = core::mem::take(&mut self.advertisements)
+            .into_iter()
+            .filter_map(|(id, payload)| Some((remap[id]?, payload)))
+            .collect();
+        remap_postings(&mut self.ibeacon_uuid, remap);
+        remap_postings(&mut self.ibeacon, remap);
+        remap_postings(&mut self.eddystone_uid, remap);
+    }
+
+    pub(crate) fn advertisement(&self, record_id: usize) -> Option<&[u8]> {
+        self.advertisements
+            .get(&record_id)
+            .map(|payload| &payload[..])
+    }
+}
+
+#[cfg(feature = "std")]
+fn remap_postings<K: crate::compat::MapKey>(
+    index: &mut HashMap<K, Vec<usize>>,
+    remap: &[Option<usize>],
+) {
+    index.retain(|_, ids| {
+        *ids = ids.iter().filter_map(|&id| remap[id]).collect();
+        !ids.is_empty()
+    });
+}
+
+impl BleCube {
+    /// Insert an observation together with its raw advertisement payload,
+    /// indexing any iBeacon / Eddystone identity it carries. Payloads longer
+    /// than 255 bytes are truncated. An observation without a `tx_power`
+    /// takes the one the advertisement announces, if any. Rate limits apply
+    /// as for [`BleCube::insert`]; an observation replacing a weaker record
+    /// replaces its advertisement too.
+    ///
+    /// # Panics
+    /// Panics if the time-unit, validation or rate-limit policy rejects the
+    /// observation, a sink fails or the write-ahead log append fails;
+    /// use [`BleCube::try_insert_advertisement`] to handle that case.
+    pub fn insert_advertisement(&mut self, obs: BleObservation, payload: &[u8]) -> usize {
+        self.insert_advertisement_logged(obs, payload)
+            .expect("insert failed")
+    }
+
+    /// Fallible form of [`BleCube::insert_advertisement`]
+    pub fn try_insert_advertisement(
+        &mut self,
+        obs: BleObservation,
+        payload: &[u8],
+    ) -> Result<usize, CubeError> {
+        self.insert_advertisement_logged(obs, payload)
+    }
+
+    fn insert_advertisement_logged(
+        &mut self,
+        obs: BleObservation,
+        payload: &[u8],
+    ) -> Result<usize, CubeError> {
+        let obs = BleObservation {
+            tx_power: obs.tx_power.or_else(|| advertised_tx_power(payload)),
+            ..obs
+        };
+        let obs = self.admit(obs)?;
+        if let Some(record_id) = self.rate_limit(&obs)? {
+            return self.replace_rate_limited(record_id, obs, payload);
+        }
+
+        #[cfg(feature = "std")]
+        self.write_sinks(&obs)?;
+
+        #[cfg(feature = "wal")]
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&crate::wal::WalEntry::InsertAdvertisement(
+                obs,
+                payload.to_vec(),
+            ))?;
+        }
+
+        let record_id = self.index_record(obs);
+        self.beacons.index(record_id, payload);
+        #[cfg(feature = "std")]
+        self.notify_subscribers(record_id);
+        self.track_rate(&obs, record_id);
+        Ok(record_id)
+    }
+
+    /// Raw advertisement payload stored with a record, if any
+    pub fn advertisement(&self, record_id: usize) -> Option<&[u8]> {
+        self.beacons.advertisement(record_id)
+    }
+
+    /// Beacon frames decoded from a record's advertisement
+    pub fn beacon_frames(&self, record_id: usize) -> Vec<BeaconFrame> {
+        self.advertisement(record_id)
+            .map(parse_advertisement)
+            .unwrap_or_default()
+    }
+
+    /// Observations of any iBeacon with this proximity UUID
+    pub fn query_ibeacon_uuid(&self, uuid: &[u8; 16]) -> Vec<&BleObservation> {
+        self.beacons
+            .ibeacon_uuid
+            .get(uuid)
+            .map(|ids| ids.iter().filter_map(|&id| self.records.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Observations of one iBeacon (UUID, major, minor)
+    pub fn query_ibeacon(&self, uuid: &[u8; 16], major: u16, minor: u16) -> Vec<&BleObservation> {
+        self.beacons
+            .ibeacon
+            .get(&(*uuid, major, minor))
+            .map(|ids| ids.iter().filter_map(|&id| self.records.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Observations of one Eddystone-UID beacon
+    pub fn query_eddystone_uid(
+        &self,
+        namespace: &[u8; 10],
+        instance: &[u8; 6],
+    ) -> Vec<&BleObservation> {
+        self.beacons
+            .eddystone_uid
+            .get(&(*namespace, *instance))
+            .map(|ids| ids.iter().filter_map(|&id| self.records.get(id)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [
+        0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB, 0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96,
+        0xE0,
+    ];
+
+    fn ibeacon_payload(major: u16, minor: u16) -> Vec<u8> {
+        let mut payload = vec![0x02, 0x01, 0x06, 0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15];
+        payload.extend_from_slice(&UUID);
+        payload.extend_from_slice(&major.to_be_bytes());
+        payload.extend_from_slice(&minor.to_be_bytes());
+        payload.push(0xC5); // -59 dBm
+        payload
+    }
+
+    fn obs(mac: u8) -> BleObservation {
+        BleObservation {
+            rssi: -70,
+            mac: [0, 0, 0, 0, 0, mac],
+            timestamp: 0,
+            lat: 0.0,
+            lon: 0.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_ibeacon() {
+        assert_eq!(
+            parse_advertisement(&ibeacon_payload(1, 2)),
+            vec![BeaconFrame::IBeacon(IBeacon {
+                uuid: UUID,
+                major: 1,
+                minor: 2,
+                tx_power: -59,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_parse_eddystone_frames() {
+        // URL: https://www.example.com/
+        let mut payload = vec![0x03, 0x03, 0xAA, 0xFE];
+        let url = [
+            0x16, 0xAA, 0xFE, 0x10, 0xEB, 0x01, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x00,
+        ];
+        payload.push(url.len() as u8);
+        payload.extend_from_slice(&url);
+
+        // TLM: 3000mV, 21.5°C, 100 PDUs, 60s uptime
+        let tlm = [
+            0x16, 0xAA, 0xFE, 0x20, 0x00, 0x0B, 0xB8, 0x15, 0x80, 0, 0, 0, 100, 0, 0, 2, 0x58,
+        ];
+        payload.push(tlm.len() as u8);
+        payload.extend_from_slice(&tlm);
+
+        let frames = parse_advertisement(&payload);
+        assert_eq!(
+            frames[0],
+            BeaconFrame::EddystoneUrl(EddystoneUrl {
+                tx_power: -21,
+                url: "https://www.example.com/".to_string(),
+            })
+        );
+        assert_eq!(
+            frames[1],
+            BeaconFrame::EddystoneTlm(EddystoneTlm {
+                version: 0,
+                battery_mv: 3000,
+                temperature_c: Some(21.5),
+                adv_count: 100,
+                uptime_ds: 600,
+            })
+        );
+    }
+
+    #[test]
+    fn test_malformed_payload_is_skipped() {
+        assert!(parse_advertisement(&[0x1A, 0xFF, 0x4C]).is_empty());
+        assert!(parse_advertisement(&[0x00, 0xFF]).is_empty());
+        assert!(parse_advertisement(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_query_by_beacon_identity_across_macs() {
+        let mut cube = BleCube::new();
+        // Same beacon seen under two rotating MACs
+        cube.insert_advertisement(obs(1), &ibeacon_payload(7, 1));
+        cube.insert_advertisement(obs(2), &ibeacon_payload(7, 1));
+        cube.insert_advertisement(obs(3), &ibeacon_payload(7, 2));
+        cube.insert(obs(4));
+
+        assert_eq!(cube.query_ibeacon_uuid(&UUID).len(), 3);
+        assert_eq!(cube.query_ibeacon(&UUID, 7, 1).len(), 2);
+        assert_eq!(cube.beacon_frames(2).len(), 1);
+        assert!(cube.advertisement(3).is_none());
+
+        let mut uid = vec![0x16, 0xAA, 0xFE, 0x00, 0xEC];
+        uid.extend_from_slice(&[1; 10]);
+        uid.extend_from_slice(&[2; 6]);
+        let mut payload = vec![uid.len() as u8];
+        payload.extend_from_slice(&uid);
+        cube.insert_advertisement(obs(5), &payload);
+        assert_eq!(cube.query_eddystone_uid(&[1; 10], &[2; 6]).len(), 1);
+    }
+
+    #[test]
+    fn test_tx_power_taken_from_advertisement() {
+        let mut cube = BleCube::new();
+        cube.insert_advertisement(obs(1), &ibeacon_payload(7, 1));
+        // Eddystone-UID calibrated to -20 dBm at 0 m
+        let mut uid = vec![0x16, 0xAA, 0xFE, 0x00, 0xEC];
+        uid.extend_from_slice(&[0; 16]);
+        let mut payload = vec![uid.len() as u8];
+        payload.extend_from_slice(&uid);
+        cube.insert_advertisement(obs(2), &payload);
+        // Generic TX Power Level AD structure: +4 dBm
+        cube.insert_advertisement(obs(3), &[0x02, 0x0A, 0x04]);
+        cube.insert_advertisement(obs(4), &[0x02, 0x01, 0x06]);
+        let explicit = BleObservation {
+            tx_power: Some(-50),
+            ..obs(5)
+        };
+        cube.insert_advertisement(explicit, &ibeacon_payload(7, 1));
+
+        let tx_power: Vec<Option<i8>> = cube.records.iter().map(|obs| obs.tx_power).collect();
+        assert_eq!(
+            tx_power,
+            vec![Some(-59), Some(-61), Some(-37), None, Some(-50)]
+        );
+        assert_eq!(cube.get(0).unwrap().path_loss(), Some(11));
+    }
+}
@@ -0,0 +1,1460 @@
+use crate::beacon::BeaconIndex;
+use crate::bloom::MacFilter;
+use crate::builder::IndexSelection;
+#[cfg(feature = "cold")]
+use crate::cold::ColdTier;
+use crate::columns::RecordStore;
+use crate::compat::prelude::*;
+use crate::compat::{HashMap, MapKey};
+use crate::crs::CoordinateSystem;
+use crate::error::CubeError;
+use crate::geo::{
+    haversine_distance, planar_distance, point_in_polygon, point_in_rings, vertices_envelope,
+    vincenty_distance,
+};
+use crate::identity::IdentityResolver;
+use crate::lifecycle::SeenIndex;
+use crate::mac::MacAddr;
+use crate::partition::TimeIndex;
+#[cfg(feature = "std")]
+use crate::query_cache::QueryCache;
+use crate::rate_limit::RateLimiter;
+use crate::record_id::RecordId;
+use crate::schedule::ScanSchedule;
+#[cfg(feature = "std")]
+use crate::sink::ObservationSink;
+#[cfg(feature = "std")]
+use crate::subscribe::Subscribers;
+use crate::tag::TagIndex;
+use crate::time::{TimeUnit, UnitMismatch};
+use crate::validate::{QuarantinedObservation, ValidationPolicy, ValidationRules};
+#[cfg(feature = "wal")]
+use crate::wal::{Wal, WalEntry};
+use crate::zone::Geofence;
+use alloc::collections::BTreeMap;
+use core::ops::{Bound, RangeBounds};
+use rstar::{RTree, RTreeObject, AABB};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+/// Single BLE observation record
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BleObservation {
+    pub rssi: i8,
+    #[cfg_attr(feature = "serde", serde(with = "crate::mac::serde_octets"))]
+    pub mac: [u8; 6],
+    pub timestamp: i64, // Unix timestamp, unit per `BleCube::set_time_unit`
+    pub lat: f64,
+    pub lon: f64,
+    /// Scanner that made the observation, for multi-receiver deployments
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub receiver_id: Option<u16>,
+    /// Building floor / level, for indoor deployments spanning storeys
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub floor: Option<i16>,
+    /// Calibrated transmit power in dBm, as the RSSI expected at 1 m (the
+    /// iBeacon "measured power" convention); see [`BleObservation::path_loss`]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub tx_power: Option<i8>,
+    /// Confidence in the record, 0 (worthless) to 255 (best), from CRC
+    /// status, GPS dilution of precision or interpolation distance; see
+    /// [`Query::min_quality`](crate::Query::min_quality)
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub quality: Option<u8>,
+}
+
+/// How [`BleCube::upsert_by_key`] resolves an existing (mac, timestamp) record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DuplicatePolicy {
+    /// Leave the stored record untouched
+    #[default]
+    KeepExisting,
+    /// Overwrite the stored record with the new observation
+    Replace,
+    /// Keep whichever observation has the higher RSSI
+    KeepStrongest,
+    /// Running mean of the coordinates over every duplicate seen since the
+    /// key index was built; RSSI is left untouched
+    AverageCoordinates,
+}
+
+/// Result of an upsert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UpsertOutcome {
+    /// No record had this key; a new one was appended
+    Inserted(usize),
+    /// An existing record was modified
+    Updated(usize),
+    /// An existing record matched and was left as is
+    Unchanged(usize),
+}
+
+impl UpsertOutcome {
+    /// Record ID of the inserted or matched record
+    pub fn record_id(self) -> usize {
+        match self {
+            UpsertOutcome::Inserted(id)
+            | UpsertOutcome::Updated(id)
+            | UpsertOutcome::Unchanged(id) => id,
+        }
+    }
+}
+
+/// Wrapper for R-tree spatial indexing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoPoint {
+    pub coords: [f64; 2], // [lat, lon]
+    pub record_id: usize,
+}
+
+impl RTreeObject for GeoPoint {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point(self.coords)
+    }
+}
+
+/// (mac, timestamp) -> (first record ID, sightings merged into it)
+type KeyIndex = HashMap<([u8; 6], i64), (usize, u32)>;
+
+/// 4-dimensional cube structure for BLE observations
+pub struct BleCube {
+    // Canonical data store (rows plus RSSI / timestamp columns)
+    pub(crate) records: RecordStore,
+
+    // Stable ID of each record (parallel to `records`, ascending) and the
+    // next one to hand out
+    pub(crate) record_ids: Vec<RecordId>,
+    pub(crate) next_record_id: u64,
+
+    // Indices (all store record IDs as usize)
+    pub(crate) mac_index: HashMap<[u8; 6], Vec<usize>>,
+    // Bloom filter over the MAC index's keys
+    pub(crate) mac_filter: MacFilter,
+    pub(crate) rssi_index: BTreeMap<i8, Vec<usize>>,
+    pub(crate) time_index: TimeIndex,
+    pub(crate) geo_index: RTree<GeoPoint>,
+    // Incremental R-tree inserts and moves since it was last bulk-loaded
+    pub(crate) geo_churn: usize,
+    pub(crate) receiver_index: HashMap<u16, Vec<usize>>,
+    pub(crate) floor_index: HashMap<i16, Vec<usize>>,
+    // Path loss (dB) of records that carry a TX power
+    pub(crate) path_loss_index: BTreeMap<i16, Vec<usize>>,
+    // Quality score of records that carry one
+    pub(crate) quality_index: BTreeMap<u8, Vec<usize>>,
+    // Which of the optional indices above are maintained; disabled ones
+    // stay empty and their queries scan instead
+    pub(crate) indexed: IndexSelection,
+
+    // Named zones with their membership postings
+    pub(crate) geofence: Geofence,
+
+    // When the collector was scanning (always, if None)
+    pub(crate) scan_schedule: Option<ScanSchedule>,
+
+    // Interned analyst tags with their record postings
+    pub(crate) tags: TagIndex,
+
+    // How lat/lon are interpreted (geodesic vs. planar meters)
+    pub(crate) crs: CoordinateSystem,
+    // Distance formula for geographic coordinates (radius queries, circle
+    // zones, analytics)
+    pub(crate) distance_metric: DistanceMetric,
+
+    // Declared timestamp unit and mismatch policy (unchecked if None)
+    pub(crate) time_unit: Option<(TimeUnit, UnitMismatch)>,
+
+    // Per-receiver RSSI offsets (dB) added at insert
+    pub(crate) rssi_offsets: HashMap<u16, i8>,
+
+    // First/last timestamp per MAC
+    pub(crate) seen_index: SeenIndex,
+
+    // Insert validation rules and policy (unchecked if None), and the
+    // observations held back by `ValidationPolicy::Quarantine`
+    pub(crate) validation: Option<(ValidationRules, ValidationPolicy)>,
+    pub(crate) quarantine: Vec<QuarantinedObservation>,
+
+    // Per-MAC insert rate limits and the records kept in each MAC's newest second
+    pub(crate) rate_limiter: RateLimiter,
+
+    // Registered IRKs and resolvable private addresses resolved with them
+    pub(crate) identities: IdentityResolver,
+
+    // Raw advertisements and iBeacon / Eddystone identity postings
+    pub(crate) beacons: BeaconIndex,
+
+    // Change-feed subscribers notified on insert
+    #[cfg(feature = "std")]
+    pub(crate) subscribers: Subscribers,
+
+    // Archival sinks every inserted observation is teed into
+    #[cfg(feature = "std")]
+    pub(crate) sinks: Vec<Box<dyn ObservationSink>>,
+
+    // Composite (mac, timestamp) -> (first record ID, duplicates merged),
+    // built lazily on the first upsert and maintained from then on
+    pub(crate) key_index: Option<KeyIndex>,
+
+    // Optional write-ahead log (see `BleCube::recover`)
+    #[cfg(feature = "wal")]
+    pub(crate) wal: Option<Wal>,
+
+    // Frozen partitions on disk (see `BleCube::attach_cold_tier`)
+    #[cfg(feature = "cold")]
+    pub(crate) cold: Option<ColdTier>,
+
+    // Grouped results of repeated queries (see `BleCube::enable_query_cache`)
+    #[cfg(feature = "std")]
+    pub(crate) query_cache: Option<Mutex<QueryCache>>,
+}
+
+impl BleCube {
+    /// Create a new empty cube
+    pub fn new() -> Self {
+        Self {
+            records: RecordStore::default(),
+            record_ids: Vec::new(),
+            next_record_id: 0,
+            mac_index: HashMap::new(),
+            mac_filter: MacFilter::default(),
+            rssi_index: BTreeMap::new(),
+            time_index: TimeIndex::default(),
+            geo_index: RTree::new(),
+            geo_churn: 0,
+            receiver_index: HashMap::new(),
+            floor_index: HashMap::new(),
+            path_loss_index: BTreeMap::new(),
+            quality_index: BTreeMap::new(),
+            indexed: IndexSelection::default(),
+            geofence: Geofence::default(),
+            scan_schedule: None,
+            tags: TagIndex::default(),
+            crs: CoordinateSystem::Wgs84,
+            distance_metric: DistanceMetric::Haversine,
+            time_unit: None,
+            rssi_offsets: HashMap::new(),
+            seen_index: SeenIndex::default(),
+            validation: None,
+            quarantine: Vec::new(),
+            rate_limiter: RateLimiter::default(),
+            identities: IdentityResolver::default(),
+            beacons: BeaconIndex::default(),
+            #[cfg(feature = "std")]
+            subscribers: Subscribers::default(),
+            #[cfg(feature = "std")]
+            sinks: Vec::new(),
+            key_index: None,
+            #[cfg(feature = "wal")]
+            wal: None,
+            #[cfg(feature = "cold")]
+            cold: None,
+            #[cfg(feature = "std")]
+            query_cache: None,
+        }
+    }
+
+    /// Create with preallocated capacity
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            records: RecordStore::with_capacity(capacity),
+            record_ids: Vec::with_capacity(capacity),
+            next_record_id: 0,
+            mac_index: HashMap::with_capacity(capacity / 100), // estimate unique MACs
+            mac_filter: MacFilter::default(),
+            rssi_index: BTreeMap::new(),
+            time_index: TimeIndex::default(),
+            geo_index: RTree::new(),
+            geo_churn: 0,
+            receiver_index: HashMap::new(),
+            floor_index: HashMap::new(),
+            path_loss_index: BTreeMap::new(),
+            quality_index: BTreeMap::new(),
+            indexed: IndexSelection::default(),
+            geofence: Geofence::default(),
+            scan_schedule: None,
+            tags: TagIndex::default(),
+            crs: CoordinateSystem::Wgs84,
+            distance_metric: DistanceMetric::Haversine,
+            time_unit: None,
+            rssi_offsets: HashMap::new(),
+            seen_index: SeenIndex::default(),
+            validation: None,
+            quarantine: Vec::new(),
+            rate_limiter: RateLimiter::default(),
+            identities: IdentityResolver::default(),
+            beacons: BeaconIndex::default(),
+            #[cfg(feature = "std")]
+            subscribers: Subscribers::default(),
+            #[cfg(feature = "std")]
+            sinks: Vec::new(),
+            key_index: None,
+            #[cfg(feature = "wal")]
+            wal: None,
+            #[cfg(feature = "cold")]
+            cold: None,
+            #[cfg(feature = "std")]
+            query_cache: None,
+        }
+    }
+
+    /// Build a cube from a batch of observations in one pass: posting lists
+    /// are grouped from sorted (key, id) runs and the R-tree is bulk-loaded,
+    /// which is much faster than inserting one record at a time.
+    /// Record IDs follow the order of `records`.
+    pub fn bulk_load(records: Vec<BleObservation>) -> Self {
+        Self::builder().bulk_load(records)
+    }
+
+    /// Rebuild the MAC, RSSI, time, geo, receiver, floor and path-loss
+    /// indices (those enabled) from the record store in one pass (zones,
+    /// beacons and the key index are left to the caller)
+    pub(crate) fn rebuild_core_indices(&mut self) {
+        let indexed = self.indexed;
+        let records = &self.records;
+        let mut mac_index: HashMap<[u8; 6], Vec<usize>> = HashMap::new();
+        let mut receiver_index: HashMap<u16, Vec<usize>> = HashMap::new();
+        let mut floor_index: HashMap<i16, Vec<usize>> = HashMap::new();
+        let mut rssi_keys = Vec::with_capacity(records.len());
+        let mut path_loss_keys = Vec::new();
+        let mut quality_keys = Vec::new();
+        let mut time_keys = Vec::with_capacity(records.len());
+        let mut points = Vec::with_capacity(records.len());
+        for (record_id, obs) in records.iter().enumerate() {
+            // IDs ascend, so hash-indexed posting lists come out sorted
+            mac_index.entry(obs.mac).or_default().push(record_id);
+            if let Some(receiver_id) = obs.receiver_id {
+                receiver_index
+                    .entry(receiver_id)
+                    .or_default()
+                    .push(record_id);
+            }
+            if let Some(floor) = obs.floor {
+                floor_index.entry(floor).or_default().push(record_id);
+            }
+            if indexed.rssi {
+                rssi_keys.push((obs.rssi, record_id));
+            }
+            if let Some(path_loss) = obs.path_loss().filter(|_| indexed.path_loss) {
+                path_loss_keys.push((path_loss, record_id));
+            }
+            if let Some(quality) = obs.quality.filter(|_| indexed.quality) {
+                quality_keys.push((quality, record_id));
+            }
+            time_keys.push((obs.timestamp, record_id));
+            if indexed.geo {
+                points.push(GeoPoint {
+                    coords: [obs.lat, obs.lon],
+                    record_id,
+                });
+            }
+        }
+
+        self.mac_filter = MacFilter::from_index(&mac_index);
+        self.mac_index = mac_index;
+        self.receiver_index = receiver_index;
+        self.floor_index = floor_index;
+        self.rssi_index = group_sorted(rssi_keys);
+        self.path_loss_index = group_sorted(path_loss_keys);
+        self.quality_index = group_sorted(quality_keys);
+        self.time_index = TimeIndex::from_pairs(self.time_index.width(), time_keys);
+        self.geo_index = RTree::bulk_load(points);
+        self.geo_churn = 0;
+        self.seen_index = SeenIndex::from_records(records);
+    }
+
+    /// Keep only the records for which `keep(record_id, obs)` holds and
+    /// renumber the survivors densely, in order; every index is rebuilt.
+    /// Stable [`RecordId`]s move with their records. Returns the removed
+    /// records in record ID order. Does not touch the write-ahead log.
+    #[cfg(feature = "std")]
+    pub(crate) fn compact<F>(&mut self, mut keep: F) -> Vec<BleObservation>
+    where
+        F: FnMut(usize, &BleObservation) -> bool,
+    {
+        let mut remap = Vec::with_capacity(self.records.len());
+        let mut kept = Vec::with_capacity(self.records.len());
+        let mut kept_ids = Vec::with_capacity(self.records.len());
+        let mut removed = Vec::new();
+        let ids = core::mem::take(&mut self.record_ids);
+        for (record_id, (obs, id)) in self.records.take_rows().into_iter().zip(ids).enumerate() {
+            if keep(record_id, &obs) {
+                remap.push(Some(kept.len()));
+                kept.push(obs);
+                kept_ids.push(id);
+            } else {
+                remap.push(None);
+                removed.push(obs);
+            }
+        }
+        self.records = RecordStore::from_rows(kept);
+        self.record_ids = kept_ids;
+
+        self.rebuild_core_indices();
+        self.reindex_zones();
+        self.beacons.remap(&remap);
+        self.tags.remap(&remap);
+        self.rate_limiter.forget_windows();
+        self.clear_query_cache();
+        if let Some(key_index) = self.key_index.as_mut() {
+            key_index.retain(|_, (record_id, _)| match remap[*record_id] {
+                Some(new_id) => {
+                    *record_id = new_id;
+                    true
+                }
+                None => false,
+            });
+        }
+        removed
+    }
+
+    /// Insert a new observation
+    ///
+    /// # Panics
+    /// Panics if the time-unit, validation or rate-limit policy rejects the
+    /// observation or the write-ahead log append fails;
+    /// use [`BleCube::try_insert`] to handle that case.
+    pub fn insert(&mut self, obs: BleObservation) -> usize {
+        self.insert_logged(obs).expect("insert failed")
+    }
+
+    /// Insert a new observation, appending it to the write-ahead log first
+    /// when one is attached. Under a [`RateLimitKeep::Strongest`] limit the
+    /// observation may instead take the place of a weaker record of its
+    /// second, whose ID is returned.
+    pub fn try_insert(&mut self, obs: BleObservation) -> Result<usize, CubeError> {
+        self.insert_logged(obs)
+    }
+
+    fn insert_logged(&mut self, obs: BleObservation) -> Result<usize, CubeError> {
+        let obs = self.admit(obs)?;
+        if let Some(record_id) = self.rate_limit(&obs)? {
+            return self.replace_rate_limited(record_id, obs, &[]);
+        }
+        let record_id = self.insert_admitted(obs)?;
+        self.track_rate(&obs, record_id);
+        Ok(record_id)
+    }
+
+    /// Run an incoming observation through the time-unit policy, validation
+    /// and RSSI calibration
+    pub(crate) fn admit(&mut self, obs: BleObservation) -> Result<BleObservation, CubeError> {
+        let obs = self.admit_calibrated(obs)?;
+        Ok(self.calibrate(obs))
+    }
+
+    /// [`BleCube::admit`] without calibration, for observations another
+    /// cube already calibrated (replicated deltas, merged collectors)
+    pub(crate) fn admit_calibrated(
+        &mut self,
+        obs: BleObservation,
+    ) -> Result<BleObservation, CubeError> {
+        let obs = self.check_time_unit(obs)?;
+        self.validate(obs)
+    }
+
+    /// Tee, log and index an observation that already passed [`BleCube::admit`]
+    fn insert_admitted(&mut self, obs: BleObservation) -> Result<usize, CubeError> {
+        #[cfg(feature = "std")]
+        self.write_sinks(&obs)?;
+
+        #[cfg(feature = "wal")]
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&WalEntry::Insert(obs))?;
+        }
+
+        let record_id = self.index_record(obs);
+        #[cfg(feature = "std")]
+        self.notify_subscribers(record_id);
+        Ok(record_id)
+    }
+
+    /// Append a record and update every index, without logging or notifying
+    pub(crate) fn index_record(&mut self, obs: BleObservation) -> usize {
+        let record_id = self.records.len();
+        self.records.push(obs);
+        self.record_ids
+            .push(RecordId::from_u64(self.next_record_id));
+        self.next_record_id += 1;
+
+        // Update MAC index, resolving addresses seen for the first time
+        let postings = self.mac_index.entry(obs.mac).or_default();
+        let first_sighting = postings.is_empty();
+        postings.push(record_id);
+        if first_sighting {
+            self.mac_filter.insert(&obs.mac, &self.mac_index);
+            self.identities.observe(obs.mac);
+        }
+        self.seen_index.observe(obs.mac, obs.timestamp);
+
+        // Update RSSI index
+        if self.indexed.rssi {
+            self.rssi_index.entry(obs.rssi).or_default().push(record_id);
+        }
+
+        // Update timestamp index
+        self.time_index.push(obs.timestamp, record_id);
+
+        // Update geo index
+        if self.indexed.geo {
+            self.geo_index.insert(GeoPoint {
+                coords: [obs.lat, obs.lon],
+                record_id,
+            });
+            self.geo_churn += 1;
+        }
+
+        // Update receiver index
+        if let Some(receiver_id) = obs.receiver_id {
+            self.receiver_index
+                .entry(receiver_id)
+                .or_default()
+                .push(record_id);
+        }
+
+        // Update floor index
+        if let Some(floor) = obs.floor {
+            self.floor_index.entry(floor).or_default().push(record_id);
+        }
+
+        // Update path-loss index
+        if let Some(path_loss) = obs.path_loss().filter(|_| self.indexed.path_loss) {
+            self.path_loss_index
+                .entry(path_loss)
+                .or_default()
+                .push(record_id);
+        }
+
+        // Update quality index
+        if let Some(quality) = obs.quality.filter(|_| self.indexed.quality) {
+            self.quality_index
+                .entry(quality)
+                .or_default()
+                .push(record_id);
+        }
+
+        // Tag zone membership
+        self.geofence
+            .tag(record_id, &obs, self.crs, self.distance_metric);
+
+        // Update composite key index, if built
+        if let Some(key_index) = self.key_index.as_mut() {
+            key_index
+                .entry((obs.mac, obs.timestamp))
+                .or_insert((record_id, 1));
+        }
+
+        #[cfg(feature = "std")]
+        self.invalidate_cached(obs.timestamp);
+
+        record_id
+    }
+
+    /// Return the ID of the record with the same (mac, timestamp), inserting
+    /// `obs` if there is none
+    ///
+    /// # Panics
+    /// Panics if the time-unit or validation policy rejects the observation
+    /// or the write-ahead log append fails.
+    pub fn get_or_insert(&mut self, obs: BleObservation) -> usize {
+        self.upsert_by_key(obs, DuplicatePolicy::KeepExisting)
+            .record_id()
+    }
+
+    /// Insert `obs` unless a record with the same (mac, timestamp) exists,
+    /// in which case `policy` decides how the two are merged. Lets the same
+    /// capture be reprocessed without double-inserting.
+    ///
+    /// # Panics
+    /// Panics if the time-unit or validation policy rejects the observation
+    /// or the write-ahead log append fails;
+    /// use [`BleCube::try_upsert_by_key`] to handle that case.
+    pub fn upsert_by_key(&mut self, obs: BleObservation, policy: DuplicatePolicy) -> UpsertOutcome {
+        self.upsert_logged(obs, policy).expect("insert failed")
+    }
+
+    /// Fallible form of [`BleCube::upsert_by_key`]
+    pub fn try_upsert_by_key(
+        &mut self,
+        obs: BleObservation,
+        policy: DuplicatePolicy,
+    ) -> Result<UpsertOutcome, CubeError> {
+        self.upsert_logged(obs, policy)
+    }
+
+    fn upsert_logged(
+        &mut self,
+        obs: BleObservation,
+        policy: DuplicatePolicy,
+    ) -> Result<UpsertOutcome, CubeError> {
+        let obs = self.admit(obs)?;
+        self.upsert_admitted(obs, policy)
+    }
+
+    /// Upsert an observation that already passed [`BleCube::admit`]
+    pub(crate) fn upsert_admitted(
+        &mut self,
+        obs: BleObservation,
+        policy: DuplicatePolicy,
+    ) -> Result<UpsertOutcome, CubeError> {
+        let key = (obs.mac, obs.timestamp);
+        let existing = self.key_index().get(&key).copied();
+
+        let Some((record_id, merged)) = existing else {
+            return self.insert_admitted(obs).map(UpsertOutcome::Inserted);
+        };
+
+        let current = self.records[record_id];
+        let updated = match policy {
+            DuplicatePolicy::KeepExisting => current,
+            DuplicatePolicy::Replace => obs,
+            DuplicatePolicy::KeepStrongest if obs.rssi > current.rssi => obs,
+            DuplicatePolicy::KeepStrongest => current,
+            DuplicatePolicy::AverageCoordinates => {
+                let n = f64::from(merged + 1);
+                BleObservation {
+                    lat: current.lat + (obs.lat - current.lat) / n,
+                    lon: current.lon + (obs.lon - current.lon) / n,
+                    ..current
+                }
+            }
+        };
+
+        if let Some(entry) = self.key_index.as_mut().and_then(|idx| idx.get_mut(&key)) {
+            entry.1 = merged.saturating_add(1);
+        }
+
+        if same_observation(&updated, &current) {
+            return Ok(UpsertOutcome::Unchanged(record_id));
+        }
+
+        #[cfg(feature = "wal")]
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&WalEntry::Replace(record_id, updated))?;
+        }
+
+        self.replace_record(record_id, updated);
+        Ok(UpsertOutcome::Updated(record_id))
+    }
+
+    /// Composite key index, building it from the record store on first use
+    fn key_index(&mut self) -> &KeyIndex {
+        let records = &self.records;
+        self.key_index.get_or_insert_with(|| {
+            let mut index = HashMap::with_capacity(records.len());
+            for (record_id, obs) in records.iter().enumerate() {
+                index
+                    .entry((obs.mac, obs.timestamp))
+                    .or_insert((record_id, 1));
+            }
+            index
+        })
+    }
+
+    /// Overwrite a record in place, moving its entries between indices as needed
+    pub(crate) fn replace_record(&mut self, record_id: usize, obs: BleObservation) {
+        let old = self.records.replace(record_id, obs);
+        #[cfg(feature = "std")]
+        for timestamp in [old.timestamp, obs.timestamp] {
+            self.invalidate_cached(timestamp);
+        }
+
+        if old.mac != obs.mac {
+            self.mac_index.remove_id(&old.mac, record_id);
+            insert_posting(self.mac_index.entry(obs.mac).or_default(), record_id);
+            self.mac_filter.insert(&obs.mac, &self.mac_index);
+            self.identities.observe(obs.mac);
+        }
+        if old.rssi != obs.rssi && self.indexed.rssi {
+            self.rssi_index.remove_id(&old.rssi, record_id);
+            insert_posting(self.rssi_index.entry(obs.rssi).or_default(), record_id);
+        }
+        if old.timestamp != obs.timestamp {
+            self.time_index.remove(old.timestamp, record_id);
+            self.time_index.insert(obs.timestamp, record_id);
+        }
+        if old.lat != obs.lat || old.lon != obs.lon {
+            if self.indexed.geo {
+                self.geo_index.remove(&GeoPoint {
+                    coords: [old.lat, old.lon],
+                    record_id,
+                });
+                self.geo_index.insert(GeoPoint {
+                    coords: [obs.lat, obs.lon],
+                    record_id,
+                });
+                self.geo_churn += 1;
+            }
+            self.geofence
+                .retag(record_id, &obs, self.crs, self.distance_metric);
+        }
+        move_optional_posting(
+            &mut self.receiver_index,
+            old.receiver_id,
+            obs.receiver_id,
+            record_id,
+        );
+        move_optional_posting(&mut self.floor_index, old.floor, obs.floor, record_id);
+        if old.path_loss() != obs.path_loss() && self.indexed.path_loss {
+            if let Some(path_loss) = old.path_loss() {
+                self.path_loss_index.remove_id(&path_loss, record_id);
+            }
+            if let Some(path_loss) = obs.path_loss() {
+                insert_posting(
+                    self.path_loss_index.entry(path_loss).or_default(),
+                    record_id,
+                );
+            }
+        }
+        if old.quality != obs.quality && self.indexed.quality {
+            if let Some(quality) = old.quality {
+                self.quality_index.remove_id(&quality, record_id);
+            }
+            if let Some(quality) = obs.quality {
+                insert_posting(self.quality_index.entry(quality).or_default(), record_id);
+            }
+        }
+        if old.mac != obs.mac || old.timestamp != obs.timestamp {
+            for mac in [old.mac, obs.mac] {
+                self.seen_index
+                    .refresh(mac, self.mac_index.get(&mac), self.records.timestamps());
+            }
+        }
+
+        if let Some(key_index) = self.key_index.as_mut() {
+            let old_key = (old.mac, old.timestamp);
+            let new_key = (obs.mac, obs.timestamp);
+            if old_key != new_key {
+                if key_index.get(&old_key).map(|&(id, _)| id) == Some(record_id) {
+                    key_index.remove(&old_key);
+                }
+                key_index.entry(new_key).or_insert((record_id, 1));
+            }
+        }
+    }
+
+    /// Get observation by record ID
+    pub fn get(&self, record_id: usize) -> Option<&BleObservation> {
+        self.records.get(record_id)
+    }
+
+    /// Total number of observations
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Every observation, indexed by record ID
+    pub fn records(&self) -> &[BleObservation] {
+        &self.records
+    }
+
+    /// Timestamp of every record, indexed by record ID
+    pub fn timestamps(&self) -> &[i64] {
+        self.records.timestamps()
+    }
+
+    /// RSSI of every record, indexed by record ID
+    pub fn rssi_values(&self) -> &[i8] {
+        self.records.rssi()
+    }
+
+    // ========== MAC ADDRESS QUERIES ==========
+
+    /// Query by exact MAC address (`[u8; 6]`, `&[u8; 6]` or [`MacAddr`])
+    pub fn query_mac<M: Into<MacAddr>>(&self, mac: M) -> Vec<&BleObservation> {
+        self.mac_index
+            .get(&mac.into().0)
+            .map(|ids| ids.iter().filter_map(|&id| self.records.get(id)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get all unique MAC addresses, sorted lexicographically
+    pub fn get_all_macs(&self) -> Vec<[u8; 6]> {
+        let mut macs: Vec<[u8; 6]> = self.mac_index.keys().copied().collect();
+        macs.sort();
+        macs
+    }
+
+    /// Record IDs of one MAC address's observations, ascending
+    pub fn mac_record_ids<M: Into<MacAddr>>(&self, mac: M) -> &[usize] {
+        self.mac_index.get(&mac.into().0).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every MAC address with the record IDs of its observations (ascending),
+    /// in no particular order
+    pub fn mac_postings(&self) -> impl Iterator<Item = (&[u8; 6], &[usize])> + '_ {
+        self.mac_index
+            .iter()
+            .map(|(mac, ids)| (mac, ids.as_slice()))
+    }
+
+    // ========== RECEIVER QUERIES ==========
+
+    /// Query observations made by one receiver
+    pub fn query_receiver(&self, receiver_id: u16) -> Vec<&BleObservation> {
+        self.receiver_index
+            .get(&receiver_id)
+            .map(|ids| ids.iter().map(|&id| &self.records[id]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get all receiver IDs seen, sorted ascending
+    pub fn get_all_receivers(&self) -> Vec<u16> {
+        let mut receivers: Vec<u16> = self.receiver_index.keys().copied().collect();
+        receivers.sort_unstable();
+        receivers
+    }
+
+    // ========== FLOOR QUERIES ==========
+
+    /// Query observations on one floor
+    pub fn query_floor(&self, floor: i16) -> Vec<&BleObservation> {
+        self.floor_index
+            .get(&floor)
+            .map(|ids| ids.iter().map(|&id| &self.records[id]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Get all floors seen, sorted ascending
+    pub fn get_all_floors(&self) -> Vec<i16> {
+        let mut floors: Vec<i16> = self.floor_index.keys().copied().collect();
+        floors.sort_unstable();
+        floors
+    }
+
+    // ========== RSSI QUERIES ==========
+
+    /// Query by exact RSSI value
+    pub fn query_rssi(&self, rssi: i8) -> Vec<&BleObservation> {
+        self.rssi_matches(rssi..=rssi)
+    }
+
+    /// Query RSSI range [min, max] inclusive
+    pub fn query_rssi_range(&self, min: i8, max: i8) -> Vec<&BleObservation> {
+        self.rssi_matches(min..=max)
+    }
+
+    /// Query RSSI greater than threshold
+    pub fn query_rssi_gt(&self, threshold: i8) -> Vec<&BleObservation> {
+        self.rssi_matches((Bound::Excluded(threshold), Bound::Unbounded))
+    }
+
+    /// Query RSSI greater than or equal to threshold
+    pub fn query_rssi_gte(&self, threshold: i8) -> Vec<&BleObservation> {
+        self.rssi_matches(threshold..)
+    }
+
+    /// Query RSSI less than threshold
+    pub fn query_rssi_lt(&self, threshold: i8) -> Vec<&BleObservation> {
+        self.rssi_matches(..threshold)
+    }
+
+    /// Query RSSI less than or equal to threshold
+    pub fn query_rssi_lte(&self, threshold: i8) -> Vec<&BleObservation> {
+        self.rssi_matches(..=threshold)
+    }
+
+    /// Observations with RSSI in `range`, ascending by RSSI then record ID;
+    /// scans the RSSI column when the index is disabled
+    fn rssi_matches<R: RangeBounds<i8>>(&self, range: R) -> Vec<&BleObservation> {
+        if !self.indexed.rssi {
+            let rssi = self.records.rssi();
+            let mut ids: Vec<usize> = (0..rssi.len())
+                .filter(|&id| range.contains(&rssi[id]))
+                .collect();
+            ids.sort_by_key(|&id| rssi[id]);
+            return ids.into_iter().map(|id| &self.records[id]).collect();
+        }
+        self.rssi_index
+            .range(range)
+            .flat_map(|(_, ids)| ids.iter().filter_map(|&id| self.records.get(id)))
+            .collect()
+    }
+
+    // ========== TIMESTAMP QUERIES ==========
+
+    /// Query by exact timestamp
+    pub fn query_timestamp(&self, timestamp: i64) -> Vec<&BleObservation> {
+        self.time_index
+            .get(timestamp)
+            .filter_map(|id| self.records.get(id))
+            .collect()
+    }
+
+    /// Query timestamp range [start, end] inclusive
+    pub fn query_time_range(&self, start: i64, end: i64) -> Vec<&BleObservation> {
+        self.time_index
+            .range(start..=end)
+            .filter_map(|(_, id)| self.records.get(id))
+            .collect()
+    }
+
+    /// Query timestamps after (greater than) a point
+    pub fn query_time_after(&self, timestamp: i64) -> Vec<&BleObservation> {
+        self.time_index
+            .range((timestamp + 1)..=i64::MAX)
+            .filter_map(|(_, id)| self.records.get(id))
+            .collect()
+    }
+
+    /// Query timestamps before (less than) a point
+    pub fn query_time_before(&self, timestamp: i64) -> Vec<&BleObservation> {
+        self.time_index
+            .range(i64::MIN..timestamp)
+            .filter_map(|(_, id)| self.records.get(id))
+            .collect()
+    }
+
+    // ========== GEOLOCATION QUERIES ==========
+
+    /// Query by radius (in meters) around a point, measured with the cube's
+    /// distance metric (Haversine unless set, Euclidean in a projected CRS)
+    pub fn query_geo_radius(&self, lat: f64, lon: f64, radius_m: f64) -> Vec<&BleObservation> {
+        self.query_geo_radius_with(lat, lon, radius_m, self.distance_metric)
+    }
+
+    /// Query by radius (in meters) around a point using an explicit distance
+    /// metric (ignored in a projected CRS, where distances are Euclidean)
+    pub fn query_geo_radius_with(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+        metric: DistanceMetric,
+    ) -> Vec<&BleObservation> {
+        let envelope = self.crs.radius_envelope(lat, lon, radius_m);
+
+        self.locate_in_envelope(envelope)
+            .filter(|point| {
+                self.crs
+                    .distance_with(metric, lat, lon, point.coords[0], point.coords[1])
+                    <= radius_m
+            })
+            .filter_map(|point| self.records.get(point.record_id))
+            .collect()
+    }
+
+    /// Radius query restricted to one floor, so sightings directly above or
+    /// below the point on other storeys are excluded
+    pub fn query_geo_radius_on_floor(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+        floor: i16,
+    ) -> Vec<&BleObservation> {
+        let mut results = self.query_geo_radius(lat, lon, radius_m);
+        results.retain(|obs| obs.floor == Some(floor));
+        results
+    }
+
+    /// Query within a bounding box (min_lat, min_lon, max_lat, max_lon).
+    /// With WGS84 coordinates a `min_lon` greater than `max_lon` selects the
+    /// box crossing the antimeridian (e.g. 170 to -170), as in GeoJSON.
+    pub fn query_geo_bbox(
+        &self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> Vec<&BleObservation> {
+        let envelope = self.crs.bbox_envelope(min_lat, min_lon, max_lat, max_lon);
+
+        self.locate_in_envelope(envelope)
+            .filter_map(|point| self.records.get(point.record_id))
+            .collect()
+    }
+
+    /// Query within a polygon (simple point-in-polygon test)
+    /// Polygon vertices as [(lat, lon), ...]
+    pub fn query_geo_polygon(&self, polygon: &[(f64, f64)]) -> Vec<&BleObservation> {
+        if polygon.len() < 3 {
+            return Vec::new();
+        }
+
+        // Bounding box of the polygon for initial filtering
+        self.locate_in_envelope(vertices_envelope(polygon))
+            .filter(|point| point_in_polygon(point.coords[0], point.coords[1], polygon))
+            .filter_map(|point| self.records.get(point.record_id))
+            .collect()
+    }
+
+    /// Query within an area bounded by several rings, each given like the
+    /// polygon of [`BleCube::query_geo_polygon`], combined by the even-odd
+    /// rule (see [`point_in_rings`]): list a boundary followed by the rings
+    /// of its holes to exclude them ("campus minus buildings"), or several
+    /// disjoint rings for a multi-polygon. Degenerate rings are ignored.
+    pub fn query_geo_multipolygon<R: AsRef<[(f64, f64)]>>(
+        &self,
+        rings: &[R],
+    ) -> Vec<&BleObservation> {
+        let vertices = rings.iter().flat_map(AsRef::as_ref);
+        if vertices.clone().next().is_none() {
+            return Vec::new();
+        }
+        self.locate_in_envelope(vertices_envelope(vertices))
+            .filter(|point| point_in_rings(point.coords[0], point.coords[1], rings))
+            .filter_map(|point| self.records.get(point.record_id))
+            .collect()
+    }
+
+    // ========== MULTI-DIMENSIONAL QUERIES ==========
+
+    /// Combined query: filter by multiple dimensions
+    /// Returns record IDs for further custom filtering
+    pub fn query_multi(
+        &self,
+        mac: Option<&[u8; 6]>,
+        rssi_range: Option<(i8, i8)>,
+        time_range: Option<(i64, i64)>,
+        geo_center: Option<(f64, f64, f64)>, // (lat, lon, radius_m)
+    ) -> Vec<&BleObservation> {
+        // Start with the most selective dimension
+        let mut result_ids: Vec<usize> = if let Some(mac_addr) = mac {
+            self.mac_index.get(mac_addr).cloned().unwrap_or_default()
+        } else {
+            (0..self.records.len()).collect()
+        };
+
+        // Filter by RSSI
+        if let Some((min_rssi, max_rssi)) = rssi_range {
+            let rssi = self.records.rssi();
+            result_ids.retain(|&id| (min_rssi..=max_rssi).contains(&rssi[id]));
+        }
+
+        // Filter by timestamp
+        if let Some((start, end)) = time_range {
+            let time_ids: Vec<usize> = self
+                .time_index
+                .range(start..=end)
+                .map(|(_, id)| id)
+                .collect();
+            result_ids.retain(|id| time_ids.contains(id));
+        }
+
+        // Filter by geolocation
+        if let Some((lat, lon, radius)) = geo_center {
+            let geo_results = self.query_geo_radius(lat, lon, radius);
+            let geo_ids: Vec<usize> = geo_results
+                .iter()
+                .filter_map(|obs| self.records.iter().position(|r| core::ptr::eq(*obs, r)))
+                .collect();
+            result_ids.retain(|id| geo_ids.contains(id));
+        }
+
+        result_ids
+            .iter()
+            .filter_map(|&id| self.records.get(id))
+            .collect()
+    }
+}
+
+// ========== HELPER FUNCTIONS ==========
+
+/// Bitwise equality of two observations (NaN-safe, unlike `==` on f64)
+fn same_observation(a: &BleObservation, b: &BleObservation) -> bool {
+    a.rssi == b.rssi
+        && a.mac == b.mac
+        && a.timestamp == b.timestamp
+        && a.lat.to_bits() == b.lat.to_bits()
+        && a.lon.to_bits() == b.lon.to_bits()
+        && a.receiver_id == b.receiver_id
+        && a.floor == b.floor
+        && a.tx_power == b.tx_power
+        && a.quality == b.quality
+}
+
+/// Move a record between posting lists of an index over an optional field
+fn move_optional_posting<K: MapKey + Copy>(
+    index: &mut HashMap<K, Vec<usize>>,
+    old: Option<K>,
+    new: Option<K>,
+    record_id: usize,
+) {
+    if old == new {
+        return;
+    }
+    if let Some(key) = old {
+        index.remove_id(&key, record_id);
+    }
+    if let Some(key) = new {
+        insert_posting(index.entry(key).or_default(), record_id);
+    }
+}
+
+/// Sort (key, record ID) pairs and collect runs of equal keys into posting
+/// lists; a sorted iterator lets `BTreeMap` build its nodes in bulk
+fn group_sorted<K: Ord + Copy>(mut pairs: Vec<(K, usize)>) -> BTreeMap<K, Vec<usize>> {
+    pairs.sort_unstable();
+    pairs
+        .chunk_by(|a, b| a.0 == b.0)
+        .map(|run| (run[0].0, run.iter().map(|&(_, id)| id).collect()))
+        .collect()
+}
+
+/// Insert a record ID into a posting list, keeping it sorted
+pub(crate) fn insert_posting(ids: &mut Vec<usize>, record_id: usize) {
+    if let Err(pos) = ids.binary_search(&record_id) {
+        ids.insert(pos, record_id);
+    }
+}
+
+/// Remove a record ID from a sorted posting list, returning true once it is empty
+pub(crate) fn remove_posting(ids: &mut Vec<usize>, record_id: usize) -> bool {
+    if let Ok(pos) = ids.binary_search(&record_id) {
+        ids.remove(pos);
+    }
+    ids.is_empty()
+}
+
+/// Posting-list removal shared by the HashMap and BTreeMap indices;
+/// keys whose posting list becomes empty are dropped
+pub(crate) trait PostingIndex<K> {
+    fn remove_id(&mut self, key: &K, record_id: usize);
+}
+
+impl<K: MapKey> PostingIndex<K> for HashMap<K, Vec<usize>> {
+    fn remove_id(&mut self, key: &K, record_id: usize) {
+        if self
+            .get_mut(key)
+            .is_some_and(|ids| remove_posting(ids, record_id))
+        {
+            self.remove(key);
+        }
+    }
+}
+
+impl<K: Ord> PostingIndex<K> for BTreeMap<K, Vec<usize>> {
+    fn remove_id(&mut self, key: &K, record_id: usize) {
+        if self
+            .get_mut(key)
+            .is_some_and(|ids| remove_posting(ids, record_id))
+        {
+            self.remove(key);
+        }
+    }
+}
+
+/// Distance formula used to evaluate radius queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DistanceMetric {
+    /// Great-circle distance on a spherical Earth (~0.5% error)
+    #[default]
+    Haversine,
+    /// Geodesic distance on the WGS84 ellipsoid (sub-millimeter, slower)
+    Vincenty,
+    /// Equirectangular approximation; fast and fine for short distances
+    Planar,
+}
+
+impl DistanceMetric {
+    /// Distance in meters between (lat1, lon1) and (lat2, lon2)
+    pub fn distance(self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        match self {
+            DistanceMetric::Haversine => haversine_distance(lat1, lon1, lat2, lon2),
+            DistanceMetric::Vincenty => vincenty_distance(lat1, lon1, lat2, lon2),
+            DistanceMetric::Planar => planar_distance(lat1, lon1, lat2, lon2),
+        }
+    }
+}
+
+impl Default for BleCube {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bulk_load_matches_inserts() {
+        let records: Vec<BleObservation> = (0..500u32)
+            .map(|i| BleObservation {
+                rssi: -30 - (i % 60) as i8,
+                mac: [0, 0, 0, 0, 0, (i % 7) as u8],
+                timestamp: i64::from(i % 50),
+                lat: 37.0 + f64::from(i % 23) * 0.001,
+                lon: -122.0 + f64::from(i % 19) * 0.001,
+                receiver_id: Some((i % 3) as u16),
+                floor: None,
+                tx_power: None,
+                quality: None,
+            })
+            .collect();
+
+        let bulk = BleCube::bulk_load(records.clone());
+        let mut incremental = BleCube::new();
+        for obs in &records {
+            incremental.insert(*obs);
+        }
+
+        assert_eq!(bulk.len(), 500);
+        assert_eq!(bulk.mac_index, incremental.mac_index);
+        assert_eq!(bulk.rssi_index, incremental.rssi_index);
+        assert_eq!(bulk.time_index, incremental.time_index);
+        assert_eq!(bulk.receiver_index, incremental.receiver_index);
+        let ids = |cube: &BleCube| {
+            let mut ids: Vec<usize> = cube
+                .geo_index
+                .locate_in_envelope(&AABB::from_corners([37.005, -122.0], [37.01, -121.99]))
+                .map(|p| p.record_id)
+                .collect();
+            ids.sort_unstable();
+            ids
+        };
+        assert_eq!(ids(&bulk), ids(&incremental));
+        assert!(!ids(&bulk).is_empty());
+    }
+
+    #[test]
+    fn test_basic_insert_and_query() {
+        let mut cube = BleCube::new();
+
+        let obs1 = BleObservation {
+            rssi: -65,
+            mac: [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF],
+            timestamp: 1700000000,
+            lat: 37.7749,
+            lon: -122.4194,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        };
+
+        let id = cube.insert(obs1);
+        assert_eq!(id, 0);
+        assert_eq!(cube.len(), 1);
+
+        let results = cube.query_mac([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].rssi, -65);
+    }
+
+    #[test]
+    fn test_rssi_range_query() {
+        let mut cube = BleCube::new();
+
+        cube.insert(BleObservation {
+            rssi: -50,
+            mac: [0; 6],
+            timestamp: 0,
+            lat: 0.0,
+            lon: 0.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+        cube.insert(BleObservation {
+            rssi: -70,
+            mac: [0; 6],
+            timestamp: 0,
+            lat: 0.0,
+            lon: 0.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+        cube.insert(BleObservation {
+            rssi: -90,
+            mac: [0; 6],
+            timestamp: 0,
+            lat: 0.0,
+            lon: 0.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+
+        let results = cube.query_rssi_range(-80, -60);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].rssi, -70);
+
+        let results_gte = cube.query_rssi_gte(-70);
+        assert_eq!(results_gte.len(), 2); // -50 and -70
+    }
+
+    #[test]
+    fn test_geo_radius_query() {
+        let mut cube = BleCube::new();
+
+        // San Francisco
+        cube.insert(BleObservation {
+            rssi: -60,
+            mac: [0; 6],
+            timestamp: 0,
+            lat: 37.7749,
+            lon: -122.4194,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+
+        // Oakland (about 13km away)
+        cube.insert(BleObservation {
+            rssi: -60,
+            mac: [0; 6],
+            timestamp: 0,
+            lat: 37.8044,
+            lon: -122.2712,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+
+        // Query 10km radius around SF
+        let results = cube.query_geo_radius(37.7749, -122.4194, 10000.0);
+        assert_eq!(results.len(), 1);
+
+        // Query 20km radius
+        let results = cube.query_geo_radius(37.7749, -122.4194, 20000.0);
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_geo_radius_high_latitude() {
+        let mut cube = BleCube::new();
+
+        // At 70°N one degree of longitude is only ~38km, so these points are
+        // ~900m and ~1100m east of the query center
+        let deg_per_m_east = 1.0 / (111_195.0 * 70.0_f64.to_radians().cos());
+        for meters in [900.0, 1100.0] {
+            cube.insert(BleObservation {
+                rssi: -60,
+                mac: [0; 6],
+                timestamp: 0,
+                lat: 70.0,
+                lon: 20.0 + meters * deg_per_m_east,
+                receiver_id: None,
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+
+        let results = cube.query_geo_radius(70.0, 20.0, 1000.0);
+        assert_eq!(results.len(), 1);
+
+        let results = cube.query_geo_radius(70.0, 20.0, 1200.0);
+        assert_eq!(results.len(), 2);
+
+        // Near the pole the envelope must span all longitudes
+        cube.insert(BleObservation {
+            rssi: -60,
+            mac: [0; 6],
+            timestamp: 0,
+            lat: 89.99,
+            lon: -160.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        });
+        let results = cube.query_geo_radius(89.99, 20.0, 3000.0);
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_upsert_by_key() {
+        let mut cube = BleCube::new();
+        let mac = [1, 2, 3, 4, 5, 6];
+        let obs = BleObservation {
+            rssi: -70,
+            mac,
+            timestamp: 1700000000,
+            lat: 37.0,
+            lon: -122.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        };
+
+        // Inserted before the key index exists; found once it is built lazily
+        cube.insert(obs);
+        assert_eq!(cube.get_or_insert(obs), 0);
+        assert_eq!(cube.len(), 1);
+
+        let weaker = BleObservation { rssi: -80, ..obs };
+        assert_eq!(
+            cube.upsert_by_key(weaker, DuplicatePolicy::KeepStrongest),
+            UpsertOutcome::Unchanged(0)
+        );
+
+        let stronger = BleObservation { rssi: -60, ..obs };
+        assert_eq!(
+            cube.upsert_by_key(stronger, DuplicatePolicy::KeepStrongest),
+            UpsertOutcome::Updated(0)
+        );
+        assert_eq!(cube.query_rssi(-70).len(), 0);
+        assert_eq!(cube.query_rssi(-60).len(), 1);
+
+        // Fifth sighting of this key: mean of 4x 37.0 and 37.5
+        let moved = BleObservation { lat: 37.5, ..obs };
+        cube.upsert_by_key(moved, DuplicatePolicy::AverageCoordinates);
+        let record = cube.get(0).unwrap();
+        assert!((record.lat - 37.1).abs() < 1e-9);
+        assert_eq!(record.rssi, -60);
+        assert_eq!(cube.query_geo_radius(37.1, -122.0, 10.0).len(), 1);
+        assert_eq!(cube.query_geo_radius(37.0, -122.0, 10.0).len(), 0);
+
+        // A new timestamp is a new key
+        let later = BleObservation {
+            timestamp: 1700000001,
+            ..obs
+        };
+        assert_eq!(
+            cube.upsert_by_key(later, DuplicatePolicy::Replace),
+            UpsertOutcome::Inserted(1)
+        );
+        assert_eq!(cube.query_mac(mac).len(), 2);
+    }
+
+    #[test]
+    fn test_distance_metrics_agree() {
+        // SF -> Oakland, ~13km
+        let (lat1, lon1, lat2, lon2) = (37.7749, -122.4194, 37.8044, -122.2712);
+        let haversine = DistanceMetric::Haversine.distance(lat1, lon1, lat2, lon2);
+        let vincenty = DistanceMetric::Vincenty.distance(lat1, lon1, lat2, lon2);
+        let planar = DistanceMetric::Planar.distance(lat1, lon1, lat2, lon2);
+
+        assert!((haversine - vincenty).abs() / vincenty < 0.005);
+        assert!((planar - haversine).abs() < 1.0);
+        assert_eq!(
+            DistanceMetric::Vincenty.distance(lat1, lon1, lat1, lon1),
+            0.0
+        );
+
+        // One degree of latitude at the equator on WGS84 is 110574.4m
+        let meridian = DistanceMetric::Vincenty.distance(0.0, 0.0, 1.0, 0.0);
+        assert!((meridian - 110_574.4).abs() < 1.0);
+    }
+}
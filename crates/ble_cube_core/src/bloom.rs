@@ -0,0 +1,142 @@
+//! Bloom filter over the MACs in the cube.
+//!
+//! Novelty detection on a high-rate ingest path asks "have we ever seen this
+//! MAC?" for every frame, and almost always gets "yes". A few bits per MAC
+//! answer the common case with seven probes into one small bit array instead
+//! of a hash lookup in the full MAC index, and a "no" is always right.
+
+use crate::ble_cube::BleCube;
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::mac::MacAddr;
+use crate::memory::ComponentMemory;
+use core::mem::size_of;
+
+/// About 0.8% false positives with [`HASHES`] probes
+const BITS_PER_MAC: usize = 10;
+const HASHES: u64 = 7;
+/// Smallest filter built, in MACs
+const MIN_CAPACITY: usize = 1024;
+
+/// Bloom filter sized for `capacity` MACs, rebuilt at twice the size from
+/// the MAC index when that is exceeded
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MacFilter {
+    // Power-of-two bit count, so probes are masked rather than divided
+    bits: Vec<u64>,
+    capacity: usize,
+    macs: usize,
+}
+
+impl MacFilter {
+    /// A filter holding every key of a MAC index
+    pub(crate) fn from_index<V>(index: &HashMap<[u8; 6], V>) -> Self {
+        let capacity = (index.len() * 2).max(MIN_CAPACITY);
+        let mut filter = Self {
+            bits: vec![0; (capacity * BITS_PER_MAC).next_power_of_two() / 64],
+            capacity,
+            macs: 0,
+        };
+        index.keys().for_each(|mac| filter.set(mac));
+        filter
+    }
+
+    /// Record a MAC just added to `index`, growing the filter if the index
+    /// outgrew it
+    pub(crate) fn insert<V>(&mut self, mac: &[u8; 6], index: &HashMap<[u8; 6], V>) {
+        if index.len() > self.capacity {
+            *self = Self::from_index(index);
+        } else {
+            self.set(mac);
+        }
+    }
+
+    pub(crate) fn may_contain(&self, mac: &[u8; 6]) -> bool {
+        !self.bits.is_empty()
+            && self
+                .probes(mac)
+                .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    pub(crate) fn memory(&self) -> ComponentMemory {
+        ComponentMemory {
+            entries: self.macs,
+            bytes: self.bits.capacity() * size_of::<u64>(),
+        }
+    }
+
+    fn set(&mut self, mac: &[u8; 6]) {
+        for bit in self.probes(mac) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.macs += 1;
+    }
+
+    /// Bit positions of a MAC (double hashing off one 64-bit mix)
+    fn probes(&self, mac: &[u8; 6]) -> impl Iterator<Item = usize> {
+        let h1 = hash_mac(mac);
+        // Odd, so successive probes visit distinct bits
+        let h2 = mix(h1) | 1;
+        let mask = (self.bits.len() * 64 - 1) as u64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) & mask) as usize)
+    }
+}
+
+/// Well-mixed 64-bit hash of a MAC
+pub fn hash_mac(mac: &[u8; 6]) -> u64 {
+    let mut key = [0u8; 8];
+    key[..6].copy_from_slice(mac);
+    mix(u64::from_le_bytes(key))
+}
+
+/// SplitMix64 finalizer
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+impl BleCube {
+    /// Fast pre-check for whether `mac` has been observed: `false` means the
+    /// cube holds no record of it; `true` means it probably does (about 1 in
+    /// 100 unseen MACs also answer `true`, as do MACs whose records were
+    /// since overwritten by an upsert). Confirm with
+    /// [`BleCube::first_seen`] when it matters.
+    pub fn maybe_contains_mac<M: Into<MacAddr>>(&self, mac: M) -> bool {
+        self.mac_filter.may_contain(&mac.into().0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    fn mac(i: u32) -> [u8; 6] {
+        let [a, b, c, d] = i.to_be_bytes();
+        [0x02, 0x11, a, b, c, d]
+    }
+
+    #[test]
+    fn test_filter_has_no_false_negatives() {
+        let mut cube = BleCube::new();
+        assert!(!cube.maybe_contains_mac(mac(0)));
+        // Crosses several growth steps
+        for i in 0..5_000 {
+            cube.insert(BleObservation {
+                mac: mac(i),
+                timestamp: i64::from(i),
+                ..Default::default()
+            });
+        }
+        assert!((0..5_000).all(|i| cube.maybe_contains_mac(mac(i))));
+        let false_positives = (5_000..105_000)
+            .filter(|&i| cube.maybe_contains_mac(mac(i)))
+            .count();
+        assert!(false_positives < 2_000, "{false_positives} false positives");
+
+        let rebuilt = BleCube::bulk_load(cube.records.iter().copied().collect());
+        assert!((0..5_000).all(|i| rebuilt.maybe_contains_mac(mac(i))));
+        assert!(rebuilt.memory_footprint().mac_filter.bytes >= 5_000 * BITS_PER_MAC / 8);
+    }
+}
@@ -0,0 +1,221 @@
+//! Cube construction options.
+//!
+//! Every index costs memory and insert time. A collector that never asks
+//! "which sightings were stronger than -60 dBm" can build its cube without
+//! the RSSI index; queries on a disabled dimension still work, by scanning
+//! the record store (or its dense RSSI column) instead of an index.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::columns::RecordStore;
+use crate::compat::prelude::*;
+use crate::query::Dimension;
+use crate::record_id::RecordId;
+
+/// Which optional indices a cube maintains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexSelection {
+    pub(crate) rssi: bool,
+    pub(crate) geo: bool,
+    pub(crate) path_loss: bool,
+    pub(crate) quality: bool,
+}
+
+impl Default for IndexSelection {
+    fn default() -> Self {
+        Self {
+            rssi: true,
+            geo: true,
+            path_loss: true,
+            quality: true,
+        }
+    }
+}
+
+/// Options for a new [`BleCube`], see [`BleCube::builder`]
+#[derive(Debug, Clone, Default)]
+pub struct CubeBuilder {
+    capacity: Option<usize>,
+    indexed: IndexSelection,
+}
+
+impl CubeBuilder {
+    /// Preallocate for this many records (see [`BleCube::with_capacity`])
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Don't maintain the RSSI B-tree. RSSI queries and filters scan the
+    /// RSSI column (1 byte per record) instead.
+    pub fn without_rssi_index(mut self) -> Self {
+        self.indexed.rssi = false;
+        self
+    }
+
+    /// Don't maintain the R-tree. Radius, bounding-box, polygon, zone,
+    /// corridor and clustering queries check every record instead.
+    pub fn without_geo_index(mut self) -> Self {
+        self.indexed.geo = false;
+        self
+    }
+
+    /// Don't maintain the path-loss B-tree; path-loss queries scan
+    pub fn without_path_loss_index(mut self) -> Self {
+        self.indexed.path_loss = false;
+        self
+    }
+
+    /// Don't maintain the quality B-tree; quality filters scan
+    pub fn without_quality_index(mut self) -> Self {
+        self.indexed.quality = false;
+        self
+    }
+
+    /// Create an empty cube with these options
+    pub fn build(self) -> BleCube {
+        let mut cube = match self.capacity {
+            Some(capacity) => BleCube::with_capacity(capacity),
+            None => BleCube::new(),
+        };
+        cube.indexed = self.indexed;
+        cube
+    }
+
+    /// Create a cube holding `records` with these options, indexed in one
+    /// pass like [`BleCube::bulk_load`]
+    pub fn bulk_load(self, records: Vec<BleObservation>) -> BleCube {
+        let mut cube = self.build();
+        cube.next_record_id = records.len() as u64;
+        cube.record_ids = (0..cube.next_record_id).map(RecordId::from_u64).collect();
+        cube.records = RecordStore::from_rows(records);
+        cube.rebuild_core_indices();
+        cube
+    }
+}
+
+impl BleCube {
+    /// Start configuring a cube, e.g. to leave out indices a deployment
+    /// never queries:
+    /// `BleCube::builder().without_rssi_index().without_geo_index().build()`
+    pub fn builder() -> CubeBuilder {
+        CubeBuilder::default()
+    }
+
+    /// Whether queries on `dimension` are served by an index; dimensions
+    /// built without one are answered by scanning
+    pub fn is_indexed(&self, dimension: Dimension) -> bool {
+        match dimension {
+            Dimension::Rssi => self.indexed.rssi,
+            Dimension::Geo => self.indexed.geo,
+            Dimension::PathLoss => self.indexed.path_loss,
+            Dimension::Quality => self.indexed.quality,
+            Dimension::Mac
+            | Dimension::Receiver
+            | Dimension::Floor
+            | Dimension::Zone
+            | Dimension::Tag
+            | Dimension::Lifecycle
+            | Dimension::Time => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use crate::zone::Zone;
+
+    fn records() -> Vec<BleObservation> {
+        (0..200)
+            .map(|i| BleObservation {
+                mac: [(i % 7) as u8; 6],
+                rssi: -40 - (i % 50) as i8,
+                timestamp: 1000 + i,
+                lat: 37.0 + (i % 20) as f64 * 1e-4,
+                lon: -122.0 + (i / 20) as f64 * 1e-4,
+                tx_power: (i % 3 == 0).then_some(-59),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn stamps(observations: Vec<&BleObservation>) -> Vec<i64> {
+        observations.iter().map(|obs| obs.timestamp).collect()
+    }
+
+    #[test]
+    fn test_disabled_indices_answer_like_indexed() {
+        let full = BleCube::bulk_load(records());
+        let mut lean = BleCube::builder()
+            .without_rssi_index()
+            .without_geo_index()
+            .without_path_loss_index()
+            .build();
+        for obs in records() {
+            lean.insert(obs);
+        }
+        let loaded = BleCube::builder().without_geo_index().bulk_load(records());
+
+        let mut expected_nearby = stamps(full.query_geo_radius(37.001, -121.9995, 30.0));
+        expected_nearby.sort_unstable();
+        assert!(!expected_nearby.is_empty());
+        for cube in [&lean, &loaded] {
+            assert!(!cube.is_indexed(Dimension::Geo));
+            assert!(cube.is_indexed(Dimension::Time));
+            assert_eq!(cube.index_stats().geo_points, 0);
+            let mut found = stamps(cube.query_geo_radius(37.001, -121.9995, 30.0));
+            found.sort_unstable();
+            assert_eq!(found, expected_nearby);
+            assert_eq!(
+                cube.query_geo_bbox(37.0, -122.0, 37.0005, -121.9995).len(),
+                full.query_geo_bbox(37.0, -122.0, 37.0005, -121.9995).len()
+            );
+        }
+        assert_eq!(lean.index_stats().rssi.entries, 0);
+        assert_eq!(lean.index_stats().path_loss.entries, 0);
+        assert_eq!(loaded.index_stats().rssi.entries, 200);
+
+        // Same results, in the same order
+        assert_eq!(
+            stamps(lean.query_rssi_range(-60, -50)),
+            stamps(full.query_rssi_range(-60, -50))
+        );
+        assert_eq!(
+            stamps(lean.query_rssi_gt(-45)),
+            stamps(full.query_rssi_gt(-45))
+        );
+        assert_eq!(
+            stamps(lean.query_rssi_lte(-88)),
+            stamps(full.query_rssi_lte(-88))
+        );
+        assert_eq!(
+            stamps(lean.query_path_loss_range(0, 5)),
+            stamps(full.query_path_loss_range(0, 5))
+        );
+        assert_eq!(
+            lean.rssi_histogram(&Query::new(), 10),
+            full.rssi_histogram(&Query::new(), 10)
+        );
+
+        // The planner drives from an enabled index or scans
+        let query = Query::new()
+            .rssi_between(-45, -40)
+            .within_radius(37.0, -122.0, 50.0);
+        assert_eq!(lean.explain(&query).driver, None);
+        assert_eq!(full.explain(&query).driver, Some(Dimension::Geo));
+        assert_eq!(lean.execute_ids(&query), full.execute_ids(&query));
+        let timed = query.clone().time_between(1000, 1100);
+        assert_eq!(lean.explain(&timed).driver, Some(Dimension::Time));
+        assert_eq!(lean.execute_ids(&timed), full.execute_ids(&timed));
+
+        // Zones still tag members
+        let mut full = full;
+        for cube in [&mut lean, &mut full] {
+            cube.add_zone("corner", Zone::circle(37.0, -122.0, 20.0));
+        }
+        let members = lean.execute_ids(&Query::new().in_zone("corner"));
+        assert!(!members.is_empty());
+        assert_eq!(members, full.execute_ids(&Query::new().in_zone("corner")));
+    }
+}
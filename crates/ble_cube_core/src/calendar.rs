@@ -0,0 +1,287 @@
+//! Local calendar time for aggregations.
+//!
+//! Fixed-width time buckets are aligned to Unix time, so "per hour of day"
+//! or "per day" in a site's local time needs the UTC offset in force at each
+//! observation, daylight saving included. [`TimeZone`] is a standard offset
+//! plus an optional US or EU daylight-saving rule, which covers the sites
+//! this crate is deployed at without a time-zone database; civil dates are
+//! computed with integer arithmetic, so it works without `std`.
+
+use crate::ble_cube::BleCube;
+use crate::query::Query;
+use crate::time::{TimeUnit, Timestamp};
+use alloc::collections::{BTreeMap, BTreeSet};
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// When a [`TimeZone`] observes daylight saving time (one hour ahead)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DstRule {
+    /// Standard time all year
+    #[default]
+    None,
+    /// Second Sunday of March, 02:00 local, to the first Sunday of
+    /// November, 02:00 local (US and Canada since 2007)
+    UnitedStates,
+    /// Last Sunday of March to the last Sunday of October, 01:00 UTC
+    /// (European Union and UK)
+    EuropeanUnion,
+}
+
+/// A UTC offset with an optional daylight-saving rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeZone {
+    /// Standard-time offset from UTC in minutes (UTC-8 is -480)
+    pub offset_minutes: i16,
+    pub dst: DstRule,
+}
+
+impl TimeZone {
+    pub const UTC: TimeZone = TimeZone::fixed(0);
+    pub const US_EASTERN: TimeZone = TimeZone::with_dst(-300, DstRule::UnitedStates);
+    pub const US_CENTRAL: TimeZone = TimeZone::with_dst(-360, DstRule::UnitedStates);
+    pub const US_MOUNTAIN: TimeZone = TimeZone::with_dst(-420, DstRule::UnitedStates);
+    pub const US_PACIFIC: TimeZone = TimeZone::with_dst(-480, DstRule::UnitedStates);
+    /// London, Dublin, Lisbon
+    pub const EUROPE_WESTERN: TimeZone = TimeZone::with_dst(0, DstRule::EuropeanUnion);
+    /// Berlin, Paris, Madrid, Rome, ...
+    pub const EUROPE_CENTRAL: TimeZone = TimeZone::with_dst(60, DstRule::EuropeanUnion);
+    /// Athens, Helsinki, Bucharest, ...
+    pub const EUROPE_EASTERN: TimeZone = TimeZone::with_dst(120, DstRule::EuropeanUnion);
+
+    /// A constant offset from UTC, in minutes
+    pub const fn fixed(offset_minutes: i16) -> Self {
+        Self::with_dst(offset_minutes, DstRule::None)
+    }
+
+    /// A standard offset from UTC (minutes) observing `dst`
+    pub const fn with_dst(offset_minutes: i16, dst: DstRule) -> Self {
+        Self {
+            offset_minutes,
+            dst,
+        }
+    }
+
+    /// Offset from UTC in seconds in force at Unix time `unix_secs`
+    pub fn offset_secs_at(&self, unix_secs: i64) -> i64 {
+        let standard = i64::from(self.offset_minutes) * 60;
+        let (year, _, _) = civil_from_days((unix_secs + standard).div_euclid(SECS_PER_DAY));
+        let (start, end) = match self.dst {
+            DstRule::None => return standard,
+            DstRule::UnitedStates => (
+                // 02:00 standard time, and 02:00 daylight time (01:00 standard)
+                nth_sunday(year, 3, 2) * SECS_PER_DAY + 7_200 - standard,
+                nth_sunday(year, 11, 1) * SECS_PER_DAY + 3_600 - standard,
+            ),
+            DstRule::EuropeanUnion => (
+                last_sunday(year, 3) * SECS_PER_DAY + 3_600,
+                last_sunday(year, 10) * SECS_PER_DAY + 3_600,
+            ),
+        };
+        if (start..end).contains(&unix_secs) {
+            standard + 3_600
+        } else {
+            standard
+        }
+    }
+
+    /// Local wall-clock time at Unix time `unix_secs`
+    pub fn local(&self, unix_secs: i64) -> LocalTime {
+        let local = unix_secs + self.offset_secs_at(unix_secs);
+        let days = local.div_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        LocalTime {
+            year,
+            month,
+            day,
+            hour: (local.rem_euclid(SECS_PER_DAY) / 3_600) as u8,
+            weekday: weekday(days),
+        }
+    }
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        TimeZone::UTC
+    }
+}
+
+/// Calendar fields of an instant in a [`TimeZone`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i32,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    /// 0-23
+    pub hour: u8,
+    /// 0 = Monday .. 6 = Sunday
+    pub weekday: u8,
+}
+
+/// (year, month, day) of a day count since 1970-01-01 (proleptic
+/// Gregorian, Howard Hinnant's algorithm)
+fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year as i32, month, day)
+}
+
+/// Days since 1970-01-01 of a civil date
+fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// 0 = Monday .. 6 = Sunday (1970-01-01 was a Thursday)
+fn weekday(days: i64) -> u8 {
+    (days + 3).rem_euclid(7) as u8
+}
+
+/// Day number of the `n`th Sunday of a month
+fn nth_sunday(year: i32, month: u8, n: i64) -> i64 {
+    let first = days_from_civil(year, month, 1);
+    first + (6 - i64::from(weekday(first))) + 7 * (n - 1)
+}
+
+/// Day number of the last Sunday of a month
+fn last_sunday(year: i32, month: u8) -> i64 {
+    let last = days_from_civil(year, month + 1, 1) - 1;
+    last - (i64::from(weekday(last)) + 1) % 7
+}
+
+impl BleCube {
+    /// Unix seconds of a stored timestamp, in the cube's declared unit
+    /// (seconds if none)
+    pub(crate) fn unix_secs(&self, timestamp: i64) -> i64 {
+        let unit = self.time_unit().unwrap_or(TimeUnit::Seconds);
+        Timestamp::from_unit(timestamp, unit)
+            .as_micros()
+            .div_euclid(1_000_000)
+    }
+
+    /// Average number of distinct devices matching `query` in each local
+    /// hour of the day (index 0 is 00:00-00:59) in `zone`, over the local
+    /// days the matches span from first to last; hours without sightings
+    /// count as zero. The typical daily occupancy profile of a site.
+    pub fn devices_per_hour_of_day(&self, query: &Query, zone: TimeZone) -> [f64; 24] {
+        let mut devices: BTreeMap<(i64, u8), BTreeSet<[u8; 6]>> = BTreeMap::new();
+        for id in self.execute_ids(query) {
+            let obs = &self.records[id];
+            let local = zone.local(self.unix_secs(obs.timestamp));
+            let day = days_from_civil(local.year, local.month, local.day);
+            devices
+                .entry((day, local.hour))
+                .or_default()
+                .insert(obs.mac);
+        }
+        let mut profile = [0.0; 24];
+        let (Some(((first, _), _)), Some(((last, _), _))) =
+            (devices.first_key_value(), devices.last_key_value())
+        else {
+            return profile;
+        };
+        let days = (last - first + 1) as f64;
+        for ((_, hour), macs) in &devices {
+            profile[usize::from(*hour)] += macs.len() as f64 / days;
+        }
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    #[test]
+    fn test_civil_dates_round_trip() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(days_from_civil(2000, 2, 29), 11_016);
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        // 2024-01-01 was a Monday
+        assert_eq!(weekday(19_723), 0);
+    }
+
+    #[test]
+    fn test_daylight_saving_transitions() {
+        // 2024: US DST 10 March 10:00 UTC to 3 November 09:00 UTC (Pacific)
+        let pacific = TimeZone::US_PACIFIC;
+        let march_10 = days_from_civil(2024, 3, 10) * SECS_PER_DAY;
+        assert_eq!(
+            pacific.offset_secs_at(march_10 + 10 * 3_600 - 1),
+            -8 * 3_600
+        );
+        assert_eq!(pacific.offset_secs_at(march_10 + 10 * 3_600), -7 * 3_600);
+        let november_3 = days_from_civil(2024, 11, 3) * SECS_PER_DAY;
+        assert_eq!(
+            pacific.offset_secs_at(november_3 + 9 * 3_600 - 1),
+            -7 * 3_600
+        );
+        assert_eq!(pacific.offset_secs_at(november_3 + 9 * 3_600), -8 * 3_600);
+
+        // 2024: EU DST 31 March to 27 October, 01:00 UTC
+        let berlin = TimeZone::EUROPE_CENTRAL;
+        let march_31 = days_from_civil(2024, 3, 31) * SECS_PER_DAY;
+        assert_eq!(berlin.offset_secs_at(march_31 + 3_599), 3_600);
+        assert_eq!(berlin.offset_secs_at(march_31 + 3_600), 7_200);
+        let october_27 = days_from_civil(2024, 10, 27) * SECS_PER_DAY;
+        assert_eq!(berlin.offset_secs_at(october_27 + 3_600), 3_600);
+
+        // 2024-07-04 19:30 UTC is 12:30 PDT, a Thursday
+        let local = pacific.local(days_from_civil(2024, 7, 4) * SECS_PER_DAY + 70_200);
+        assert_eq!((local.year, local.month, local.day), (2024, 7, 4));
+        assert_eq!((local.hour, local.weekday), (12, 3));
+        assert_eq!(TimeZone::fixed(330).local(0).hour, 5);
+    }
+
+    #[test]
+    fn test_devices_per_hour_of_day() {
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Milliseconds, crate::UnitMismatch::Reject);
+        // Two summer days; 16:00 UTC is 09:00 PDT
+        let day = days_from_civil(2024, 7, 1) * SECS_PER_DAY + 16 * 3_600;
+        let sightings = [
+            (1, 0),
+            (1, 60),
+            (2, 0),
+            (3, SECS_PER_DAY),
+            (1, SECS_PER_DAY + 3_600),
+        ];
+        for (mac, offset) in sightings {
+            cube.insert(BleObservation {
+                mac: [mac; 6],
+                timestamp: (day + offset) * 1_000,
+                ..Default::default()
+            });
+        }
+        let profile = cube.devices_per_hour_of_day(&Query::new(), TimeZone::US_PACIFIC);
+        // 09:00: two devices on day one, one on day two; 10:00: one on day two
+        assert_eq!((profile[9], profile[10]), (1.5, 0.5));
+        assert_eq!(profile.iter().sum::<f64>(), 2.0);
+        assert_eq!(
+            cube.devices_per_hour_of_day(&Query::new().mac([9; 6]), TimeZone::UTC),
+            [0.0; 24]
+        );
+    }
+}
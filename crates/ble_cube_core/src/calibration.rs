@@ -0,0 +1,208 @@
+//! Per-receiver RSSI calibration.
+//!
+//! Scanner dongles disagree on absolute RSSI by several dB. A cube can hold
+//! an offset per receiver that is added to the RSSI of every observation
+//! from that receiver at insert time, so indices, queries and the
+//! write-ahead log all see calibrated values. Observations that arrive from
+//! another cube (replicated deltas, [`BleCube::merge_aligned`]) were
+//! calibrated there and are not offset again. Offsets can be estimated from
+//! beacons that several receivers heard at the same time.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+
+/// Offset of one receiver relative to a reference, see
+/// [`BleCube::estimate_rssi_offsets`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RssiOffsetEstimate {
+    pub receiver_id: u16,
+    /// dB to add to this receiver's readings to match the reference
+    pub offset_db: i8,
+    /// (MAC, time window) pairs both receivers observed
+    pub samples: usize,
+}
+
+/// Receiver -> (RSSI sum, count) for one (MAC, time window)
+type Cell = HashMap<u16, (i64, i64)>;
+
+impl BleCube {
+    /// Add `offset_db` to the RSSI of every later observation from
+    /// `receiver_id` (saturating). Records already in the cube are not
+    /// rewritten; an offset of 0 removes the entry.
+    ///
+    /// Offsets are not persisted: the write-ahead log stores calibrated
+    /// values, and a recovered cube starts with an empty table.
+    pub fn set_rssi_offset(&mut self, receiver_id: u16, offset_db: i8) {
+        if offset_db == 0 {
+            self.rssi_offsets.remove(&receiver_id);
+        } else {
+            self.rssi_offsets.insert(receiver_id, offset_db);
+        }
+    }
+
+    /// Offset applied to `receiver_id`, 0 if none is configured
+    pub fn rssi_offset(&self, receiver_id: u16) -> i8 {
+        self.rssi_offsets.get(&receiver_id).copied().unwrap_or(0)
+    }
+
+    /// Configured (receiver, offset) pairs, in no particular order
+    pub fn rssi_offsets(&self) -> impl Iterator<Item = (u16, i8)> + '_ {
+        self.rssi_offsets.iter().map(|(&receiver, &offset)| (receiver, offset))
+    }
+
+    /// Drop every configured offset
+    pub fn clear_rssi_offsets(&mut self) {
+        self.rssi_offsets.clear();
+    }
+
+    /// Apply the receiver's offset; untagged observations pass through
+    pub(crate) fn calibrate(&self, mut obs: BleObservation) -> BleObservation {
+        if let Some(offset) = obs.receiver_id.and_then(|r| self.rssi_offsets.get(&r)) {
+            obs.rssi = obs.rssi.saturating_add(*offset);
+        }
+        obs
+    }
+
+    /// Estimate each receiver's offset relative to `reference_receiver`.
+    ///
+    /// Records are grouped by MAC and time window (`window` timestamp units);
+    /// wherever the reference and another receiver both heard a MAC in the
+    /// same window, the difference of their mean RSSI is one sample. The
+    /// estimate is the median sample, so a few beacons that sit much closer
+    /// to one receiver do not skew it. This assumes the receivers are
+    /// co-located (or the beacons equidistant) while calibrating.
+    ///
+    /// Returns one entry per receiver with at least one sample, sorted by
+    /// receiver ID; nothing if `window` is not positive. Feed the results
+    /// to [`BleCube::set_rssi_offset`] to apply them.
+    pub fn estimate_rssi_offsets(
+        &self,
+        reference_receiver: u16,
+        window: i64,
+    ) -> Vec<RssiOffsetEstimate> {
+        if window <= 0 {
+            return Vec::new();
+        }
+
+        let mut cells: HashMap<([u8; 6], i64), Cell> = HashMap::new();
+        for obs in &self.records {
+            let Some(receiver) = obs.receiver_id else {
+                continue;
+            };
+            let cell = cells
+                .entry((obs.mac, obs.timestamp.div_euclid(window)))
+                .or_default();
+            let (sum, count) = cell.entry(receiver).or_default();
+            *sum += i64::from(obs.rssi);
+            *count += 1;
+        }
+
+        let mean = |(sum, count): (i64, i64)| sum as f64 / count as f64;
+        let mut diffs: HashMap<u16, Vec<f64>> = HashMap::new();
+        for cell in cells.values() {
+            let Some(&reference) = cell.get(&reference_receiver) else {
+                continue;
+            };
+            for (&receiver, &stats) in cell {
+                if receiver != reference_receiver {
+                    diffs
+                        .entry(receiver)
+                        .or_default()
+                        .push(mean(reference) - mean(stats));
+                }
+            }
+        }
+
+        let mut estimates: Vec<RssiOffsetEstimate> = diffs
+            .into_iter()
+            .map(|(receiver_id, mut samples)| {
+                samples.sort_by(f64::total_cmp);
+                let mid = samples.len() / 2;
+                let median = if samples.len() % 2 == 0 {
+                    (samples[mid - 1] + samples[mid]) / 2.0
+                } else {
+                    samples[mid]
+                };
+                RssiOffsetEstimate {
+                    receiver_id,
+                    offset_db: median.round().clamp(-128.0, 127.0) as i8,
+                    samples: samples.len(),
+                }
+            })
+            .collect();
+        estimates.sort_by_key(|e| e.receiver_id);
+        estimates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heard(receiver: u16, mac: u8, timestamp: i64, rssi: i8) -> BleObservation {
+        BleObservation {
+            rssi,
+            mac: [0, 0, 0, 0, 0, mac],
+            timestamp,
+            receiver_id: Some(receiver),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_estimate_and_apply_offsets() {
+        let mut cube = BleCube::new();
+        // Receiver 2 reads 6 dB low, receiver 3 reads 3 dB high
+        for t in 0..5 {
+            for mac in 1..=3u8 {
+                let rssi = -60 - mac as i8;
+                cube.insert(heard(1, mac, t * 10, rssi));
+                cube.insert(heard(2, mac, t * 10 + 1, rssi - 6));
+                cube.insert(heard(3, mac, t * 10 + 2, rssi + 3));
+            }
+        }
+        // A beacon right next to receiver 2 is an outlier the median ignores
+        cube.insert(heard(1, 9, 0, -90));
+        cube.insert(heard(2, 9, 0, -30));
+        // Heard by receiver 4 only: no overlap with the reference
+        cube.insert(heard(4, 1, 500, -50));
+
+        let estimates = cube.estimate_rssi_offsets(1, 10);
+        assert_eq!(
+            estimates,
+            vec![
+                RssiOffsetEstimate {
+                    receiver_id: 2,
+                    offset_db: 6,
+                    samples: 16,
+                },
+                RssiOffsetEstimate {
+                    receiver_id: 3,
+                    offset_db: -3,
+                    samples: 15,
+                },
+            ]
+        );
+        assert!(cube.estimate_rssi_offsets(1, 0).is_empty());
+
+        for e in &estimates {
+            cube.set_rssi_offset(e.receiver_id, e.offset_db);
+        }
+        let id = cube.insert(heard(2, 1, 100, -67));
+        assert_eq!(cube.get(id).unwrap().rssi, -61);
+        let id = cube.insert(heard(3, 1, 101, -127));
+        assert_eq!(cube.get(id).unwrap().rssi, -128);
+        let untagged = cube.insert(BleObservation {
+            rssi: -70,
+            ..Default::default()
+        });
+        assert_eq!(cube.get(untagged).unwrap().rssi, -70);
+        assert_eq!(cube.query_rssi_range(-61, -61).len(), 6);
+
+        cube.set_rssi_offset(2, 0);
+        assert_eq!(cube.rssi_offset(2), 0);
+        assert_eq!(cube.rssi_offsets().count(), 1);
+    }
+}
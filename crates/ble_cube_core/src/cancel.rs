@@ -0,0 +1,95 @@
+//! Cooperative cancellation for long-running query execution.
+//!
+//! A [`Query`](crate::Query) can carry a deadline and/or a [`CancelToken`].
+//! Execution checks them between batches of candidates, so a cancelled
+//! query stops within one batch instead of filtering every candidate.
+
+use crate::compat::prelude::*;
+use alloc::sync::Arc;
+use core::error::Error;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Candidates filtered between two interruption checks
+pub(crate) const CHECK_INTERVAL: usize = 1024;
+
+/// Shared flag for cancelling queries from another thread. Clones share
+/// the flag; once cancelled it stays cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every query holding this token (or a clone) to stop
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Tokens are equal if they share the same flag
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Why a query stopped early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    DeadlineExceeded,
+    Cancelled,
+}
+
+/// A query stopped before checking every candidate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryInterrupted {
+    pub reason: Interrupt,
+    /// Record IDs matched before stopping, ascending: a subset of the full
+    /// result
+    pub partial: Vec<usize>,
+}
+
+impl fmt::Display for QueryInterrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            Interrupt::DeadlineExceeded => "deadline exceeded",
+            Interrupt::Cancelled => "cancelled",
+        };
+        write!(f, "query {} after {} matches", reason, self.partial.len())
+    }
+}
+
+impl Error for QueryInterrupted {}
+
+/// Whether a query with this deadline and token should stop now
+#[cfg(feature = "std")]
+pub(crate) fn interrupt(
+    deadline: Option<Instant>,
+    token: Option<&CancelToken>,
+) -> Option<Interrupt> {
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Some(Interrupt::DeadlineExceeded);
+    }
+    interrupt_token(token)
+}
+
+/// Without `std` there is no clock, so only tokens can interrupt
+#[cfg(not(feature = "std"))]
+pub(crate) fn interrupt(token: Option<&CancelToken>) -> Option<Interrupt> {
+    interrupt_token(token)
+}
+
+fn interrupt_token(token: Option<&CancelToken>) -> Option<Interrupt> {
+    token
+        .is_some_and(CancelToken::is_cancelled)
+        .then_some(Interrupt::Cancelled)
+}
@@ -0,0 +1,285 @@
+//! Clock skew between collectors.
+//!
+//! Collectors stamp observations with their own clocks, and those drift:
+//! NTP-less gateways, phones with the wrong time zone, loggers that were
+//! never set. Merged naively, a device walking past two collectors appears
+//! at two different times, and every cross-collector time query is off.
+//!
+//! Devices both collectors heard give the skew away. For each MAC the two
+//! cubes share, every pair of sightings within `max_skew` of each other is
+//! a candidate offset; pairs that belong together agree on one offset, the
+//! rest scatter. [`BleCube::estimate_clock_offset`] takes the densest
+//! `tolerance`-wide cluster of candidates and returns its median.
+//! [`BleCube::merge_aligned`] applies the estimate while merging one cube
+//! into another, and [`CubeSet::align_clocks`] estimates every member
+//! against a reference.
+//!
+//! Strictly periodic beacons alone are ambiguous: shifting by a whole
+//! advertising interval lines them up just as well. Irregular sightings
+//! (devices coming and going, duty-cycled scanners, several beacons with
+//! different intervals) are what pin the offset down.
+
+use crate::ble_cube::{BleCube, BleObservation, DuplicatePolicy};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::cube_set::CubeSet;
+use crate::error::CubeError;
+
+/// Estimated clock offset of one collector's cube against a reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClockOffset {
+    /// Timestamp units to add to this cube's timestamps to match the
+    /// reference clock
+    pub offset: i64,
+    /// Sighting pairs that agreed on the offset
+    pub matches: usize,
+    /// Sighting pairs of shared MACs within `max_skew` of each other; a
+    /// small `matches` share means a weak estimate
+    pub pairs: usize,
+}
+
+impl BleCube {
+    /// How far this cube's clock is off from `reference`'s, judged by the
+    /// MACs both cubes observed: every pair of their sightings within
+    /// `max_skew` is a candidate offset, and the estimate is the median of
+    /// the densest `tolerance`-wide cluster of candidates.
+    ///
+    /// `max_skew` bounds the offsets considered and `tolerance` is the
+    /// timing jitter between two collectors hearing the same advertising
+    /// event, both in timestamp units. Returns `None` if the cubes share no
+    /// sightings within `max_skew`, or if `tolerance` is not positive or
+    /// `max_skew` is negative. The cost grows with the number of sighting
+    /// pairs of shared MACs within `max_skew` of each other.
+    pub fn estimate_clock_offset(
+        &self,
+        reference: &BleCube,
+        max_skew: i64,
+        tolerance: i64,
+    ) -> Option<ClockOffset> {
+        if tolerance <= 0 || max_skew < 0 {
+            return None;
+        }
+        let shared = self.shared_sightings(reference);
+        let candidates = |visit: &mut dyn FnMut(i64)| {
+            for (ours, theirs) in &shared {
+                for &t in ours {
+                    let from = theirs.partition_point(|&r| r < t.saturating_sub(max_skew));
+                    for &r in theirs[from..]
+                        .iter()
+                        .take_while(|&&r| r <= t.saturating_add(max_skew))
+                    {
+                        visit(r.saturating_sub(t));
+                    }
+                }
+            }
+        };
+
+        // Count candidates per tolerance-wide bin and find the two adjacent
+        // bins holding the most, so a cluster straddling a bin edge counts
+        // in full
+        let mut bins: HashMap<i64, usize> = HashMap::new();
+        let mut pairs = 0;
+        candidates(&mut |diff| {
+            *bins.entry(diff.div_euclid(tolerance)).or_default() += 1;
+            pairs += 1;
+        });
+        let mut keys: Vec<i64> = bins.keys().copied().collect();
+        keys.sort_unstable();
+        // Ties go to the smaller correction
+        let best = keys.into_iter().max_by_key(|&bin| {
+            let count = bins[&bin] + bins.get(&(bin + 1)).copied().unwrap_or(0);
+            (count, core::cmp::Reverse(bin.unsigned_abs()))
+        })?;
+
+        let low = best.saturating_mul(tolerance);
+        let high = best.saturating_add(2).saturating_mul(tolerance);
+        let mut cluster = Vec::new();
+        candidates(&mut |diff| {
+            if (low..high).contains(&diff) {
+                cluster.push(diff);
+            }
+        });
+        cluster.sort_unstable();
+        let mid = cluster.len() / 2;
+        let offset = if cluster.len() % 2 == 0 {
+            cluster[mid - 1].saturating_add(cluster[mid]).div_euclid(2)
+        } else {
+            cluster[mid]
+        };
+        Some(ClockOffset {
+            offset,
+            matches: cluster.len(),
+            pairs,
+        })
+    }
+
+    /// Insert the observations of `source`, another collector's cube, with
+    /// its clock corrected to this cube's: the offset comes from
+    /// [`BleCube::estimate_clock_offset`] and is added to every timestamp.
+    /// Observations go through the usual admission path, except for RSSI
+    /// offsets, which `source` already applied; those whose corrected
+    /// (mac, timestamp) is already stored are skipped, as in
+    /// [`BleCube::apply_delta`].
+    ///
+    /// Returns the estimate, or `None` without merging anything when the
+    /// offset cannot be estimated. Fails like [`BleCube::try_upsert_by_key`];
+    /// observations before the failing one stay merged.
+    pub fn merge_aligned(
+        &mut self,
+        source: &BleCube,
+        max_skew: i64,
+        tolerance: i64,
+    ) -> Result<Option<ClockOffset>, CubeError> {
+        let Some(estimate) = source.estimate_clock_offset(self, max_skew, tolerance) else {
+            return Ok(None);
+        };
+        for obs in source.records.iter() {
+            let shifted = BleObservation {
+                timestamp: obs.timestamp.saturating_add(estimate.offset),
+                ..*obs
+            };
+            let shifted = self.admit_calibrated(shifted)?;
+            self.upsert_admitted(shifted, DuplicatePolicy::KeepExisting)?;
+        }
+        Ok(Some(estimate))
+    }
+
+    /// Sorted sighting times of every MAC both cubes observed, this cube's
+    /// first
+    fn shared_sightings(&self, reference: &BleCube) -> Vec<(Vec<i64>, Vec<i64>)> {
+        let times = |cube: &BleCube, ids: &[usize]| {
+            let mut times: Vec<i64> = ids.iter().map(|&id| cube.records[id].timestamp).collect();
+            times.sort_unstable();
+            times
+        };
+        self.mac_index
+            .iter()
+            .filter_map(|(mac, ids)| {
+                let theirs = reference.mac_index.get(mac)?;
+                Some((times(self, ids), times(reference, theirs)))
+            })
+            .collect()
+    }
+}
+
+impl CubeSet {
+    /// [`BleCube::estimate_clock_offset`] of every other member against the
+    /// member named `reference`, in registration order; empty if there is
+    /// no such member
+    pub fn align_clocks(
+        &self,
+        reference: &str,
+        max_skew: i64,
+        tolerance: i64,
+    ) -> Vec<(&str, Option<ClockOffset>)> {
+        let Some(reference_cube) = self.get(reference) else {
+            return Vec::new();
+        };
+        self.iter()
+            .filter(|&(name, _)| name != reference)
+            .map(|(name, cube)| {
+                (
+                    name,
+                    cube.estimate_clock_offset(reference_cube, max_skew, tolerance),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::SplitMix64;
+
+    /// The same devices heard by two collectors, the second one's clock
+    /// running `skew` behind, with a unit of jitter and some devices only
+    /// one of them heard
+    fn collectors(skew: i64) -> (BleCube, BleCube) {
+        let mut rng = SplitMix64(5);
+        let (mut reference, mut drifting) = (BleCube::new(), BleCube::new());
+        for _ in 0..400 {
+            let mac = [0, 0, 0, 0, 0, (rng.next_u64() % 40) as u8];
+            let timestamp = 1_000 + (rng.next_u64() % 20_000) as i64;
+            reference.insert(BleObservation {
+                mac,
+                timestamp,
+                ..Default::default()
+            });
+            let jitter = (rng.next_u64() % 3) as i64 - 1;
+            drifting.insert(BleObservation {
+                mac,
+                timestamp: timestamp - skew + jitter,
+                ..Default::default()
+            });
+            drifting.insert(BleObservation {
+                mac: [1, 0, 0, 0, 0, (rng.next_u64() % 40) as u8],
+                timestamp,
+                ..Default::default()
+            });
+        }
+        (reference, drifting)
+    }
+
+    #[test]
+    fn test_estimate_clock_offset() {
+        let (reference, drifting) = collectors(137);
+        let estimate = drifting.estimate_clock_offset(&reference, 600, 2).unwrap();
+        assert_eq!(estimate.offset, 137);
+        assert!(estimate.matches >= 400 && estimate.matches < estimate.pairs);
+        let back = reference.estimate_clock_offset(&drifting, 600, 2).unwrap();
+        assert_eq!(back.offset, -137);
+
+        // The true offset is outside the search range
+        let narrow = drifting.estimate_clock_offset(&reference, 100, 2).unwrap();
+        assert!(narrow.matches < estimate.matches / 4);
+        assert!(drifting.estimate_clock_offset(&reference, 600, 0).is_none());
+        assert!(drifting
+            .estimate_clock_offset(&BleCube::new(), 600, 2)
+            .is_none());
+    }
+
+    #[test]
+    fn test_merge_aligned() {
+        let (mut reference, drifting) = collectors(-45);
+        let before = reference.len();
+        let estimate = reference.merge_aligned(&drifting, 300, 2).unwrap().unwrap();
+        assert_eq!(estimate.offset, -45);
+        // The 400 devices only the drifting collector heard are new; of the
+        // shared sightings, those without jitter now coincide with the
+        // reference ones and are skipped
+        let added = reference.len() - before;
+        assert!(added > 400 + 200 && added < 400 + 400, "{added}");
+
+        let mut set = CubeSet::new();
+        set.add("ref", reference);
+        set.add("drifting", drifting);
+        set.add("empty", BleCube::new());
+        let offsets: Vec<(&str, Option<i64>)> = set
+            .align_clocks("ref", 300, 2)
+            .into_iter()
+            .map(|(name, estimate)| (name, estimate.map(|e| e.offset)))
+            .collect();
+        assert_eq!(offsets, [("drifting", Some(-45)), ("empty", None)]);
+        assert!(set.align_clocks("missing", 300, 2).is_empty());
+    }
+
+    #[test]
+    fn test_merge_aligned_keeps_calibration() {
+        let (mut reference, mut drifting) = collectors(0);
+        for cube in [&mut reference, &mut drifting] {
+            cube.set_rssi_offset(7, -4);
+        }
+        let tagged = BleObservation {
+            mac: [2; 6],
+            rssi: -60,
+            timestamp: 500,
+            receiver_id: Some(7),
+            ..Default::default()
+        };
+        drifting.insert(tagged);
+        reference.merge_aligned(&drifting, 300, 2).unwrap().unwrap();
+        assert_eq!(reference.query_mac([2; 6])[0].rssi, -64);
+    }
+}
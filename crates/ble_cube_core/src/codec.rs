@@ -0,0 +1,106 @@
+//! Compact binary encoding of observations.
+//!
+//! Shared by the write-ahead log (segments and snapshots) and replication
+//! deltas: the fixed fields little-endian (31 bytes), then a flags byte
+//! announcing which optional fields follow.
+
+use crate::ble_cube::BleObservation;
+use crate::compat::prelude::*;
+
+/// Encoded size of the fixed observation fields (rssi, mac, timestamp, lat, lon)
+pub(crate) const CORE_OBSERVATION_LEN: usize = 31;
+/// Encoded size of an observation without optional fields
+pub(crate) const MIN_OBSERVATION_LEN: usize = CORE_OBSERVATION_LEN + 1;
+/// Encoded size of an observation with every optional field present
+pub(crate) const MAX_OBSERVATION_LEN: usize = CORE_OBSERVATION_LEN + 1 + 2 + 2 + 1 + 1;
+
+/// Optional-field flags
+const FIELD_RECEIVER: u8 = 0x01;
+const FIELD_FLOOR: u8 = 0x02;
+const FIELD_TX_POWER: u8 = 0x04;
+const FIELD_QUALITY: u8 = 0x08;
+
+/// Append the encoding of `obs` to `buf`
+pub(crate) fn encode_observation(obs: &BleObservation, buf: &mut Vec<u8>) {
+    buf.push(obs.rssi as u8);
+    buf.extend_from_slice(&obs.mac);
+    buf.extend_from_slice(&obs.timestamp.to_le_bytes());
+    buf.extend_from_slice(&obs.lat.to_le_bytes());
+    buf.extend_from_slice(&obs.lon.to_le_bytes());
+
+    let mut flags = 0;
+    if obs.receiver_id.is_some() {
+        flags |= FIELD_RECEIVER;
+    }
+    if obs.floor.is_some() {
+        flags |= FIELD_FLOOR;
+    }
+    if obs.tx_power.is_some() {
+        flags |= FIELD_TX_POWER;
+    }
+    if obs.quality.is_some() {
+        flags |= FIELD_QUALITY;
+    }
+    buf.push(flags);
+    if let Some(receiver_id) = obs.receiver_id {
+        buf.extend_from_slice(&receiver_id.to_le_bytes());
+    }
+    if let Some(floor) = obs.floor {
+        buf.extend_from_slice(&floor.to_le_bytes());
+    }
+    if let Some(tx_power) = obs.tx_power {
+        buf.push(tx_power as u8);
+    }
+    if let Some(quality) = obs.quality {
+        buf.push(quality);
+    }
+}
+
+/// The fixed fields at the start of `buf`, optional fields unset; `None`
+/// if `buf` is shorter than [`CORE_OBSERVATION_LEN`]
+pub(crate) fn decode_core(buf: &[u8]) -> Option<BleObservation> {
+    let core = buf.get(..CORE_OBSERVATION_LEN)?;
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&core[1..7]);
+    Some(BleObservation {
+        rssi: core[0] as i8,
+        mac,
+        timestamp: i64::from_le_bytes(core[7..15].try_into().ok()?),
+        lat: f64::from_le_bytes(core[15..23].try_into().ok()?),
+        lon: f64::from_le_bytes(core[23..31].try_into().ok()?),
+        receiver_id: None,
+        floor: None,
+        tx_power: None,
+        quality: None,
+    })
+}
+
+/// Inverse of [`encode_observation`]: the observation at the start of `buf`
+/// and the number of bytes it occupied, `None` if truncated or malformed
+pub(crate) fn decode_observation(buf: &[u8]) -> Option<(BleObservation, usize)> {
+    let mut obs = decode_core(buf)?;
+    let flags = *buf.get(CORE_OBSERVATION_LEN)?;
+    if flags & !(FIELD_RECEIVER | FIELD_FLOOR | FIELD_TX_POWER | FIELD_QUALITY) != 0 {
+        return None;
+    }
+    let mut len = CORE_OBSERVATION_LEN + 1;
+    if flags & FIELD_RECEIVER != 0 {
+        let bytes = buf.get(len..len + 2)?;
+        obs.receiver_id = Some(u16::from_le_bytes([bytes[0], bytes[1]]));
+        len += 2;
+    }
+    if flags & FIELD_FLOOR != 0 {
+        let bytes = buf.get(len..len + 2)?;
+        obs.floor = Some(i16::from_le_bytes([bytes[0], bytes[1]]));
+        len += 2;
+    }
+    if flags & FIELD_TX_POWER != 0 {
+        obs.tx_power = Some(*buf.get(len)? as i8);
+        len += 1;
+    }
+    if flags & FIELD_QUALITY != 0 {
+        obs.quality = Some(*buf.get(len)?);
+        len += 1;
+    }
+    Some((obs, len))
+}
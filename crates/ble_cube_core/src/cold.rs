@@ -0,0 +1,894 @@
+//! Cold tier: frozen partitions in immutable, memory-mapped segment files.
+//!
+//! [`BleCube::freeze_partitions_before`] moves aged-out time partitions from
+//! memory into a new segment file in the cold-tier directory. Segments are
+//! never modified after being written and are read through `mmap` (a plain
+//! read into memory on non-Unix targets), so a week-long capture only keeps
+//! its recent partitions in RAM and lets the page cache hold the rest.
+//!
+//! Segment layout (`cold-00000001.seg`, ...), little-endian:
+//!
+//! | Section   | Size         | Contents |
+//! |-----------|--------------|----------|
+//! | header    | 72           | magic, CRC-32 of everything after the header, record and MAC counts, time / lat / lon bounds |
+//! | rows      | 40 per record | observations sorted by timestamp (the segment's time index) |
+//! | directory | 16 per MAC   | MAC, offset and length of its postings, sorted by MAC |
+//! | postings  | 4 per record | row numbers grouped by MAC, ascending |
+//!
+//! Queries prune whole segments on the time and bounding-box header, then
+//! drive from the MAC directory or a binary search over the sorted rows and
+//! check the remaining filters row by row.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::checksum::crc32;
+use crate::lifecycle::span_matches;
+use crate::query::{distinct_positions, Query};
+use crate::sample::Reservoir;
+use rstar::{Envelope, AABB};
+use std::collections::BTreeMap;
+#[cfg(feature = "wal")]
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+
+const SEGMENT_MAGIC: &[u8; 8] = b"BLECLD01";
+const SEGMENT_EXTENSION: &str = "seg";
+const HEADER_LEN: usize = 72;
+const ROW_LEN: usize = 40;
+const DIRECTORY_ENTRY_LEN: usize = 16;
+const POSTING_LEN: usize = 4;
+
+/// Optional-field flags of a row
+const FIELD_RECEIVER: u8 = 0x01;
+const FIELD_FLOOR: u8 = 0x02;
+const FIELD_TX_POWER: u8 = 0x04;
+const FIELD_QUALITY: u8 = 0x08;
+
+/// Read-only view of a whole file
+struct Mmap {
+    #[cfg(unix)]
+    ptr: *mut libc::c_void,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+// The mapping is private, read-only and never handed out mutably
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    #[cfg(unix)]
+    fn open(path: &Path) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "segment too large"))?;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty segment"));
+        }
+        // SAFETY: fresh private read-only mapping of an open file; segments
+        // are immutable once renamed into place
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    #[cfg(not(unix))]
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            bytes: fs::read(path)?,
+        })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(unix)]
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` maps `len` readable bytes until `drop`
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmapping exactly the region `open` mapped
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+impl std::fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mmap({} bytes)", self.len())
+    }
+}
+
+/// One immutable segment file of the cold tier
+#[derive(Debug)]
+pub struct ColdSegment {
+    path: PathBuf,
+    map: Mmap,
+    records: usize,
+    macs: usize,
+    time_range: (i64, i64),
+    lat_range: (f64, f64),
+    lon_range: (f64, f64),
+}
+
+impl ColdSegment {
+    /// Map a segment and check its header, size, MAC directory and posting
+    /// row numbers (not the checksum, see [`ColdTier::verify`]), so lookups
+    /// never index past the rows
+    fn open(path: &Path) -> io::Result<Self> {
+        let invalid = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {msg}", path.display()),
+            )
+        };
+        let map = Mmap::open(path)?;
+        if map.len() < HEADER_LEN || &map[..8] != SEGMENT_MAGIC {
+            return Err(invalid("not a cold segment"));
+        }
+        let records = read_u32(&map, 12) as usize;
+        let macs = read_u32(&map, 16) as usize;
+        let expected = HEADER_LEN + records * (ROW_LEN + POSTING_LEN) + macs * DIRECTORY_ENTRY_LEN;
+        if map.len() != expected {
+            return Err(invalid("truncated segment"));
+        }
+        let segment = Self {
+            path: path.to_path_buf(),
+            records,
+            macs,
+            time_range: (read_i64(&map, 24), read_i64(&map, 32)),
+            lat_range: (read_f64(&map, 40), read_f64(&map, 48)),
+            lon_range: (read_f64(&map, 56), read_f64(&map, 64)),
+            map,
+        };
+        let directory = segment.directory_offset();
+        for i in 0..macs {
+            let entry = directory + i * DIRECTORY_ENTRY_LEN;
+            let start = read_u32(&segment.map, entry + 8) as usize;
+            let len = read_u32(&segment.map, entry + 12) as usize;
+            if start + len > records {
+                return Err(invalid("MAC directory entry out of bounds"));
+            }
+        }
+        if (0..records).any(|i| segment.posting(i) >= records) {
+            return Err(invalid("posting row out of bounds"));
+        }
+        Ok(segment)
+    }
+
+    /// Write `records` as a new segment (temp file + rename) and map it
+    fn write(path: &Path, mut records: Vec<BleObservation>) -> io::Result<Self> {
+        if u32::try_from(records.len()).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many records for one segment",
+            ));
+        }
+        records.sort_by_key(|obs| obs.timestamp);
+
+        let mut postings: BTreeMap<[u8; 6], Vec<u32>> = BTreeMap::new();
+        for (row, obs) in records.iter().enumerate() {
+            postings.entry(obs.mac).or_default().push(row as u32);
+        }
+
+        let mut body = Vec::with_capacity(
+            records.len() * (ROW_LEN + POSTING_LEN) + postings.len() * DIRECTORY_ENTRY_LEN,
+        );
+        for obs in &records {
+            encode_row(&mut body, obs);
+        }
+        let mut offset = 0u32;
+        for (mac, rows) in &postings {
+            body.extend_from_slice(mac);
+            body.extend_from_slice(&[0; 2]);
+            body.extend_from_slice(&offset.to_le_bytes());
+            body.extend_from_slice(&(rows.len() as u32).to_le_bytes());
+            offset += rows.len() as u32;
+        }
+        for row in postings.values().flatten() {
+            body.extend_from_slice(&row.to_le_bytes());
+        }
+
+        let bounds = |field: fn(&BleObservation) -> f64| {
+            records
+                .iter()
+                .map(field)
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+                    (lo.min(v), hi.max(v))
+                })
+        };
+        let (min_lat, max_lat) = bounds(|obs| obs.lat);
+        let (min_lon, max_lon) = bounds(|obs| obs.lon);
+        let first = records.first().map_or(0, |obs| obs.timestamp);
+        let last = records.last().map_or(0, |obs| obs.timestamp);
+
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        writer.write_all(SEGMENT_MAGIC)?;
+        writer.write_all(&crc32(&body).to_le_bytes())?;
+        writer.write_all(&(records.len() as u32).to_le_bytes())?;
+        writer.write_all(&(postings.len() as u32).to_le_bytes())?;
+        writer.write_all(&[0; 4])?;
+        for value in [first, last] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for value in [min_lat, max_lat, min_lon, max_lon] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&body)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        fs::rename(&tmp, path)?;
+
+        Self::open(path)
+    }
+
+    /// File backing this segment
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Earliest and latest timestamp stored (inclusive)
+    pub fn time_range(&self) -> (i64, i64) {
+        self.time_range
+    }
+
+    /// Observation at `row`; rows are in timestamp order
+    pub fn get(&self, row: usize) -> Option<BleObservation> {
+        (row < self.records).then(|| self.row(row))
+    }
+
+    /// Every observation, in timestamp order
+    pub fn iter(&self) -> impl Iterator<Item = BleObservation> + '_ {
+        (0..self.records).map(|row| self.row(row))
+    }
+
+    fn row(&self, row: usize) -> BleObservation {
+        decode_row(&self.map[HEADER_LEN + row * ROW_LEN..][..ROW_LEN])
+    }
+
+    fn timestamp(&self, row: usize) -> i64 {
+        read_i64(&self.map, HEADER_LEN + row * ROW_LEN)
+    }
+
+    fn directory_offset(&self) -> usize {
+        HEADER_LEN + self.records * ROW_LEN
+    }
+
+    /// Row numbers of `mac`, ascending (and so in timestamp order)
+    fn postings(&self, mac: &[u8; 6]) -> Option<Range<usize>> {
+        let directory = self.directory_offset();
+        let entry =
+            |i: usize| &self.map[directory + i * DIRECTORY_ENTRY_LEN..][..DIRECTORY_ENTRY_LEN];
+        let mut lo = 0;
+        let mut hi = self.macs;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match entry(mid)[..6].cmp(mac) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    let start = read_u32(entry(mid), 8) as usize;
+                    let len = read_u32(entry(mid), 12) as usize;
+                    return Some(start..start + len);
+                }
+            }
+        }
+        None
+    }
+
+    fn posting(&self, i: usize) -> usize {
+        let postings = self.directory_offset() + self.macs * DIRECTORY_ENTRY_LEN;
+        read_u32(&self.map, postings + i * POSTING_LEN) as usize
+    }
+
+    /// First and last timestamp of `mac` within this segment
+    fn span(&self, mac: &[u8; 6]) -> Option<(i64, i64)> {
+        let postings = self.postings(mac).filter(|range| !range.is_empty())?;
+        Some((
+            self.timestamp(self.posting(postings.start)),
+            self.timestamp(self.posting(postings.end - 1)),
+        ))
+    }
+
+    /// Rows with timestamps in [start, end]
+    fn rows_between(&self, start: i64, end: i64) -> Range<usize> {
+        let first = partition_point(self.records, |row| self.timestamp(row) < start);
+        let last = partition_point(self.records, |row| self.timestamp(row) <= end);
+        first..last.max(first)
+    }
+
+    /// Whether the header bounds rule out every match of `query`
+    fn pruned(&self, cube: &BleCube, query: &Query) -> bool {
+        let (first, last) = self.time_range;
+        if query
+            .time_range
+            .is_some_and(|(start, end)| end < first || start > last)
+        {
+            return true;
+        }
+        query.geo_radius.is_some_and(|(lat, lon, radius_m)| {
+            let bounds = AABB::from_corners(
+                [self.lat_range.0, self.lon_range.0],
+                [self.lat_range.1, self.lon_range.1],
+            );
+            !cube
+                .crs
+                .wrap_envelope(cube.crs.radius_envelope(lat, lon, radius_m))
+                .any(|part| part.intersects(&bounds))
+        })
+    }
+
+    /// Matches of `query` in timestamp order
+    fn execute(&self, cube: &BleCube, query: &Query, mut emit: impl FnMut(BleObservation)) {
+        if self.pruned(cube, query) {
+            return;
+        }
+        let mut check = |row: usize| {
+            let obs = self.row(row);
+            if cube.matches_cold(query, &obs) {
+                emit(obs);
+            }
+        };
+        if let Some(mac) = query.mac {
+            self.postings(&mac)
+                .into_iter()
+                .flatten()
+                .for_each(|i| check(self.posting(i)));
+        } else {
+            let (start, end) = query.time_range.unwrap_or((i64::MIN, i64::MAX));
+            self.rows_between(start, end).for_each(check);
+        }
+    }
+
+    fn verify(&self) -> io::Result<()> {
+        if read_u32(&self.map, 8) != crc32(&self.map[HEADER_LEN..]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: checksum mismatch", self.path.display()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Directory of frozen segments attached to a cube, oldest first.
+/// See [`BleCube::attach_cold_tier`].
+#[derive(Debug)]
+pub struct ColdTier {
+    dir: PathBuf,
+    segments: Vec<(u64, ColdSegment)>,
+}
+
+impl ColdTier {
+    fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut numbered = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let number = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix("cold-"))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(number) = number {
+                numbered.push((number, path));
+            }
+        }
+        numbered.sort();
+        let segments = numbered
+            .into_iter()
+            .map(|(number, path)| Ok((number, ColdSegment::open(&path)?)))
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            segments,
+        })
+    }
+
+    /// Write `records` as the next segment
+    fn freeze(&mut self, records: Vec<BleObservation>) -> io::Result<()> {
+        let number = self.segments.last().map_or(1, |(number, _)| number + 1);
+        let path = self
+            .dir
+            .join(format!("cold-{number:08}.{SEGMENT_EXTENSION}"));
+        let segment = ColdSegment::write(&path, records)?;
+        self.segments.push((number, segment));
+        Ok(())
+    }
+
+    /// Number of the newest segment, 0 if there is none
+    #[cfg(feature = "wal")]
+    pub(crate) fn last_segment(&self) -> u64 {
+        self.segments.last().map_or(0, |&(number, _)| number)
+    }
+
+    /// Directory holding the segment files
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Segments, oldest first
+    pub fn segments(&self) -> impl Iterator<Item = &ColdSegment> {
+        self.segments.iter().map(|(_, segment)| segment)
+    }
+
+    /// Records across all segments
+    pub fn len(&self) -> usize {
+        self.segments().map(ColdSegment::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check every segment's checksum (reads each file in full)
+    pub fn verify(&self) -> io::Result<()> {
+        self.segments().try_for_each(ColdSegment::verify)
+    }
+
+    /// First and last timestamp of `mac` across all segments
+    pub(crate) fn span(&self, mac: &[u8; 6]) -> Option<(i64, i64)> {
+        self.segments()
+            .filter_map(|segment| segment.span(mac))
+            .reduce(|(first, last), (f, l)| (first.min(f), last.max(l)))
+    }
+}
+
+impl BleCube {
+    /// Open (or create) a cold-tier directory and attach its segments.
+    /// Partitions frozen with [`BleCube::freeze_partitions_before`] go there,
+    /// and [`BleCube::execute_tiered`] searches them.
+    ///
+    /// With a write-ahead log attached (attach after [`BleCube::recover`]),
+    /// segments newer than the last checkpoint are freezes interrupted
+    /// before their records were evicted: records identical to their rows
+    /// are evicted now and the cube is checkpointed, so nothing is stored in
+    /// both tiers.
+    pub fn attach_cold_tier<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        let cold = ColdTier::open(dir.as_ref())?;
+        #[cfg(feature = "wal")]
+        if let Some(frozen_through) = self.wal.as_ref().map(|wal| wal.frozen_through) {
+            if cold.last_segment() > frozen_through {
+                self.evict_frozen(&cold, frozen_through);
+                self.cold = Some(cold);
+                return self.checkpoint();
+            }
+        }
+        self.cold = Some(cold);
+        Ok(())
+    }
+
+    /// Evict one in-memory record per row of the segments after
+    /// `frozen_through` that it is identical to
+    #[cfg(feature = "wal")]
+    fn evict_frozen(&mut self, cold: &ColdTier, frozen_through: u64) {
+        let mut rows: HashMap<Vec<u8>, usize> = HashMap::new();
+        for (_, segment) in cold
+            .segments
+            .iter()
+            .filter(|&&(number, _)| number > frozen_through)
+        {
+            for obs in segment.iter() {
+                *rows.entry(row_bytes(&obs)).or_default() += 1;
+            }
+        }
+        self.compact(|_, obs| match rows.get_mut(&row_bytes(obs)) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// The attached cold tier, if any
+    pub fn cold_tier(&self) -> Option<&ColdTier> {
+        self.cold.as_ref()
+    }
+
+    /// Move every partition that ends at or before `cutoff` into a new cold
+    /// segment, returning how many records were frozen. The segment is
+    /// synced to disk before the records are evicted from memory (see
+    /// [`BleCube::evict_partitions_before`], including its write-ahead log
+    /// checkpoint). Fails if no cold tier is attached.
+    pub fn freeze_partitions_before(&mut self, cutoff: i64) -> io::Result<usize> {
+        if self.cold.is_none() {
+            return Err(io::Error::other("cube has no cold tier attached"));
+        }
+        let expired = self.expired_records(cutoff);
+        let frozen: Vec<BleObservation> = self
+            .records
+            .iter()
+            .zip(&expired)
+            .filter(|&(_, &expired)| expired)
+            .map(|(obs, _)| *obs)
+            .collect();
+        if frozen.is_empty() {
+            return Ok(0);
+        }
+        let count = frozen.len();
+        if let Some(cold) = self.cold.as_mut() {
+            cold.freeze(frozen)?;
+        }
+        self.evict_partitions_before(cutoff)?;
+        Ok(count)
+    }
+
+    /// Run a query over the cold segments and the in-memory records,
+    /// returning owned copies: cold matches segment by segment in timestamp
+    /// order, then in-memory matches in record ID order. Per-device matches
+    /// ([`Query::distinct_by_mac`]) are picked and a sample is drawn over the
+    /// combined matches; on deadline or cancellation the matches found so
+    /// far are returned.
+    pub fn execute_tiered(&self, query: &Query) -> Vec<BleObservation> {
+        let mut matches = Vec::new();
+        for segment in self.cold.iter().flat_map(ColdTier::segments) {
+            if query.interrupted().is_some() {
+                return matches;
+            }
+            segment.execute(self, query, |obs| matches.push(obs));
+        }
+        let hot = Query {
+            sample: None,
+            ..query.clone()
+        };
+        matches.extend(self.execute(&hot).into_iter().copied());
+        if let Some(keep) = query.distinct {
+            let positions = distinct_positions(keep, &matches);
+            matches = positions.into_iter().map(|i| matches[i]).collect();
+        }
+
+        match query.sample {
+            Some((n, seed)) => {
+                let mut reservoir = Reservoir::new(n, seed);
+                (0..matches.len()).for_each(|i| reservoir.offer(i));
+                reservoir
+                    .into_sorted()
+                    .into_iter()
+                    .map(|i| matches[i])
+                    .collect()
+            }
+            None => matches,
+        }
+    }
+
+    /// Whether a cold row satisfies every filter of `query`
+    fn matches_cold(&self, query: &Query, obs: &BleObservation) -> bool {
+        // Tags are not frozen with their records
+        query.tag.is_none()
+            && query.mac.is_none_or(|mac| obs.mac == mac)
+            && query
+                .receiver
+                .is_none_or(|receiver| obs.receiver_id == Some(receiver))
+            && query.floor.is_none_or(|floor| obs.floor == Some(floor))
+            && query.zone.as_deref().is_none_or(|name| {
+                self.zone(name).is_some_and(|zone| {
+                    zone.contains_with(self.crs, self.distance_metric, obs.lat, obs.lon)
+                })
+            })
+            && query.geo_radius.is_none_or(|(lat, lon, radius_m)| {
+                self.distance(lat, lon, obs.lat, obs.lon) <= radius_m
+            })
+            && (query.new_since.is_none() && query.not_seen_since.is_none()
+                || self
+                    .device_span(&obs.mac)
+                    .is_some_and(|span| span_matches(span, query.new_since, query.not_seen_since)))
+            && query
+                .time_range
+                .is_none_or(|(start, end)| (start..=end).contains(&obs.timestamp))
+            && query
+                .rssi_range
+                .is_none_or(|(min, max)| (min..=max).contains(&obs.rssi))
+            && query.path_loss_range.is_none_or(|(min, max)| {
+                obs.path_loss()
+                    .is_some_and(|path_loss| (min..=max).contains(&path_loss))
+            })
+            && query
+                .min_quality
+                .is_none_or(|min| obs.quality.is_some_and(|quality| quality >= min))
+    }
+}
+
+/// First index in `0..len` for which `pred` is false (`pred` must be true
+/// on a prefix)
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+fn encode_row(buf: &mut Vec<u8>, obs: &BleObservation) {
+    let mut flags = 0;
+    if obs.receiver_id.is_some() {
+        flags |= FIELD_RECEIVER;
+    }
+    if obs.floor.is_some() {
+        flags |= FIELD_FLOOR;
+    }
+    if obs.tx_power.is_some() {
+        flags |= FIELD_TX_POWER;
+    }
+    if obs.quality.is_some() {
+        flags |= FIELD_QUALITY;
+    }
+    buf.extend_from_slice(&obs.timestamp.to_le_bytes());
+    buf.extend_from_slice(&obs.lat.to_le_bytes());
+    buf.extend_from_slice(&obs.lon.to_le_bytes());
+    buf.extend_from_slice(&obs.mac);
+    buf.extend_from_slice(&obs.rssi.to_le_bytes());
+    buf.push(flags);
+    buf.extend_from_slice(&obs.receiver_id.unwrap_or(0).to_le_bytes());
+    buf.extend_from_slice(&obs.floor.unwrap_or(0).to_le_bytes());
+    buf.extend_from_slice(&obs.tx_power.unwrap_or(0).to_le_bytes());
+    buf.push(obs.quality.unwrap_or(0));
+    buf.extend_from_slice(&[0; 2]);
+}
+
+/// A record's segment row, as an exact-match key
+#[cfg(feature = "wal")]
+fn row_bytes(obs: &BleObservation) -> Vec<u8> {
+    let mut row = Vec::with_capacity(ROW_LEN);
+    encode_row(&mut row, obs);
+    row
+}
+
+fn decode_row(row: &[u8]) -> BleObservation {
+    let flags = row[31];
+    BleObservation {
+        timestamp: read_i64(row, 0),
+        lat: read_f64(row, 8),
+        lon: read_f64(row, 16),
+        mac: row[24..30].try_into().expect("6-byte slice"),
+        rssi: row[30] as i8,
+        receiver_id: (flags & FIELD_RECEIVER != 0).then(|| u16::from_le_bytes([row[32], row[33]])),
+        floor: (flags & FIELD_FLOOR != 0).then(|| i16::from_le_bytes([row[34], row[35]])),
+        tx_power: (flags & FIELD_TX_POWER != 0).then_some(row[36] as i8),
+        quality: (flags & FIELD_QUALITY != 0).then_some(row[37]),
+    }
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().expect("4-byte slice"))
+}
+
+fn read_i64(buf: &[u8], at: usize) -> i64 {
+    i64::from_le_bytes(buf[at..at + 8].try_into().expect("8-byte slice"))
+}
+
+fn read_f64(buf: &[u8], at: usize) -> f64 {
+    f64::from_le_bytes(buf[at..at + 8].try_into().expect("8-byte slice"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "ble_cube_cold_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Four hours, one observation every 10 minutes; MAC = hour
+    fn cube_with_hours(hours: i64) -> BleCube {
+        let mut cube = BleCube::new();
+        for t in (0..hours * 3600).step_by(600) {
+            cube.insert(BleObservation {
+                mac: [0, 0, 0, 0, 0, (t / 3600) as u8],
+                rssi: -40 - (t / 600 % 6) as i8 * 10,
+                timestamp: t,
+                lat: 37.0 + t as f64 * 1e-6,
+                lon: -122.0,
+                receiver_id: (t % 1200 == 0).then_some(7),
+                floor: None,
+                tx_power: (t % 1800 == 0).then_some(-59),
+                quality: None,
+            });
+        }
+        cube
+    }
+
+    #[test]
+    fn test_freeze_and_query_tiers() {
+        let dir = temp_dir();
+        let mut cube = cube_with_hours(4);
+        assert!(cube.freeze_partitions_before(7200).is_err());
+        cube.attach_cold_tier(&dir).unwrap();
+
+        assert_eq!(cube.freeze_partitions_before(7200).unwrap(), 12);
+        assert_eq!(cube.freeze_partitions_before(7200).unwrap(), 0);
+        assert_eq!(cube.len(), 12);
+        let cold = cube.cold_tier().unwrap();
+        assert_eq!(cold.len(), 12);
+        let segment = cold.segments().next().unwrap();
+        assert_eq!(segment.time_range(), (0, 6600));
+        assert_eq!(segment.get(1).unwrap().receiver_id, None);
+        assert_eq!(segment.get(2).unwrap().receiver_id, Some(7));
+        assert_eq!(segment.get(3).unwrap().tx_power, Some(-59));
+        assert_eq!(segment.get(4).unwrap().tx_power, None);
+        cold.verify().unwrap();
+
+        let all = cube.execute_tiered(&Query::new());
+        assert_eq!(all.len(), 24);
+        assert!(all.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+
+        let span = Query::new().time_between(3000, 7800);
+        let hits: Vec<i64> = cube
+            .execute_tiered(&span)
+            .iter()
+            .map(|obs| obs.timestamp)
+            .collect();
+        assert_eq!(
+            hits,
+            vec![3000, 3600, 4200, 4800, 5400, 6000, 6600, 7200, 7800]
+        );
+
+        let mac = Query::new().mac([0, 0, 0, 0, 0, 1]).rssi_between(-60, -40);
+        assert_eq!(cube.execute_tiered(&mac).len(), 3);
+        assert_eq!(cube.execute_tiered(&Query::new().receiver(7)).len(), 12);
+        // rssi -40 with tx_power -59 at t = 0, 3600, ...: path loss -19
+        let loud = Query::new().path_loss_between(-19, -19);
+        assert_eq!(cube.execute_tiered(&loud).len(), 4);
+        let near = Query::new().within_radius(37.0, -122.0, 1.0);
+        assert_eq!(cube.execute_tiered(&near).len(), 1);
+        assert_eq!(cube.execute_tiered(&Query::new().sample(5, 1)).len(), 5);
+
+        // Device history spans both tiers
+        assert_eq!(cube.first_seen([0; 6]), Some(0));
+        assert!(cube
+            .execute_tiered(&Query::new().new_since(3600))
+            .iter()
+            .all(|obs| obs.timestamp >= 3600));
+        assert_eq!(
+            cube.execute_tiered(&Query::new().not_seen_since(7200))
+                .len(),
+            12
+        );
+
+        // Segments survive reopening
+        let mut reopened = BleCube::new();
+        reopened.attach_cold_tier(&dir).unwrap();
+        assert_eq!(reopened.execute_tiered(&span).len(), 7);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "wal")]
+    #[test]
+    fn test_interrupted_freeze_is_completed_on_attach() {
+        let (wal_dir, cold_dir) = (temp_dir(), temp_dir());
+        {
+            let mut cube = BleCube::recover(&wal_dir).unwrap();
+            for obs in cube_with_hours(4).records.iter() {
+                cube.insert(*obs);
+            }
+            cube.attach_cold_tier(&cold_dir).unwrap();
+            assert_eq!(cube.freeze_partitions_before(3600).unwrap(), 6);
+            // Crash after the next segment was written, before eviction
+            let expired = cube.expired_records(7200);
+            let frozen = cube
+                .records
+                .iter()
+                .zip(expired)
+                .filter(|&(_, expired)| expired)
+                .map(|(obs, _)| *obs)
+                .collect();
+            cube.cold.as_mut().unwrap().freeze(frozen).unwrap();
+        }
+
+        let mut cube = BleCube::recover(&wal_dir).unwrap();
+        assert_eq!(cube.len(), 18);
+        cube.attach_cold_tier(&cold_dir).unwrap();
+        assert_eq!((cube.len(), cube.cold_tier().unwrap().len()), (12, 12));
+        assert_eq!(cube.execute_tiered(&Query::new()).len(), 24);
+        drop(cube);
+
+        // Completed freezes are not evicted again
+        let mut cube = BleCube::recover(&wal_dir).unwrap();
+        cube.insert(cube_with_hours(1).records[0]);
+        cube.attach_cold_tier(&cold_dir).unwrap();
+        assert_eq!(cube.len(), 13);
+        fs::remove_dir_all(&wal_dir).unwrap();
+        fs::remove_dir_all(&cold_dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_segment_detected() {
+        let dir = temp_dir();
+        let mut cube = cube_with_hours(2);
+        cube.attach_cold_tier(&dir).unwrap();
+        cube.freeze_partitions_before(3600).unwrap();
+        let path = cube
+            .cold_tier()
+            .unwrap()
+            .segments()
+            .next()
+            .unwrap()
+            .path()
+            .to_path_buf();
+        drop(cube);
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        // The first row's RSSI
+        bytes[HEADER_LEN + 30] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        let mut cube = BleCube::new();
+        cube.attach_cold_tier(&dir).unwrap();
+        assert!(cube.cold_tier().unwrap().verify().is_err());
+
+        // Correctly sized, but the last posting points past the rows
+        bytes[last - 3..=last].copy_from_slice(&1000u32.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let err = BleCube::new().attach_cold_tier(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // A directory entry whose postings run past the end
+        bytes[last - 3..=last].copy_from_slice(&0u32.to_le_bytes());
+        let directory = HEADER_LEN + 6 * ROW_LEN;
+        bytes[directory + 12..directory + 16].copy_from_slice(&7u32.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let err = BleCube::new().attach_cold_tier(&dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        bytes.truncate(last);
+        fs::write(&path, &bytes).unwrap();
+        assert!(BleCube::new().attach_cold_tier(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
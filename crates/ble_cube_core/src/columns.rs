@@ -0,0 +1,187 @@
+//! Record store with columnar copies of the scan-hot fields.
+//!
+//! Rows stay array-of-structs because the public API hands out
+//! `&BleObservation`, but residual filters, projections and aggregations
+//! mostly read one or two fields. RSSI, MAC, timestamp, latitude and
+//! longitude are also kept as dense columns (31 bytes per record instead of
+//! a 48-byte row per touch), so a scan pulls only the bytes it compares
+//! through the cache. The coordinate columns serve geo scans when the
+//! R-tree is disabled, and the MAC column serves MAC and lifecycle filters
+//! on candidates driven by another index.
+//!
+//! The gain is in full scans: an unindexed RSSI filter over 100k records
+//! (`scan_100k/rssi_full_scan`) runs about 2.5x faster on the column than
+//! on the rows. Scans driven by an index, whose candidates mostly arrive in
+//! record order, gain nothing measurable.
+//!
+//! Every mutation goes through [`RecordStore`], which keeps the columns in
+//! step with the rows; reads see a plain `[BleObservation]` via `Deref`.
+
+use crate::ble_cube::BleObservation;
+use crate::compat::prelude::*;
+use core::mem::size_of;
+use core::ops::Deref;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RecordStore {
+    rows: Vec<BleObservation>,
+    rssi: Vec<i8>,
+    macs: Vec<[u8; 6]>,
+    timestamps: Vec<i64>,
+    lats: Vec<f64>,
+    lons: Vec<f64>,
+}
+
+impl RecordStore {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            rows: Vec::with_capacity(capacity),
+            rssi: Vec::with_capacity(capacity),
+            macs: Vec::with_capacity(capacity),
+            timestamps: Vec::with_capacity(capacity),
+            lats: Vec::with_capacity(capacity),
+            lons: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn from_rows(rows: Vec<BleObservation>) -> Self {
+        Self {
+            rssi: rows.iter().map(|obs| obs.rssi).collect(),
+            macs: rows.iter().map(|obs| obs.mac).collect(),
+            timestamps: rows.iter().map(|obs| obs.timestamp).collect(),
+            lats: rows.iter().map(|obs| obs.lat).collect(),
+            lons: rows.iter().map(|obs| obs.lon).collect(),
+            rows,
+        }
+    }
+
+    /// Take every row out, leaving the store empty
+    #[cfg(feature = "std")]
+    pub(crate) fn take_rows(&mut self) -> Vec<BleObservation> {
+        self.rssi.clear();
+        self.macs.clear();
+        self.timestamps.clear();
+        self.lats.clear();
+        self.lons.clear();
+        core::mem::take(&mut self.rows)
+    }
+
+    pub(crate) fn push(&mut self, obs: BleObservation) {
+        self.rssi.push(obs.rssi);
+        self.macs.push(obs.mac);
+        self.timestamps.push(obs.timestamp);
+        self.lats.push(obs.lat);
+        self.lons.push(obs.lon);
+        self.rows.push(obs);
+    }
+
+    /// Overwrite a record, returning the previous one
+    pub(crate) fn replace(&mut self, record_id: usize, obs: BleObservation) -> BleObservation {
+        self.rssi[record_id] = obs.rssi;
+        self.macs[record_id] = obs.mac;
+        self.timestamps[record_id] = obs.timestamp;
+        self.lats[record_id] = obs.lat;
+        self.lons[record_id] = obs.lon;
+        core::mem::replace(&mut self.rows[record_id], obs)
+    }
+
+    /// RSSI of every record, indexed by record ID
+    pub(crate) fn rssi(&self) -> &[i8] {
+        &self.rssi
+    }
+
+    /// MAC of every record, indexed by record ID
+    pub(crate) fn macs(&self) -> &[[u8; 6]] {
+        &self.macs
+    }
+
+    /// Timestamp of every record, indexed by record ID
+    pub(crate) fn timestamps(&self) -> &[i64] {
+        &self.timestamps
+    }
+
+    /// Latitude of every record, indexed by record ID
+    pub(crate) fn lats(&self) -> &[f64] {
+        &self.lats
+    }
+
+    /// Longitude of every record, indexed by record ID
+    pub(crate) fn lons(&self) -> &[f64] {
+        &self.lons
+    }
+
+    /// Heap bytes of rows and columns
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.rows.capacity() * size_of::<BleObservation>()
+            + self.rssi.capacity() * size_of::<i8>()
+            + self.macs.capacity() * size_of::<[u8; 6]>()
+            + self.timestamps.capacity() * size_of::<i64>()
+            + (self.lats.capacity() + self.lons.capacity()) * size_of::<f64>()
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.rows.shrink_to_fit();
+        self.rssi.shrink_to_fit();
+        self.macs.shrink_to_fit();
+        self.timestamps.shrink_to_fit();
+        self.lats.shrink_to_fit();
+        self.lons.shrink_to_fit();
+    }
+}
+
+impl Deref for RecordStore {
+    type Target = [BleObservation];
+
+    fn deref(&self) -> &[BleObservation] {
+        &self.rows
+    }
+}
+
+impl<'a> IntoIterator for &'a RecordStore {
+    type Item = &'a BleObservation;
+    type IntoIter = core::slice::Iter<'a, BleObservation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(rssi: i8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            rssi,
+            mac: [0, 0, 0, 0, 0, timestamp as u8],
+            timestamp,
+            lat: timestamp as f64,
+            lon: -timestamp as f64,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_columns_follow_rows() {
+        let mut store = RecordStore::from_rows(vec![obs(-50, 10), obs(-60, 20)]);
+        store.push(obs(-70, 30));
+        let old = store.replace(1, obs(-65, 25));
+        assert_eq!((old.rssi, old.timestamp), (-60, 20));
+
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.rssi(), &[-50, -65, -70]);
+        assert_eq!(store.timestamps(), &[10, 25, 30]);
+        assert_eq!(store.macs()[1], [0, 0, 0, 0, 0, 25]);
+        assert_eq!(store.lats(), &[10.0, 25.0, 30.0]);
+        assert_eq!(store.lons(), &[-10.0, -25.0, -30.0]);
+        assert_eq!(store[1].rssi, -65);
+
+        #[cfg(feature = "std")]
+        {
+            let rows = store.take_rows();
+            assert_eq!(rows.len(), 3);
+            assert!(store.is_empty() && store.rssi().is_empty());
+            assert!(store.macs().is_empty() && store.lats().is_empty());
+        }
+    }
+}
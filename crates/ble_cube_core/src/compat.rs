@@ -0,0 +1,110 @@
+//! Shims that let the core build with or without `std`.
+//!
+//! Without the `std` feature the crate is `no_std` + `alloc`: collection
+//! and string types come from `alloc`, hash maps come from `hashbrown`
+//! (the table behind std's `HashMap`, hashed with foldhash, which needs no
+//! entropy source), and float math goes through `libm`.
+
+#[cfg(feature = "std")]
+pub use std::collections::HashMap;
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::HashMap;
+
+/// Bound for keys of [`HashMap`]
+pub trait MapKey: Eq + core::hash::Hash {}
+
+impl<T: Eq + core::hash::Hash> MapKey for T {}
+
+/// Types and macros the `std` prelude provides but `core`'s does not
+pub mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::format;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec;
+    pub use alloc::vec::Vec;
+
+    #[cfg(not(feature = "std"))]
+    #[allow(unused_imports)]
+    pub use super::F64Ext;
+}
+
+/// Make renames and deletions inside `dir` durable. Directories cannot be
+/// opened for syncing on every platform; elsewhere this is a no-op.
+#[cfg(feature = "wal")]
+pub(crate) fn sync_dir(dir: &std::path::Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    std::fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// The `f64` methods `std` adds on top of `core`, backed by `libm`.
+///
+/// Unused whenever something else in the build (tests, dev-dependencies)
+/// links `std`, because its inherent methods take precedence.
+#[cfg(not(feature = "std"))]
+#[allow(dead_code)]
+pub trait F64Ext {
+    fn sqrt(self) -> f64;
+    fn sin(self) -> f64;
+    fn cos(self) -> f64;
+    fn tan(self) -> f64;
+    fn asin(self) -> f64;
+    fn atan(self) -> f64;
+    fn atan2(self, other: f64) -> f64;
+    fn sin_cos(self) -> (f64, f64);
+    fn hypot(self, other: f64) -> f64;
+    fn powi(self, n: i32) -> f64;
+    fn powf(self, n: f64) -> f64;
+    fn round(self) -> f64;
+    fn ceil(self) -> f64;
+    fn floor(self) -> f64;
+}
+
+#[cfg(not(feature = "std"))]
+impl F64Ext for f64 {
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+    fn sin(self) -> f64 {
+        libm::sin(self)
+    }
+    fn cos(self) -> f64 {
+        libm::cos(self)
+    }
+    fn tan(self) -> f64 {
+        libm::tan(self)
+    }
+    fn asin(self) -> f64 {
+        libm::asin(self)
+    }
+    fn atan(self) -> f64 {
+        libm::atan(self)
+    }
+    fn atan2(self, other: f64) -> f64 {
+        libm::atan2(self, other)
+    }
+    fn sin_cos(self) -> (f64, f64) {
+        libm::sincos(self)
+    }
+    fn hypot(self, other: f64) -> f64 {
+        libm::hypot(self, other)
+    }
+    fn powi(self, n: i32) -> f64 {
+        libm::pow(self, f64::from(n))
+    }
+    fn powf(self, n: f64) -> f64 {
+        libm::pow(self, n)
+    }
+    fn round(self) -> f64 {
+        libm::round(self)
+    }
+    fn ceil(self) -> f64 {
+        libm::ceil(self)
+    }
+    fn floor(self) -> f64 {
+        libm::floor(self)
+    }
+}
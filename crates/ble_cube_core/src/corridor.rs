@@ -0,0 +1,232 @@
+//! Corridor (buffered polyline) queries for route coverage.
+//!
+//! Each path segment contributes a candidate envelope to the R-tree search;
+//! candidates are then kept if they lie within the buffer distance of the
+//! segment. WGS84 segments are great-circle arcs on the same sphere as
+//! Haversine, so long legs bulge poleward exactly as a geodesic path does.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::crs::CoordinateSystem;
+use crate::geo::{haversine_distance, EARTH_RADIUS_M};
+use rstar::{Envelope, AABB};
+
+type Vec3 = [f64; 3];
+
+impl BleCube {
+    /// Observations within `width_m` meters of a path given as
+    /// `[(lat, lon), ...]`, i.e. inside a corridor `2 * width_m` wide
+    /// centered on it. Results are in record ID order, each at most once.
+    ///
+    /// A single vertex degenerates to a radius query; an empty path or a
+    /// negative width matches nothing.
+    pub fn query_geo_corridor(
+        &self,
+        polyline: &[(f64, f64)],
+        width_m: f64,
+    ) -> Vec<&BleObservation> {
+        self.corridor_ids(polyline, width_m)
+            .into_iter()
+            .map(|record_id| &self.records[record_id])
+            .collect()
+    }
+
+    pub(crate) fn corridor_ids(&self, polyline: &[(f64, f64)], width_m: f64) -> Vec<usize> {
+        if polyline.is_empty() || width_m.is_nan() || width_m < 0.0 {
+            return Vec::new();
+        }
+        let segments: Vec<((f64, f64), (f64, f64))> = if polyline.len() == 1 {
+            vec![(polyline[0], polyline[0])]
+        } else {
+            polyline.windows(2).map(|w| (w[0], w[1])).collect()
+        };
+
+        let mut ids = Vec::new();
+        for (a, b) in segments {
+            let envelope = self.segment_envelope(a, b, width_m);
+            ids.extend(
+                self.locate_in_envelope(envelope)
+                    .filter(|point| {
+                        self.segment_distance((point.coords[0], point.coords[1]), a, b) <= width_m
+                    })
+                    .map(|point| point.record_id),
+            );
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Distance in meters from `p` to the segment a-b
+    fn segment_distance(&self, p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+        match self.crs {
+            CoordinateSystem::Wgs84 => great_circle_segment_distance(p, a, b),
+            CoordinateSystem::Projected => planar_segment_distance(p, a, b),
+        }
+    }
+
+    /// Envelope enclosing every point within `width_m` of segment a-b
+    fn segment_envelope(&self, a: (f64, f64), b: (f64, f64), width_m: f64) -> AABB<[f64; 2]> {
+        let around = |(lat, lon): (f64, f64)| self.crs.radius_envelope(lat, lon, width_m);
+        let mut envelope = around(a).merged(&around(b));
+
+        if self.crs == CoordinateSystem::Wgs84 {
+            // The arc can reach further north or south than either endpoint
+            let (va, vb) = (to_vec(a), to_vec(b));
+            let n = cross(va, vb);
+            if let Some(vertex) = normalize([-n[0] * n[2], -n[1] * n[2], n[0] * n[0] + n[1] * n[1]])
+            {
+                for v in [vertex, scale(vertex, -1.0)] {
+                    if on_arc(v, va, vb, n) {
+                        let lat = v[2].clamp(-1.0, 1.0).asin().to_degrees();
+                        let lon = v[1].atan2(v[0]).to_degrees();
+                        envelope.merge(&around((lat, lon)));
+                    }
+                }
+            }
+        }
+        envelope
+    }
+}
+
+/// Meters from `p` to the minor great-circle arc a-b
+fn great_circle_segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let endpoints =
+        || haversine_distance(p.0, p.1, a.0, a.1).min(haversine_distance(p.0, p.1, b.0, b.1));
+
+    let (vp, va, vb) = (to_vec(p), to_vec(a), to_vec(b));
+    let Some(n) = normalize(cross(va, vb)) else {
+        // Coincident (or antipodal) endpoints: no unique arc
+        return endpoints();
+    };
+    // Foot of the perpendicular from p onto the great circle
+    let sin_xt = dot(vp, n);
+    let Some(foot) = normalize(sub(vp, scale(n, sin_xt))) else {
+        return endpoints();
+    };
+    if on_arc(foot, va, vb, n) {
+        EARTH_RADIUS_M * sin_xt.abs().min(1.0).asin()
+    } else {
+        endpoints()
+    }
+}
+
+/// Euclidean distance from `p` to segment a-b in projected meters
+fn planar_segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dy, dx) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dy * dy + dx * dx;
+    let t = if len_sq == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dy + (p.1 - a.1) * dx) / len_sq).clamp(0.0, 1.0)
+    };
+    (p.0 - (a.0 + t * dy)).hypot(p.1 - (a.1 + t * dx))
+}
+
+/// Whether `v` (on the great circle with normal `n`) lies between a and b
+fn on_arc(v: Vec3, a: Vec3, b: Vec3, n: Vec3) -> bool {
+    dot(cross(a, v), n) >= 0.0 && dot(cross(v, b), n) >= 0.0
+}
+
+fn to_vec((lat, lon): (f64, f64)) -> Vec3 {
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_lon, cos_lon) = lon.to_radians().sin_cos();
+    [cos_lat * cos_lon, cos_lat * sin_lon, sin_lat]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: Vec3, k: f64) -> Vec3 {
+    [a[0] * k, a[1] * k, a[2] * k]
+}
+
+/// Unit vector along `a`, `None` if it is (numerically) zero
+fn normalize(a: Vec3) -> Option<Vec3> {
+    let len = dot(a, a).sqrt();
+    (len > 1e-15).then(|| scale(a, 1.0 / len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corridor_follows_path() {
+        let mut cube = BleCube::new();
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        // L-shaped road: east along y = 0 to x = 100, then north to y = 100
+        let road = [(0.0, 0.0), (0.0, 100.0), (100.0, 100.0)];
+        for (y, x) in [
+            (4.0, 50.0),    // beside the first leg
+            (50.0, 96.0),   // beside the second leg
+            (-3.0, -3.0),   // 4.24 m off the start vertex
+            (50.0, 50.0),   // inside the bend, far from both legs
+            (104.0, 100.0), // just past the end
+            (0.0, 100.0),   // on the corner, near both legs
+        ] {
+            cube.insert(BleObservation {
+                lat: y,
+                lon: x,
+                ..Default::default()
+            });
+        }
+
+        let ids = cube.corridor_ids(&road, 5.0);
+        assert_eq!(ids, vec![0, 1, 2, 4, 5]);
+        assert_eq!(cube.corridor_ids(&road, 4.0), vec![0, 1, 4, 5]);
+        assert_eq!(cube.corridor_ids(&road[..1], 5.0), vec![2]);
+        assert!(cube.corridor_ids(&[], 5.0).is_empty());
+        assert_eq!(cube.query_geo_corridor(&road, 5.0).len(), 5);
+    }
+
+    #[test]
+    fn test_great_circle_corridor() {
+        let mut cube = BleCube::new();
+        // ~111 m north and south of an east-west leg along the equator
+        cube.insert(BleObservation {
+            lat: 0.001,
+            lon: 0.5,
+            ..Default::default()
+        });
+        cube.insert(BleObservation {
+            lat: -0.001,
+            lon: 1.0,
+            ..Default::default()
+        });
+        // Beyond the end of the leg
+        cube.insert(BleObservation {
+            lat: 0.0,
+            lon: 1.01,
+            ..Default::default()
+        });
+        let leg = [(0.0, 0.0), (0.0, 1.0)];
+        assert_eq!(cube.corridor_ids(&leg, 120.0), vec![0, 1]);
+        assert!(cube.corridor_ids(&leg, 100.0).is_empty());
+
+        // A transatlantic great circle peaks well north of both endpoints
+        // (~53.7N at ~22.9W); a point near that peak must still be found
+        let mut cube = BleCube::new();
+        cube.insert(BleObservation {
+            lat: 53.6,
+            lon: -22.9,
+            ..Default::default()
+        });
+        let route = [(40.6, -73.8), (51.5, -0.5)];
+        assert_eq!(cube.corridor_ids(&route, 50_000.0), vec![0]);
+        assert!(cube.corridor_ids(&route, 1_000.0).is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+//! Coordinate reference systems.
+//!
+//! By default coordinates are WGS84 degrees and distances are geodesic. A
+//! cube can instead store projected, meter-based coordinates (UTM, a local
+//! east-north-up frame, a floorplan) where distances are Euclidean.
+
+use crate::ble_cube::{BleCube, DistanceMetric, GeoPoint};
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use crate::compat::F64Ext;
+use crate::geo::radius_envelope;
+use rstar::{Envelope, AABB};
+
+/// How `BleObservation::lat` / `lon` are interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CoordinateSystem {
+    /// `lat` / `lon` are WGS84 degrees
+    #[default]
+    Wgs84,
+    /// `lat` holds the northing (y) and `lon` the easting (x), both in
+    /// meters, in any planar projection (UTM zone, local ENU, floorplan)
+    Projected,
+}
+
+impl CoordinateSystem {
+    /// Distance in meters between two stored positions, (lat, lon) order.
+    /// Geographic coordinates use Haversine.
+    pub fn distance(self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        self.distance_with(DistanceMetric::Haversine, lat1, lon1, lat2, lon2)
+    }
+
+    /// Like [`CoordinateSystem::distance`] with an explicit metric for
+    /// geographic coordinates; projected coordinates are always Euclidean
+    pub fn distance_with(
+        self,
+        metric: DistanceMetric,
+        lat1: f64,
+        lon1: f64,
+        lat2: f64,
+        lon2: f64,
+    ) -> f64 {
+        match self {
+            CoordinateSystem::Wgs84 => metric.distance(lat1, lon1, lat2, lon2),
+            CoordinateSystem::Projected => (lat2 - lat1).hypot(lon2 - lon1),
+        }
+    }
+
+    /// Candidate envelope enclosing every point within `radius_m` of (lat, lon)
+    #[doc(hidden)]
+    pub fn radius_envelope(self, lat: f64, lon: f64, radius_m: f64) -> AABB<[f64; 2]> {
+        match self {
+            CoordinateSystem::Wgs84 => radius_envelope(lat, lon, radius_m),
+            CoordinateSystem::Projected => AABB::from_corners(
+                [lat - radius_m, lon - radius_m],
+                [lat + radius_m, lon + radius_m],
+            ),
+        }
+    }
+
+    /// Candidate envelope for the box between two corners. A WGS84 box whose
+    /// `min_lon` is east of its `max_lon` crosses the antimeridian.
+    #[doc(hidden)]
+    pub fn bbox_envelope(
+        self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> AABB<[f64; 2]> {
+        let max_lon = if self == CoordinateSystem::Wgs84 && min_lon > max_lon {
+            max_lon + 360.0
+        } else {
+            max_lon
+        };
+        AABB::from_corners([min_lat, min_lon], [max_lat, max_lon])
+    }
+
+    /// Pieces of `envelope` to look up in the geo index. A WGS84 envelope
+    /// running past ±180° longitude also covers its image on the other side
+    /// of the antimeridian; one spanning 360° or more covers every longitude.
+    pub(crate) fn wrap_envelope(
+        self,
+        envelope: AABB<[f64; 2]>,
+    ) -> impl Iterator<Item = AABB<[f64; 2]>> {
+        let [min_lat, min_lon] = envelope.lower();
+        let [max_lat, max_lon] = envelope.upper();
+        let span = |min_lon: f64, max_lon: f64| {
+            Some(AABB::from_corners([min_lat, min_lon], [max_lat, max_lon]))
+        };
+        let parts = match self {
+            CoordinateSystem::Wgs84 if max_lon - min_lon >= 360.0 => {
+                [span(min_lon.min(-180.0), max_lon.max(180.0)), None]
+            }
+            CoordinateSystem::Wgs84 if min_lon < -180.0 => {
+                [Some(envelope), span(min_lon + 360.0, 180.0)]
+            }
+            CoordinateSystem::Wgs84 if max_lon > 180.0 => {
+                [Some(envelope), span(-180.0, max_lon - 360.0)]
+            }
+            _ => [Some(envelope), None],
+        };
+        parts.into_iter().flatten()
+    }
+
+    /// Whether (lat, lon) lies in `envelope`, across the antimeridian
+    pub(crate) fn envelope_contains(self, envelope: &AABB<[f64; 2]>, lat: f64, lon: f64) -> bool {
+        self.wrap_envelope(*envelope)
+            .any(|part| part.contains_point(&[lat, lon]))
+    }
+}
+
+impl BleCube {
+    /// Geo index points inside `envelope`, across the antimeridian. The
+    /// pieces of a wrapped envelope are disjoint, so no point repeats.
+    /// Without a geo index every record is checked instead.
+    #[doc(hidden)]
+    pub fn locate_in_envelope(
+        &self,
+        envelope: AABB<[f64; 2]>,
+    ) -> impl Iterator<Item = GeoPoint> + '_ {
+        let indexed = self.indexed.geo.then(|| {
+            self.crs
+                .wrap_envelope(envelope)
+                .flat_map(move |part| self.geo_index.locate_in_envelope(&part).copied())
+        });
+        let scanned = (!self.indexed.geo).then(|| {
+            (0..)
+                .zip(self.records.lats().iter().zip(self.records.lons()))
+                .filter(move |&(_, (&lat, &lon))| self.crs.envelope_contains(&envelope, lat, lon))
+                .map(|(record_id, (&lat, &lon))| GeoPoint {
+                    coords: [lat, lon],
+                    record_id,
+                })
+        });
+        indexed
+            .into_iter()
+            .flatten()
+            .chain(scanned.into_iter().flatten())
+    }
+
+    /// Declare how stored coordinates are interpreted. Radius queries, circle
+    /// zones, dwell/anomaly analytics and query filters all follow it.
+    /// Registered zones are re-evaluated; stored records are not converted.
+    pub fn set_coordinate_system(&mut self, crs: CoordinateSystem) {
+        if self.crs == crs {
+            return;
+        }
+        self.crs = crs;
+        self.reindex_zones();
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+    }
+
+    /// Coordinate system stored positions are interpreted in
+    pub fn coordinate_system(&self) -> CoordinateSystem {
+        self.crs
+    }
+
+    /// Choose the distance formula for WGS84 coordinates: Haversine (the
+    /// default, a spherical Earth), Vincenty (the WGS84 ellipsoid, accurate
+    /// to well under a millimeter) or the planar approximation. Radius
+    /// queries, circle zones and distance analytics all follow it;
+    /// registered zones are re-evaluated. Ignored in a projected CRS.
+    pub fn set_distance_metric(&mut self, metric: DistanceMetric) {
+        if self.distance_metric == metric {
+            return;
+        }
+        self.distance_metric = metric;
+        self.reindex_zones();
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+    }
+
+    /// Distance formula used for WGS84 coordinates
+    pub fn distance_metric(&self) -> DistanceMetric {
+        self.distance_metric
+    }
+
+    /// Distance in meters between two stored positions under the cube's
+    /// coordinate system and distance metric
+    pub fn distance(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        self.crs
+            .distance_with(self.distance_metric, lat1, lon1, lat2, lon2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+    use crate::compat::prelude::*;
+    use crate::query::Query;
+    use crate::zone::Zone;
+    use proptest::prelude::*;
+
+    /// A position near the antimeridian, near a pole, or anywhere
+    fn position() -> impl Strategy<Value = (f64, f64)> {
+        prop_oneof![
+            (-60.0..60.0, 175.0..180.0, any::<bool>()).prop_map(
+                |(lat, lon, west): (f64, f64, bool)| (lat, if west { -lon } else { lon })
+            ),
+            (87.0..90.0, any::<bool>(), -180.0..180.0).prop_map(
+                |(lat, south, lon): (f64, bool, f64)| (if south { -lat } else { lat }, lon)
+            ),
+            (-90.0..90.0, -180.0..180.0),
+        ]
+    }
+
+    /// Observations at random positions, timestamped by index
+    fn records() -> impl Strategy<Value = Vec<BleObservation>> {
+        prop::collection::vec(position(), 1..300).prop_map(|positions| {
+            (0..)
+                .zip(positions)
+                .map(|(timestamp, (lat, lon))| BleObservation {
+                    timestamp,
+                    lat,
+                    lon,
+                    ..Default::default()
+                })
+                .collect()
+        })
+    }
+
+    /// Sorted timestamps (one per record) of the observations
+    fn stamps<'a>(observations: impl IntoIterator<Item = &'a BleObservation>) -> Vec<i64> {
+        let mut stamps: Vec<i64> = observations.into_iter().map(|obs| obs.timestamp).collect();
+        stamps.sort_unstable();
+        stamps
+    }
+
+    fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        DistanceMetric::Haversine.distance(lat1, lon1, lat2, lon2)
+    }
+
+    // Property tests: every index query must match a brute-force scan
+    proptest! {
+        #[test]
+        fn test_radius_queries_match_scan_near_antimeridian_and_poles(
+            records in records(),
+            (lat, lon) in position(),
+            radius_m in 1_000.0..800_000.0,
+        ) {
+            let mut cube = BleCube::bulk_load(records.clone());
+            for metric in [
+                DistanceMetric::Haversine,
+                DistanceMetric::Vincenty,
+                DistanceMetric::Planar,
+            ] {
+                let scan = stamps(
+                    records
+                        .iter()
+                        .filter(|obs| metric.distance(lat, lon, obs.lat, obs.lon) <= radius_m),
+                );
+                let found = cube.query_geo_radius_with(lat, lon, radius_m, metric);
+                prop_assert_eq!(stamps(found), scan, "{:?}", metric);
+            }
+
+            let scan = stamps(
+                records
+                    .iter()
+                    .filter(|obs| haversine(lat, lon, obs.lat, obs.lon) <= radius_m),
+            );
+            let ids = cube.execute_ids(&Query::new().within_radius(lat, lon, radius_m));
+            prop_assert_eq!(ids.iter().map(|&id| id as i64).collect::<Vec<_>>(), scan.clone());
+            cube.add_zone("circle", Zone::circle(lat, lon, radius_m));
+            prop_assert_eq!(stamps(cube.query_zone("circle", None)), scan);
+        }
+
+        #[test]
+        fn test_bbox_queries_match_scan_across_antimeridian(
+            records in records(),
+            (lat_a, lat_b) in (-90.0..90.0f64, -90.0..90.0f64),
+            min_lon in 150.0..180.0,
+            max_lon in -180.0..-150.0,
+        ) {
+            let cube = BleCube::bulk_load(records.clone());
+            let (min_lat, max_lat) = (lat_a.min(lat_b), lat_a.max(lat_b));
+            let scan = stamps(records.iter().filter(|obs| {
+                (min_lat..=max_lat).contains(&obs.lat)
+                    && (obs.lon >= min_lon || obs.lon <= max_lon)
+            }));
+            prop_assert_eq!(
+                stamps(cube.query_geo_bbox(min_lat, min_lon, max_lat, max_lon)),
+                scan
+            );
+        }
+    }
+
+    #[test]
+    fn test_queries_wrap_at_antimeridian() {
+        let mut cube = BleCube::new();
+        let positions = [
+            (0.0, 179.999),
+            (0.0, -179.999),
+            (0.0, 0.0),
+            (89.5, 0.0),
+            (89.5, 180.0),
+        ];
+        for (timestamp, (lat, lon)) in (0..).zip(positions) {
+            cube.insert(BleObservation {
+                timestamp,
+                lat,
+                lon,
+                ..Default::default()
+            });
+        }
+
+        // Both sides of the date line are about 111 m from (0, 180)
+        assert_eq!(stamps(cube.query_geo_radius(0.0, 180.0, 200.0)), vec![0, 1]);
+        assert_eq!(
+            stamps(cube.query_geo_radius(0.0, -180.0, 200.0)),
+            vec![0, 1]
+        );
+        assert_eq!(
+            stamps(cube.query_geo_bbox(-1.0, 179.0, 1.0, -179.0)),
+            vec![0, 1]
+        );
+        assert_eq!(
+            stamps(cube.query_geo_bbox(-1.0, -179.0, 1.0, 179.0)),
+            vec![2]
+        );
+        // Everything within 100 km of the pole, whatever the longitude
+        assert_eq!(
+            stamps(cube.query_geo_radius(90.0, 0.0, 100_000.0)),
+            vec![3, 4]
+        );
+
+        // Projected coordinates never wrap: swapped corners are normalized
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        assert_eq!(
+            stamps(cube.query_geo_bbox(-1.0, 179.0, 1.0, -179.0)),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_projected_floorplan_coordinates() {
+        let mut cube = BleCube::new();
+        cube.set_coordinate_system(CoordinateSystem::Projected);
+        cube.add_zone("desk", Zone::circle(10.0, 10.0, 2.0));
+
+        // Positions in meters on a floorplan: (y, x)
+        for (y, x) in [(10.0, 10.0), (11.0, 11.0), (13.0, 14.0), (40.0, 40.0)] {
+            cube.insert(BleObservation {
+                lat: y,
+                lon: x,
+                ..Default::default()
+            });
+        }
+
+        // (13, 14) is exactly 5 m from (10, 10)
+        assert_eq!(cube.query_geo_radius(10.0, 10.0, 5.0).len(), 3);
+        assert_eq!(cube.query_geo_radius(10.0, 10.0, 4.9).len(), 2);
+        assert_eq!(
+            cube.execute_ids(&Query::new().within_radius(10.0, 10.0, 2.0)),
+            vec![0, 1]
+        );
+        assert_eq!(cube.query_zone("desk", None).len(), 2);
+
+        // Switching back re-evaluates zones with geodesic distances: 2 m is
+        // far less than a degree, so only the exact center stays inside
+        cube.set_coordinate_system(CoordinateSystem::Wgs84);
+        assert_eq!(cube.query_zone("desk", None).len(), 1);
+    }
+
+    #[test]
+    fn test_distance_metric_applies_cube_wide() {
+        // One degree of latitude at the equator: 111.19 km on the Haversine
+        // sphere, 110.57 km on the WGS84 ellipsoid
+        let mut cube = BleCube::bulk_load(vec![BleObservation {
+            lat: 1.0,
+            lon: 0.0,
+            ..Default::default()
+        }]);
+        cube.add_zone("ring", Zone::circle(0.0, 0.0, 110_800.0));
+        let query = Query::new().within_radius(0.0, 0.0, 110_800.0);
+        assert!(cube.query_geo_radius(0.0, 0.0, 110_800.0).is_empty());
+        assert!(cube.execute(&query).is_empty());
+
+        cube.set_distance_metric(DistanceMetric::Vincenty);
+        assert_eq!(cube.distance_metric(), DistanceMetric::Vincenty);
+        assert_eq!(cube.query_geo_radius(0.0, 0.0, 110_800.0).len(), 1);
+        assert_eq!(cube.execute(&query).len(), 1);
+        assert_eq!(cube.query_zone("ring", None).len(), 1);
+        cube.insert(BleObservation {
+            lat: -1.0,
+            timestamp: 1,
+            ..Default::default()
+        });
+        assert_eq!(cube.query_zone("ring", None).len(), 2);
+    }
+}
@@ -0,0 +1,246 @@
+//! Federated queries over several cubes.
+//!
+//! Deployments that shard observations (one cube per sensor, per day, per
+//! site) keep each shard as its own [`BleCube`] and register them in a
+//! [`CubeSet`], which fans a [`Query`] out to every member and merges the
+//! results into one timestamp-ordered list, optionally dropping the
+//! duplicates overlapping sensors produce.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::query::{distinct_positions, Query};
+use crate::sample::Reservoir;
+use alloc::collections::BTreeSet;
+
+/// Named cubes queried as one, see the [module docs](self)
+#[derive(Default)]
+pub struct CubeSet {
+    // Registration order, which breaks ties when merging
+    cubes: Vec<(String, BleCube)>,
+}
+
+impl CubeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `cube` under `name`, returning the cube it replaces
+    pub fn add(&mut self, name: &str, cube: BleCube) -> Option<BleCube> {
+        match self.cubes.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, slot)) => Some(core::mem::replace(slot, cube)),
+            None => {
+                self.cubes.push((name.to_string(), cube));
+                None
+            }
+        }
+    }
+
+    /// Unregister and return the cube named `name`
+    pub fn remove(&mut self, name: &str) -> Option<BleCube> {
+        let position = self
+            .cubes
+            .iter()
+            .position(|(existing, _)| existing == name)?;
+        Some(self.cubes.remove(position).1)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BleCube> {
+        self.cubes
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, cube)| cube)
+    }
+
+    /// Mutable access to a member, e.g. to insert into it
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut BleCube> {
+        self.cubes
+            .iter_mut()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, cube)| cube)
+    }
+
+    /// Names and cubes in registration order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &BleCube)> {
+        self.cubes.iter().map(|(name, cube)| (name.as_str(), cube))
+    }
+
+    /// Number of member cubes
+    pub fn len(&self) -> usize {
+        self.cubes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cubes.is_empty()
+    }
+
+    /// Observations across all members
+    pub fn total_records(&self) -> usize {
+        self.cubes.iter().map(|(_, cube)| cube.len()).sum()
+    }
+
+    /// Run `query` on every member, returning the matches ascending by
+    /// timestamp (ties in registration order). A sampled or per-device
+    /// ([`Query::distinct_by_mac`]) query draws its sample or picks each
+    /// device's match from the merged matches, not per cube.
+    pub fn execute(&self, query: &Query) -> Vec<&BleObservation> {
+        self.execute_with_source(query)
+            .into_iter()
+            .map(|(_, obs)| obs)
+            .collect()
+    }
+
+    /// [`CubeSet::execute`], dropping all but the first of the
+    /// observations that share a (mac, timestamp), such as the same
+    /// advertisement heard by two sensors with their own cubes
+    pub fn execute_deduplicated(&self, query: &Query) -> Vec<&BleObservation> {
+        let mut seen = BTreeSet::new();
+        self.execute(query)
+            .into_iter()
+            .filter(|obs| seen.insert((obs.mac, obs.timestamp)))
+            .collect()
+    }
+
+    /// [`CubeSet::execute`], pairing each match with the name of the cube
+    /// it came from
+    pub fn execute_with_source(&self, query: &Query) -> Vec<(&str, &BleObservation)> {
+        let mut merged = self.fan_out(query);
+        merged.sort_by_key(|(_, obs)| obs.timestamp);
+        if let Some(keep) = query.distinct {
+            let positions = distinct_positions(keep, merged.iter().map(|&(_, obs)| obs));
+            merged = positions.into_iter().map(|index| merged[index]).collect();
+        }
+        if let Some((n, seed)) = query.sample {
+            let mut reservoir = Reservoir::new(n, seed);
+            (0..merged.len()).for_each(|index| reservoir.offer(index));
+            let keep = reservoir.into_sorted();
+            merged = keep.into_iter().map(|index| merged[index]).collect();
+        }
+        merged
+    }
+
+    /// Number of matches of `query` across all members (duplicates counted)
+    pub fn count(&self, query: &Query) -> usize {
+        let matches = self.fan_out(query);
+        let total = match query.distinct {
+            Some(keep) => distinct_positions(keep, matches.iter().map(|&(_, obs)| obs)).len(),
+            None => matches.len(),
+        };
+        query.sample.map_or(total, |(n, _)| total.min(n))
+    }
+
+    /// Every member's matches in registration order, ignoring sampling
+    fn fan_out(&self, query: &Query) -> Vec<(&str, &BleObservation)> {
+        let unsampled;
+        let query = match query.sample {
+            Some(_) => {
+                unsampled = Query {
+                    sample: None,
+                    ..query.clone()
+                };
+                &unsampled
+            }
+            None => query,
+        };
+        self.cubes
+            .iter()
+            .flat_map(|(name, cube)| {
+                cube.execute(query)
+                    .into_iter()
+                    .map(move |obs| (name.as_str(), obs))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(mac: u8, timestamp: i64, rssi: i8) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fan_out_merges_and_deduplicates() {
+        let mut set = CubeSet::new();
+        let mut north = BleCube::new();
+        north.insert(obs(1, 300, -60));
+        north.insert(obs(2, 100, -70));
+        let mut south = BleCube::new();
+        south.insert(obs(1, 200, -50));
+        // Same advertisement heard by both sensors
+        south.insert(obs(2, 100, -65));
+        assert!(set.add("north", north).is_none());
+        assert!(set.add("south", south).is_none());
+        assert_eq!((set.len(), set.total_records()), (2, 4));
+
+        let all = set.execute_with_source(&Query::new());
+        let merged: Vec<(&str, i64)> = all
+            .iter()
+            .map(|(name, obs)| (*name, obs.timestamp))
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                ("north", 100),
+                ("south", 100),
+                ("south", 200),
+                ("north", 300)
+            ]
+        );
+        let unique = set.execute_deduplicated(&Query::new());
+        assert_eq!(unique.len(), 3);
+        assert_eq!(unique[0].rssi, -70);
+
+        // Each device's strongest sighting across both members
+        let devices = Query::new().distinct_by_mac(crate::DistinctKeep::Strongest);
+        let strongest: Vec<(i64, i8)> = set
+            .execute(&devices)
+            .iter()
+            .map(|obs| (obs.timestamp, obs.rssi))
+            .collect();
+        assert_eq!(strongest, vec![(100, -65), (200, -50)]);
+        assert_eq!(set.count(&devices), 2);
+
+        let query = Query::new().mac([1; 6]).rssi_between(-55, -40);
+        assert_eq!(set.count(&query), 1);
+        assert_eq!(set.execute(&query)[0].timestamp, 200);
+
+        // Members stay writable and replaceable
+        set.get_mut("north").unwrap().insert(obs(3, 50, -80));
+        assert_eq!(set.execute(&Query::new())[0].mac, [3; 6]);
+        assert_eq!(set.add("north", BleCube::new()).unwrap().len(), 3);
+        assert_eq!(set.remove("south").unwrap().len(), 2);
+        assert!(set.execute(&Query::new()).is_empty());
+        assert!(set.get("south").is_none());
+    }
+
+    #[test]
+    fn test_sample_spans_members() {
+        let mut set = CubeSet::new();
+        for day in 0..4 {
+            let mut cube = BleCube::new();
+            for t in 0..25 {
+                cube.insert(obs(day, day as i64 * 86_400 + t, -60));
+            }
+            set.add(&format!("day-{day}"), cube);
+        }
+        let stamps = |query: &Query| -> Vec<i64> {
+            set.execute(query).iter().map(|obs| obs.timestamp).collect()
+        };
+        let sampled = stamps(&Query::new().sample(10, 3));
+        assert_eq!(sampled.len(), 10);
+        assert!(sampled.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(sampled, stamps(&Query::new().sample(10, 3)));
+        // Drawn from the merged matches, not 10 per cube
+        assert_eq!(set.execute(&Query::new().sample(200, 3)).len(), 100);
+        assert_eq!(set.count(&Query::new().sample(30, 3)), 30);
+    }
+}
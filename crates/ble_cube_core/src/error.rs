@@ -0,0 +1,267 @@
+//! The crate-wide error type.
+//!
+//! Fallible cube operations (`try_insert`, `try_upsert_by_key`,
+//! `try_insert_advertisement`, `try_apply_delta`) return [`CubeError`], and
+//! every narrower error the crate defines converts into it, so one `?` covers
+//! parsing a MAC or a query string, decoding a delta or an HCI event, running
+//! an interruptible query and inserting the results. The enum is
+//! `#[non_exhaustive]`: match the variants you handle and keep a `_` arm.
+//!
+//! Whole-file operations (write-ahead log recovery, JSONL import, cold
+//! segments) keep returning `std::io::Result`; `CubeError` converts into
+//! `io::Error` with a matching [`io::ErrorKind`] where the two meet.
+
+use crate::cancel::QueryInterrupted;
+use crate::hci::HciError;
+use crate::mac::MacParseError;
+use crate::query_str::QueryParseError;
+use crate::replication::DeltaDecodeError;
+use crate::time::TimeUnit;
+use crate::validate::Violation;
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Why a cube operation failed
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CubeError {
+    /// A write-ahead log append, insert sink or other I/O failed
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// The declared time unit rejected a timestamp
+    /// ([`UnitMismatch::Reject`](crate::UnitMismatch::Reject))
+    TimeUnit {
+        timestamp: i64,
+        /// Unit the timestamp's magnitude points to
+        guessed: TimeUnit,
+        /// Unit the cube stores
+        unit: TimeUnit,
+    },
+    /// The validation policy refused an observation
+    Invalid {
+        /// First rule it failed (for [`ValidationPolicy::Clamp`], the first
+        /// one that cannot be clamped)
+        ///
+        /// [`ValidationPolicy::Clamp`]: crate::ValidationPolicy::Clamp
+        violation: Violation,
+        /// Kept in [`BleCube::quarantine`](crate::BleCube::quarantine)
+        quarantined: bool,
+    },
+    /// A rate limit suppressed the observation; nothing was stored
+    /// ([`BleCube::set_rate_limit`](crate::BleCube::set_rate_limit))
+    RateLimited,
+    /// Not a MAC address
+    Mac(MacParseError),
+    /// Not a query string
+    Query(QueryParseError),
+    /// Not a replication delta, or a corrupted one
+    Delta(DeltaDecodeError),
+    /// Not an advertising report event
+    Hci(HciError),
+    /// A query was cancelled or ran past its deadline
+    Interrupted(QueryInterrupted),
+}
+
+impl CubeError {
+    /// Whether the input itself was refused (time unit, validation, rate
+    /// limit or a parse error), as opposed to an I/O failure or an
+    /// interrupted query.
+    /// Retrying the same input will fail again.
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            CubeError::TimeUnit { .. }
+                | CubeError::Invalid { .. }
+                | CubeError::RateLimited
+                | CubeError::Mac(_)
+                | CubeError::Query(_)
+                | CubeError::Delta(_)
+                | CubeError::Hci(_)
+        )
+    }
+}
+
+impl fmt::Display for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            CubeError::Io(e) => e.fmt(f),
+            CubeError::TimeUnit {
+                timestamp,
+                guessed,
+                unit,
+            } => write!(
+                f,
+                "timestamp {timestamp} looks like {guessed:?}, cube stores {unit:?}"
+            ),
+            CubeError::Invalid {
+                violation,
+                quarantined,
+            } => {
+                write!(f, "observation failed validation: {violation}")?;
+                if *quarantined {
+                    f.write_str(" (quarantined)")?;
+                }
+                Ok(())
+            }
+            CubeError::RateLimited => f.write_str("observation over the rate limit"),
+            CubeError::Mac(e) => e.fmt(f),
+            CubeError::Query(e) => e.fmt(f),
+            CubeError::Delta(e) => e.fmt(f),
+            CubeError::Hci(e) => e.fmt(f),
+            CubeError::Interrupted(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for CubeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            CubeError::Io(e) => Some(e),
+            CubeError::TimeUnit { .. } | CubeError::Invalid { .. } | CubeError::RateLimited => {
+                None
+            }
+            CubeError::Mac(e) => Some(e),
+            CubeError::Query(e) => Some(e),
+            CubeError::Delta(e) => Some(e),
+            CubeError::Hci(e) => Some(e),
+            CubeError::Interrupted(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for CubeError {
+    fn from(e: io::Error) -> Self {
+        CubeError::Io(e)
+    }
+}
+
+impl From<MacParseError> for CubeError {
+    fn from(e: MacParseError) -> Self {
+        CubeError::Mac(e)
+    }
+}
+
+impl From<QueryParseError> for CubeError {
+    fn from(e: QueryParseError) -> Self {
+        CubeError::Query(e)
+    }
+}
+
+impl From<DeltaDecodeError> for CubeError {
+    fn from(e: DeltaDecodeError) -> Self {
+        CubeError::Delta(e)
+    }
+}
+
+impl From<HciError> for CubeError {
+    fn from(e: HciError) -> Self {
+        CubeError::Hci(e)
+    }
+}
+
+impl From<QueryInterrupted> for CubeError {
+    fn from(e: QueryInterrupted) -> Self {
+        CubeError::Interrupted(e)
+    }
+}
+
+/// For `?` inside functions returning `io::Result`: I/O errors pass through
+/// unchanged, time-unit rejections become `InvalidInput`, interrupted
+/// queries `TimedOut` or `Interrupted`, and everything else `InvalidData`.
+#[cfg(feature = "std")]
+impl From<CubeError> for io::Error {
+    fn from(e: CubeError) -> Self {
+        use crate::cancel::Interrupt;
+        let kind = match e {
+            CubeError::Io(e) => return e,
+            CubeError::TimeUnit { .. } => io::ErrorKind::InvalidInput,
+            CubeError::Interrupted(ref interrupted) => match interrupted.reason {
+                Interrupt::DeadlineExceeded => io::ErrorKind::TimedOut,
+                Interrupt::Cancelled => io::ErrorKind::Interrupted,
+            },
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::{BleCube, BleObservation};
+    use crate::compat::prelude::*;
+    use crate::mac::MacAddr;
+    use crate::time::UnitMismatch;
+    use crate::validate::{ValidationPolicy, ValidationRules};
+
+    #[test]
+    fn test_errors_convert_and_match() {
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Milliseconds, UnitMismatch::Reject);
+        let err = cube
+            .try_insert(BleObservation {
+                mac: [1; 6],
+                timestamp: 1_700_000_000,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CubeError::TimeUnit {
+                guessed: TimeUnit::Seconds,
+                unit: TimeUnit::Milliseconds,
+                ..
+            }
+        ));
+        assert!(err.is_rejected());
+
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Quarantine);
+        let err = cube
+            .try_insert(BleObservation {
+                timestamp: 1_700_000_000_000,
+                lat: 1.0,
+                lon: 1.0,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            CubeError::Invalid {
+                violation: Violation::ZeroMac,
+                quarantined: true
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "observation failed validation: all-zero MAC address (quarantined)"
+        );
+
+        let parse = |s: &str| -> Result<MacAddr, CubeError> { Ok(s.parse::<MacAddr>()?) };
+        let err = parse("not a mac").unwrap_err();
+        assert!(matches!(err, CubeError::Mac(_)));
+        assert!(err.source().is_some());
+        assert_eq!(cube.len(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_into_io_error() {
+        let rejected = CubeError::TimeUnit {
+            timestamp: 1,
+            guessed: TimeUnit::Seconds,
+            unit: TimeUnit::Nanoseconds,
+        };
+        assert_eq!(
+            io::Error::from(rejected).kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let io = io::Error::from(CubeError::Io(io::ErrorKind::BrokenPipe.into()));
+        assert_eq!(io.kind(), io::ErrorKind::BrokenPipe);
+        assert!(!CubeError::from(io).is_rejected());
+    }
+}
@@ -0,0 +1,337 @@
+//! Query plan introspection and index cardinality statistics.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::query::{Dimension, Query};
+use rstar::{Envelope, AABB};
+
+/// How a query was (or would be) executed
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryPlan {
+    /// Index that produced the candidate set; `None` means a full scan
+    pub driver: Option<Dimension>,
+    pub total_records: usize,
+    /// Record IDs pulled from the driving index
+    pub candidates: usize,
+    /// One stage per constrained dimension, in the order filters are applied
+    pub stages: Vec<PlanStage>,
+    /// Product of per-dimension estimates (assumes independent dimensions)
+    pub estimated_selectivity: f64,
+    /// Fraction of all records that matched
+    pub actual_selectivity: f64,
+    pub results: usize,
+}
+
+/// One filtering step of a [`QueryPlan`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlanStage {
+    pub dimension: Dimension,
+    /// Fraction of all records expected to pass this filter on its own,
+    /// estimated from index statistics
+    pub estimated_selectivity: f64,
+    /// Candidates still alive after this filter
+    pub remaining: usize,
+}
+
+/// Cardinality of one posting-list index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PostingStats {
+    /// Distinct keys
+    pub keys: usize,
+    /// Record IDs across all posting lists
+    pub entries: usize,
+    /// Length of the longest posting list
+    pub max_posting: usize,
+}
+
+impl PostingStats {
+    /// Fold over posting-list lengths
+    fn of<I: IntoIterator<Item = usize>>(lengths: I) -> Self {
+        lengths
+            .into_iter()
+            .fold(Self::default(), |stats, len| Self {
+                keys: stats.keys + 1,
+                entries: stats.entries + len,
+                max_posting: stats.max_posting.max(len),
+            })
+    }
+
+    /// Mean posting-list length (0 when empty)
+    pub fn avg_posting(&self) -> f64 {
+        if self.keys == 0 {
+            0.0
+        } else {
+            self.entries as f64 / self.keys as f64
+        }
+    }
+}
+
+/// Per-index cardinality snapshot, see [`BleCube::index_stats`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndexStats {
+    pub records: usize,
+    pub mac: PostingStats,
+    pub rssi: PostingStats,
+    pub time: PostingStats,
+    pub receiver: PostingStats,
+    pub floor: PostingStats,
+    pub path_loss: PostingStats,
+    pub quality: PostingStats,
+    pub geo_points: usize,
+    /// (min_lat, min_lon, max_lat, max_lon) of all points, `None` when empty
+    pub geo_bounds: Option<(f64, f64, f64, f64)>,
+    pub zones: usize,
+}
+
+impl BleCube {
+    /// Execute `query` while recording the driving index, how many
+    /// candidates survive each filter, and estimated vs. actual selectivity
+    pub fn explain(&self, query: &Query) -> QueryPlan {
+        let total_records = self.records.len();
+        let (driver, mut ids) = self.candidate_ids(query);
+        let candidates = ids.len();
+
+        let mut stages = Vec::new();
+        let mut estimated_selectivity = 1.0;
+        for dimension in Dimension::DRIVER_PRIORITY {
+            if !query.constrains(dimension) {
+                continue;
+            }
+            ids.retain(|&id| self.matches_dimension(query, dimension, id));
+            let estimate = self.estimate_selectivity(query, dimension);
+            estimated_selectivity *= estimate;
+            stages.push(PlanStage {
+                dimension,
+                estimated_selectivity: estimate,
+                remaining: ids.len(),
+            });
+        }
+
+        let results = ids.len();
+        QueryPlan {
+            driver,
+            total_records,
+            candidates,
+            stages,
+            estimated_selectivity,
+            actual_selectivity: fraction(results, total_records),
+            results,
+        }
+    }
+
+    /// Key/entry counts for every index
+    pub fn index_stats(&self) -> IndexStats {
+        IndexStats {
+            records: self.records.len(),
+            mac: PostingStats::of(self.mac_index.values().map(Vec::len)),
+            rssi: PostingStats::of(self.rssi_index.values().map(Vec::len)),
+            time: PostingStats::of(self.time_index.posting_lens()),
+            receiver: PostingStats::of(self.receiver_index.values().map(Vec::len)),
+            floor: PostingStats::of(self.floor_index.values().map(Vec::len)),
+            path_loss: PostingStats::of(self.path_loss_index.values().map(Vec::len)),
+            quality: PostingStats::of(self.quality_index.values().map(Vec::len)),
+            geo_points: self.geo_index.size(),
+            geo_bounds: self.geo_bounds().map(|env| {
+                let (lower, upper) = (env.lower(), env.upper());
+                (lower[0], lower[1], upper[0], upper[1])
+            }),
+            zones: self.zone_names().len(),
+        }
+    }
+
+    /// Fraction of records expected to pass the filter on `dimension`
+    fn estimate_selectivity(&self, query: &Query, dimension: Dimension) -> f64 {
+        let total = self.records.len();
+        let count = match dimension {
+            Dimension::Mac => query
+                .mac
+                .and_then(|mac| self.mac_index.get(&mac))
+                .map_or(0, Vec::len),
+            Dimension::Receiver => query
+                .receiver
+                .and_then(|receiver| self.receiver_index.get(&receiver))
+                .map_or(0, Vec::len),
+            Dimension::Floor => query
+                .floor
+                .and_then(|floor| self.floor_index.get(&floor))
+                .map_or(0, Vec::len),
+            Dimension::Zone => query
+                .zone
+                .as_deref()
+                .and_then(|zone| self.geofence.members(zone))
+                .map_or(0, <[usize]>::len),
+            Dimension::Tag => query
+                .tag
+                .as_deref()
+                .and_then(|tag| self.tags.members(tag))
+                .map_or(0, <[usize]>::len),
+            Dimension::Lifecycle => self
+                .seen_index
+                .macs_matching(query.new_since, query.not_seen_since)
+                .filter_map(|mac| self.mac_index.get(mac))
+                .map(Vec::len)
+                .sum(),
+            Dimension::Time => query
+                .time_range
+                .map_or(0, |(start, end)| self.time_index.range(start..=end).count()),
+            Dimension::Rssi if !self.indexed.rssi => query.rssi_range.map_or(0, |(min, max)| {
+                self.records
+                    .rssi()
+                    .iter()
+                    .filter(|rssi| (min..=max).contains(*rssi))
+                    .count()
+            }),
+            Dimension::PathLoss if !self.indexed.path_loss => {
+                query.path_loss_range.map_or(0, |(min, max)| {
+                    self.records
+                        .iter()
+                        .filter_map(BleObservation::path_loss)
+                        .filter(|path_loss| (min..=max).contains(path_loss))
+                        .count()
+                })
+            }
+            Dimension::Quality if !self.indexed.quality => query.min_quality.map_or(0, |min| {
+                self.records
+                    .iter()
+                    .filter(|obs| obs.quality.is_some_and(|quality| quality >= min))
+                    .count()
+            }),
+            Dimension::Rssi => query.rssi_range.map_or(0, |(min, max)| {
+                self.rssi_index
+                    .range(min..=max)
+                    .map(|(_, ids)| ids.len())
+                    .sum()
+            }),
+            Dimension::PathLoss => query.path_loss_range.map_or(0, |(min, max)| {
+                self.path_loss_index
+                    .range(min..=max)
+                    .map(|(_, ids)| ids.len())
+                    .sum()
+            }),
+            Dimension::Quality => query.min_quality.map_or(0, |min| {
+                self.quality_index
+                    .range(min..)
+                    .map(|(_, ids)| ids.len())
+                    .sum()
+            }),
+            Dimension::Geo => {
+                // Share of the data's bounding box covered by the search
+                // envelope, assuming points are spread uniformly
+                let (Some((lat, lon, radius_m)), Some(bounds)) =
+                    (query.geo_radius, self.geo_bounds())
+                else {
+                    return 0.0;
+                };
+                let search = self.crs.radius_envelope(lat, lon, radius_m);
+                let overlap: f64 = self
+                    .crs
+                    .wrap_envelope(search)
+                    .map(|part| bounds.intersection_area(&part))
+                    .sum();
+                let area = bounds.area();
+                return if area > 0.0 {
+                    (overlap / area).min(1.0)
+                } else if self
+                    .crs
+                    .wrap_envelope(search)
+                    .any(|part| bounds.intersects(&part))
+                {
+                    1.0
+                } else {
+                    0.0
+                };
+            }
+        };
+        fraction(count, total)
+    }
+
+    /// Bounding box of every point
+    fn geo_bounds(&self) -> Option<AABB<[f64; 2]>> {
+        if !self.indexed.geo {
+            return self
+                .records
+                .iter()
+                .map(|obs| AABB::from_point([obs.lat, obs.lon]))
+                .reduce(|bounds, point| bounds.merged(&point));
+        }
+        let root = self.geo_index.root();
+        if root.children().is_empty() {
+            return None;
+        }
+        Some(root.envelope())
+    }
+}
+
+fn fraction(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    #[test]
+    fn test_explain_reports_driver_and_stages() {
+        let mut cube = BleCube::new();
+        for i in 0..100u8 {
+            cube.insert(BleObservation {
+                rssi: -40 - (i % 50) as i8,
+                mac: [0, 0, 0, 0, 0, i % 10],
+                timestamp: i64::from(i),
+                lat: f64::from(i) * 0.001,
+                lon: 0.0,
+                receiver_id: Some(u16::from(i % 4)),
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+
+        let query = Query::new()
+            .mac([0, 0, 0, 0, 0, 3])
+            .time_between(0, 49)
+            .rssi_between(-60, -40);
+        let plan = cube.explain(&query);
+        assert_eq!(plan.driver, Some(Dimension::Mac));
+        assert_eq!(plan.candidates, 10);
+        let stages: Vec<(Dimension, usize)> = plan
+            .stages
+            .iter()
+            .map(|s| (s.dimension, s.remaining))
+            .collect();
+        // mac 3 at i = 3, 13, ..., 93; i < 50 keeps 5; rssi >= -60 keeps i % 50 <= 20
+        assert_eq!(
+            stages,
+            vec![
+                (Dimension::Mac, 10),
+                (Dimension::Time, 5),
+                (Dimension::Rssi, 2)
+            ]
+        );
+        assert_eq!(plan.results, cube.execute_ids(&query).len());
+        assert!((plan.estimated_selectivity - 0.1 * 0.5 * 0.42).abs() < 1e-9);
+        assert!((plan.actual_selectivity - 0.02).abs() < 1e-9);
+
+        let scan = cube.explain(&Query::new());
+        assert_eq!(scan.driver, None);
+        assert_eq!(scan.candidates, 100);
+        assert!(scan.stages.is_empty());
+
+        let stats = cube.index_stats();
+        assert_eq!(stats.records, 100);
+        assert_eq!(stats.mac.keys, 10);
+        assert_eq!(stats.mac.max_posting, 10);
+        assert_eq!(stats.receiver.entries, 100);
+        assert_eq!(stats.rssi.keys, 50);
+        assert_eq!(stats.geo_bounds, Some((0.0, 0.0, 0.099, 0.0)));
+    }
+}
@@ -0,0 +1,424 @@
+//! Standalone geodesy helpers on WGS84 (lat, lon) degrees.
+//!
+//! These are the functions the cube's own radius, polygon and corridor
+//! queries use, exposed so callers can compute distances, bearings and
+//! search boxes that agree with the cube to the last bit.
+//!
+//! Precision:
+//! - [`haversine_distance`], [`initial_bearing`] and [`destination_point`]
+//!   work on a sphere of radius [`EARTH_RADIUS_M`]. Against the WGS84
+//!   ellipsoid that is up to ~0.5% off (worst along meridians near the
+//!   poles), i.e. ~5 m per km.
+//! - [`vincenty_distance`] works on the ellipsoid and is accurate to well
+//!   under a millimeter; it needs a few iterations, and falls back to
+//!   Haversine for nearly antipodal points where the series does not
+//!   converge.
+//! - [`planar_distance`] (equirectangular) is the cheapest; it matches
+//!   Haversine to within a meter over a few kilometers away from the poles,
+//!   and degrades quickly beyond that.
+//! - [`point_in_polygon`] and [`point_in_rings`] treat edges as straight
+//!   lines in (lat, lon) space, which is exact for fences of a few kilometers but not for
+//!   continent-sized polygons or ones crossing the antimeridian.
+//!
+//! Longitudes may be given in any range; differences are taken the short
+//! way around, so points either side of the antimeridian are close.
+
+use rstar::AABB;
+
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use crate::compat::F64Ext;
+
+/// Mean Earth radius in meters (IUGG), the sphere behind the Haversine
+/// based helpers
+pub const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Shortest meridian degree on the WGS84 ellipsoid (at the equator), used so
+/// candidate envelopes never undershoot the true search radius
+const MIN_METERS_PER_DEGREE: f64 = 110_574.0;
+
+/// Great-circle distance in meters between (lat1, lon1) and (lat2, lon2)
+pub fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let lat1_rad = lat1.to_radians();
+    let lat2_rad = lat2.to_radians();
+    let delta_lat = (lat2 - lat1).to_radians();
+    let delta_lon = (lon2 - lon1).to_radians();
+
+    let a = (delta_lat / 2.0).sin().powi(2)
+        + lat1_rad.cos() * lat2_rad.cos() * (delta_lon / 2.0).sin().powi(2);
+    // Rounding can push `a` just past 1 for antipodal points
+    let a = a.clamp(0.0, 1.0);
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+
+    EARTH_RADIUS_M * c
+}
+
+/// Geodesic distance in meters on the WGS84 ellipsoid (Vincenty's inverse
+/// formula). Falls back to Haversine for near-antipodal points where it
+/// fails to converge.
+pub fn vincenty_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const A: f64 = 6378137.0;
+    const F: f64 = 1.0 / 298.257223563;
+    const B: f64 = A * (1.0 - F);
+
+    let l = lon_delta(lon1, lon2).to_radians();
+    let u1 = ((1.0 - F) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - F) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            return 0.0; // coincident points
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        let cos_2sigma_m = if cos_sq_alpha == 0.0 {
+            0.0 // equatorial line
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = F / 16.0 * cos_sq_alpha * (4.0 + F * (4.0 - 3.0 * cos_sq_alpha));
+
+        let lambda_prev = lambda;
+        lambda = l
+            + (1.0 - c)
+                * F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+
+        if (lambda - lambda_prev).abs() < 1e-12 {
+            let u_sq = cos_sq_alpha * (A * A - B * B) / (B * B);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            return B * big_a * (sigma - delta_sigma);
+        }
+    }
+
+    haversine_distance(lat1, lon1, lat2, lon2)
+}
+
+/// Equirectangular (flat-Earth) distance approximation in meters
+pub fn planar_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let mean_lat = ((lat1 + lat2) / 2.0).to_radians();
+    let x = lon_delta(lon1, lon2).to_radians() * mean_lat.cos();
+    let y = (lat2 - lat1).to_radians();
+
+    EARTH_RADIUS_M * (x * x + y * y).sqrt()
+}
+
+/// Initial great-circle bearing from (lat1, lon1) towards (lat2, lon2), in
+/// degrees clockwise from true north, in [0, 360). 0 for coincident points.
+pub fn initial_bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (sin_lat1, cos_lat1) = lat1.to_radians().sin_cos();
+    let (sin_lat2, cos_lat2) = lat2.to_radians().sin_cos();
+    let (sin_dlon, cos_dlon) = lon_delta(lon1, lon2).to_radians().sin_cos();
+
+    let y = sin_dlon * cos_lat2;
+    let x = cos_lat1 * sin_lat2 - sin_lat1 * cos_lat2 * cos_dlon;
+    normalize_degrees(y.atan2(x).to_degrees(), 360.0)
+}
+
+/// Point reached by travelling `distance_m` meters from (lat, lon) along a
+/// great circle starting at `bearing_deg` (clockwise from north). The
+/// returned longitude is normalized to [-180, 180).
+pub fn destination_point(lat: f64, lon: f64, bearing_deg: f64, distance_m: f64) -> (f64, f64) {
+    let angular = distance_m / EARTH_RADIUS_M;
+    let (sin_lat, cos_lat) = lat.to_radians().sin_cos();
+    let (sin_bearing, cos_bearing) = bearing_deg.to_radians().sin_cos();
+    let (sin_angular, cos_angular) = angular.sin_cos();
+
+    let sin_lat2 = (sin_lat * cos_angular + cos_lat * sin_angular * cos_bearing).clamp(-1.0, 1.0);
+    let lat2 = sin_lat2.asin();
+    let dlon = (sin_bearing * sin_angular * cos_lat).atan2(cos_angular - sin_lat * sin_lat2);
+    (
+        lat2.to_degrees(),
+        normalize_longitude(lon + dlon.to_degrees()),
+    )
+}
+
+/// (min_lat, min_lon, max_lat, max_lon) of a box containing every point
+/// within `radius_m` meters of (lat, lon), in the form
+/// [`BleCube::query_geo_bbox`](crate::BleCube::query_geo_bbox) takes: a box
+/// reaching past the antimeridian comes back with `min_lon > max_lon`, and
+/// one reaching a pole spans every longitude. Slightly larger than the
+/// circle (it is sized with the ellipsoid's shortest degree), never smaller.
+pub fn bbox_around(lat: f64, lon: f64, radius_m: f64) -> (f64, f64, f64, f64) {
+    let envelope = radius_envelope(lat, lon, radius_m);
+    let ([min_lat, min_lon], [max_lat, max_lon]) = (envelope.lower(), envelope.upper());
+    let (min_lat, max_lat) = (min_lat.max(-90.0), max_lat.min(90.0));
+    if max_lon - min_lon >= 360.0 {
+        return (min_lat, -180.0, max_lat, 180.0);
+    }
+    (
+        min_lat,
+        normalize_longitude(min_lon),
+        max_lat,
+        // Keep an east edge of exactly 180 rather than folding it to -180
+        -normalize_longitude(-max_lon),
+    )
+}
+
+/// Whether (lat, lon) lies inside `polygon`, given as `[(lat, lon), ...]`
+/// with the closing edge implied (ray casting). Polygons with fewer than
+/// three vertices contain nothing.
+pub fn point_in_polygon(lat: f64, lon: f64, polygon: &[(f64, f64)]) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (lat_i, lon_i) = polygon[i];
+        let (lat_j, lon_j) = polygon[j];
+
+        if ((lon_i > lon) != (lon_j > lon))
+            && (lat < (lat_j - lat_i) * (lon - lon_i) / (lon_j - lon_i) + lat_i)
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+/// Whether (lat, lon) lies inside the area bounded by `rings`, by the
+/// even-odd rule: inside if an odd number of rings contain it. An outer
+/// boundary listed with rings inside it therefore has those as holes, a ring
+/// inside a hole is an island again, and disjoint rings are the separate
+/// parts of a multi-polygon. Rings are given as for [`point_in_polygon`], in
+/// any winding order; degenerate rings (fewer than three distinct vertices,
+/// or all on one line) are ignored.
+pub fn point_in_rings<R: AsRef<[(f64, f64)]>>(lat: f64, lon: f64, rings: &[R]) -> bool {
+    rings
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|ring| !is_degenerate(ring) && point_in_polygon(lat, lon, ring))
+        .count()
+        % 2
+        == 1
+}
+
+/// Whether a ring encloses no area: fewer than three distinct vertices, or
+/// all of them on one line
+fn is_degenerate(ring: &[(f64, f64)]) -> bool {
+    let Some(&(lat0, lon0)) = ring.first() else {
+        return true;
+    };
+    let Some(&(lat1, lon1)) = ring.iter().find(|&&vertex| vertex != (lat0, lon0)) else {
+        return true;
+    };
+    let direction = (lat1 - lat0, lon1 - lon0);
+    ring.iter()
+        .all(|&(lat, lon)| (lat - lat0) * direction.1 - (lon - lon0) * direction.0 == 0.0)
+}
+
+/// Envelope of a set of (lat, lon) vertices
+pub(crate) fn vertices_envelope<'a, I>(vertices: I) -> AABB<[f64; 2]>
+where
+    I: IntoIterator<Item = &'a (f64, f64)>,
+{
+    let (min_lat, max_lat, min_lon, max_lon) = vertices.into_iter().fold(
+        (f64::MAX, f64::MIN, f64::MAX, f64::MIN),
+        |(min_lat, max_lat, min_lon, max_lon), &(lat, lon)| {
+            (
+                min_lat.min(lat),
+                max_lat.max(lat),
+                min_lon.min(lon),
+                max_lon.max(lon),
+            )
+        },
+    );
+    AABB::from_corners([min_lat, min_lon], [max_lat, max_lon])
+}
+
+/// `lon` wrapped into [-180, 180)
+pub fn normalize_longitude(lon: f64) -> f64 {
+    normalize_degrees(lon + 180.0, 360.0) - 180.0
+}
+
+/// `degrees` wrapped into [0, `full`)
+fn normalize_degrees(degrees: f64, full: f64) -> f64 {
+    let wrapped = degrees % full;
+    if wrapped < 0.0 {
+        // -1e-15 % 360 + 360 rounds to 360
+        (wrapped + full) % full
+    } else {
+        wrapped
+    }
+}
+
+/// Shortest signed longitude difference from `lon1` to `lon2`, in degrees,
+/// so points either side of the antimeridian are close
+pub fn lon_delta(lon1: f64, lon2: f64) -> f64 {
+    let delta = (lon2 - lon1) % 360.0;
+    if delta > 180.0 {
+        delta - 360.0
+    } else if delta < -180.0 {
+        delta + 360.0
+    } else {
+        delta
+    }
+}
+
+/// Candidate envelope for a radius query, with longitude scaled by cos(lat)
+/// at the most poleward latitude the circle reaches
+pub(crate) fn radius_envelope(lat: f64, lon: f64, radius_m: f64) -> AABB<[f64; 2]> {
+    let lat_delta = radius_m / MIN_METERS_PER_DEGREE;
+    let max_abs_lat = lat.abs() + lat_delta;
+
+    let lon_delta = if max_abs_lat >= 90.0 {
+        180.0
+    } else {
+        (radius_m / (MIN_METERS_PER_DEGREE * max_abs_lat.to_radians().cos())).min(180.0)
+    };
+
+    AABB::from_corners(
+        [lat - lat_delta, lon - lon_delta],
+        [lat + lat_delta, lon + lon_delta],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances_and_bearings() {
+        // Paris -> London, ~343.5 km
+        let (paris, london) = ((48.8566, 2.3522), (51.5074, -0.1278));
+        let d = haversine_distance(paris.0, paris.1, london.0, london.1);
+        assert!((d - 343_500.0).abs() < 1_000.0, "{d}");
+        let geodesic = vincenty_distance(paris.0, paris.1, london.0, london.1);
+        assert!((d - geodesic).abs() / geodesic < 0.005);
+
+        assert_eq!(initial_bearing(0.0, 0.0, 1.0, 0.0), 0.0);
+        assert!((initial_bearing(0.0, 0.0, 0.0, 1.0) - 90.0).abs() < 1e-9);
+        assert!((initial_bearing(0.0, 0.0, -1.0, 0.0) - 180.0).abs() < 1e-9);
+        assert!((initial_bearing(0.0, 0.0, 0.0, -1.0) - 270.0).abs() < 1e-9);
+        assert_eq!(initial_bearing(10.0, 10.0, 10.0, 10.0), 0.0);
+        // Eastward across the antimeridian, not the long way west
+        assert!((initial_bearing(0.0, 179.5, 0.0, -179.5) - 90.0).abs() < 1e-9);
+        assert!((haversine_distance(0.0, 179.5, 0.0, -179.5) - 111_195.0).abs() < 1.0);
+
+        // Antipodal points: exactly half the circumference, no NaN
+        let half = haversine_distance(0.0, 0.0, 0.0, 180.0);
+        assert!((half - core::f64::consts::PI * EARTH_RADIUS_M).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_destination_round_trips() {
+        for (lat, lon) in [(37.77, -122.42), (-33.9, 151.2), (0.0, 179.9), (89.0, 0.0)] {
+            for bearing in [0.0, 45.0, 135.0, 200.0, 300.0] {
+                let (lat2, lon2) = destination_point(lat, lon, bearing, 5_000.0);
+                assert!((-180.0..180.0).contains(&lon2));
+                let back = haversine_distance(lat, lon, lat2, lon2);
+                assert!((back - 5_000.0).abs() < 1e-3, "{back}");
+                if lat.abs() < 80.0 {
+                    let heading = initial_bearing(lat, lon, lat2, lon2);
+                    let off = lon_delta(heading, bearing).abs();
+                    assert!(off < 1e-6, "{heading} vs {bearing}");
+                }
+            }
+        }
+        assert_eq!(destination_point(37.0, -122.0, 90.0, 0.0), (37.0, -122.0));
+    }
+
+    #[test]
+    fn test_bbox_around_contains_circle() {
+        for (lat, lon) in [(37.77, -122.42), (0.0, 179.99), (-60.0, -179.999)] {
+            let (min_lat, min_lon, max_lat, max_lon) = bbox_around(lat, lon, 2_000.0);
+            for bearing in (0..360).step_by(15) {
+                let (plat, plon) = destination_point(lat, lon, f64::from(bearing), 2_000.0);
+                assert!((min_lat..=max_lat).contains(&plat));
+                if min_lon <= max_lon {
+                    assert!((min_lon..=max_lon).contains(&plon));
+                } else {
+                    assert!(plon >= min_lon || plon <= max_lon);
+                }
+            }
+        }
+        let (_, min_lon, _, max_lon) = bbox_around(0.0, 179.99, 2_000.0);
+        assert!(min_lon > max_lon);
+        let (_, min_lon, max_lat, max_lon) = bbox_around(89.99, 10.0, 5_000.0);
+        assert_eq!((min_lon, max_lat, max_lon), (-180.0, 90.0, 180.0));
+        assert_eq!(bbox_around(0.0, 0.0, 0.0), (0.0, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_point_in_polygon_and_normalization() {
+        let square = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)];
+        assert!(point_in_polygon(0.5, 0.5, &square));
+        assert!(!point_in_polygon(1.5, 0.5, &square));
+        assert!(!point_in_polygon(0.0, 0.0, &[]));
+        assert!(!point_in_polygon(0.0, 0.0, &square[..2]));
+
+        assert_eq!(normalize_longitude(190.0), -170.0);
+        assert_eq!(normalize_longitude(-190.0), 170.0);
+        assert_eq!(normalize_longitude(180.0), -180.0);
+        assert_eq!(normalize_longitude(-180.0), -180.0);
+        assert_eq!(normalize_longitude(540.0), -180.0);
+        assert_eq!(normalize_longitude(-0.5), -0.5);
+    }
+
+    #[test]
+    fn test_point_in_rings_even_odd() {
+        let outer = [(0.0, 0.0), (0.0, 10.0), (10.0, 10.0), (10.0, 0.0)];
+        let hole = [(2.0, 2.0), (2.0, 8.0), (8.0, 8.0), (8.0, 2.0)];
+        let island = [(4.0, 4.0), (4.0, 6.0), (6.0, 6.0), (6.0, 4.0)];
+        let apart = [(20.0, 20.0), (20.0, 21.0), (21.0, 21.0)];
+        let rings: [&[(f64, f64)]; 4] = [&outer, &hole, &island, &apart];
+
+        assert!(point_in_rings(1.0, 1.0, &rings));
+        assert!(!point_in_rings(3.0, 3.0, &rings)); // in the hole
+        assert!(point_in_rings(5.0, 5.0, &rings)); // on the island
+        assert!(point_in_rings(20.2, 20.5, &rings)); // second part
+        assert!(!point_in_rings(15.0, 15.0, &rings));
+        assert!(!point_in_rings(1.0, 1.0, &[] as &[&[(f64, f64)]]));
+
+        // Winding order and an explicit closing vertex do not matter
+        let mut reversed = hole.to_vec();
+        reversed.reverse();
+        reversed.push(reversed[0]);
+        assert!(!point_in_rings(3.0, 3.0, &[outer.to_vec(), reversed]));
+
+        // Degenerate rings never punch holes or add area
+        let degenerate: [&[(f64, f64)]; 5] = [
+            &outer,
+            &[(3.0, 3.0), (7.0, 7.0)],
+            &[(1.0, 1.0), (3.0, 3.0), (9.0, 9.0)],
+            &[(3.0, 3.0), (3.0, 3.0), (3.0, 3.0)],
+            &[(0.0, 3.0), (5.0, 3.0), (5.0, 3.0), (0.0, 3.0)],
+        ];
+        for (lat, lon) in [(3.0, 3.0), (2.0, 2.0), (4.0, 3.0), (5.0, 1.0)] {
+            assert!(point_in_rings(lat, lon, &degenerate), "({lat}, {lon})");
+        }
+        assert!(is_degenerate(&[(1.0, 1.0), (3.0, 3.0), (9.0, 9.0)]));
+
+        // A self-crossing ring keeps both lobes although its signed area is 0
+        let bow_tie = [(0.0, 0.0), (2.0, 2.0), (2.0, 0.0), (0.0, 2.0)];
+        assert!(point_in_rings(0.5, 1.0, &[bow_tie]));
+        assert!(point_in_rings(1.5, 1.0, &[bow_tie]));
+        assert!(!point_in_rings(1.0, 0.3, &[bow_tie]));
+    }
+}
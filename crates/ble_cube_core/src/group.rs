@@ -0,0 +1,358 @@
+//! GROUP BY over query results.
+//!
+//! [`Query::group_by`] adds grouping keys (MAC, geohash cell, time bucket,
+//! local date / weekday / hour of day, or any combination such as
+//! per-MAC-per-hour) and [`BleCube::execute_grouped`] folds the matches into
+//! one [`Group`] per distinct key with count, distinct-device, RSSI and
+//! bounding-box aggregates, without the caller materializing the
+//! observations.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::calendar::TimeZone;
+use crate::compat::prelude::*;
+use crate::query::Query;
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// Longest geohash produced (12 characters, cells of a few centimeters)
+const MAX_GEOHASH_PRECISION: u8 = 12;
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// A grouping key of [`Query::group_by`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GroupBy {
+    Mac,
+    /// Geohash cell of this many characters (1-12; 6 is about 1.2 x 0.6 km,
+    /// 8 about 38 x 19 m). Assumes WGS84 coordinates.
+    Geohash(u8),
+    /// Time buckets this many timestamp units wide, aligned to multiples of
+    /// the width
+    TimeBucket(i64),
+    /// Calendar date in a time zone (timestamps in the cube's declared
+    /// unit, seconds if none)
+    LocalDate(TimeZone),
+    /// Day of the week in a time zone, pooling all weeks
+    DayOfWeek(TimeZone),
+    /// Hour of the day (0-23) in a time zone, pooling all days
+    HourOfDay(TimeZone),
+}
+
+/// Aggregates for one distinct grouping key, see [`BleCube::execute_grouped`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Group {
+    /// Set when grouping by [`GroupBy::Mac`]
+    #[cfg_attr(feature = "serde", serde(with = "crate::mac::serde_octets::option"))]
+    pub mac: Option<[u8; 6]>,
+    /// Set when grouping by [`GroupBy::Geohash`]
+    pub geohash: Option<String>,
+    /// Start of the bucket when grouping by [`GroupBy::TimeBucket`]
+    pub time_bucket: Option<i64>,
+    /// (year, month, day) when grouping by [`GroupBy::LocalDate`]
+    pub local_date: Option<(i32, u8, u8)>,
+    /// 0 = Monday .. 6 = Sunday when grouping by [`GroupBy::DayOfWeek`]
+    pub day_of_week: Option<u8>,
+    /// 0-23 when grouping by [`GroupBy::HourOfDay`]
+    pub hour_of_day: Option<u8>,
+    pub count: usize,
+    /// Distinct MACs among the group's observations
+    pub devices: usize,
+    pub min_rssi: i8,
+    pub max_rssi: i8,
+    pub avg_rssi: f64,
+    /// (min_lat, min_lon, max_lat, max_lon) of the group's observations
+    pub bbox: (f64, f64, f64, f64),
+    /// Fraction of the time bucket the collector was scanning, when
+    /// grouping by [`GroupBy::TimeBucket`] with a scan schedule set (see
+    /// [`BleCube::set_scan_schedule`])
+    pub scan_coverage: Option<f64>,
+}
+
+/// Grouping key values; the field order is the result order
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct GroupKey {
+    mac: Option<[u8; 6]>,
+    geohash: Option<String>,
+    time_bucket: Option<i64>,
+    local_date: Option<(i32, u8, u8)>,
+    day_of_week: Option<u8>,
+    hour_of_day: Option<u8>,
+}
+
+impl Group {
+    fn new(key: GroupKey, obs: &BleObservation) -> Self {
+        Self {
+            mac: key.mac,
+            geohash: key.geohash,
+            time_bucket: key.time_bucket,
+            local_date: key.local_date,
+            day_of_week: key.day_of_week,
+            hour_of_day: key.hour_of_day,
+            count: 1,
+            devices: 0,
+            min_rssi: obs.rssi,
+            max_rssi: obs.rssi,
+            avg_rssi: f64::from(obs.rssi),
+            bbox: (obs.lat, obs.lon, obs.lat, obs.lon),
+            scan_coverage: None,
+        }
+    }
+
+    /// Fold in another observation (`avg_rssi` holds the sum until `finish`)
+    fn add(&mut self, obs: &BleObservation) {
+        self.count += 1;
+        self.min_rssi = self.min_rssi.min(obs.rssi);
+        self.max_rssi = self.max_rssi.max(obs.rssi);
+        self.avg_rssi += f64::from(obs.rssi);
+        let (min_lat, min_lon, max_lat, max_lon) = self.bbox;
+        self.bbox = (
+            min_lat.min(obs.lat),
+            min_lon.min(obs.lon),
+            max_lat.max(obs.lat),
+            max_lon.max(obs.lon),
+        );
+    }
+
+    fn finish(mut self) -> Self {
+        self.avg_rssi /= self.count as f64;
+        self
+    }
+}
+
+impl Query {
+    /// Group the results of [`BleCube::execute_grouped`] by `key` as well;
+    /// call repeatedly to combine keys (e.g. MAC then hourly buckets). A key
+    /// given twice replaces the earlier one. Ignored by `execute`.
+    ///
+    /// # Panics
+    /// Panics if a time bucket width is not positive.
+    pub fn group_by(mut self, key: GroupBy) -> Self {
+        if let GroupBy::TimeBucket(width) = key {
+            assert!(width > 0, "time bucket width must be positive");
+        }
+        self.group_by.retain(|&existing| {
+            core::mem::discriminant(&existing) != core::mem::discriminant(&key)
+        });
+        self.group_by.push(key);
+        self
+    }
+}
+
+impl BleCube {
+    /// Run a query and aggregate its matches per distinct value of the
+    /// query's [`Query::group_by`] keys, sorted by MAC, then geohash, time
+    /// bucket, local date, weekday and hour. Without grouping keys all
+    /// matches form a single group.
+    ///
+    /// Served from the query cache when one is enabled, see
+    /// [`BleCube::enable_query_cache`].
+    pub fn execute_grouped(&self, query: &Query) -> Vec<Group> {
+        #[cfg(feature = "std")]
+        return self.cached_groups(query, || self.group_matches(query));
+        #[cfg(not(feature = "std"))]
+        self.group_matches(query)
+    }
+
+    fn group_matches(&self, query: &Query) -> Vec<Group> {
+        let mut groups: BTreeMap<GroupKey, (Group, BTreeSet<[u8; 6]>)> = BTreeMap::new();
+        for id in self.execute_ids(query) {
+            let obs = &self.records[id];
+            let mut key = GroupKey::default();
+            for &by in &query.group_by {
+                match by {
+                    GroupBy::Mac => key.mac = Some(obs.mac),
+                    GroupBy::Geohash(precision) => {
+                        key.geohash = Some(geohash(obs.lat, obs.lon, precision))
+                    }
+                    GroupBy::TimeBucket(width) => {
+                        key.time_bucket = Some(obs.timestamp.div_euclid(width) * width)
+                    }
+                    GroupBy::LocalDate(zone) => {
+                        let local = zone.local(self.unix_secs(obs.timestamp));
+                        key.local_date = Some((local.year, local.month, local.day));
+                    }
+                    GroupBy::DayOfWeek(zone) => {
+                        key.day_of_week = Some(zone.local(self.unix_secs(obs.timestamp)).weekday)
+                    }
+                    GroupBy::HourOfDay(zone) => {
+                        key.hour_of_day = Some(zone.local(self.unix_secs(obs.timestamp)).hour)
+                    }
+                }
+            }
+            match groups.get_mut(&key) {
+                Some((group, macs)) => {
+                    group.add(obs);
+                    macs.insert(obs.mac);
+                }
+                None => {
+                    groups.insert(
+                        key.clone(),
+                        (Group::new(key, obs), BTreeSet::from([obs.mac])),
+                    );
+                }
+            }
+        }
+        let bucket_width = query.group_by.iter().find_map(|by| match by {
+            GroupBy::TimeBucket(width) => Some(*width),
+            _ => None,
+        });
+        let coverage = |group: &Group| {
+            let schedule = self.scan_schedule.as_ref()?;
+            let (start, width) = (group.time_bucket?, bucket_width?);
+            Some(schedule.coverage(start, start.saturating_add(width)))
+        };
+        groups
+            .into_values()
+            .map(|(group, macs)| {
+                Group {
+                    devices: macs.len(),
+                    scan_coverage: coverage(&group),
+                    ..group
+                }
+                .finish()
+            })
+            .collect()
+    }
+}
+
+/// Geohash of (lat, lon) with `precision` characters (clamped to 1-12)
+pub(crate) fn geohash(lat: f64, lon: f64, precision: u8) -> String {
+    let precision = precision.clamp(1, MAX_GEOHASH_PRECISION);
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision as usize);
+    let mut even = true;
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value): (&mut (f64, f64), f64) = if even {
+                (&mut lon_range, lon)
+            } else {
+                (&mut lat_range, lat)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geohash_known_cells() {
+        assert_eq!(geohash(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(geohash(37.7749, -122.4194, 5), "9q8yy");
+        assert_eq!(geohash(37.7749, -122.4194, 0), "9");
+        assert_eq!(geohash(0.0, 0.0, 20).len(), 12);
+    }
+
+    #[test]
+    fn test_group_by_mac_and_hour() {
+        let mut cube = BleCube::new();
+        // Two devices, one sighting every 20 minutes for two hours
+        for t in (0..7200).step_by(1200) {
+            for mac in [1u8, 2] {
+                cube.insert(BleObservation {
+                    mac: [mac; 6],
+                    rssi: -50 - (t / 1200) as i8 - mac as i8,
+                    timestamp: t,
+                    lat: 37.0 + t as f64 * 1e-5,
+                    lon: -122.0,
+                    ..Default::default()
+                });
+            }
+        }
+
+        let groups = cube.execute_grouped(
+            &Query::new()
+                .group_by(GroupBy::Mac)
+                .group_by(GroupBy::TimeBucket(3600)),
+        );
+        assert_eq!(groups.len(), 4);
+        let first = &groups[0];
+        assert_eq!(
+            (first.mac, first.time_bucket, first.geohash.as_deref()),
+            (Some([1; 6]), Some(0), None)
+        );
+        assert_eq!((first.count, first.min_rssi, first.max_rssi), (3, -53, -51));
+        assert_eq!(first.avg_rssi, -52.0);
+        let (min_lat, min_lon, max_lat, _) = first.bbox;
+        assert_eq!((min_lat, min_lon), (37.0, -122.0));
+        assert!((max_lat - 37.024).abs() < 1e-9);
+        assert_eq!(
+            (groups[1].mac, groups[1].time_bucket),
+            (Some([1; 6]), Some(3600))
+        );
+
+        // Filters apply before grouping; no keys gives one group
+        let all = cube.execute_grouped(&Query::new().rssi_between(-52, -50));
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].count, 3);
+        assert_eq!(all[0].mac, None);
+        assert!(cube
+            .execute_grouped(&Query::new().mac([9; 6]).group_by(GroupBy::Mac))
+            .is_empty());
+
+        let cells = cube.execute_grouped(&Query::new().group_by(GroupBy::Geohash(4)));
+        assert_eq!(cells.len(), 1);
+        assert_eq!((cells[0].count, cells[0].devices), (12, 2));
+        let replaced = Query::new()
+            .group_by(GroupBy::TimeBucket(3600))
+            .group_by(GroupBy::TimeBucket(7200));
+        assert_eq!(cube.execute_grouped(&replaced).len(), 1);
+    }
+
+    #[test]
+    fn test_group_by_local_calendar() {
+        let mut cube = BleCube::new();
+        // Saturday 2024-01-06 23:30 UTC is 15:30 PST the same day; an hour
+        // later it is Sunday in UTC but still Saturday in California
+        for (mac, timestamp) in [(1u8, 1_704_583_800), (2, 1_704_587_400), (1, 1_704_587_500)] {
+            cube.insert(BleObservation {
+                mac: [mac; 6],
+                timestamp,
+                ..Default::default()
+            });
+        }
+        let pacific = TimeZone::US_PACIFIC;
+        let by_day = |zone| {
+            cube.execute_grouped(&Query::new().group_by(GroupBy::DayOfWeek(zone)))
+                .iter()
+                .map(|group| (group.day_of_week, group.count, group.devices))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(by_day(pacific), vec![(Some(5), 3, 2)]);
+        assert_eq!(
+            by_day(TimeZone::UTC),
+            vec![(Some(5), 1, 1), (Some(6), 2, 2)]
+        );
+
+        let hourly = cube.execute_grouped(
+            &Query::new()
+                .group_by(GroupBy::LocalDate(pacific))
+                .group_by(GroupBy::HourOfDay(pacific)),
+        );
+        let keys: Vec<_> = hourly
+            .iter()
+            .map(|group| (group.local_date, group.hour_of_day, group.devices))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (Some((2024, 1, 6)), Some(15), 1),
+                (Some((2024, 1, 6)), Some(16), 2)
+            ]
+        );
+    }
+}
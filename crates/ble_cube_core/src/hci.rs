@@ -0,0 +1,401 @@
+//! Raw HCI advertising reports.
+//!
+//! A host scanning for BLE devices receives each advertisement as an HCI LE
+//! Meta event: an LE Advertising Report (legacy) or LE Extended Advertising
+//! Report carrying the advertiser's address, RSSI and AD structures.
+//! [`parse_hci_event`] decodes those events, and
+//! [`BleCube::insert_hci_event`] inserts their reports along with the
+//! advertisement payload, so iBeacon / Eddystone identities are indexed too.
+//! Whole `btmon -w` / `hcidump` capture files are read by `ble_cube_io`.
+//!
+//! Input is untrusted: every length is checked against the bytes actually
+//! present, malformed events are reported as [`HciError`], and nothing here
+//! panics or allocates more than the input size on arbitrary bytes.
+
+use crate::beacon::ad_structures;
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use core::error::Error;
+use core::fmt;
+
+const H4_EVENT: u8 = 0x04;
+const EVENT_LE_META: u8 = 0x3E;
+const SUBEVENT_ADVERTISING_REPORT: u8 = 0x02;
+const SUBEVENT_EXTENDED_ADVERTISING_REPORT: u8 = 0x0D;
+/// RSSI / TX power value meaning "not available"
+const UNAVAILABLE: i8 = 127;
+const AD_TYPE_FLAGS: u8 = 0x01;
+const AD_TYPE_SHORTENED_NAME: u8 = 0x08;
+const AD_TYPE_COMPLETE_NAME: u8 = 0x09;
+
+/// One advertisement from an HCI advertising report event
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdvertisingReport {
+    /// Legacy PDU type (0 `ADV_IND` .. 4 `SCAN_RSP`) or the extended
+    /// report's event-type bit field
+    pub event_type: u16,
+    /// 0 public, 1 random, 2 / 3 resolved public / random identity
+    pub address_type: u8,
+    /// Advertiser address, most significant byte first (display order)
+    #[cfg_attr(feature = "serde", serde(with = "crate::mac::serde_octets"))]
+    pub mac: [u8; 6],
+    /// `None` if the controller reported it unavailable
+    pub rssi: Option<i8>,
+    /// Advertised TX power (extended reports only), dBm
+    pub tx_power: Option<i8>,
+    /// AD structures, as sent over the air
+    pub data: Vec<u8>,
+}
+
+impl AdvertisingReport {
+    /// (AD type, data) of each well-formed AD structure
+    pub fn ad_structures(&self) -> impl Iterator<Item = (u8, &[u8])> {
+        ad_structures(&self.data)
+    }
+
+    /// Complete or shortened local name, if it is UTF-8
+    pub fn local_name(&self) -> Option<&str> {
+        self.ad_structures()
+            .find(|&(ad_type, _)| {
+                ad_type == AD_TYPE_COMPLETE_NAME || ad_type == AD_TYPE_SHORTENED_NAME
+            })
+            .and_then(|(_, name)| core::str::from_utf8(name).ok())
+    }
+
+    /// Flags AD structure (discoverability, BR/EDR support)
+    pub fn flags(&self) -> Option<u8> {
+        self.ad_structures()
+            .find(|&(ad_type, _)| ad_type == AD_TYPE_FLAGS)
+            .and_then(|(_, data)| data.first().copied())
+    }
+}
+
+/// Why bytes could not be decoded as an advertising report event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HciError {
+    /// A length field points past the end of the input
+    Truncated,
+    /// A well-formed event other than an advertising report; capture
+    /// readers skip these
+    NotAdvertisingReport,
+    /// Lengths that disagree with each other
+    Malformed(&'static str),
+}
+
+impl fmt::Display for HciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HciError::Truncated => f.write_str("truncated HCI event"),
+            HciError::NotAdvertisingReport => f.write_str("not an LE advertising report event"),
+            HciError::Malformed(what) => write!(f, "malformed HCI event: {what}"),
+        }
+    }
+}
+
+impl Error for HciError {}
+
+/// Bounds-checked reads from an event's parameters
+struct Cursor<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], HciError> {
+        if len > self.rest.len() {
+            return Err(HciError::Truncated);
+        }
+        let (taken, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, HciError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8, HciError> {
+        Ok(self.u8()? as i8)
+    }
+
+    fn u16(&mut self) -> Result<u16, HciError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A 6-byte address, sent least significant byte first
+    fn address(&mut self) -> Result<[u8; 6], HciError> {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(self.take(6)?);
+        mac.reverse();
+        Ok(mac)
+    }
+}
+
+/// Decode an LE Advertising Report or LE Extended Advertising Report event:
+/// an HCI event packet starting with the event code (`0x3E`), optionally
+/// preceded by the H4 packet indicator (`0x04`) as in UART transports and
+/// H4 captures. Extended reports split across several events (data status
+/// "incomplete") are returned fragment by fragment.
+pub fn parse_hci_event(packet: &[u8]) -> Result<Vec<AdvertisingReport>, HciError> {
+    let event = match packet {
+        [H4_EVENT, EVENT_LE_META, ..] => &packet[1..],
+        _ => packet,
+    };
+    let mut cursor = Cursor { rest: event };
+    if cursor.u8()? != EVENT_LE_META {
+        return Err(HciError::NotAdvertisingReport);
+    }
+    let len = cursor.u8()? as usize;
+    let mut cursor = Cursor {
+        rest: cursor.take(len)?,
+    };
+    let reports = match cursor.u8()? {
+        SUBEVENT_ADVERTISING_REPORT => legacy_reports(&mut cursor)?,
+        SUBEVENT_EXTENDED_ADVERTISING_REPORT => extended_reports(&mut cursor)?,
+        _ => return Err(HciError::NotAdvertisingReport),
+    };
+    if !cursor.rest.is_empty() {
+        return Err(HciError::Malformed("bytes after the last report"));
+    }
+    Ok(reports)
+}
+
+fn legacy_reports(cursor: &mut Cursor<'_>) -> Result<Vec<AdvertisingReport>, HciError> {
+    let count = cursor.u8()?;
+    let mut reports = Vec::with_capacity(usize::from(count).min(cursor.rest.len() / 10));
+    for _ in 0..count {
+        let event_type = u16::from(cursor.u8()?);
+        let address_type = cursor.u8()?;
+        let mac = cursor.address()?;
+        let data_len = cursor.u8()? as usize;
+        if data_len > 31 {
+            return Err(HciError::Malformed("legacy advertising data over 31 bytes"));
+        }
+        let data = cursor.take(data_len)?.to_vec();
+        let rssi = cursor.i8()?;
+        reports.push(AdvertisingReport {
+            event_type,
+            address_type,
+            mac,
+            rssi: (rssi != UNAVAILABLE).then_some(rssi),
+            tx_power: None,
+            data,
+        });
+    }
+    Ok(reports)
+}
+
+fn extended_reports(cursor: &mut Cursor<'_>) -> Result<Vec<AdvertisingReport>, HciError> {
+    let count = cursor.u8()?;
+    let mut reports = Vec::with_capacity(usize::from(count).min(cursor.rest.len() / 24));
+    for _ in 0..count {
+        let event_type = cursor.u16()?;
+        let address_type = cursor.u8()?;
+        let mac = cursor.address()?;
+        // Primary PHY, secondary PHY, advertising SID
+        cursor.take(3)?;
+        let tx_power = cursor.i8()?;
+        let rssi = cursor.i8()?;
+        // Periodic advertising interval, direct address type and address
+        cursor.take(9)?;
+        let data_len = cursor.u8()? as usize;
+        let data = cursor.take(data_len)?.to_vec();
+        reports.push(AdvertisingReport {
+            event_type,
+            address_type,
+            mac,
+            rssi: (rssi != UNAVAILABLE).then_some(rssi),
+            tx_power: (tx_power != UNAVAILABLE).then_some(tx_power),
+            data,
+        });
+    }
+    Ok(reports)
+}
+
+/// Position and identity of the scanner that produced HCI events, stamped
+/// on the observations made from them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HciReceiver {
+    pub lat: f64,
+    pub lon: f64,
+    pub receiver_id: Option<u16>,
+    pub floor: Option<i16>,
+}
+
+impl HciReceiver {
+    /// A scanner at (lat, lon) without receiver ID or floor
+    pub fn at(lat: f64, lon: f64) -> Self {
+        Self {
+            lat,
+            lon,
+            ..Default::default()
+        }
+    }
+
+    /// The observation `report` makes when heard at `timestamp`; `None` if
+    /// the controller did not report an RSSI
+    pub fn observation(
+        &self,
+        report: &AdvertisingReport,
+        timestamp: i64,
+    ) -> Option<BleObservation> {
+        Some(BleObservation {
+            mac: report.mac,
+            rssi: report.rssi?,
+            timestamp,
+            lat: self.lat,
+            lon: self.lon,
+            receiver_id: self.receiver_id,
+            floor: self.floor,
+            tx_power: report.tx_power,
+            quality: None,
+        })
+    }
+}
+
+impl BleCube {
+    /// Decode one HCI event (see [`parse_hci_event`]) and insert each of its
+    /// advertising reports heard at `timestamp` by `receiver`, with its
+    /// advertising data as the record's advertisement. Reports without an
+    /// RSSI are skipped. Returns the new record IDs.
+    ///
+    /// # Panics
+    /// Panics where [`BleCube::insert_advertisement`] does.
+    pub fn insert_hci_event(
+        &mut self,
+        packet: &[u8],
+        timestamp: i64,
+        receiver: &HciReceiver,
+    ) -> Result<Vec<usize>, HciError> {
+        Ok(parse_hci_event(packet)?
+            .iter()
+            .filter_map(|report| {
+                let obs = receiver.observation(report, timestamp)?;
+                Some(self.insert_advertisement(obs, &report.data))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::SplitMix64;
+
+    /// iBeacon advertisement: flags, then Apple manufacturer data
+    const IBEACON_DATA: [u8; 30] = [
+        0x02, 0x01, 0x06, 0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15, 0xE2, 0xC5, 0x6D, 0xB5, 0xDF, 0xFB,
+        0x48, 0xD2, 0xB0, 0x60, 0xD0, 0xF5, 0xA7, 0x10, 0x96, 0xE0, 0x00, 0x01, 0x00, 0x02, 0xC5,
+    ];
+
+    /// H4 LE Advertising Report with two reports
+    fn legacy_event() -> Vec<u8> {
+        let mut params = vec![SUBEVENT_ADVERTISING_REPORT, 2];
+        // ADV_IND from a random address, iBeacon payload, -60 dBm
+        params.extend([0x00, 0x01, 0x66, 0x55, 0x44, 0x33, 0x22, 0xC1]);
+        params.push(IBEACON_DATA.len() as u8);
+        params.extend(IBEACON_DATA);
+        params.push(-60i8 as u8);
+        // SCAN_RSP with a name, RSSI unavailable
+        params.extend([0x04, 0x00, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+        params.extend([0x06, 0x05, 0x09, b'T', b'a', b'g', b'1', 0x7F]);
+        let mut event = vec![H4_EVENT, EVENT_LE_META, params.len() as u8];
+        event.extend(params);
+        event
+    }
+
+    fn extended_event() -> Vec<u8> {
+        let mut params = vec![SUBEVENT_EXTENDED_ADVERTISING_REPORT, 1];
+        params.extend([0x13, 0x00, 0x00, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+        // PHYs, SID, TX power -4, RSSI -71, interval, direct address
+        params.extend([0x01, 0x00, 0xFF, 0xFC, -71i8 as u8]);
+        params.extend([0; 9]);
+        params.extend([0x03, 0x02, 0x01, 0x06]);
+        let mut event = vec![EVENT_LE_META, params.len() as u8];
+        event.extend(params);
+        event
+    }
+
+    #[test]
+    fn test_parse_legacy_and_extended_reports() {
+        let reports = parse_hci_event(&legacy_event()).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].mac, [0xC1, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        assert_eq!((reports[0].address_type, reports[0].rssi), (1, Some(-60)));
+        assert_eq!(reports[0].flags(), Some(0x06));
+        assert_eq!(reports[1].event_type, 4);
+        assert_eq!(reports[1].rssi, None);
+        assert_eq!(reports[1].local_name(), Some("Tag1"));
+
+        let extended = parse_hci_event(&extended_event()).unwrap();
+        assert_eq!(extended[0].event_type, 0x13);
+        assert_eq!(
+            (extended[0].rssi, extended[0].tx_power),
+            (Some(-71), Some(-4))
+        );
+        assert_eq!(extended[0].mac, [1, 2, 3, 4, 5, 6]);
+
+        // Command Complete, and a length byte past the end
+        assert_eq!(
+            parse_hci_event(&[0x0E, 0x04, 0x01, 0x0C, 0x20, 0x00]),
+            Err(HciError::NotAdvertisingReport)
+        );
+        let mut cut = legacy_event();
+        cut.pop();
+        assert_eq!(parse_hci_event(&cut), Err(HciError::Truncated));
+    }
+
+    #[test]
+    fn test_insert_hci_event_indexes_beacons() {
+        let mut cube = BleCube::new();
+        let receiver = HciReceiver {
+            receiver_id: Some(3),
+            ..HciReceiver::at(37.0, -122.0)
+        };
+        let ids = cube
+            .insert_hci_event(&legacy_event(), 1_700_000_000, &receiver)
+            .unwrap();
+        // The scan response without RSSI is skipped
+        assert_eq!(ids, vec![0]);
+        let obs = cube.get(0).unwrap();
+        assert_eq!(
+            (obs.rssi, obs.receiver_id, obs.tx_power),
+            (-60, Some(3), Some(-59))
+        );
+        assert_eq!(cube.beacon_frames(0).len(), 1);
+        assert!(cube.insert_hci_event(&[0x3E], 0, &receiver).is_err());
+    }
+
+    #[test]
+    fn test_random_bytes_never_panic() {
+        let mut rng = SplitMix64(7);
+        let valid = [legacy_event(), extended_event()];
+        for round in 0..20_000 {
+            let mut packet = valid[round % 2].clone();
+            match round % 3 {
+                // Flip bytes of a valid event
+                0 => {
+                    for _ in 0..3 {
+                        let at = rng.next_u64() as usize % packet.len();
+                        packet[at] = rng.next_u64() as u8;
+                    }
+                }
+                // Cut it short
+                1 => packet.truncate(rng.next_u64() as usize % packet.len()),
+                // Noise behind a plausible header
+                _ => {
+                    packet.truncate(4);
+                    let len = rng.next_u64() as usize % 300;
+                    packet.extend((0..len).map(|_| rng.next_u64() as u8));
+                }
+            }
+            if let Ok(reports) = parse_hci_event(&packet) {
+                assert!(reports
+                    .iter()
+                    .all(|report| report.data.len() <= packet.len()));
+            }
+        }
+    }
+}
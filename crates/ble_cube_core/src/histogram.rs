@@ -0,0 +1,161 @@
+//! Binned distributions computed from the indices, so callers can plot a
+//! distribution without materializing every matching observation.
+
+use crate::ble_cube::BleCube;
+use crate::compat::prelude::*;
+use crate::mac::MacAddr;
+use crate::query::{Dimension, Query};
+use alloc::collections::BTreeMap;
+
+/// One histogram bin covering `[start, end)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistogramBin {
+    pub start: i64,
+    pub end: i64,
+    pub count: usize,
+}
+
+impl BleCube {
+    /// RSSI distribution of observations matching `filter`, in bins of
+    /// `bin_width` dB aligned to multiples of the width (e.g. -70..-60).
+    ///
+    /// Bins run contiguously from the lowest to the highest occupied one,
+    /// empty bins in between included. When `filter` constrains nothing but
+    /// RSSI the counts come straight from the RSSI index's posting lengths.
+    /// Returns nothing if `bin_width` is 0.
+    pub fn rssi_histogram(&self, filter: &Query, bin_width: u8) -> Vec<HistogramBin> {
+        if bin_width == 0 {
+            return Vec::new();
+        }
+        let rssi_only = self.indexed.rssi
+            && Dimension::DRIVER_PRIORITY
+                .iter()
+                .all(|&d| d == Dimension::Rssi || !filter.constrains(d));
+
+        let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+        if rssi_only {
+            let (min, max) = filter.rssi_range.unwrap_or((i8::MIN, i8::MAX));
+            if min > max {
+                return Vec::new();
+            }
+            for (&rssi, ids) in self.rssi_index.range(min..=max) {
+                *counts.entry(i64::from(rssi)).or_default() += ids.len();
+            }
+        } else {
+            let rssi = self.records.rssi();
+            for id in self.execute_ids(filter) {
+                *counts.entry(i64::from(rssi[id])).or_default() += 1;
+            }
+        }
+        bin(counts, i64::from(bin_width))
+    }
+
+    /// Distribution of the gaps between consecutive observations of `mac`,
+    /// in time order, in bins of `bin_width` timestamp units starting at 0.
+    ///
+    /// Observations sharing a timestamp contribute a gap of 0. Returns
+    /// nothing if `bin_width` is not positive or the MAC was seen fewer than
+    /// twice.
+    pub fn interarrival_histogram<M: Into<MacAddr>>(
+        &self,
+        mac: M,
+        bin_width: i64,
+    ) -> Vec<HistogramBin> {
+        let Some(ids) = self.mac_index.get(&mac.into().0) else {
+            return Vec::new();
+        };
+        if bin_width <= 0 {
+            return Vec::new();
+        }
+        let column = self.records.timestamps();
+        let mut timestamps: Vec<i64> = ids.iter().map(|&id| column[id]).collect();
+        timestamps.sort_unstable();
+
+        let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+        for pair in timestamps.windows(2) {
+            *counts.entry(pair[1].saturating_sub(pair[0])).or_default() += 1;
+        }
+        bin(counts, bin_width)
+    }
+}
+
+/// Fold per-value counts into contiguous bins of `width`
+fn bin(counts: BTreeMap<i64, usize>, width: i64) -> Vec<HistogramBin> {
+    let mut bins: Vec<HistogramBin> = Vec::new();
+    for (value, count) in counts {
+        let start = value.div_euclid(width).saturating_mul(width);
+        // Fill the gap up to this value's bin with empty bins
+        while let Some(last) = bins.last() {
+            if last.start >= start {
+                break;
+            }
+            let next = last.end;
+            bins.push(HistogramBin {
+                start: next,
+                end: next.saturating_add(width),
+                count: 0,
+            });
+        }
+        match bins.last_mut() {
+            Some(last) if last.start == start => last.count += count,
+            _ => bins.push(HistogramBin {
+                start,
+                end: start.saturating_add(width),
+                count,
+            }),
+        }
+    }
+    bins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    #[test]
+    fn test_histograms() {
+        let mut cube = BleCube::new();
+        for (rssi, timestamp, receiver) in [
+            (-45, 0, 1),
+            (-61, 10, 1),
+            (-68, 12, 2),
+            (-70, 12, 1),
+            (-62, 40, 2),
+        ] {
+            cube.insert(BleObservation {
+                rssi,
+                mac: [0, 0, 0, 0, 0, 1],
+                timestamp,
+                receiver_id: Some(receiver),
+                ..Default::default()
+            });
+        }
+
+        let counts = |bins: Vec<HistogramBin>| -> Vec<(i64, usize)> {
+            bins.iter().map(|b| (b.start, b.count)).collect()
+        };
+        assert_eq!(
+            counts(cube.rssi_histogram(&Query::new(), 10)),
+            vec![(-70, 4), (-60, 0), (-50, 1)]
+        );
+        assert_eq!(
+            counts(cube.rssi_histogram(&Query::new().rssi_between(-69, -50), 10)),
+            vec![(-70, 3)]
+        );
+        assert_eq!(
+            counts(cube.rssi_histogram(&Query::new().receiver(2), 5)),
+            vec![(-70, 1), (-65, 1)]
+        );
+        assert!(cube.rssi_histogram(&Query::new(), 0).is_empty());
+
+        // Gaps: 10, 2, 0, 28
+        let gaps = cube.interarrival_histogram([0, 0, 0, 0, 0, 1], 10);
+        assert_eq!(counts(gaps.clone()), vec![(0, 2), (10, 1), (20, 1)]);
+        assert_eq!(gaps[2].end, 30);
+        assert!(cube
+            .interarrival_histogram([0, 0, 0, 0, 0, 2], 10)
+            .is_empty());
+    }
+}
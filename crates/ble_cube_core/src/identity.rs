@@ -0,0 +1,286 @@
+//! Resolvable private address (RPA) resolution with Identity Resolving Keys.
+//!
+//! BLE devices using privacy rotate a resolvable private address every few
+//! minutes. Its upper three octets are a random `prand` (top bits `01`) and
+//! its lower three a hash of `prand` under the device's IRK, so anyone
+//! holding the key can map each rotation back to the device's identity
+//! address. Registered keys are tried once per distinct address as it first
+//! appears in the cube; records keep their over-the-air MAC and the
+//! identity -> addresses map lets device queries span rotations.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::mac::{MacAddr, RandomAddressKind};
+
+/// Registered IRKs and the addresses resolved with them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IdentityResolver {
+    keys: Vec<([u8; 6], Aes128)>,
+    /// RPA -> identity address
+    resolved: HashMap<[u8; 6], [u8; 6]>,
+    /// Identity address -> RPAs resolved to it, in order of first sighting
+    addresses: HashMap<[u8; 6], Vec<[u8; 6]>>,
+}
+
+impl IdentityResolver {
+    /// Try to resolve an address the cube has not seen before
+    pub(crate) fn observe(&mut self, mac: [u8; 6]) {
+        if self.keys.is_empty() || self.resolved.contains_key(&mac) {
+            return;
+        }
+        if let Some(&(identity, _)) = self.keys.iter().find(|(_, aes)| rpa_matches(aes, mac)) {
+            self.record(mac, identity);
+        }
+    }
+
+    fn record(&mut self, rpa: [u8; 6], identity: [u8; 6]) {
+        self.resolved.insert(rpa, identity);
+        self.addresses.entry(identity).or_default().push(rpa);
+    }
+}
+
+impl BleCube {
+    /// Register a device's Identity Resolving Key. Resolvable private
+    /// addresses generated with it, already stored or inserted later, then
+    /// resolve to `identity` (the device's public or static address).
+    ///
+    /// `irk` is most significant octet first, the order the Bluetooth Core
+    /// specification prints keys in; keys captured little-endian off HCI
+    /// must be reversed. Keys are not persisted by the write-ahead log.
+    pub fn register_irk<M: Into<MacAddr>>(&mut self, identity: M, irk: [u8; 16]) {
+        let identity = identity.into().0;
+        let aes = Aes128::new(&irk);
+        let resolver = &mut self.identities;
+        let matches: Vec<[u8; 6]> = self
+            .mac_index
+            .keys()
+            .filter(|mac| !resolver.resolved.contains_key(*mac) && rpa_matches(&aes, **mac))
+            .copied()
+            .collect();
+        for rpa in matches {
+            resolver.record(rpa, identity);
+        }
+        resolver.keys.push((identity, aes));
+    }
+
+    /// Identity address a resolvable private address belongs to, if one of
+    /// the registered keys generated it
+    pub fn resolve_identity<M: Into<MacAddr>>(&self, mac: M) -> Option<MacAddr> {
+        self.identities
+            .resolved
+            .get(&mac.into().0)
+            .copied()
+            .map(MacAddr)
+    }
+
+    /// Private addresses seen so far that resolved to `identity`
+    pub fn device_addresses<M: Into<MacAddr>>(&self, identity: M) -> Vec<MacAddr> {
+        self.identities
+            .addresses
+            .get(&identity.into().0)
+            .map(|rpas| rpas.iter().copied().map(MacAddr).collect())
+            .unwrap_or_default()
+    }
+
+    /// Every observation of a device across address rotations: records with
+    /// the identity address itself plus every RPA resolved to it, in record
+    /// ID order
+    pub fn query_device<M: Into<MacAddr>>(&self, identity: M) -> Vec<&BleObservation> {
+        let identity = identity.into().0;
+        let rpas = self.identities.addresses.get(&identity);
+        let mut ids: Vec<usize> = core::iter::once(&identity)
+            .chain(rpas.into_iter().flatten())
+            .filter_map(|mac| self.mac_index.get(mac))
+            .flatten()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.into_iter().map(|id| &self.records[id]).collect()
+    }
+}
+
+/// Whether `mac` is an RPA whose hash matches its `prand` under `aes`
+/// (the Core specification's `ah` function)
+fn rpa_matches(aes: &Aes128, mac: [u8; 6]) -> bool {
+    if MacAddr(mac).random_address_kind() != RandomAddressKind::ResolvablePrivate {
+        return false;
+    }
+    let mut block = [0u8; 16];
+    block[13..].copy_from_slice(&mac[..3]);
+    let out = aes.encrypt(block);
+    out[13..] == mac[3..]
+}
+
+// ========== AES-128 (encryption only) ==========
+
+/// Expanded AES-128 key (FIPS 197), encryption only (`ah`, pseudonyms)
+#[derive(Clone)]
+pub struct Aes128 {
+    round_keys: [[u8; 16]; 11],
+}
+
+impl core::fmt::Debug for Aes128 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // Never print key material
+        f.write_str("Aes128 { .. }")
+    }
+}
+
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+impl Aes128 {
+    pub fn new(key: &[u8; 16]) -> Self {
+        let mut round_keys = [[0u8; 16]; 11];
+        round_keys[0] = *key;
+        for round in 1..11 {
+            let prev = round_keys[round - 1];
+            let mut word = [prev[13], prev[14], prev[15], prev[12]];
+            for byte in &mut word {
+                *byte = SBOX[usize::from(*byte)];
+            }
+            word[0] ^= RCON[round - 1];
+
+            let next = &mut round_keys[round];
+            for i in 0..16 {
+                let feed = if i < 4 { word[i] } else { next[i - 4] };
+                next[i] = prev[i] ^ feed;
+            }
+        }
+        Self { round_keys }
+    }
+
+    pub fn encrypt(&self, mut state: [u8; 16]) -> [u8; 16] {
+        add_round_key(&mut state, &self.round_keys[0]);
+        for round in 1..10 {
+            sub_shift(&mut state);
+            mix_columns(&mut state);
+            add_round_key(&mut state, &self.round_keys[round]);
+        }
+        sub_shift(&mut state);
+        add_round_key(&mut state, &self.round_keys[10]);
+        state
+    }
+}
+
+fn add_round_key(state: &mut [u8; 16], key: &[u8; 16]) {
+    for (s, k) in state.iter_mut().zip(key) {
+        *s ^= k;
+    }
+}
+
+/// SubBytes followed by ShiftRows (state is column-major)
+fn sub_shift(state: &mut [u8; 16]) {
+    let old = *state;
+    for col in 0..4 {
+        for row in 0..4 {
+            state[col * 4 + row] = SBOX[usize::from(old[((col + row) % 4) * 4 + row])];
+        }
+    }
+}
+
+fn mix_columns(state: &mut [u8; 16]) {
+    let xtime = |b: u8| (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 };
+    for column in state.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IRK from the Core specification's `ah` sample data
+    const IRK: [u8; 16] = [
+        0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39, 0x7d,
+        0x9b,
+    ];
+
+    #[test]
+    fn test_aes_and_ah_vectors() {
+        // FIPS 197 appendix C.1
+        let key: [u8; 16] = core::array::from_fn(|i| i as u8);
+        let plain: [u8; 16] = core::array::from_fn(|i| (i as u8) * 0x11);
+        assert_eq!(
+            Aes128::new(&key).encrypt(plain),
+            [
+                0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+                0xc5, 0x5a
+            ]
+        );
+        // ah(IRK, 0x708194) = 0x0dfbaa
+        let aes = Aes128::new(&IRK);
+        assert!(rpa_matches(&aes, [0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa]));
+        assert!(!rpa_matches(&aes, [0x70, 0x81, 0x94, 0x0d, 0xfb, 0xab]));
+    }
+
+    #[test]
+    fn test_queries_span_rotations() {
+        let identity = [0x00, 0x1a, 0x7d, 0xda, 0x71, 0x13];
+        let rpa = [0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa];
+        let other = [0x70, 0x81, 0x94, 0x00, 0x00, 0x00];
+        let mut cube = BleCube::new();
+        for (mac, timestamp) in [(identity, 1), (rpa, 2), (other, 3)] {
+            cube.insert(BleObservation {
+                mac,
+                timestamp,
+                ..Default::default()
+            });
+        }
+        assert_eq!(cube.query_device(identity).len(), 1);
+
+        // Registering resolves addresses already stored...
+        cube.register_irk(identity, IRK);
+        assert_eq!(cube.resolve_identity(rpa), Some(MacAddr(identity)));
+        assert_eq!(cube.resolve_identity(other), None);
+        // ...and ones that first show up afterwards
+        let rotated = [0x5a, 0x5a, 0x5a, 0, 0, 0];
+        let mut block = [0u8; 16];
+        block[13..].copy_from_slice(&rotated[..3]);
+        let hash = Aes128::new(&IRK).encrypt(block);
+        let rotated = [0x5a, 0x5a, 0x5a, hash[13], hash[14], hash[15]];
+        cube.insert(BleObservation {
+            mac: rotated,
+            timestamp: 4,
+            ..Default::default()
+        });
+
+        let seen: Vec<i64> = cube
+            .query_device(identity)
+            .iter()
+            .map(|o| o.timestamp)
+            .collect();
+        assert_eq!(seen, vec![1, 2, 4]);
+        assert_eq!(
+            cube.device_addresses(identity),
+            vec![MacAddr(rpa), MacAddr(rotated)]
+        );
+        // Raw MACs are untouched
+        assert_eq!(cube.query_mac(rpa).len(), 1);
+    }
+}
@@ -0,0 +1,341 @@
+//! Index/record consistency checks.
+//!
+//! Every index is derived from the record store, and every write path
+//! (insert, upsert, compaction, eviction, recovery, cold-tier loads) keeps
+//! the two in step. [`BleCube::verify_integrity`] checks that they are: each
+//! index's (key, record ID) entries are compared with the ones the records
+//! imply, reporting entries no record backs (orphans) and records an index
+//! misses. [`BleCube::rebuild_indices`] throws the derived indices away and
+//! builds them again from the records.
+//!
+//! Tags and raw advertisements are not derived from the records (they are
+//! the only copy), so neither method touches them.
+
+use crate::ble_cube::BleCube;
+use crate::compat::prelude::*;
+use crate::lifecycle::SeenIndex;
+use crate::mac::MacAddr;
+use crate::query::Dimension;
+
+/// A disagreement between an index and the record store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IndexIssue {
+    /// The index lists a record ID that does not exist, or lists it under a
+    /// key the record does not have
+    Orphan { index: Dimension, record_id: usize },
+    /// The record is not listed under its key
+    Missing { index: Dimension, record_id: usize },
+    /// The device's first/last-seen span does not match its records
+    StaleSpan { mac: MacAddr },
+}
+
+/// Result of [`BleCube::verify_integrity`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IntegrityReport {
+    /// Records checked
+    pub records: usize,
+    /// Orphans then missing entries of each index in turn (MAC, RSSI,
+    /// time, geo, receiver, floor, path loss, quality, zones), by record
+    /// ID, followed by stale spans
+    pub issues: Vec<IndexIssue>,
+}
+
+impl IntegrityReport {
+    /// Whether every index agrees with the record store
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl BleCube {
+    /// Cross-check every derived index (MAC, RSSI, time, R-tree, receiver,
+    /// floor, path loss, quality, zone memberships, first/last seen) against
+    /// the record store. Disabled indices are expected to be empty. Takes
+    /// O(n log n) time and O(n) extra memory per index; meant for tests,
+    /// after recovering from disk, or before trusting a long-lived cube.
+    pub fn verify_integrity(&self) -> IntegrityReport {
+        let records = &self.records;
+        let indexed = self.indexed;
+        let mut issues = Vec::new();
+
+        diff(
+            &mut issues,
+            Dimension::Mac,
+            postings(self.mac_index.iter()),
+            records.iter().map(|obs| obs.mac).zip(0..).collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::Rssi,
+            postings(self.rssi_index.iter()),
+            records
+                .iter()
+                .map(|obs| obs.rssi)
+                .zip(0..)
+                .filter(|_| indexed.rssi)
+                .collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::Time,
+            self.time_index.range(..).collect(),
+            records.iter().map(|obs| obs.timestamp).zip(0..).collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::Geo,
+            self.geo_index
+                .iter()
+                .map(|point| (coord_bits(point.coords), point.record_id))
+                .collect(),
+            records
+                .iter()
+                .map(|obs| coord_bits([obs.lat, obs.lon]))
+                .zip(0..)
+                .filter(|_| indexed.geo)
+                .collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::Receiver,
+            postings(self.receiver_index.iter()),
+            records
+                .iter()
+                .enumerate()
+                .filter_map(|(id, obs)| Some((obs.receiver_id?, id)))
+                .collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::Floor,
+            postings(self.floor_index.iter()),
+            records
+                .iter()
+                .enumerate()
+                .filter_map(|(id, obs)| Some((obs.floor?, id)))
+                .collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::PathLoss,
+            postings(self.path_loss_index.iter()),
+            records
+                .iter()
+                .enumerate()
+                .filter(|_| indexed.path_loss)
+                .filter_map(|(id, obs)| Some((obs.path_loss()?, id)))
+                .collect(),
+        );
+        diff(
+            &mut issues,
+            Dimension::Quality,
+            postings(self.quality_index.iter()),
+            records
+                .iter()
+                .enumerate()
+                .filter(|_| indexed.quality)
+                .filter_map(|(id, obs)| Some((obs.quality?, id)))
+                .collect(),
+        );
+        let (stored, implied) = self.geofence.audit(records, self.crs, self.distance_metric);
+        diff(&mut issues, Dimension::Zone, stored, implied);
+
+        let expected = SeenIndex::from_records(records);
+        let mut stale: Vec<[u8; 6]> = expected
+            .macs()
+            .chain(self.seen_index.macs())
+            .filter(|mac| expected.get(mac) != self.seen_index.get(mac))
+            .copied()
+            .collect();
+        stale.sort_unstable();
+        stale.dedup();
+        issues.extend(
+            stale
+                .into_iter()
+                .map(|mac| IndexIssue::StaleSpan { mac: MacAddr(mac) }),
+        );
+
+        IntegrityReport {
+            records: records.len(),
+            issues,
+        }
+    }
+
+    /// Rebuild every derived index from the record store: the MAC index and
+    /// its Bloom filter, RSSI, time, R-tree, receiver, floor, path loss,
+    /// quality, first/last seen and zone memberships. The (mac, timestamp)
+    /// key index, if built, drops entries no record backs and gains the
+    /// records it lacks. Time partitions come back unpacked.
+    pub fn rebuild_indices(&mut self) {
+        self.rebuild_core_indices();
+        self.reindex_zones();
+        if let Some(mut key_index) = self.key_index.take() {
+            let records = &self.records;
+            key_index.retain(|&(mac, timestamp), (record_id, _)| {
+                records
+                    .get(*record_id)
+                    .is_some_and(|obs| obs.mac == mac && obs.timestamp == timestamp)
+            });
+            for (record_id, obs) in records.iter().enumerate() {
+                key_index
+                    .entry((obs.mac, obs.timestamp))
+                    .or_insert((record_id, 1));
+            }
+            self.key_index = Some(key_index);
+        }
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+    }
+}
+
+/// (key, record ID) entries of a posting-list index
+fn postings<'a, K: Copy + 'a>(
+    index: impl Iterator<Item = (&'a K, &'a Vec<usize>)>,
+) -> Vec<(K, usize)> {
+    index
+        .flat_map(|(&key, ids)| ids.iter().map(move |&id| (key, id)))
+        .collect()
+}
+
+/// Coordinates as a totally ordered key
+fn coord_bits([lat, lon]: [f64; 2]) -> (u64, u64) {
+    (lat.to_bits(), lon.to_bits())
+}
+
+/// Report entries only in `stored` as orphans and only in `implied` as
+/// missing
+fn diff<K: Ord>(
+    issues: &mut Vec<IndexIssue>,
+    index: Dimension,
+    mut stored: Vec<(K, usize)>,
+    mut implied: Vec<(K, usize)>,
+) {
+    stored.sort_unstable();
+    implied.sort_unstable();
+    let mut orphans = Vec::new();
+    let mut missing = Vec::new();
+    let (mut s, mut i) = (0, 0);
+    while s < stored.len() || i < implied.len() {
+        match (stored.get(s), implied.get(i)) {
+            (Some(a), Some(b)) if a == b => {
+                s += 1;
+                i += 1;
+            }
+            (Some(a), b) if b.is_none_or(|b| a < b) => {
+                orphans.push(a.1);
+                s += 1;
+            }
+            (_, Some(b)) => {
+                missing.push(b.1);
+                i += 1;
+            }
+            (_, None) => unreachable!("loop runs while either side has entries"),
+        }
+    }
+    orphans.sort_unstable();
+    missing.sort_unstable();
+    issues.extend(
+        orphans
+            .into_iter()
+            .map(|record_id| IndexIssue::Orphan { index, record_id }),
+    );
+    issues.extend(
+        missing
+            .into_iter()
+            .map(|record_id| IndexIssue::Missing { index, record_id }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::{BleObservation, DuplicatePolicy};
+    use crate::query::Query;
+    use crate::zone::Zone;
+
+    fn obs(i: u8) -> BleObservation {
+        BleObservation {
+            mac: [i % 3; 6],
+            rssi: -40 - i as i8,
+            timestamp: i as i64 * 10,
+            lat: 37.0 + i as f64 * 1e-4,
+            lon: -122.0,
+            receiver_id: Some(u16::from(i % 2)),
+            tx_power: Some(-59),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_verify_and_rebuild() {
+        let mut cube = BleCube::new();
+        cube.add_zone("near", Zone::circle(37.0, -122.0, 200.0));
+        for i in 0..10 {
+            cube.insert(obs(i));
+        }
+        cube.upsert_by_key(obs(3), DuplicatePolicy::KeepExisting);
+        assert!(cube.verify_integrity().is_consistent());
+        assert_eq!(cube.verify_integrity().records, 10);
+
+        // Drift: a record rewritten behind the indices' back, a stray
+        // posting and a lost one
+        cube.records.replace(
+            4,
+            BleObservation {
+                mac: [9; 6],
+                ..obs(4)
+            },
+        );
+        cube.rssi_index.entry(-99).or_default().push(12);
+        cube.receiver_index
+            .get_mut(&1)
+            .unwrap()
+            .retain(|&id| id != 7);
+
+        let report = cube.verify_integrity();
+        assert!(!report.is_consistent());
+        assert_eq!(
+            report.issues,
+            [
+                IndexIssue::Orphan {
+                    index: Dimension::Mac,
+                    record_id: 4
+                },
+                IndexIssue::Missing {
+                    index: Dimension::Mac,
+                    record_id: 4
+                },
+                IndexIssue::Orphan {
+                    index: Dimension::Rssi,
+                    record_id: 12
+                },
+                IndexIssue::Missing {
+                    index: Dimension::Receiver,
+                    record_id: 7
+                },
+                IndexIssue::StaleSpan {
+                    mac: MacAddr([9; 6])
+                },
+            ]
+        );
+        assert!(cube.execute(&Query::new().mac([9; 6])).is_empty());
+
+        cube.rebuild_indices();
+        assert!(cube.verify_integrity().is_consistent());
+        assert_eq!(cube.execute(&Query::new().mac([9; 6])).len(), 1);
+        assert_eq!(cube.execute(&Query::new().receiver(1)).len(), 5);
+        assert_eq!(cube.query_zone("near", None).len(), 10);
+        // The key index follows the rewritten record
+        let outcome = cube.upsert_by_key(
+            BleObservation {
+                mac: [9; 6],
+                ..obs(4)
+            },
+            DuplicatePolicy::KeepExisting,
+        );
+        assert_eq!(outcome.record_id(), 4);
+        assert_eq!(cube.len(), 10);
+    }
+}
@@ -0,0 +1,144 @@
+//! Core of `ble-cube`: the in-memory 4-dimensional index for BLE scanner
+//! observations.
+//!
+//! `BleCube`, `BleObservation`, the query engine, geo math, zones, tags,
+//! beacon decoding and the HCI report parser live here with no dependencies
+//! beyond `rstar` and `libm`. File formats are in `ble-cube-io`, analytics
+//! in `ble-cube-analytics`, and threaded runners (MQTT, sharding, surveys,
+//! background maintenance) in `ble-cube-server`; the `ble-cube` crate
+//! re-exports all of them behind features.
+//!
+//! Without `std` the core is `no_std` + `alloc` (hash maps become B-tree maps
+//! and float math uses `libm`). Change feeds, query deadlines, retention
+//! eviction, maintenance passes, the query cache and insert sinks need
+//! `std`; the `try_*` inserts work without it but cannot fail on I/O.
+//!
+//! | Feature | Default | Module |
+//! |---------|---------|--------|
+//! | `std`   | yes     | standard library support; off = `no_std` + `alloc` core |
+//! | `wal`   | no      | write-ahead log (`BleCube::recover`, `checkpoint`, implies `std`) |
+//! | `serde` | no      | `Serialize`/`Deserialize` for observations, queries and results |
+//! | `cold`  | no      | memory-mapped segments for frozen partitions (`freeze_partitions_before`, implies `std`) |
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+extern crate std;
+
+#[cfg(feature = "std")]
+mod alert;
+mod asof;
+mod beacon;
+mod ble_cube;
+mod bloom;
+mod builder;
+mod calendar;
+mod calibration;
+mod cancel;
+mod checksum;
+mod clock;
+mod codec;
+#[cfg(feature = "cold")]
+mod cold;
+mod columns;
+mod compat;
+mod corridor;
+mod crs;
+mod cube_set;
+mod error;
+mod explain;
+pub mod geo;
+mod group;
+mod hci;
+mod histogram;
+mod identity;
+mod integrity;
+mod lifecycle;
+mod mac;
+#[cfg(feature = "std")]
+mod maintenance;
+mod memory;
+mod partition;
+mod path_loss;
+mod postings;
+mod projection;
+mod quality;
+mod query;
+#[cfg(feature = "std")]
+mod query_cache;
+mod query_str;
+mod rate_limit;
+mod record_id;
+mod replication;
+mod sample;
+mod schedule;
+#[cfg(feature = "std")]
+mod sink;
+#[cfg(feature = "std")]
+mod subscribe;
+mod synthetic;
+mod tag;
+mod time;
+mod validate;
+mod visit;
+#[cfg(feature = "wal")]
+mod wal;
+mod zone;
+
+#[cfg(feature = "std")]
+pub use alert::{Alert, AlertRule};
+pub use beacon::{
+    parse_advertisement, BeaconFrame, EddystoneTlm, EddystoneUid, EddystoneUrl, IBeacon,
+};
+pub use ble_cube::{BleCube, BleObservation, DistanceMetric, DuplicatePolicy, UpsertOutcome};
+pub use builder::CubeBuilder;
+pub use calendar::{DstRule, LocalTime, TimeZone};
+pub use calibration::RssiOffsetEstimate;
+pub use cancel::{CancelToken, Interrupt, QueryInterrupted};
+pub use clock::ClockOffset;
+#[cfg(feature = "cold")]
+pub use cold::{ColdSegment, ColdTier};
+pub use crs::CoordinateSystem;
+pub use cube_set::CubeSet;
+pub use error::CubeError;
+pub use explain::{IndexStats, PlanStage, PostingStats, QueryPlan};
+pub use group::{Group, GroupBy};
+pub use hci::{parse_hci_event, AdvertisingReport, HciError, HciReceiver};
+pub use histogram::HistogramBin;
+pub use integrity::{IndexIssue, IntegrityReport};
+pub use mac::{MacAddr, MacParseError, RandomAddressKind};
+#[cfg(feature = "std")]
+pub use maintenance::{MaintenanceConfig, MaintenanceReport};
+pub use memory::{ComponentMemory, MemoryFootprint};
+pub use partition::TimePartition;
+pub use projection::{Field, Projection};
+pub use query::{Dimension, DistinctKeep, Query};
+#[cfg(feature = "std")]
+pub use query_cache::QueryCacheStats;
+pub use query_str::QueryParseError;
+pub use rate_limit::{RateLimitKeep, RateLimitStats};
+pub use record_id::RecordId;
+pub use replication::{Delta, DeltaDecodeError};
+pub use schedule::{Presence, ScanSchedule};
+#[cfg(feature = "std")]
+pub use sink::ObservationSink;
+pub use synthetic::SyntheticConfig;
+pub use time::{TimeUnit, Timestamp, UnitMismatch};
+pub use validate::{QuarantinedObservation, ValidationPolicy, ValidationRules, Violation};
+#[cfg(feature = "wal")]
+pub use wal::WalConfig;
+pub use zone::Zone;
+
+/// Helpers shared with the other `ble-cube` crates. Not a stable API.
+#[doc(hidden)]
+pub mod internal {
+    pub use crate::ble_cube::GeoPoint;
+    pub use crate::bloom::hash_mac;
+    pub use crate::checksum::crc32;
+    pub use crate::compat::{prelude, HashMap, MapKey};
+    pub use crate::identity::Aes128;
+    #[cfg(feature = "serde")]
+    pub use crate::mac::serde_octets;
+    pub use crate::sample::{Reservoir, SplitMix64};
+}
@@ -0,0 +1,198 @@
+//! First/last-seen tracking per device.
+//!
+//! Keeps the earliest and latest timestamp of every MAC, updated on insert,
+//! so `first_seen` / `last_seen` are a hash lookup and the lifecycle filters
+//! of [`Query`](crate::Query) (`new_since`, `not_seen_since`) can pick whole
+//! devices without touching their records.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::mac::MacAddr;
+use crate::memory::{hash_table_bytes, ComponentMemory};
+
+/// MAC -> (first seen, last seen)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SeenIndex {
+    spans: HashMap<[u8; 6], (i64, i64)>,
+}
+
+impl SeenIndex {
+    pub(crate) fn from_records(records: &[BleObservation]) -> Self {
+        let mut index = Self::default();
+        for obs in records {
+            index.observe(obs.mac, obs.timestamp);
+        }
+        index
+    }
+
+    pub(crate) fn observe(&mut self, mac: [u8; 6], timestamp: i64) {
+        self.spans
+            .entry(mac)
+            .and_modify(|(first, last)| {
+                *first = (*first).min(timestamp);
+                *last = (*last).max(timestamp);
+            })
+            .or_insert((timestamp, timestamp));
+    }
+
+    /// Recompute one MAC's span from its postings after records moved or
+    /// changed (dropping it if it has none left)
+    pub(crate) fn refresh(
+        &mut self,
+        mac: [u8; 6],
+        postings: Option<&Vec<usize>>,
+        timestamps: &[i64],
+    ) {
+        let span = postings.and_then(|ids| {
+            let first = ids.iter().map(|&id| timestamps[id]).min()?;
+            let last = ids.iter().map(|&id| timestamps[id]).max()?;
+            Some((first, last))
+        });
+        match span {
+            Some(span) => {
+                self.spans.insert(mac, span);
+            }
+            None => {
+                self.spans.remove(&mac);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, mac: &[u8; 6]) -> Option<(i64, i64)> {
+        self.spans.get(mac).copied()
+    }
+
+    pub(crate) fn macs(&self) -> impl Iterator<Item = &[u8; 6]> + '_ {
+        self.spans.keys()
+    }
+
+    /// MACs whose span passes both lifecycle bounds
+    pub(crate) fn macs_matching(
+        &self,
+        new_since: Option<i64>,
+        not_seen_since: Option<i64>,
+    ) -> impl Iterator<Item = &[u8; 6]> + '_ {
+        self.spans
+            .iter()
+            .filter(move |(_, &span)| span_matches(span, new_since, not_seen_since))
+            .map(|(mac, _)| mac)
+    }
+
+    pub(crate) fn memory(&self) -> ComponentMemory {
+        ComponentMemory {
+            entries: self.spans.len(),
+            bytes: hash_table_bytes(&self.spans),
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.spans.shrink_to_fit();
+    }
+}
+
+/// `new_since`: first seen at or after it; `not_seen_since`: last seen
+/// strictly before it
+pub(crate) fn span_matches(
+    (first, last): (i64, i64),
+    new_since: Option<i64>,
+    not_seen_since: Option<i64>,
+) -> bool {
+    new_since.is_none_or(|ts| first >= ts) && not_seen_since.is_none_or(|ts| last < ts)
+}
+
+impl BleCube {
+    /// Timestamp of the earliest observation of `mac`
+    pub fn first_seen<M: Into<MacAddr>>(&self, mac: M) -> Option<i64> {
+        self.device_span(&mac.into().0).map(|(first, _)| first)
+    }
+
+    /// Timestamp of the latest observation of `mac`
+    pub fn last_seen<M: Into<MacAddr>>(&self, mac: M) -> Option<i64> {
+        self.device_span(&mac.into().0).map(|(_, last)| last)
+    }
+
+    /// First and last sighting of `mac`, including frozen cold segments
+    pub(crate) fn device_span(&self, mac: &[u8; 6]) -> Option<(i64, i64)> {
+        let hot = self.seen_index.get(mac);
+        #[cfg(feature = "cold")]
+        if let Some(cold) = self.cold.as_ref().and_then(|cold| cold.span(mac)) {
+            return Some(hot.map_or(cold, |(first, last)| (first.min(cold.0), last.max(cold.1))));
+        }
+        hot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+
+    fn obs(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_first_and_last_seen() {
+        let mut cube = BleCube::new();
+        for (mac, ts) in [(1, 50), (1, 10), (1, 30), (2, 40)] {
+            cube.insert(obs(mac, ts));
+        }
+        assert_eq!(cube.first_seen([1; 6]), Some(10));
+        assert_eq!(cube.last_seen([1; 6]), Some(50));
+        assert_eq!(cube.first_seen([2; 6]), Some(40));
+        assert_eq!(cube.last_seen([3; 6]), None);
+
+        // Rewriting records recomputes the spans they leave and join
+        cube.replace_record(3, obs(3, 45));
+        assert_eq!(cube.last_seen([2; 6]), None);
+        assert_eq!(cube.first_seen([3; 6]), Some(45));
+        cube.replace_record(1, obs(1, 60));
+        assert_eq!(cube.first_seen([1; 6]), Some(30));
+        assert_eq!(cube.last_seen([1; 6]), Some(60));
+
+        let bulk = BleCube::bulk_load(vec![obs(1, 7), obs(1, 3)]);
+        assert_eq!(bulk.first_seen([1; 6]), Some(3));
+    }
+
+    #[test]
+    fn test_lifecycle_filters() {
+        let mut cube = BleCube::new();
+        // MAC 1: regular, MAC 2: departed after t=20, MAC 3: new at t=100
+        for (mac, ts) in [
+            (1, 0),
+            (2, 10),
+            (1, 50),
+            (2, 20),
+            (3, 100),
+            (1, 110),
+            (3, 120),
+        ] {
+            cube.insert(obs(mac, ts));
+        }
+
+        let new = Query::new().new_since(100);
+        assert_eq!(cube.execute_ids(&new), vec![4, 6]);
+        // Only the new device's sightings within the window
+        assert_eq!(cube.execute_ids(&new.time_between(0, 110)), vec![4]);
+
+        let departed = Query::new().not_seen_since(100);
+        assert_eq!(cube.execute_ids(&departed), vec![1, 3]);
+        assert!(cube
+            .execute_ids(&Query::new().new_since(100).not_seen_since(100))
+            .is_empty());
+
+        // Evaluated against the cube as it is now: a late sighting of MAC 2
+        // makes it no longer departed
+        cube.insert(obs(2, 130));
+        assert!(cube.execute_ids(&departed).is_empty());
+
+        let plan = cube.explain(&Query::new().new_since(100).rssi_between(-100, 0));
+        assert_eq!(plan.driver, Some(crate::query::Dimension::Lifecycle));
+        assert_eq!(plan.candidates, 2);
+    }
+}
@@ -0,0 +1,365 @@
+//! MAC address newtype with parsing, formatting, and address-type helpers.
+
+use crate::ble_cube::BleObservation;
+use crate::compat::prelude::*;
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+
+/// 48-bit MAC address
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+/// Sub-type of a BLE random device address (top two bits of the MSB)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RandomAddressKind {
+    /// `11` — static random, stable until power cycle
+    Static,
+    /// `01` — resolvable private address (RPA), rotates; resolvable with an IRK
+    ResolvablePrivate,
+    /// `00` — non-resolvable private address, rotates
+    NonResolvablePrivate,
+    /// `10` — reserved for future use
+    Reserved,
+}
+
+impl MacAddr {
+    pub const fn new(octets: [u8; 6]) -> Self {
+        Self(octets)
+    }
+
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Organizationally Unique Identifier (first three octets)
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+
+    /// U/L bit set: the address was assigned locally (e.g. randomized for
+    /// privacy) rather than burned in by the vendor, so the OUI is meaningless
+    pub fn is_locally_administered(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+
+    /// I/G bit set: group (multicast) address
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Random-address sub-type, assuming this is a BLE random address
+    /// (the advertiser's TxAdd bit is not part of the address itself)
+    pub fn random_address_kind(&self) -> RandomAddressKind {
+        match self.0[0] >> 6 {
+            0b11 => RandomAddressKind::Static,
+            0b01 => RandomAddressKind::ResolvablePrivate,
+            0b00 => RandomAddressKind::NonResolvablePrivate,
+            _ => RandomAddressKind::Reserved,
+        }
+    }
+
+    /// Assuming a BLE random address, whether it rotates over time
+    pub fn is_rotating(&self) -> bool {
+        matches!(
+            self.random_address_kind(),
+            RandomAddressKind::ResolvablePrivate | RandomAddressKind::NonResolvablePrivate
+        )
+    }
+}
+
+impl BleObservation {
+    /// This observation's MAC as a [`MacAddr`]
+    pub fn mac_addr(&self) -> MacAddr {
+        MacAddr(self.mac)
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self {
+        Self(octets)
+    }
+}
+
+impl From<&[u8; 6]> for MacAddr {
+    fn from(octets: &[u8; 6]) -> Self {
+        Self(*octets)
+    }
+}
+
+impl From<&MacAddr> for MacAddr {
+    fn from(mac: &MacAddr) -> Self {
+        *mac
+    }
+}
+
+impl From<MacAddr> for [u8; 6] {
+    fn from(mac: MacAddr) -> Self {
+        mac.0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02X}:{b:02X}:{c:02X}:{d:02X}:{e:02X}:{g:02X}")
+    }
+}
+
+impl fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MacAddr({self})")
+    }
+}
+
+/// Error returned when a string is not a recognized MAC address format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacParseError {
+    input: String,
+}
+
+impl fmt::Display for MacParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid MAC address: {:?}", self.input)
+    }
+}
+
+impl Error for MacParseError {}
+
+impl FromStr for MacAddr {
+    type Err = MacParseError;
+
+    /// Accepts `AA:BB:CC:DD:EE:FF`, `AA-BB-CC-DD-EE-FF`, `aabb.ccdd.eeff`
+    /// and bare `aabbccddeeff`, case-insensitive
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || MacParseError {
+            input: s.to_string(),
+        };
+
+        let groups: Vec<&str> = if s.contains(':') {
+            s.split(':').collect()
+        } else if s.contains('-') {
+            s.split('-').collect()
+        } else if s.contains('.') {
+            s.split('.').collect()
+        } else {
+            vec![s]
+        };
+
+        let group_len = match groups.len() {
+            6 => 2,
+            3 => 4,
+            1 => 12,
+            _ => return Err(err()),
+        };
+        if groups
+            .iter()
+            .any(|g| g.len() != group_len || !g.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            return Err(err());
+        }
+
+        let hex: String = groups.concat();
+        let mut octets = [0u8; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            *octet = hex
+                .get(i * 2..i * 2 + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(err)?;
+        }
+        Ok(Self(octets))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MacAddr {
+    /// `"AA:BB:CC:DD:EE:FF"` in human-readable formats, 6 raw bytes otherwise
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MacAddr {
+    /// Accepts any string format [`MacAddr::from_str`] does, 6 raw bytes, or
+    /// a sequence of 6 integers
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error as _, SeqAccess, Visitor};
+
+        struct MacVisitor;
+
+        impl<'de> Visitor<'de> for MacVisitor {
+            type Value = MacAddr;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a MAC address string or 6 bytes")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<MacAddr, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<MacAddr, E> {
+                <[u8; 6]>::try_from(v)
+                    .map(MacAddr)
+                    .map_err(|_| E::invalid_length(v.len(), &self))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MacAddr, A::Error> {
+                let mut octets = [0u8; 6];
+                for (i, octet) in octets.iter_mut().enumerate() {
+                    *octet = seq
+                        .next_element()?
+                        .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+                }
+                if seq.next_element::<u8>()?.is_some() {
+                    return Err(A::Error::invalid_length(7, &self));
+                }
+                Ok(MacAddr(octets))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(MacVisitor)
+        } else {
+            deserializer.deserialize_bytes(MacVisitor)
+        }
+    }
+}
+
+/// `#[serde(with = ...)]` adapter so raw `[u8; 6]` fields serialize like
+/// [`MacAddr`]
+#[cfg(feature = "serde")]
+pub mod serde_octets {
+    use super::MacAddr;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(mac: &[u8; 6], serializer: S) -> Result<S::Ok, S::Error> {
+        MacAddr(*mac).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 6], D::Error> {
+        MacAddr::deserialize(deserializer).map(|mac| mac.0)
+    }
+
+    pub mod option {
+        use super::MacAddr;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            mac: &Option<[u8; 6]>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            mac.map(MacAddr).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<[u8; 6]>, D::Error> {
+            Option::<MacAddr>::deserialize(deserializer).map(|mac| mac.map(|mac| mac.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let expected = MacAddr([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        for input in [
+            "AA:BB:CC:DD:EE:FF",
+            "aa:bb:cc:dd:ee:ff",
+            "AA-BB-CC-DD-EE-FF",
+            "aabb.ccdd.eeff",
+            "AABBCCDDEEFF",
+        ] {
+            assert_eq!(input.parse::<MacAddr>(), Ok(expected), "{input}");
+        }
+
+        for bad in [
+            "",
+            "AA:BB:CC:DD:EE",
+            "AA:BB:CC:DD:EE:GG",
+            "A:BB:CC:DD:EE:FFF",
+            "+a:bb:cc:dd:ee:ff",
+        ] {
+            assert!(bad.parse::<MacAddr>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip_and_ordering() {
+        let mac: MacAddr = "01:23:45:67:89:ab".parse().unwrap();
+        assert_eq!(mac.to_string(), "01:23:45:67:89:AB");
+        assert_eq!(mac.to_string().parse::<MacAddr>().unwrap(), mac);
+        assert_eq!(mac.oui(), [0x01, 0x23, 0x45]);
+        assert!(MacAddr([0; 6]) < mac);
+    }
+
+    #[test]
+    fn test_address_type_bits() {
+        let public = MacAddr([0x00, 0x1A, 0x7D, 0, 0, 1]);
+        assert!(!public.is_locally_administered());
+        assert!(!public.is_multicast());
+
+        assert_eq!(
+            MacAddr([0xC3, 0, 0, 0, 0, 0]).random_address_kind(),
+            RandomAddressKind::Static
+        );
+        let rpa = MacAddr([0x4A, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            rpa.random_address_kind(),
+            RandomAddressKind::ResolvablePrivate
+        );
+        assert!(rpa.is_rotating());
+        assert!(rpa.is_locally_administered());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_round_trip() {
+        use crate::query::Query;
+
+        let obs = BleObservation {
+            rssi: -61,
+            mac: [0xAA, 0xBB, 0xCC, 0x00, 0x11, 0x22],
+            timestamp: 1_700_000_000,
+            lat: 37.5,
+            lon: -122.25,
+            receiver_id: Some(4),
+            floor: None,
+            tx_power: None,
+            quality: None,
+        };
+        let json = serde_json::to_string(&obs).unwrap();
+        assert_eq!(
+            json,
+            r#"{"rssi":-61,"mac":"AA:BB:CC:00:11:22","timestamp":1700000000,"lat":37.5,"lon":-122.25,"receiver_id":4}"#
+        );
+        let back: BleObservation = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.mac, obs.mac);
+        assert_eq!(back.receiver_id, Some(4));
+        assert_eq!(back.floor, None);
+
+        // Byte arrays and other MAC spellings are accepted on input
+        let mac: MacAddr = serde_json::from_str("[170, 187, 204, 0, 17, 34]").unwrap();
+        assert_eq!(mac, MacAddr(obs.mac));
+        let mac: MacAddr = serde_json::from_str(r#""aabb.cc00.1122""#).unwrap();
+        assert_eq!(mac, MacAddr(obs.mac));
+        assert!(serde_json::from_str::<MacAddr>(r#""not a mac""#).is_err());
+        assert!(serde_json::from_str::<MacAddr>("[1, 2, 3]").is_err());
+
+        let query = Query::new().mac(obs.mac).time_between(0, 10);
+        let json = serde_json::to_string(&query).unwrap();
+        assert_eq!(serde_json::from_str::<Query>(&json).unwrap(), query);
+        let partial: Query = serde_json::from_str(r#"{"receiver": 2}"#).unwrap();
+        assert_eq!(partial, Query::new().receiver(2));
+    }
+}
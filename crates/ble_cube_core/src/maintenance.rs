@@ -0,0 +1,168 @@
+//! Periodic housekeeping for long-running cubes.
+//!
+//! A service that inserts for weeks needs two chores done regularly: old
+//! partitions evicted under a retention window, and the R-tree rebuilt once
+//! enough points were inserted or moved one at a time (incremental inserts
+//! leave overlapping nodes that slow every spatial query, a bulk-loaded tree
+//! does not). Closed time partitions can also be packed to save memory.
+//! [`BleCube::run_maintenance`] does one pass; the `Maintenance` runner in
+//! `ble_cube_server` runs passes on a background thread against a shared
+//! cube.
+//!
+//! Posting lists are kept sorted and duplicate-free on every write, and
+//! removed records are compacted out immediately rather than tombstoned, so
+//! neither needs background work.
+
+use crate::ble_cube::{BleCube, GeoPoint};
+use rstar::RTree;
+use std::io;
+use std::time::Duration;
+
+/// What a maintenance pass does
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaintenanceConfig {
+    /// Time between passes of a background runner
+    pub interval: Duration,
+    /// Evict partitions that ended more than this many timestamp units
+    /// before the newest observation (see
+    /// [`BleCube::evict_partitions_before`]); `None` keeps everything
+    pub retention: Option<i64>,
+    /// Rebuild the R-tree once incremental inserts and moves since it was
+    /// last bulk-loaded exceed this fraction of its points
+    pub geo_rebuild_ratio: f64,
+    /// Pack closed time partitions (see [`BleCube::compress_time_index`])
+    pub compress_time_index: bool,
+}
+
+impl MaintenanceConfig {
+    /// Passes every `interval`, no retention, R-tree rebuilt at 50% churn,
+    /// no time index packing
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            retention: None,
+            geo_rebuild_ratio: 0.5,
+            compress_time_index: false,
+        }
+    }
+}
+
+/// Work done by one [`BleCube::run_maintenance`] pass
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MaintenanceReport {
+    /// Records dropped by the retention window
+    pub evicted: usize,
+    /// Whether the R-tree was bulk-loaded again (eviction always rebuilds it)
+    pub geo_rebuilt: bool,
+    /// Time partitions packed
+    pub compressed_partitions: usize,
+}
+
+impl BleCube {
+    /// Do one maintenance pass now: retention eviction, then an R-tree
+    /// rebuild if churn exceeds `config.geo_rebuild_ratio`, then time index
+    /// packing if `config.compress_time_index` is set. Eviction
+    /// compacts the store, shifting record IDs, and releases the freed
+    /// capacity.
+    pub fn run_maintenance(&mut self, config: &MaintenanceConfig) -> io::Result<MaintenanceReport> {
+        let mut report = MaintenanceReport::default();
+        if let (Some(retention), Some(latest)) = (config.retention, self.time_index.latest()) {
+            report.evicted = self
+                .evict_partitions_before(latest.saturating_sub(retention))?
+                .len();
+            if report.evicted > 0 {
+                self.shrink_to_fit();
+                report.geo_rebuilt = self.indexed.geo;
+            }
+        }
+        if self.indexed.geo
+            && self.geo_churn > 0
+            && self.geo_churn as f64 >= config.geo_rebuild_ratio * self.geo_index.size() as f64
+        {
+            self.rebuild_geo_index();
+            report.geo_rebuilt = true;
+        }
+        if config.compress_time_index {
+            report.compressed_partitions = self.compress_time_index();
+        }
+        Ok(report)
+    }
+
+    /// Bulk-load the R-tree again from the record store, undoing the
+    /// overlap left by incremental inserts and moves
+    pub fn rebuild_geo_index(&mut self) {
+        if !self.indexed.geo {
+            return;
+        }
+        let points = self
+            .records
+            .iter()
+            .enumerate()
+            .map(|(record_id, obs)| GeoPoint {
+                coords: [obs.lat, obs.lon],
+                record_id,
+            })
+            .collect();
+        self.geo_index = RTree::bulk_load(points);
+        self.geo_churn = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    fn obs(i: i64) -> BleObservation {
+        BleObservation {
+            mac: [(i % 5) as u8; 6],
+            rssi: -60,
+            timestamp: i * 60,
+            lat: 37.0 + (i % 40) as f64 * 1e-4,
+            lon: -122.0 + (i / 40) as f64 * 1e-4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pass_evicts_and_rebuilds() {
+        let mut cube = BleCube::bulk_load((0..600).map(obs).collect());
+        let quiet = MaintenanceConfig::new(Duration::from_secs(60));
+        // Bulk-loaded, no retention: nothing to do
+        assert_eq!(
+            cube.run_maintenance(&quiet).unwrap(),
+            MaintenanceReport::default()
+        );
+
+        for i in 600..800 {
+            cube.insert(obs(i));
+        }
+        let nearby = cube.query_geo_radius(37.001, -121.999, 50.0).len();
+        let config = MaintenanceConfig {
+            retention: Some(4 * 3600),
+            ..quiet.clone()
+        };
+        let report = cube.run_maintenance(&config).unwrap();
+        // 800 minutes of data, partitions ending more than 4h before the
+        // newest observation go
+        assert_eq!(report.evicted, 9 * 60);
+        assert!(report.geo_rebuilt);
+        assert_eq!(cube.len(), 800 - 540);
+        assert_eq!(cube.geo_churn, 0);
+
+        // Few incremental inserts: no rebuild
+        let before = cube.query_geo_radius(37.001, -121.999, 50.0).len();
+        assert!(before < nearby);
+        cube.insert(obs(800));
+        assert!(!cube.run_maintenance(&quiet).unwrap().geo_rebuilt);
+        assert!(
+            cube.run_maintenance(&MaintenanceConfig {
+                geo_rebuild_ratio: 0.0,
+                ..quiet
+            })
+            .unwrap()
+            .geo_rebuilt
+        );
+        assert_eq!(cube.index_stats().geo_points, cube.len());
+    }
+}
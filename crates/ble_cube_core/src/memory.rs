@@ -0,0 +1,235 @@
+//! Heap usage accounting and capacity trimming.
+//!
+//! Byte counts are estimates of heap allocations (capacity, not length, and
+//! a fixed per-entry overhead for hash tables, B-trees and the R-tree); they
+//! are meant for capacity planning, not exact allocator accounting.
+
+use crate::ble_cube::{BleCube, GeoPoint};
+use crate::compat::prelude::*;
+use crate::compat::{HashMap, MapKey};
+use crate::record_id::RecordId;
+use alloc::collections::BTreeMap;
+use core::mem::size_of;
+
+/// Entry count and estimated heap bytes of one component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComponentMemory {
+    /// Records, distinct keys, or points, depending on the component
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+/// Per-component heap usage, see [`BleCube::memory_footprint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryFootprint {
+    /// Record rows plus their RSSI and timestamp columns
+    pub records: ComponentMemory,
+    /// Stable record IDs, parallel to the record store
+    pub record_ids: ComponentMemory,
+    pub mac_index: ComponentMemory,
+    /// Bloom filter behind [`BleCube::maybe_contains_mac`]
+    pub mac_filter: ComponentMemory,
+    pub rssi_index: ComponentMemory,
+    pub time_index: ComponentMemory,
+    pub geo_index: ComponentMemory,
+    pub receiver_index: ComponentMemory,
+    pub floor_index: ComponentMemory,
+    /// Path loss of records carrying a TX power
+    pub path_loss_index: ComponentMemory,
+    /// Quality score of records carrying one
+    pub quality_index: ComponentMemory,
+    /// Composite (mac, timestamp) index, only built once upserts are used
+    pub key_index: ComponentMemory,
+    /// First/last-seen timestamps per MAC
+    pub seen_index: ComponentMemory,
+    /// Registered zones and their membership postings
+    pub zones: ComponentMemory,
+    /// Interned tags and their record postings
+    pub tags: ComponentMemory,
+    /// Stored advertisements and beacon-identity postings
+    pub beacons: ComponentMemory,
+}
+
+impl MemoryFootprint {
+    /// Sum of every component
+    pub fn total_bytes(&self) -> usize {
+        [
+            self.records,
+            self.record_ids,
+            self.mac_index,
+            self.mac_filter,
+            self.rssi_index,
+            self.time_index,
+            self.geo_index,
+            self.receiver_index,
+            self.floor_index,
+            self.path_loss_index,
+            self.quality_index,
+            self.key_index,
+            self.seen_index,
+            self.zones,
+            self.tags,
+            self.beacons,
+        ]
+        .iter()
+        .map(|c| c.bytes)
+        .sum()
+    }
+}
+
+/// Control byte per bucket plus load-factor slack of hashbrown tables
+const HASH_BUCKET_OVERHEAD: usize = 1;
+/// Parent pointers, lengths and half-full nodes of std B-trees
+pub(crate) const BTREE_ENTRY_OVERHEAD: usize = 16;
+/// Internal R-tree nodes (envelope + child pointer per entry, amortized)
+const RTREE_ENTRY_OVERHEAD: usize = 24;
+
+/// Estimated heap bytes of a hash table (buckets only)
+pub(crate) fn hash_table_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + HASH_BUCKET_OVERHEAD)
+}
+
+/// Estimated heap bytes of a hash map of posting lists
+pub(crate) fn hash_postings_bytes<K>(map: &HashMap<K, Vec<usize>>) -> usize {
+    hash_table_bytes(map) + map.values().map(posting_bytes).sum::<usize>()
+}
+
+/// Estimated heap bytes of a B-tree map of posting lists
+pub(crate) fn btree_postings_bytes<K>(map: &BTreeMap<K, Vec<usize>>) -> usize {
+    map.len() * (size_of::<(K, Vec<usize>)>() + BTREE_ENTRY_OVERHEAD)
+        + map.values().map(posting_bytes).sum::<usize>()
+}
+
+pub(crate) fn posting_bytes(ids: &Vec<usize>) -> usize {
+    ids.capacity() * size_of::<usize>()
+}
+
+/// Release spare capacity of a hash map of posting lists
+pub(crate) fn shrink_hash_postings<K: MapKey>(map: &mut HashMap<K, Vec<usize>>) {
+    map.values_mut().for_each(Vec::shrink_to_fit);
+    map.shrink_to_fit();
+}
+
+impl BleCube {
+    /// Estimated heap usage broken down by component
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        let key_index = self.key_index.as_ref();
+        MemoryFootprint {
+            records: ComponentMemory {
+                entries: self.records.len(),
+                bytes: self.records.heap_bytes(),
+            },
+            record_ids: ComponentMemory {
+                entries: self.record_ids.len(),
+                bytes: self.record_ids.capacity() * size_of::<RecordId>(),
+            },
+            mac_index: ComponentMemory {
+                entries: self.mac_index.len(),
+                bytes: hash_postings_bytes(&self.mac_index),
+            },
+            mac_filter: self.mac_filter.memory(),
+            rssi_index: ComponentMemory {
+                entries: self.rssi_index.len(),
+                bytes: btree_postings_bytes(&self.rssi_index),
+            },
+            time_index: ComponentMemory {
+                entries: self.time_index.len(),
+                bytes: self.time_index.heap_bytes(),
+            },
+            geo_index: ComponentMemory {
+                entries: self.geo_index.size(),
+                bytes: self.geo_index.size() * (size_of::<GeoPoint>() + RTREE_ENTRY_OVERHEAD),
+            },
+            receiver_index: ComponentMemory {
+                entries: self.receiver_index.len(),
+                bytes: hash_postings_bytes(&self.receiver_index),
+            },
+            floor_index: ComponentMemory {
+                entries: self.floor_index.len(),
+                bytes: hash_postings_bytes(&self.floor_index),
+            },
+            path_loss_index: ComponentMemory {
+                entries: self.path_loss_index.len(),
+                bytes: btree_postings_bytes(&self.path_loss_index),
+            },
+            quality_index: ComponentMemory {
+                entries: self.quality_index.len(),
+                bytes: btree_postings_bytes(&self.quality_index),
+            },
+            key_index: ComponentMemory {
+                entries: key_index.map_or(0, HashMap::len),
+                bytes: key_index.map_or(0, hash_table_bytes),
+            },
+            seen_index: self.seen_index.memory(),
+            zones: self.geofence.memory(),
+            tags: self.tags.memory(),
+            beacons: self.beacons.memory(),
+        }
+    }
+
+    /// Release over-allocated capacity in the record store and every index.
+    /// Useful after a bulk load on memory-constrained gateways; subsequent
+    /// inserts will reallocate.
+    pub fn shrink_to_fit(&mut self) {
+        self.records.shrink_to_fit();
+        self.record_ids.shrink_to_fit();
+        shrink_hash_postings(&mut self.mac_index);
+        shrink_hash_postings(&mut self.receiver_index);
+        shrink_hash_postings(&mut self.floor_index);
+        self.rssi_index.values_mut().for_each(Vec::shrink_to_fit);
+        self.path_loss_index
+            .values_mut()
+            .for_each(Vec::shrink_to_fit);
+        self.quality_index.values_mut().for_each(Vec::shrink_to_fit);
+        self.time_index.shrink_to_fit();
+        if let Some(key_index) = self.key_index.as_mut() {
+            key_index.shrink_to_fit();
+        }
+        self.seen_index.shrink_to_fit();
+        self.geofence.shrink_to_fit();
+        self.tags.shrink_to_fit();
+        self.beacons.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    #[test]
+    fn test_footprint_and_shrink() {
+        let mut cube = BleCube::with_capacity(10_000);
+        for i in 0..100u8 {
+            cube.insert(BleObservation {
+                mac: [0, 0, 0, 0, 0, i % 5],
+                timestamp: i64::from(i),
+                ..Default::default()
+            });
+        }
+
+        let before = cube.memory_footprint();
+        assert_eq!(before.records.entries, 100);
+        assert_eq!(before.mac_index.entries, 5);
+        assert_eq!(before.time_index.entries, 100);
+        assert_eq!(before.geo_index.entries, 100);
+        assert_eq!(before.key_index, ComponentMemory::default());
+        assert!(before.records.bytes >= 10_000 * size_of::<BleObservation>());
+
+        cube.shrink_to_fit();
+        let after = cube.memory_footprint();
+        // Rows plus the RSSI, MAC, timestamp and coordinate columns
+        assert_eq!(
+            after.records.bytes,
+            100 * (size_of::<BleObservation>()
+                + size_of::<i8>()
+                + size_of::<[u8; 6]>()
+                + size_of::<i64>()
+                + 2 * size_of::<f64>())
+        );
+        assert!(after.total_bytes() < before.total_bytes());
+        assert_eq!(cube.query_mac([0, 0, 0, 0, 0, 1]).len(), 20);
+    }
+}
@@ -0,0 +1,528 @@
+//! Time-partitioned timestamp index.
+//!
+//! Timestamps are bucketed into fixed-width partitions (one hour of seconds
+//! by default), each holding its own `BTreeMap` of posting lists. Range
+//! queries only descend into the partitions they overlap, and whole
+//! partitions can be evicted once they age out.
+
+#[cfg(feature = "std")]
+use crate::ble_cube::BleObservation;
+use crate::ble_cube::{insert_posting, remove_posting, BleCube};
+use crate::compat::prelude::*;
+use crate::memory::{btree_postings_bytes, BTREE_ENTRY_OVERHEAD};
+use crate::postings::CompressedPostings;
+use alloc::collections::BTreeMap;
+use core::ops::{Bound, RangeBounds};
+#[cfg(feature = "std")]
+use std::io;
+
+/// Default partition width: one hour of second-resolution timestamps
+pub(crate) const DEFAULT_PARTITION_WIDTH: i64 = 3600;
+
+/// Timestamp -> record IDs, split into partitions keyed by
+/// `timestamp.div_euclid(width)`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TimeIndex {
+    width: i64,
+    partitions: BTreeMap<i64, Partition>,
+}
+
+/// Postings of one partition: a B-tree while it takes writes, packed once
+/// sealed by [`TimeIndex::compress`]
+#[derive(Debug, Clone, PartialEq)]
+enum Partition {
+    Open(BTreeMap<i64, Vec<usize>>),
+    Sealed(CompressedPostings),
+}
+
+impl Default for Partition {
+    fn default() -> Self {
+        Self::Open(BTreeMap::new())
+    }
+}
+
+impl Partition {
+    /// The B-tree form, unpacking a sealed partition first
+    fn open(&mut self) -> &mut BTreeMap<i64, Vec<usize>> {
+        if let Self::Sealed(packed) = self {
+            *self = Self::Open(packed.to_map());
+        }
+        match self {
+            Self::Open(map) => map,
+            Self::Sealed(_) => unreachable!("partition was just unpacked"),
+        }
+    }
+
+    /// `(timestamp, record ID)` pairs within `bounds`
+    fn range(&self, bounds: (Bound<i64>, Bound<i64>)) -> impl Iterator<Item = (i64, usize)> + '_ {
+        let (open, sealed) = match self {
+            Self::Open(map) => (Some(map.range(bounds)), None),
+            Self::Sealed(packed) => (None, Some(packed.range(bounds))),
+        };
+        let open = open
+            .into_iter()
+            .flatten()
+            .flat_map(|(&timestamp, ids)| ids.iter().map(move |&id| (timestamp, id)));
+        open.chain(sealed.into_iter().flatten())
+    }
+
+    /// Distinct timestamps
+    fn keys(&self) -> usize {
+        match self {
+            Self::Open(map) => map.len(),
+            Self::Sealed(packed) => packed.keys(),
+        }
+    }
+
+    fn records(&self) -> usize {
+        match self {
+            Self::Open(map) => map.values().map(Vec::len).sum(),
+            Self::Sealed(packed) => packed.len(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn last_key(&self) -> Option<i64> {
+        match self {
+            Self::Open(map) => map.last_key_value().map(|(&timestamp, _)| timestamp),
+            // Only asked of the newest partition, which is never packed by
+            // `compress` itself
+            Self::Sealed(packed) => packed.range(..).last().map(|(timestamp, _)| timestamp),
+        }
+    }
+}
+
+impl Default for TimeIndex {
+    fn default() -> Self {
+        Self::new(DEFAULT_PARTITION_WIDTH)
+    }
+}
+
+impl TimeIndex {
+    pub(crate) fn new(width: i64) -> Self {
+        Self {
+            width,
+            partitions: BTreeMap::new(),
+        }
+    }
+
+    /// Build from (timestamp, record ID) pairs in one pass
+    pub(crate) fn from_pairs(width: i64, mut pairs: Vec<(i64, usize)>) -> Self {
+        pairs.sort_unstable();
+        let mut index = Self::new(width);
+        for run in pairs.chunk_by(|a, b| a.0 == b.0) {
+            let timestamp = run[0].0;
+            index
+                .partitions
+                .entry(timestamp.div_euclid(width))
+                .or_default()
+                .open()
+                .insert(timestamp, run.iter().map(|&(_, id)| id).collect());
+        }
+        index
+    }
+
+    pub(crate) fn width(&self) -> i64 {
+        self.width
+    }
+
+    fn postings_mut(&mut self, timestamp: i64) -> &mut Vec<usize> {
+        self.partitions
+            .entry(timestamp.div_euclid(self.width))
+            .or_default()
+            .open()
+            .entry(timestamp)
+            .or_default()
+    }
+
+    /// Append a record ID larger than any already indexed
+    pub(crate) fn push(&mut self, timestamp: i64, record_id: usize) {
+        self.postings_mut(timestamp).push(record_id);
+    }
+
+    /// Insert a record ID anywhere, keeping its posting list sorted
+    pub(crate) fn insert(&mut self, timestamp: i64, record_id: usize) {
+        insert_posting(self.postings_mut(timestamp), record_id);
+    }
+
+    /// Remove a record ID, dropping empty posting lists and partitions
+    pub(crate) fn remove(&mut self, timestamp: i64, record_id: usize) {
+        let key = timestamp.div_euclid(self.width);
+        let Some(partition) = self.partitions.get_mut(&key) else {
+            return;
+        };
+        let partition = partition.open();
+        if partition
+            .get_mut(&timestamp)
+            .is_some_and(|ids| remove_posting(ids, record_id))
+        {
+            partition.remove(&timestamp);
+            if partition.is_empty() {
+                self.partitions.remove(&key);
+            }
+        }
+    }
+
+    /// `(timestamp, record ID)` pairs in timestamp then record ID order
+    /// within `range`, visiting only the partitions it overlaps. An inverted
+    /// range yields nothing.
+    pub(crate) fn range<R: RangeBounds<i64>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (i64, usize)> + '_ {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        let partition_of = |bound: Bound<i64>, unbounded: i64| match bound {
+            Bound::Included(t) | Bound::Excluded(t) => t.div_euclid(self.width),
+            Bound::Unbounded => unbounded,
+        };
+        let first = partition_of(bounds.0, i64::MIN);
+        let last = partition_of(bounds.1, i64::MAX);
+
+        let partitions = if is_inverted(bounds) {
+            None
+        } else {
+            Some(self.partitions.range(first..=last))
+        };
+        partitions
+            .into_iter()
+            .flatten()
+            .flat_map(move |(_, partition)| partition.range(bounds))
+    }
+
+    /// Record IDs of one timestamp
+    pub(crate) fn get(&self, timestamp: i64) -> impl Iterator<Item = usize> + '_ {
+        self.range(timestamp..=timestamp).map(|(_, id)| id)
+    }
+
+    /// Posting list lengths, in timestamp order
+    pub(crate) fn posting_lens(&self) -> impl Iterator<Item = usize> + '_ {
+        self.partitions.values().flat_map(|partition| {
+            let (open, sealed) = match partition {
+                Partition::Open(map) => (Some(map.values().map(Vec::len)), None),
+                Partition::Sealed(packed) => (None, Some(packed.posting_lens())),
+            };
+            open.into_iter()
+                .flatten()
+                .chain(sealed.into_iter().flatten())
+        })
+    }
+
+    /// Distinct timestamps
+    pub(crate) fn len(&self) -> usize {
+        self.partitions.values().map(Partition::keys).sum()
+    }
+
+    /// Newest indexed timestamp
+    #[cfg(feature = "std")]
+    pub(crate) fn latest(&self) -> Option<i64> {
+        self.partitions.values().next_back()?.last_key()
+    }
+
+    /// Pack every open partition except the newest (which takes the
+    /// appends of a live feed), returning how many were packed
+    pub(crate) fn compress(&mut self) -> usize {
+        let newest = self.partitions.keys().next_back().copied();
+        let mut packed = 0;
+        for (&key, partition) in &mut self.partitions {
+            if let Partition::Open(map) = partition {
+                if Some(key) != newest {
+                    *partition = Partition::Sealed(CompressedPostings::from_map(map));
+                    packed += 1;
+                }
+            }
+        }
+        packed
+    }
+
+    /// Estimated heap bytes, see [`BleCube::memory_footprint`]
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.partitions
+            .values()
+            .map(|partition| {
+                BTREE_ENTRY_OVERHEAD
+                    + match partition {
+                        Partition::Open(map) => btree_postings_bytes(map),
+                        Partition::Sealed(packed) => packed.heap_bytes(),
+                    }
+            })
+            .sum()
+    }
+
+    /// Release spare capacity of open posting lists
+    pub(crate) fn shrink_to_fit(&mut self) {
+        for partition in self.partitions.values_mut() {
+            if let Partition::Open(map) = partition {
+                map.values_mut().for_each(Vec::shrink_to_fit);
+            }
+        }
+    }
+
+    fn summaries(&self) -> impl Iterator<Item = TimePartition> + '_ {
+        self.partitions
+            .iter()
+            .map(|(&key, partition)| TimePartition {
+                start: key.saturating_mul(self.width),
+                end: key.saturating_add(1).saturating_mul(self.width),
+                records: partition.records(),
+                compressed: matches!(partition, Partition::Sealed(_)),
+            })
+    }
+}
+
+/// Whether `BTreeMap::range` would reject these bounds (start past end)
+fn is_inverted(bounds: (Bound<i64>, Bound<i64>)) -> bool {
+    match bounds {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e)) => s >= e,
+        _ => false,
+    }
+}
+
+/// One time partition, see [`BleCube::time_partitions`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimePartition {
+    /// First timestamp covered (inclusive)
+    pub start: i64,
+    /// End of the partition (exclusive)
+    pub end: i64,
+    pub records: usize,
+    /// Whether the partition's postings are packed, see
+    /// [`BleCube::compress_time_index`]
+    pub compressed: bool,
+}
+
+impl BleCube {
+    /// Re-bucket the timestamp index into partitions `width` timestamp units
+    /// wide (default 3600, one hour of seconds). Pick a width that matches
+    /// the cube's time unit and retention granularity.
+    ///
+    /// # Panics
+    /// Panics if `width` is not positive.
+    pub fn set_time_partition_width(&mut self, width: i64) {
+        assert!(width > 0, "time partition width must be positive");
+        if width == self.time_index.width() {
+            return;
+        }
+        let pairs = self
+            .records
+            .iter()
+            .enumerate()
+            .map(|(record_id, obs)| (obs.timestamp, record_id))
+            .collect();
+        self.time_index = TimeIndex::from_pairs(width, pairs);
+    }
+
+    /// Width of each time partition, in timestamp units
+    pub fn time_partition_width(&self) -> i64 {
+        self.time_index.width()
+    }
+
+    /// Pack the timestamp postings of every partition except the newest
+    /// into delta + varint encoded blocks, returning how many partitions
+    /// were packed. A packed partition typically needs under a tenth of the
+    /// memory (compare [`BleCube::memory_footprint`] before and after); range
+    /// scans over packed partitions decode as they go. A write landing in a
+    /// packed partition (a late insert, an upsert, a delete) unpacks it
+    /// again, so call this, or let [`BleCube::run_maintenance`] call it,
+    /// once partitions have closed.
+    pub fn compress_time_index(&mut self) -> usize {
+        self.time_index.compress()
+    }
+
+    /// Non-empty time partitions in ascending order
+    pub fn time_partitions(&self) -> Vec<TimePartition> {
+        self.time_index.summaries().collect()
+    }
+
+    /// Drop every partition that ends at or before `cutoff`, returning the
+    /// evicted observations in record ID order (e.g. to archive them).
+    ///
+    /// Remaining records are compacted, so their record IDs shift down. With
+    /// a write-ahead log attached the cube is checkpointed afterwards, so
+    /// recovery does not resurrect evicted records.
+    #[cfg(feature = "std")]
+    pub fn evict_partitions_before(&mut self, cutoff: i64) -> io::Result<Vec<BleObservation>> {
+        let evict = self.expired_records(cutoff);
+        if !evict.contains(&true) {
+            return Ok(Vec::new());
+        }
+
+        let evicted = self.compact(|record_id, _| !evict[record_id]);
+        #[cfg(feature = "wal")]
+        if self.wal.is_some() {
+            self.checkpoint()?;
+        }
+        Ok(evicted)
+    }
+
+    /// Per record ID, whether its partition ends at or before `cutoff`
+    #[cfg(feature = "std")]
+    pub(crate) fn expired_records(&self, cutoff: i64) -> Vec<bool> {
+        let mut expired = vec![false; self.records.len()];
+        let partitions = self.time_index.partitions.iter().take_while(|&(&key, _)| {
+            key.saturating_add(1).saturating_mul(self.time_index.width) <= cutoff
+        });
+        for (_, partition) in partitions {
+            for (_, id) in partition.range((Bound::Unbounded, Bound::Unbounded)) {
+                expired[id] = true;
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    fn cube_with_hours(hours: i64) -> BleCube {
+        let mut cube = BleCube::new();
+        for t in (0..hours * 3600).step_by(600) {
+            cube.insert(BleObservation {
+                mac: [0, 0, 0, 0, 0, (t / 3600) as u8],
+                timestamp: t,
+                ..Default::default()
+            });
+        }
+        cube
+    }
+
+    #[test]
+    fn test_range_prunes_partitions() {
+        let cube = cube_with_hours(4);
+        let parts = cube.time_partitions();
+        assert_eq!(parts.len(), 4);
+        assert_eq!(
+            (parts[1].start, parts[1].end, parts[1].records),
+            (3600, 7200, 6)
+        );
+
+        // Spans the end of hour 0 and the start of hour 1
+        let hits: Vec<i64> = cube
+            .query_time_range(3000, 4200)
+            .iter()
+            .map(|o| o.timestamp)
+            .collect();
+        assert_eq!(hits, vec![3000, 3600, 4200]);
+        assert_eq!(cube.query_time_before(600).len(), 1);
+        assert_eq!(cube.query_time_after(13_200).len(), 1);
+        assert!(cube.query_time_range(5000, 4000).is_empty());
+        assert_eq!(cube.query_timestamp(7200).len(), 1);
+
+        let mut daily = cube_with_hours(4);
+        daily.set_time_partition_width(86_400);
+        assert_eq!(daily.time_partitions().len(), 1);
+        assert_eq!(daily.query_time_range(3000, 4200).len(), 3);
+    }
+
+    #[test]
+    fn test_negative_timestamps_partition_by_floor() {
+        let mut index = TimeIndex::new(10);
+        for (id, t) in [-11, -10, -1, 0, 9, 10].into_iter().enumerate() {
+            index.push(t, id);
+        }
+        let keys: Vec<i64> = index.partitions.keys().copied().collect();
+        assert_eq!(keys, vec![-2, -1, 0, 1]);
+        let hits: Vec<i64> = index.range(-10..10).map(|(t, _)| t).collect();
+        assert_eq!(hits, vec![-10, -1, 0, 9]);
+
+        index.remove(-11, 0);
+        assert_eq!(index.partitions.len(), 3);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_evict_compacts_remaining_records() {
+        let mut cube = cube_with_hours(3);
+        cube.add_zone("all", crate::zone::Zone::circle(0.0, 0.0, 10.0));
+
+        // 5400 is mid-partition: only hour 0 ends by then
+        let evicted = cube.evict_partitions_before(5400).unwrap();
+        assert_eq!(evicted.len(), 6);
+        assert!(evicted.iter().all(|o| o.timestamp < 3600));
+        assert_eq!(cube.len(), 12);
+        assert_eq!(cube.get(0).unwrap().timestamp, 3600);
+        assert_eq!(cube.time_partitions().len(), 2);
+        assert!(cube.query_mac([0, 0, 0, 0, 0, 0]).is_empty());
+        assert_eq!(cube.query_mac([0, 0, 0, 0, 0, 2]).len(), 6);
+        assert_eq!(cube.query_zone("all", None).len(), 12);
+        assert_eq!(cube.query_geo_radius(0.0, 0.0, 1.0).len(), 12);
+        assert!(cube.evict_partitions_before(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compressed_partitions_answer_queries() {
+        let seconds = |hours: i64| {
+            BleCube::bulk_load(
+                (0..hours * 3600)
+                    .map(|t| BleObservation {
+                        mac: [0, 0, 0, 0, 0, (t % 7) as u8],
+                        timestamp: t,
+                        ..Default::default()
+                    })
+                    .collect(),
+            )
+        };
+        let mut cube = seconds(4);
+        let plain = seconds(4);
+        let before = cube.memory_footprint().time_index;
+
+        // The newest partition keeps taking appends
+        assert_eq!(cube.compress_time_index(), 3);
+        assert_eq!(cube.compress_time_index(), 0);
+        let compressed: Vec<bool> = cube
+            .time_partitions()
+            .iter()
+            .map(|p| p.compressed)
+            .collect();
+        assert_eq!(compressed, [true, true, true, false]);
+        let after = cube.memory_footprint().time_index;
+        assert_eq!(after.entries, before.entries);
+        // Three of four partitions shrink more than tenfold
+        assert!(after.bytes * 3 < before.bytes);
+
+        let ids = |cube: &BleCube, start, end| -> Vec<usize> {
+            cube.time_index
+                .range(start..=end)
+                .map(|(_, id)| id)
+                .collect()
+        };
+        for (start, end) in [
+            (0, 14_399),
+            (1000, 1000),
+            (3599, 7300),
+            (-5, 3),
+            (9000, 8000),
+        ] {
+            assert_eq!(ids(&cube, start, end), ids(&plain, start, end));
+        }
+        assert_eq!(cube.query_timestamp(5000).len(), 1);
+        assert_eq!(
+            cube.execute(
+                &crate::Query::new()
+                    .time_between(100, 7300)
+                    .mac([0, 0, 0, 0, 0, 3])
+            )
+            .len(),
+            plain
+                .execute(
+                    &crate::Query::new()
+                        .time_between(100, 7300)
+                        .mac([0, 0, 0, 0, 0, 3])
+                )
+                .len()
+        );
+        assert_eq!(cube.index_stats().time, plain.index_stats().time);
+
+        // A late insert unpacks its partition
+        cube.insert(BleObservation {
+            timestamp: 10,
+            ..Default::default()
+        });
+        assert!(!cube.time_partitions()[0].compressed);
+        assert_eq!(cube.query_timestamp(10).len(), 2);
+    }
+}
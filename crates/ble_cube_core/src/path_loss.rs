@@ -0,0 +1,100 @@
+//! TX-power-aware signal strength.
+//!
+//! Raw RSSI depends on how loud the transmitter is: a -70 dBm reading is a
+//! nearby whisper from a low-power tag and a distant shout from a gateway.
+//! An observation that carries the transmitter's calibrated power
+//! ([`BleObservation::tx_power`], the RSSI expected at 1 m) also has a path
+//! loss, `tx_power - rssi`, which compares across beacons and maps to
+//! distance. Path loss is indexed like RSSI and queried with
+//! [`BleCube::query_path_loss_range`] or [`Query::path_loss_between`](crate::Query::path_loss_between).
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+
+impl BleObservation {
+    /// Attenuation in dB beyond the 1 m calibration (`tx_power - rssi`),
+    /// `None` without a TX power. 0 at 1 m, about +6 dB per doubling of
+    /// distance in free space.
+    pub fn path_loss(&self) -> Option<i16> {
+        self.tx_power
+            .map(|tx_power| i16::from(tx_power) - i16::from(self.rssi))
+    }
+}
+
+impl BleCube {
+    /// Observations with path loss in [min, max] dB inclusive, ascending by
+    /// path loss; observations without a TX power are never returned
+    pub fn query_path_loss_range(&self, min: i16, max: i16) -> Vec<&BleObservation> {
+        if min > max {
+            return Vec::new();
+        }
+        if !self.indexed.path_loss {
+            let mut matches: Vec<(i16, &BleObservation)> = self
+                .records
+                .iter()
+                .filter_map(|obs| obs.path_loss().map(|path_loss| (path_loss, obs)))
+                .filter(|(path_loss, _)| (min..=max).contains(path_loss))
+                .collect();
+            matches.sort_by_key(|&(path_loss, _)| path_loss);
+            return matches.into_iter().map(|(_, obs)| obs).collect();
+        }
+        self.path_loss_index
+            .range(min..=max)
+            .flat_map(|(_, ids)| ids.iter().filter_map(|&id| self.records.get(id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{Dimension, Query};
+
+    fn obs(mac: u8, rssi: i8, tx_power: Option<i8>) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi,
+            tx_power,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_path_loss_normalizes_tx_power() {
+        let mut cube = BleCube::new();
+        // Same RSSI, very different distances: a -59 dBm beacon is 11 dB
+        // down, a -12 dBm gateway 58 dB down
+        cube.insert(obs(1, -70, Some(-59)));
+        cube.insert(obs(2, -70, Some(-12)));
+        cube.insert(obs(3, -70, None));
+        cube.insert(obs(4, -60, Some(-59)));
+
+        assert_eq!(cube.get(0).unwrap().path_loss(), Some(11));
+        assert_eq!(cube.get(2).unwrap().path_loss(), None);
+        let macs = |found: Vec<&BleObservation>| -> Vec<u8> {
+            found.iter().map(|obs| obs.mac[0]).collect()
+        };
+        assert_eq!(macs(cube.query_path_loss_range(0, 20)), vec![4, 1]);
+        assert_eq!(macs(cube.query_path_loss_range(50, 60)), vec![2]);
+        assert!(cube.query_path_loss_range(20, 0).is_empty());
+
+        let query = Query::new().path_loss_between(0, 20);
+        assert_eq!(cube.execute_ids(&query), vec![0, 3]);
+        assert_eq!(cube.explain(&query).driver, Some(Dimension::PathLoss));
+        assert_eq!(
+            cube.execute_ids(
+                &Query::new()
+                    .rssi_between(-70, -70)
+                    .path_loss_between(0, 100)
+            ),
+            vec![0, 1]
+        );
+
+        // Updates move records between path-loss keys
+        cube.replace_record(1, obs(2, -70, None));
+        assert!(cube.query_path_loss_range(50, 60).is_empty());
+        cube.replace_record(2, obs(3, -80, Some(-40)));
+        assert_eq!(macs(cube.query_path_loss_range(40, 40)), vec![3]);
+        assert_eq!(cube.index_stats().path_loss.entries, 3);
+    }
+}
@@ -0,0 +1,281 @@
+//! Delta + varint compressed posting lists.
+//!
+//! A `BTreeMap<i64, Vec<usize>>` spends a B-tree entry and a `Vec` header
+//! per key plus eight bytes per record ID. Once a range of keys stops
+//! changing it can be packed into one byte stream instead: keys as gaps
+//! from the previous key, record IDs as zigzag gaps from the previous ID,
+//! all as LEB128 varints. IDs inserted in order are one byte each. Every
+//! [`BLOCK_KEYS`] keys the stream restarts from scratch and a seek entry
+//! records where, so a range lookup decodes at most one block it does not
+//! need.
+
+use crate::compat::prelude::*;
+use alloc::collections::BTreeMap;
+use core::mem::size_of;
+use core::ops::{Bound, RangeBounds};
+
+/// Keys per seek block
+const BLOCK_KEYS: usize = 64;
+
+/// Immutable map from ascending `i64` keys to sorted record ID lists
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CompressedPostings {
+    data: Vec<u8>,
+    /// First key and byte offset of every block
+    blocks: Vec<(i64, usize)>,
+    keys: usize,
+    ids: usize,
+}
+
+impl CompressedPostings {
+    /// Pack a posting map; empty lists are dropped
+    pub(crate) fn from_map(map: &BTreeMap<i64, Vec<usize>>) -> Self {
+        let mut packed = Self {
+            data: Vec::new(),
+            blocks: Vec::new(),
+            keys: 0,
+            ids: 0,
+        };
+        let (mut prev_key, mut prev_id) = (0i64, 0usize);
+        for (&key, ids) in map.iter().filter(|(_, ids)| !ids.is_empty()) {
+            if packed.keys.is_multiple_of(BLOCK_KEYS) {
+                packed.blocks.push((key, packed.data.len()));
+                (prev_key, prev_id) = (key, 0);
+            }
+            write_varint(&mut packed.data, key.wrapping_sub(prev_key) as u64);
+            write_varint(&mut packed.data, ids.len() as u64);
+            for &id in ids {
+                write_varint(&mut packed.data, zigzag(id.wrapping_sub(prev_id) as i64));
+                prev_id = id;
+            }
+            prev_key = key;
+            packed.keys += 1;
+            packed.ids += ids.len();
+        }
+        packed.data.shrink_to_fit();
+        packed.blocks.shrink_to_fit();
+        packed
+    }
+
+    /// Unpack into a posting map again
+    pub(crate) fn to_map(&self) -> BTreeMap<i64, Vec<usize>> {
+        let mut map = BTreeMap::new();
+        let mut cursor = self.cursor(0);
+        while let Some((key, len)) = cursor.next_key() {
+            map.insert(key, (0..len).map(|_| cursor.next_id()).collect());
+        }
+        map
+    }
+
+    /// Distinct keys
+    pub(crate) fn keys(&self) -> usize {
+        self.keys
+    }
+
+    /// Record IDs across all keys
+    pub(crate) fn len(&self) -> usize {
+        self.ids
+    }
+
+    pub(crate) fn heap_bytes(&self) -> usize {
+        self.data.capacity() + self.blocks.capacity() * size_of::<(i64, usize)>()
+    }
+
+    /// `(key, record ID)` pairs with keys in `range`, in key then ID order
+    pub(crate) fn range<R: RangeBounds<i64>>(&self, range: R) -> Range<'_> {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        // Last block starting at or before the lower bound
+        let block = match start {
+            Bound::Included(key) | Bound::Excluded(key) => self
+                .blocks
+                .partition_point(|&(first, _)| first <= key)
+                .saturating_sub(1),
+            Bound::Unbounded => 0,
+        };
+        Range {
+            cursor: self.cursor(block),
+            start,
+            end,
+            key: 0,
+            left: 0,
+        }
+    }
+
+    /// Posting list lengths in key order
+    pub(crate) fn posting_lens(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut cursor = self.cursor(0);
+        core::iter::from_fn(move || {
+            let (_, len) = cursor.next_key()?;
+            (0..len).for_each(|_| {
+                cursor.next_id();
+            });
+            Some(len)
+        })
+    }
+
+    fn cursor(&self, block: usize) -> Cursor<'_> {
+        let (prev_key, pos) = self
+            .blocks
+            .get(block)
+            .copied()
+            .unwrap_or((0, self.data.len()));
+        Cursor {
+            postings: self,
+            pos,
+            key_index: block * BLOCK_KEYS,
+            prev_key,
+            prev_id: 0,
+        }
+    }
+}
+
+/// Decoding state within the byte stream
+struct Cursor<'a> {
+    postings: &'a CompressedPostings,
+    pos: usize,
+    /// Index of the next key to decode
+    key_index: usize,
+    prev_key: i64,
+    prev_id: usize,
+}
+
+impl Cursor<'_> {
+    /// The next key and its posting length; the caller must then read
+    /// exactly that many IDs
+    fn next_key(&mut self) -> Option<(i64, usize)> {
+        if self.key_index == self.postings.keys {
+            return None;
+        }
+        if self.key_index.is_multiple_of(BLOCK_KEYS) {
+            self.prev_key = self.postings.blocks[self.key_index / BLOCK_KEYS].0;
+            self.prev_id = 0;
+        }
+        self.key_index += 1;
+        let key = self.prev_key.wrapping_add(self.read() as i64);
+        self.prev_key = key;
+        Some((key, self.read() as usize))
+    }
+
+    fn next_id(&mut self) -> usize {
+        let id = self.prev_id.wrapping_add(unzigzag(self.read()) as usize);
+        self.prev_id = id;
+        id
+    }
+
+    fn read(&mut self) -> u64 {
+        let data = &self.postings.data;
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = data[self.pos];
+            self.pos += 1;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+}
+
+/// Iterator of [`CompressedPostings::range`]
+pub(crate) struct Range<'a> {
+    cursor: Cursor<'a>,
+    start: Bound<i64>,
+    end: Bound<i64>,
+    /// Key of the IDs being yielded and how many are left
+    key: i64,
+    left: usize,
+}
+
+impl Iterator for Range<'_> {
+    type Item = (i64, usize);
+
+    fn next(&mut self) -> Option<(i64, usize)> {
+        while self.left == 0 {
+            let (key, len) = self.cursor.next_key()?;
+            let past_end = match self.end {
+                Bound::Included(end) => key > end,
+                Bound::Excluded(end) => key >= end,
+                Bound::Unbounded => false,
+            };
+            if past_end {
+                self.cursor.key_index = self.cursor.postings.keys;
+                return None;
+            }
+            let before_start = match self.start {
+                Bound::Included(start) => key < start,
+                Bound::Excluded(start) => key <= start,
+                Bound::Unbounded => false,
+            };
+            if before_start {
+                (0..len).for_each(|_| {
+                    self.cursor.next_id();
+                });
+            } else {
+                (self.key, self.left) = (key, len);
+            }
+        }
+        self.left -= 1;
+        Some((self.key, self.cursor.next_id()))
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_and_range() {
+        let mut map: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+        for key in 0..200i64 {
+            // Mostly ascending IDs, with a few jumps backwards
+            let id = if key % 50 == 7 { 3 } else { key as usize * 2 };
+            map.insert(key * 10 - 1000, vec![id, id + 1]);
+        }
+        map.insert(i64::MIN, vec![usize::MAX]);
+        map.insert(i64::MAX, vec![0]);
+        map.insert(5, Vec::new());
+
+        let packed = CompressedPostings::from_map(&map);
+        map.remove(&5);
+        assert_eq!(packed.to_map(), map);
+        assert_eq!((packed.keys(), packed.len()), (202, 402));
+        assert_eq!(packed.posting_lens().sum::<usize>(), 402);
+        // Small gaps cost a byte each, against eight per ID unpacked
+        assert!(packed.heap_bytes() < 402 * 3);
+
+        let expected: Vec<(i64, usize)> = map
+            .range(-5..=640)
+            .flat_map(|(&key, ids)| ids.iter().map(move |&id| (key, id)))
+            .collect();
+        assert_eq!(packed.range(-5..=640).collect::<Vec<_>>(), expected);
+        assert_eq!(packed.range(..).count(), 402);
+        assert_eq!(
+            packed.range(i64::MAX..).collect::<Vec<_>>(),
+            [(i64::MAX, 0)]
+        );
+        assert_eq!(packed.range(..i64::MIN).count(), 0);
+        assert_eq!(packed.range(10_000..20_000).count(), 0);
+
+        let empty = CompressedPostings::from_map(&BTreeMap::new());
+        assert_eq!(empty.range(..).count(), 0);
+    }
+}
@@ -0,0 +1,180 @@
+//! Column projection of query results.
+//!
+//! [`Query::select`] names the fields a caller needs and
+//! [`BleCube::execute_projected`] returns them as one typed vector per
+//! field, instead of a `&BleObservation` per match that the caller then
+//! picks apart. RSSI and timestamps are copied from the record store's dense
+//! columns, so a timestamp-and-RSSI projection of millions of matches never
+//! touches the rows.
+
+use crate::ble_cube::BleCube;
+use crate::compat::prelude::*;
+use crate::query::Query;
+
+/// An observation field of [`Query::select`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Field {
+    Mac,
+    Rssi,
+    Timestamp,
+    Lat,
+    Lon,
+    ReceiverId,
+    Floor,
+    TxPower,
+    Quality,
+}
+
+impl Field {
+    const ALL: [Field; 9] = [
+        Field::Mac,
+        Field::Rssi,
+        Field::Timestamp,
+        Field::Lat,
+        Field::Lon,
+        Field::ReceiverId,
+        Field::Floor,
+        Field::TxPower,
+        Field::Quality,
+    ];
+}
+
+/// Selected fields of a query's matches, see [`BleCube::execute_projected`].
+/// Each selected field is a vector with one entry per match, in record ID
+/// order; fields that were not selected are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Projection {
+    /// Number of matches (the length of every selected column)
+    pub len: usize,
+    pub mac: Option<Vec<[u8; 6]>>,
+    pub rssi: Option<Vec<i8>>,
+    pub timestamp: Option<Vec<i64>>,
+    pub lat: Option<Vec<f64>>,
+    pub lon: Option<Vec<f64>>,
+    pub receiver_id: Option<Vec<Option<u16>>>,
+    pub floor: Option<Vec<Option<i16>>>,
+    pub tx_power: Option<Vec<Option<i8>>>,
+    pub quality: Option<Vec<Option<u8>>>,
+}
+
+impl Query {
+    /// Return only `fields` from [`BleCube::execute_projected`]; replaces
+    /// any earlier selection. Without a selection every field is
+    /// projected. Ignored by `execute`.
+    pub fn select(mut self, fields: &[Field]) -> Self {
+        self.select.clear();
+        for &field in fields {
+            if !self.select.contains(&field) {
+                self.select.push(field);
+            }
+        }
+        self
+    }
+}
+
+impl BleCube {
+    /// Run a query and return the fields chosen with [`Query::select`] as
+    /// columns. Matches, sampling and interruption behave as in
+    /// [`BleCube::execute_ids`].
+    pub fn execute_projected(&self, query: &Query) -> Projection {
+        let ids = self.execute_ids(query);
+        let fields = if query.select.is_empty() {
+            &Field::ALL[..]
+        } else {
+            &query.select[..]
+        };
+        let mut projection = Projection {
+            len: ids.len(),
+            ..Default::default()
+        };
+        for &field in fields {
+            match field {
+                Field::Mac => {
+                    let column = self.records.macs();
+                    projection.mac = Some(ids.iter().map(|&id| column[id]).collect());
+                }
+                Field::Rssi => {
+                    let column = self.records.rssi();
+                    projection.rssi = Some(ids.iter().map(|&id| column[id]).collect());
+                }
+                Field::Timestamp => {
+                    let column = self.records.timestamps();
+                    projection.timestamp = Some(ids.iter().map(|&id| column[id]).collect());
+                }
+                Field::Lat => {
+                    let column = self.records.lats();
+                    projection.lat = Some(ids.iter().map(|&id| column[id]).collect());
+                }
+                Field::Lon => {
+                    let column = self.records.lons();
+                    projection.lon = Some(ids.iter().map(|&id| column[id]).collect());
+                }
+                Field::ReceiverId => {
+                    projection.receiver_id =
+                        Some(ids.iter().map(|&id| self.records[id].receiver_id).collect())
+                }
+                Field::Floor => {
+                    projection.floor = Some(ids.iter().map(|&id| self.records[id].floor).collect())
+                }
+                Field::TxPower => {
+                    projection.tx_power =
+                        Some(ids.iter().map(|&id| self.records[id].tx_power).collect())
+                }
+                Field::Quality => {
+                    projection.quality =
+                        Some(ids.iter().map(|&id| self.records[id].quality).collect())
+                }
+            }
+        }
+        projection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    #[test]
+    fn test_select_projects_columns() {
+        let mut cube = BleCube::new();
+        for t in 0..10 {
+            cube.insert(BleObservation {
+                mac: [(t % 2) as u8; 6],
+                rssi: -50 - t as i8,
+                timestamp: 100 - t,
+                lat: 37.0,
+                lon: -122.0,
+                floor: (t == 4).then_some(2),
+                ..Default::default()
+            });
+        }
+
+        let query =
+            Query::new()
+                .mac([0; 6])
+                .select(&[Field::Timestamp, Field::Rssi, Field::Timestamp]);
+        assert_eq!(query.select, vec![Field::Timestamp, Field::Rssi]);
+        let projection = cube.execute_projected(&query);
+        assert_eq!(projection.len, 5);
+        assert_eq!(projection.timestamp, Some(vec![100, 98, 96, 94, 92]));
+        assert_eq!(projection.rssi, Some(vec![-50, -52, -54, -56, -58]));
+        assert_eq!((projection.mac, projection.lat), (None, None));
+
+        // Same order as `execute`; no selection projects everything
+        let all = cube.execute_projected(&Query::new().rssi_between(-56, -53));
+        let rows = cube.execute(&Query::new().rssi_between(-56, -53));
+        assert_eq!(all.len, rows.len());
+        assert_eq!(
+            all.timestamp.unwrap(),
+            rows.iter().map(|obs| obs.timestamp).collect::<Vec<_>>()
+        );
+        assert_eq!(all.floor, Some(vec![None, Some(2), None, None]));
+        assert_eq!(all.tx_power, Some(vec![None; 4]));
+
+        let empty = cube.execute_projected(&Query::new().mac([9; 6]).select(&[Field::Lon]));
+        assert_eq!((empty.len, empty.lon), (0, Some(vec![])));
+    }
+}
@@ -0,0 +1,130 @@
+//! Per-record confidence scores.
+//!
+//! Not every georeferenced sighting is equally trustworthy: a sniffer
+//! packet may have failed its CRC, the GPS fix may have had poor geometry,
+//! or the position may have been interpolated between distant fixes. An
+//! observation can carry a [`BleObservation::quality`] score from 0
+//! (worthless) to 255 (best). Scores are indexed like RSSI, and
+//! [`Query::min_quality`](crate::Query::min_quality) drops low-confidence
+//! points before analytics see them. The helpers below map the usual
+//! sources onto the scale.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+
+/// HDOP at or above which a fix scores 0
+const WORST_HDOP: f64 = 20.0;
+
+impl BleObservation {
+    /// Score of a packet whose CRC check passed (255) or failed (0)
+    pub fn quality_from_crc(crc_valid: bool) -> u8 {
+        if crc_valid {
+            u8::MAX
+        } else {
+            0
+        }
+    }
+
+    /// Score of a position from a GPS fix with this horizontal dilution of
+    /// precision: 255 at HDOP 1 or better, falling linearly to 0 at HDOP 20
+    pub fn quality_from_hdop(hdop: f64) -> u8 {
+        let score = (WORST_HDOP - hdop) / (WORST_HDOP - 1.0) * f64::from(u8::MAX);
+        // `as` saturates, and maps NaN to 0
+        score as u8
+    }
+
+    /// Score of a position interpolated `distance_m` meters away from the
+    /// nearest real fix: 255 at the fix, one point less per meter
+    pub fn quality_from_interpolation(distance_m: f64) -> u8 {
+        (f64::from(u8::MAX) - distance_m) as u8
+    }
+}
+
+impl BleCube {
+    /// Observations with a quality score of at least `min`, ascending by
+    /// score; observations without a score are never returned
+    pub fn query_min_quality(&self, min: u8) -> Vec<&BleObservation> {
+        if !self.indexed.quality {
+            let mut matches: Vec<(u8, &BleObservation)> = self
+                .records
+                .iter()
+                .filter_map(|obs| obs.quality.map(|quality| (quality, obs)))
+                .filter(|&(quality, _)| quality >= min)
+                .collect();
+            matches.sort_by_key(|&(quality, _)| quality);
+            return matches.into_iter().map(|(_, obs)| obs).collect();
+        }
+        self.quality_index
+            .range(min..)
+            .flat_map(|(_, ids)| ids.iter().filter_map(|&id| self.records.get(id)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+
+    fn obs(timestamp: i64, quality: Option<u8>) -> BleObservation {
+        BleObservation {
+            mac: [1; 6],
+            rssi: -60,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            quality,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_quality_scores() {
+        assert_eq!(BleObservation::quality_from_crc(true), 255);
+        assert_eq!(BleObservation::quality_from_crc(false), 0);
+        assert_eq!(BleObservation::quality_from_hdop(0.8), 255);
+        assert_eq!(BleObservation::quality_from_hdop(1.0), 255);
+        assert_eq!(BleObservation::quality_from_hdop(10.5), 127);
+        assert_eq!(BleObservation::quality_from_hdop(25.0), 0);
+        assert_eq!(BleObservation::quality_from_hdop(f64::NAN), 0);
+        assert_eq!(BleObservation::quality_from_interpolation(0.0), 255);
+        assert_eq!(BleObservation::quality_from_interpolation(55.0), 200);
+        assert_eq!(BleObservation::quality_from_interpolation(1000.0), 0);
+    }
+
+    #[test]
+    fn test_min_quality_filters_indexed_and_scanned() {
+        let records = vec![
+            obs(0, Some(250)),
+            obs(1, Some(120)),
+            obs(2, None),
+            obs(3, Some(200)),
+        ];
+        let indexed = BleCube::bulk_load(records.clone());
+        let scanned = BleCube::builder()
+            .without_quality_index()
+            .bulk_load(records);
+
+        for cube in [&indexed, &scanned] {
+            let stamps: Vec<i64> = cube
+                .execute(&Query::new().min_quality(200))
+                .iter()
+                .map(|obs| obs.timestamp)
+                .collect();
+            assert_eq!(stamps, [0, 3]);
+            let ascending: Vec<i64> = cube
+                .query_min_quality(100)
+                .iter()
+                .map(|obs| obs.timestamp)
+                .collect();
+            assert_eq!(ascending, [1, 3, 0]);
+        }
+        assert_eq!(indexed.index_stats().quality.entries, 3);
+        assert_eq!(scanned.index_stats().quality.entries, 0);
+
+        // Upserts move records between score postings
+        let mut cube = indexed;
+        cube.upsert_by_key(obs(1, Some(210)), crate::DuplicatePolicy::Replace);
+        assert_eq!(cube.execute(&Query::new().min_quality(200)).len(), 3);
+    }
+}
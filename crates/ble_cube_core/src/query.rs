@@ -0,0 +1,753 @@
+//! Owned, cube-independent query specification.
+//!
+//! A [`Query`] is built once and can be executed against any cube, matched
+//! against single observations (subscriptions), or stored for reuse.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::cancel::{self, CancelToken, Interrupt, QueryInterrupted, CHECK_INTERVAL};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::group::GroupBy;
+use crate::lifecycle::span_matches;
+use crate::mac::MacAddr;
+use crate::projection::Field;
+use crate::sample::Reservoir;
+use core::cmp::Reverse;
+use core::ops::ControlFlow;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// A filterable dimension of a [`Query`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dimension {
+    Mac,
+    Receiver,
+    Floor,
+    Zone,
+    /// Analyst label, see [`BleCube::tag`]
+    Tag,
+    Geo,
+    /// First/last-seen bounds of the record's device
+    Lifecycle,
+    Time,
+    Rssi,
+    /// TX power minus RSSI, see [`BleObservation::path_loss`]
+    PathLoss,
+    /// Confidence score, see [`BleObservation::quality`]
+    Quality,
+}
+
+impl Dimension {
+    /// Order in which a query picks its driving index: the first constrained
+    /// dimension wins
+    pub(crate) const DRIVER_PRIORITY: [Dimension; 11] = [
+        Dimension::Mac,
+        Dimension::Tag,
+        Dimension::Zone,
+        Dimension::Geo,
+        Dimension::Receiver,
+        Dimension::Floor,
+        Dimension::Lifecycle,
+        Dimension::PathLoss,
+        Dimension::Quality,
+        Dimension::Time,
+        Dimension::Rssi,
+    ];
+}
+
+/// Which observation [`Query::distinct_by_mac`] keeps for each device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DistinctKeep {
+    /// Highest RSSI (ties: most recent)
+    Strongest,
+    /// Most recent timestamp (ties: highest RSSI)
+    Latest,
+}
+
+impl DistinctKeep {
+    /// Whether `candidate` beats `kept`, each given with its position in
+    /// some result order; full ties go to the earlier position
+    pub(crate) fn prefers(
+        self,
+        candidate: (usize, &BleObservation),
+        kept: (usize, &BleObservation),
+    ) -> bool {
+        let key = |(position, obs): (usize, &BleObservation)| match self {
+            DistinctKeep::Strongest => (i64::from(obs.rssi), obs.timestamp, Reverse(position)),
+            DistinctKeep::Latest => (obs.timestamp, i64::from(obs.rssi), Reverse(position)),
+        };
+        key(candidate) > key(kept)
+    }
+}
+
+/// Ascending positions of the observation kept for each MAC
+pub(crate) fn distinct_positions<'a>(
+    keep: DistinctKeep,
+    observations: impl IntoIterator<Item = &'a BleObservation>,
+) -> Vec<usize> {
+    let mut kept: HashMap<[u8; 6], (usize, &BleObservation)> = HashMap::new();
+    for (position, obs) in observations.into_iter().enumerate() {
+        kept.entry(obs.mac)
+            .and_modify(|best| {
+                if keep.prefers((position, obs), *best) {
+                    *best = (position, obs);
+                }
+            })
+            .or_insert((position, obs));
+    }
+    let mut positions: Vec<usize> = kept.into_values().map(|(position, _)| position).collect();
+    positions.sort_unstable();
+    positions
+}
+
+/// Conjunction of per-dimension filters; unset dimensions match everything
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Query {
+    #[cfg_attr(feature = "serde", serde(with = "crate::mac::serde_octets::option"))]
+    pub(crate) mac: Option<[u8; 6]>,
+    pub(crate) rssi_range: Option<(i8, i8)>,
+    pub(crate) path_loss_range: Option<(i16, i16)>,
+    pub(crate) min_quality: Option<u8>,
+    pub(crate) time_range: Option<(i64, i64)>,
+    pub(crate) geo_radius: Option<(f64, f64, f64)>,
+    pub(crate) zone: Option<String>,
+    pub(crate) tag: Option<String>,
+    pub(crate) receiver: Option<u16>,
+    pub(crate) floor: Option<i16>,
+    pub(crate) new_since: Option<i64>,
+    pub(crate) not_seen_since: Option<i64>,
+    // One match per MAC, chosen by the rule
+    pub(crate) distinct: Option<DistinctKeep>,
+    // Uniform sample of at most n matches, drawn with the seed
+    pub(crate) sample: Option<(usize, u64)>,
+    // Keys for `BleCube::execute_grouped`
+    pub(crate) group_by: Vec<GroupBy>,
+    // Fields for `BleCube::execute_projected`
+    pub(crate) select: Vec<Field>,
+    // Execution limits, not part of the filter (and not serialized)
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) deadline: Option<Instant>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) cancel: Option<CancelToken>,
+}
+
+impl Query {
+    /// Query matching every observation
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restrict to one MAC address
+    pub fn mac<M: Into<MacAddr>>(mut self, mac: M) -> Self {
+        self.mac = Some(mac.into().0);
+        self
+    }
+
+    /// Restrict to observations made by one receiver
+    pub fn receiver(mut self, receiver_id: u16) -> Self {
+        self.receiver = Some(receiver_id);
+        self
+    }
+
+    /// Restrict to observations on one floor
+    pub fn on_floor(mut self, floor: i16) -> Self {
+        self.floor = Some(floor);
+        self
+    }
+
+    /// Restrict to RSSI in [min, max] inclusive
+    pub fn rssi_between(mut self, min: i8, max: i8) -> Self {
+        self.rssi_range = Some((min, max));
+        self
+    }
+
+    /// Restrict to path loss (TX power minus RSSI) in [min, max] dB
+    /// inclusive; observations without a TX power never match
+    pub fn path_loss_between(mut self, min: i16, max: i16) -> Self {
+        self.path_loss_range = Some((min, max));
+        self
+    }
+
+    /// Only observations with a quality score of at least `min`; those
+    /// without a score never match
+    pub fn min_quality(mut self, min: u8) -> Self {
+        self.min_quality = Some(min);
+        self
+    }
+
+    /// Restrict to timestamps in [start, end] inclusive
+    pub fn time_between(mut self, start: i64, end: i64) -> Self {
+        self.time_range = Some((start, end));
+        self
+    }
+
+    /// Restrict to within `radius_m` meters of (lat, lon), measured with the
+    /// cube's distance metric, or Euclidean in a projected coordinate system
+    pub fn within_radius(mut self, lat: f64, lon: f64, radius_m: f64) -> Self {
+        self.geo_radius = Some((lat, lon, radius_m));
+        self
+    }
+
+    /// Restrict to records inside a registered zone
+    pub fn in_zone(mut self, zone: &str) -> Self {
+        self.zone = Some(zone.to_string());
+        self
+    }
+
+    /// Restrict to records labelled with `tag` (see [`BleCube::tag`])
+    pub fn tagged(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+
+    /// Restrict to devices first seen at or after `timestamp` (newly
+    /// appearing devices). Judged on the device's whole history in the cube,
+    /// not only on the records other filters keep.
+    pub fn new_since(mut self, timestamp: i64) -> Self {
+        self.new_since = Some(timestamp);
+        self
+    }
+
+    /// Restrict to devices with no observation at or after `timestamp`
+    /// (departed devices), judged like [`Query::new_since`]
+    pub fn not_seen_since(mut self, timestamp: i64) -> Self {
+        self.not_seen_since = Some(timestamp);
+        self
+    }
+
+    /// Return one match per device (MAC) instead of every observation: the
+    /// strongest or the most recent one, per `keep`. Answers "which devices
+    /// were here" rather than "which observations". Combined with
+    /// [`Query::sample`], the sample is drawn from the per-device matches.
+    /// Does not affect subscriptions.
+    pub fn distinct_by_mac(mut self, keep: DistinctKeep) -> Self {
+        self.distinct = Some(keep);
+        self
+    }
+
+    /// Return a uniform random sample of at most `n` matches instead of all
+    /// of them, in record ID order. Matches are streamed through a
+    /// reservoir, so memory stays at `n` IDs however many records match.
+    /// The same `seed` over the same cube gives the same sample.
+    /// Does not affect subscriptions.
+    pub fn sample(mut self, n: usize, seed: u64) -> Self {
+        self.sample = Some((n, seed));
+        self
+    }
+
+    /// MAC address the query is restricted to, if any
+    pub fn mac_filter(&self) -> Option<[u8; 6]> {
+        self.mac
+    }
+
+    /// Sample size and seed, if the query samples its matches
+    pub fn sampling(&self) -> Option<(usize, u64)> {
+        self.sample
+    }
+
+    /// The same query without its sample
+    pub fn unsampled(&self) -> Self {
+        Self {
+            sample: None,
+            ..self.clone()
+        }
+    }
+
+    /// Stop executing once `deadline` passes. [`BleCube::execute`] then
+    /// returns the matches found so far; [`BleCube::try_execute`] reports
+    /// the interruption. Does not affect subscriptions.
+    #[cfg(feature = "std")]
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stop executing once `token` is cancelled, with the same partial
+    /// result semantics as [`Query::with_deadline`]
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Whether results need every match before any can be returned
+    /// (deduplication or sampling), so they cannot be streamed
+    pub(crate) fn post_processed(&self) -> bool {
+        self.distinct.is_some() || self.sample.is_some()
+    }
+
+    /// Deadline or cancellation that should stop execution now
+    pub(crate) fn interrupted(&self) -> Option<Interrupt> {
+        #[cfg(feature = "std")]
+        return cancel::interrupt(self.deadline, self.cancel.as_ref());
+        #[cfg(not(feature = "std"))]
+        cancel::interrupt(self.cancel.as_ref())
+    }
+
+    /// Whether this query constrains `dimension`
+    pub(crate) fn constrains(&self, dimension: Dimension) -> bool {
+        match dimension {
+            Dimension::Mac => self.mac.is_some(),
+            Dimension::Receiver => self.receiver.is_some(),
+            Dimension::Floor => self.floor.is_some(),
+            Dimension::Zone => self.zone.is_some(),
+            Dimension::Tag => self.tag.is_some(),
+            Dimension::Geo => self.geo_radius.is_some(),
+            Dimension::Lifecycle => self.new_since.is_some() || self.not_seen_since.is_some(),
+            Dimension::Time => self.time_range.is_some(),
+            Dimension::Rssi => self.rssi_range.is_some(),
+            Dimension::PathLoss => self.path_loss_range.is_some(),
+            Dimension::Quality => self.min_quality.is_some(),
+        }
+    }
+}
+
+impl BleCube {
+    /// Run a query, returning matching observations in record ID order
+    pub fn execute(&self, query: &Query) -> Vec<&BleObservation> {
+        self.execute_ids(query)
+            .into_iter()
+            .map(|id| &self.records[id])
+            .collect()
+    }
+
+    /// Run a query, returning matching record IDs in ascending order.
+    /// If the query's deadline passes or it is cancelled, returns the
+    /// matches found until then.
+    pub fn execute_ids(&self, query: &Query) -> Vec<usize> {
+        self.try_execute_ids(query)
+            .unwrap_or_else(|interrupted| interrupted.partial)
+    }
+
+    /// Like [`BleCube::execute`], but fails if the query's deadline passes
+    /// or it is cancelled before every candidate was checked
+    pub fn try_execute(&self, query: &Query) -> Result<Vec<&BleObservation>, QueryInterrupted> {
+        let ids = self.try_execute_ids(query)?;
+        Ok(ids.into_iter().map(|id| &self.records[id]).collect())
+    }
+
+    /// Like [`BleCube::execute_ids`], but fails (carrying the partial
+    /// matches) if the query is interrupted
+    pub fn try_execute_ids(&self, query: &Query) -> Result<Vec<usize>, QueryInterrupted> {
+        let mut ids = Vec::new();
+        let mut reservoir = query.sample.map(|(n, seed)| Reservoir::new(n, seed));
+        let mut per_mac: HashMap<[u8; 6], usize> = HashMap::new();
+        let scanned = self.scan(query, |id| match (query.distinct, reservoir.as_mut()) {
+            (Some(keep), _) => {
+                let obs = &self.records[id];
+                per_mac
+                    .entry(obs.mac)
+                    .and_modify(|kept| {
+                        if keep.prefers((id, obs), (*kept, &self.records[*kept])) {
+                            *kept = id;
+                        }
+                    })
+                    .or_insert(id);
+            }
+            (None, Some(reservoir)) => reservoir.offer(id),
+            (None, None) => ids.push(id),
+        });
+        if query.distinct.is_some() {
+            // The sample, if any, is drawn from the devices' matches
+            let mut kept: Vec<usize> = per_mac.into_values().collect();
+            kept.sort_unstable();
+            match reservoir.as_mut() {
+                Some(reservoir) => kept.into_iter().for_each(|id| reservoir.offer(id)),
+                None => ids = kept,
+            }
+        }
+        let ids = match reservoir {
+            Some(reservoir) => reservoir.into_sorted(),
+            None => {
+                ids.sort_unstable();
+                ids
+            }
+        };
+        match scanned {
+            Ok(()) => Ok(ids),
+            Err(reason) => Err(QueryInterrupted {
+                reason,
+                partial: ids,
+            }),
+        }
+    }
+
+    /// Pass the ID of every match of `query` (ignoring sampling) to `emit`,
+    /// in driving-index order, checking for interruption between batches
+    pub(crate) fn scan(&self, query: &Query, mut emit: impl FnMut(usize)) -> Result<(), Interrupt> {
+        let mut checked = 0usize;
+        let mut result = Ok(());
+        self.for_each_candidate(query, |id| {
+            if checked.is_multiple_of(CHECK_INTERVAL) {
+                if let Some(reason) = query.interrupted() {
+                    result = Err(reason);
+                    return ControlFlow::Break(());
+                }
+            }
+            checked += 1;
+            if self.matches(query, id) {
+                emit(id);
+            }
+            ControlFlow::Continue(())
+        });
+        result
+    }
+
+    /// Whether a stored record satisfies every filter of `query`
+    pub(crate) fn matches(&self, query: &Query, record_id: usize) -> bool {
+        record_id < self.records.len()
+            && Dimension::DRIVER_PRIORITY
+                .iter()
+                .all(|&dimension| self.matches_dimension(query, dimension, record_id))
+    }
+
+    /// Whether a stored record passes the filter on one dimension (trivially
+    /// true if the query leaves it unconstrained)
+    pub(crate) fn matches_dimension(
+        &self,
+        query: &Query,
+        dimension: Dimension,
+        record_id: usize,
+    ) -> bool {
+        let obs = &self.records[record_id];
+        match dimension {
+            Dimension::Mac => query
+                .mac
+                .is_none_or(|mac| self.records.macs()[record_id] == mac),
+            Dimension::Receiver => query
+                .receiver
+                .is_none_or(|receiver| obs.receiver_id == Some(receiver)),
+            Dimension::Floor => query.floor.is_none_or(|floor| obs.floor == Some(floor)),
+            Dimension::Zone => query.zone.as_deref().is_none_or(|zone| {
+                self.geofence
+                    .members(zone)
+                    .is_some_and(|members| members.binary_search(&record_id).is_ok())
+            }),
+            Dimension::Tag => query.tag.as_deref().is_none_or(|tag| {
+                self.tags
+                    .members(tag)
+                    .is_some_and(|members| members.binary_search(&record_id).is_ok())
+            }),
+            Dimension::Geo => query.geo_radius.is_none_or(|(lat, lon, radius_m)| {
+                let (obs_lat, obs_lon) = (
+                    self.records.lats()[record_id],
+                    self.records.lons()[record_id],
+                );
+                self.distance(lat, lon, obs_lat, obs_lon) <= radius_m
+            }),
+            Dimension::Lifecycle => self
+                .device_span(&self.records.macs()[record_id])
+                .is_some_and(|span| span_matches(span, query.new_since, query.not_seen_since)),
+            Dimension::Time => query.time_range.is_none_or(|(start, end)| {
+                (start..=end).contains(&self.records.timestamps()[record_id])
+            }),
+            Dimension::Rssi => query
+                .rssi_range
+                .is_none_or(|(min, max)| (min..=max).contains(&self.records.rssi()[record_id])),
+            Dimension::PathLoss => query.path_loss_range.is_none_or(|(min, max)| {
+                obs.path_loss()
+                    .is_some_and(|path_loss| (min..=max).contains(&path_loss))
+            }),
+            Dimension::Quality => query
+                .min_quality
+                .is_none_or(|min| obs.quality.is_some_and(|quality| quality >= min)),
+        }
+    }
+
+    /// Candidate IDs from the first constrained dimension in
+    /// [`Dimension::DRIVER_PRIORITY`] (`None`: full scan)
+    pub(crate) fn candidate_ids(&self, query: &Query) -> (Option<Dimension>, Vec<usize>) {
+        let mut ids = Vec::new();
+        let driver = self.for_each_candidate(query, |id| {
+            ids.push(id);
+            ControlFlow::Continue(())
+        });
+        (driver, ids)
+    }
+
+    /// Feed the candidates of [`BleCube::candidate_ids`] to `visit` straight
+    /// from the driving index, without collecting them, until it breaks
+    pub(crate) fn for_each_candidate(
+        &self,
+        query: &Query,
+        visit: impl FnMut(usize) -> ControlFlow<()>,
+    ) -> Option<Dimension> {
+        let Some(driver) = Dimension::DRIVER_PRIORITY
+            .into_iter()
+            .find(|&dimension| query.constrains(dimension) && self.is_indexed(dimension))
+        else {
+            let _ = (0..self.records.len()).try_for_each(visit);
+            return None;
+        };
+
+        let _ = match driver {
+            Dimension::Mac => {
+                postings(query.mac.and_then(|mac| self.mac_index.get(&mac))).try_for_each(visit)
+            }
+            Dimension::Zone => query
+                .zone
+                .as_deref()
+                .and_then(|zone| self.geofence.members(zone))
+                .unwrap_or_default()
+                .iter()
+                .copied()
+                .try_for_each(visit),
+            Dimension::Tag => query
+                .tag
+                .as_deref()
+                .and_then(|tag| self.tags.members(tag))
+                .unwrap_or_default()
+                .iter()
+                .copied()
+                .try_for_each(visit),
+            Dimension::Geo => match query.geo_radius {
+                Some((lat, lon, radius_m)) => self
+                    .locate_in_envelope(self.crs.radius_envelope(lat, lon, radius_m))
+                    .map(|point| point.record_id)
+                    .try_for_each(visit),
+                None => ControlFlow::Continue(()),
+            },
+            Dimension::Receiver => postings(
+                query
+                    .receiver
+                    .and_then(|receiver| self.receiver_index.get(&receiver)),
+            )
+            .try_for_each(visit),
+            Dimension::Floor => {
+                postings(query.floor.and_then(|floor| self.floor_index.get(&floor)))
+                    .try_for_each(visit)
+            }
+            Dimension::Lifecycle => self
+                .seen_index
+                .macs_matching(query.new_since, query.not_seen_since)
+                .filter_map(|mac| self.mac_index.get(mac))
+                .flatten()
+                .copied()
+                .try_for_each(visit),
+            Dimension::Time => match query.time_range {
+                Some((start, end)) => self
+                    .time_index
+                    .range(start..=end)
+                    .map(|(_, id)| id)
+                    .try_for_each(visit),
+                None => ControlFlow::Continue(()),
+            },
+            Dimension::Rssi => match query.rssi_range {
+                Some((min, max)) => self
+                    .rssi_index
+                    .range(min..=max)
+                    .flat_map(|(_, ids)| ids.iter().copied())
+                    .try_for_each(visit),
+                None => ControlFlow::Continue(()),
+            },
+            Dimension::PathLoss => match query.path_loss_range {
+                Some((min, max)) => self
+                    .path_loss_index
+                    .range(min..=max)
+                    .flat_map(|(_, ids)| ids.iter().copied())
+                    .try_for_each(visit),
+                None => ControlFlow::Continue(()),
+            },
+            Dimension::Quality => match query.min_quality {
+                Some(min) => self
+                    .quality_index
+                    .range(min..)
+                    .flat_map(|(_, ids)| ids.iter().copied())
+                    .try_for_each(visit),
+                None => ControlFlow::Continue(()),
+            },
+        };
+        Some(driver)
+    }
+}
+
+/// IDs of an optional posting list, empty when absent
+fn postings(ids: Option<&Vec<usize>>) -> impl Iterator<Item = usize> + '_ {
+    ids.map_or(&[][..], Vec::as_slice).iter().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::Zone;
+
+    #[test]
+    fn test_execute_combines_filters() {
+        let mut cube = BleCube::new();
+        for i in 0..10u8 {
+            cube.insert(BleObservation {
+                rssi: -50 - i as i8,
+                mac: [0, 0, 0, 0, 0, i % 2],
+                timestamp: 1000 + i as i64,
+                lat: 37.0 + i as f64 * 0.01,
+                lon: -122.0,
+                receiver_id: None,
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+        cube.add_zone("north", Zone::circle(37.09, -122.0, 2000.0));
+
+        let query = Query::new()
+            .mac([0, 0, 0, 0, 0, 1])
+            .rssi_between(-58, -51)
+            .time_between(1000, 1006);
+        let results: Vec<i8> = cube.execute(&query).iter().map(|o| o.rssi).collect();
+        assert_eq!(results, vec![-51, -53, -55]);
+
+        assert_eq!(cube.execute_ids(&Query::new().in_zone("north")), vec![8, 9]);
+        assert_eq!(
+            cube.execute_ids(&Query::new().within_radius(37.0, -122.0, 1500.0)),
+            vec![0, 1]
+        );
+        assert_eq!(cube.execute(&Query::new()).len(), 10);
+        assert!(cube.execute(&Query::new().in_zone("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_receiver_filter() {
+        let mut cube = BleCube::new();
+        for i in 0..6u8 {
+            cube.insert(BleObservation {
+                rssi: -60,
+                mac: [0, 0, 0, 0, 0, i],
+                timestamp: 100 * i as i64,
+                lat: 0.0,
+                lon: 0.0,
+                receiver_id: (i > 0).then_some(u16::from(i % 2)),
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+
+        let ids = cube.execute_ids(&Query::new().receiver(1).time_between(0, 300));
+        assert_eq!(ids, vec![1, 3]);
+        assert_eq!(cube.query_receiver(0).len(), 2);
+        assert_eq!(cube.get_all_receivers(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_floor_filter_excludes_other_storeys() {
+        let mut cube = BleCube::new();
+        // Same footprint on three floors, plus one sighting with no floor
+        for (i, floor) in [Some(-1), Some(0), Some(0), Some(2), None]
+            .into_iter()
+            .enumerate()
+        {
+            cube.insert(BleObservation {
+                mac: [0, 0, 0, 0, 0, i as u8],
+                lat: 37.0,
+                lon: -122.0,
+                floor,
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(cube.query_geo_radius(37.0, -122.0, 10.0).len(), 5);
+        assert_eq!(
+            cube.query_geo_radius_on_floor(37.0, -122.0, 10.0, 0).len(),
+            2
+        );
+        assert_eq!(
+            cube.execute_ids(&Query::new().within_radius(37.0, -122.0, 10.0).on_floor(2)),
+            vec![3]
+        );
+        assert_eq!(cube.execute_ids(&Query::new().on_floor(-1)), vec![0]);
+        assert_eq!(cube.query_floor(0).len(), 2);
+        assert_eq!(cube.get_all_floors(), vec![-1, 0, 2]);
+    }
+
+    #[test]
+    fn test_distinct_by_mac_keeps_one_match_per_device() {
+        let mut cube = BleCube::new();
+        // (mac, timestamp, rssi), all around one point
+        for (mac, timestamp, rssi) in [(1, 10, -70), (1, 20, -50), (1, 30, -60), (2, 15, -80)]
+            .into_iter()
+            .chain([(2, 25, -80), (3, 5, -40)])
+        {
+            cube.insert(BleObservation {
+                mac: [mac; 6],
+                rssi,
+                timestamp,
+                lat: 37.0,
+                lon: -122.0,
+                ..Default::default()
+            });
+        }
+        // Far away, so the radius filter leaves MAC 3 out
+        cube.insert(BleObservation {
+            mac: [3; 6],
+            timestamp: 40,
+            lat: 38.0,
+            ..Default::default()
+        });
+
+        let near = Query::new().within_radius(37.0, -122.0, 100.0);
+        let strongest = near.clone().distinct_by_mac(DistinctKeep::Strongest);
+        // MAC 2 ties on RSSI, so its most recent sighting wins
+        assert_eq!(cube.execute_ids(&strongest), vec![1, 4, 5]);
+        let latest = near.clone().distinct_by_mac(DistinctKeep::Latest);
+        assert_eq!(cube.execute_ids(&latest), vec![2, 4, 5]);
+        // A MAC-driven scan visits records in another order; same answer
+        let one = Query::new()
+            .mac([1; 6])
+            .distinct_by_mac(DistinctKeep::Strongest);
+        assert_eq!(cube.execute_ids(&one), vec![1]);
+
+        let mut visited = Vec::new();
+        cube.query_visit(&latest, |obs| visited.push(obs.timestamp))
+            .unwrap();
+        assert_eq!(visited, vec![30, 25, 5]);
+        let sampled = latest.sample(2, 9);
+        let ids = cube.execute_ids(&sampled);
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|id| [2, 4, 5].contains(id)));
+        assert_eq!(
+            cube.execute(&Query::new().distinct_by_mac(DistinctKeep::Latest))
+                .len(),
+            3
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_deadline_and_cancellation() {
+        let mut cube = BleCube::new();
+        for t in 0..3000 {
+            cube.insert(BleObservation {
+                timestamp: t,
+                ..Default::default()
+            });
+        }
+        let query = Query::new().time_between(0, 2999);
+
+        let later = Instant::now() + std::time::Duration::from_secs(3600);
+        assert_eq!(
+            cube.try_execute(&query.clone().with_deadline(later))
+                .unwrap()
+                .len(),
+            3000
+        );
+
+        let expired = query.clone().with_deadline(Instant::now());
+        let err = cube.try_execute_ids(&expired).unwrap_err();
+        assert_eq!(err.reason, Interrupt::DeadlineExceeded);
+        assert!(err.partial.is_empty());
+        assert!(cube.execute(&expired).is_empty());
+
+        let token = CancelToken::new();
+        let cancellable = query.with_cancel_token(token.clone());
+        assert_eq!(cube.execute_ids(&cancellable).len(), 3000);
+        token.cancel();
+        assert_eq!(
+            cube.try_execute_ids(&cancellable).unwrap_err().reason,
+            Interrupt::Cancelled
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Result cache for repeated aggregate queries.
+//!
+//! Dashboards re-run the same [`BleCube::execute_grouped`] queries every few
+//! seconds. With [`BleCube::enable_query_cache`] the cube keeps the most
+//! recently used results in a bounded LRU keyed by the normalized query
+//! (execution limits and projections stripped, grouping keys in canonical
+//! order). An insert only drops the entries whose time range overlaps the
+//! new record's time partition, so queries over closed partitions keep
+//! hitting while live ones refresh. Deletes, tag and zone edits and
+//! coordinate or time-unit changes clear the whole cache.
+
+use crate::ble_cube::BleCube;
+use crate::group::{Group, GroupBy};
+use crate::query::Query;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Counters of the query cache, see [`BleCube::query_cache_stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryCacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that ran the query (and cached its result)
+    pub misses: u64,
+    /// Entries dropped to stay within capacity
+    pub evictions: u64,
+    /// Entries dropped because the data they cover changed
+    pub invalidations: u64,
+    /// Results currently cached
+    pub entries: usize,
+    /// Maximum number of cached results
+    pub capacity: usize,
+}
+
+struct Entry {
+    query: Query,
+    groups: Vec<Group>,
+}
+
+/// Bounded LRU of grouped results, least recently used first
+pub(crate) struct QueryCache {
+    entries: Vec<Entry>,
+    stats: QueryCacheStats,
+}
+
+impl QueryCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            stats: QueryCacheStats {
+                capacity,
+                ..QueryCacheStats::default()
+            },
+        }
+    }
+
+    fn get(&mut self, query: &Query) -> Option<Vec<Group>> {
+        let Some(i) = self.entries.iter().position(|entry| entry.query == *query) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        let entry = self.entries.remove(i);
+        let groups = entry.groups.clone();
+        self.entries.push(entry);
+        Some(groups)
+    }
+
+    fn put(&mut self, query: Query, groups: Vec<Group>) {
+        // Another reader may have filled it since the lookup missed
+        if self.entries.iter().any(|entry| entry.query == query) {
+            return;
+        }
+        if self.entries.len() == self.stats.capacity {
+            self.entries.remove(0);
+            self.stats.evictions += 1;
+        }
+        self.entries.push(Entry { query, groups });
+    }
+
+    /// Drop entries a record with a timestamp in [start, end) may change
+    fn invalidate_span(&mut self, start: i64, end: i64) {
+        let before = self.entries.len();
+        self.entries.retain(|entry| {
+            let query = &entry.query;
+            // First/last-seen filters depend on each device's whole history
+            let lifecycle = query.new_since.is_some() || query.not_seen_since.is_some();
+            let overlaps = query
+                .time_range
+                .is_none_or(|(from, to)| from < end && to >= start);
+            !lifecycle && !overlaps
+        });
+        self.stats.invalidations += (before - self.entries.len()) as u64;
+    }
+
+    fn clear(&mut self) {
+        self.stats.invalidations += self.entries.len() as u64;
+        self.entries.clear();
+    }
+}
+
+/// `query` without the parts that do not affect grouped results
+fn normalize(query: &Query) -> Query {
+    let mut key = query.clone();
+    key.deadline = None;
+    key.cancel = None;
+    key.select.clear();
+    // Each key kind appears once and the result order is fixed, so the
+    // order keys were added in does not matter
+    key.group_by.sort_by_key(|by| match by {
+        GroupBy::Mac => 0,
+        GroupBy::Geohash(_) => 1,
+        GroupBy::TimeBucket(_) => 2,
+        GroupBy::LocalDate(_) => 3,
+        GroupBy::DayOfWeek(_) => 4,
+        GroupBy::HourOfDay(_) => 5,
+    });
+    key
+}
+
+fn lock(cache: &Mutex<QueryCache>) -> MutexGuard<'_, QueryCache> {
+    // The cache is only touched between consistent states
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl BleCube {
+    /// Cache up to `capacity` results of [`BleCube::execute_grouped`],
+    /// replacing any existing cache (and its statistics). A capacity of 0
+    /// turns caching off.
+    pub fn enable_query_cache(&mut self, capacity: usize) {
+        self.query_cache = (capacity > 0).then(|| Mutex::new(QueryCache::new(capacity)));
+    }
+
+    /// Drop the query cache and stop caching
+    pub fn disable_query_cache(&mut self) {
+        self.query_cache = None;
+    }
+
+    /// Hit, miss and invalidation counters of the query cache, if enabled
+    pub fn query_cache_stats(&self) -> Option<QueryCacheStats> {
+        self.query_cache.as_ref().map(|cache| {
+            let cache = lock(cache);
+            QueryCacheStats {
+                entries: cache.entries.len(),
+                ..cache.stats
+            }
+        })
+    }
+
+    /// Cached result of `query`, or the result of `run` (then cached)
+    pub(crate) fn cached_groups(
+        &self,
+        query: &Query,
+        run: impl FnOnce() -> Vec<Group>,
+    ) -> Vec<Group> {
+        let Some(cache) = self.query_cache.as_ref() else {
+            return run();
+        };
+        let key = normalize(query);
+        if let Some(groups) = lock(cache).get(&key) {
+            return groups;
+        }
+        // Run unlocked so concurrent readers are not serialized
+        let groups = run();
+        lock(cache).put(key, groups.clone());
+        groups
+    }
+
+    /// Drop cached results covering the time partition of `timestamp`
+    pub(crate) fn invalidate_cached(&mut self, timestamp: i64) {
+        if let Some(cache) = self.query_cache.as_mut() {
+            let width = self.time_index.width();
+            let start = timestamp.div_euclid(width) * width;
+            cache
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .invalidate_span(start, start.saturating_add(width));
+        }
+    }
+
+    /// Drop every cached result
+    pub(crate) fn clear_query_cache(&mut self) {
+        if let Some(cache) = self.query_cache.as_mut() {
+            cache
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    fn obs(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -60,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_query_cache_hits_and_partition_invalidation() {
+        let mut cube = BleCube::new();
+        cube.enable_query_cache(8);
+        for (mac, ts) in [(1, 100), (2, 200), (1, 4000)] {
+            cube.insert(obs(mac, ts));
+        }
+        let first_hour = Query::new().time_between(0, 3599).group_by(GroupBy::Mac);
+        let second_hour = Query::new().time_between(3600, 7199).group_by(GroupBy::Mac);
+        // Same query, built in another order
+        let first_hour_again = Query::new().group_by(GroupBy::Mac).time_between(0, 3599);
+
+        assert_eq!(cube.execute_grouped(&first_hour).len(), 2);
+        assert_eq!(cube.execute_grouped(&first_hour_again).len(), 2);
+        assert_eq!(cube.execute_grouped(&second_hour).len(), 1);
+        let stats = cube.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 2, 2));
+
+        // Only the second hour's entry covers the new record
+        cube.insert(obs(3, 5000));
+        assert_eq!(cube.query_cache_stats().unwrap().invalidations, 1);
+        assert_eq!(cube.execute_grouped(&second_hour).len(), 2);
+        assert_eq!(cube.execute_grouped(&first_hour).len(), 2);
+        let stats = cube.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (2, 3));
+
+        // Tag edits clear everything
+        cube.tag(0, "x");
+        assert_eq!(cube.query_cache_stats().unwrap().entries, 0);
+    }
+
+    #[test]
+    fn test_query_cache_evicts_least_recently_used() {
+        let mut cube = BleCube::new();
+        cube.enable_query_cache(2);
+        cube.insert(obs(1, 100));
+        let queries: Vec<Query> = (0..3)
+            .map(|i| Query::new().time_between(0, 1000 + i))
+            .collect();
+        cube.execute_grouped(&queries[0]);
+        cube.execute_grouped(&queries[1]);
+        cube.execute_grouped(&queries[0]);
+        cube.execute_grouped(&queries[2]);
+        // queries[1] was least recently used
+        cube.execute_grouped(&queries[0]);
+        let stats = cube.query_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 3, 1));
+
+        cube.enable_query_cache(0);
+        assert_eq!(cube.query_cache_stats(), None);
+    }
+}
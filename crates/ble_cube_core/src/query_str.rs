@@ -0,0 +1,507 @@
+//! SQL-ish query strings compiled to [`Query`].
+//!
+//! For tooling that cannot call the builder (REPLs, HTTP endpoints, config
+//! files). A query string is a list of conditions joined by `AND`; keywords
+//! and field names are case-insensitive:
+//!
+//! ```text
+//! mac = 'AA:BB:CC:DD:EE:FF' AND rssi >= -70
+//!   AND time BETWEEN 1700000000 AND 1700003600
+//!   WITHIN 500m OF (37.77, -122.41)
+//! ```
+//!
+//! | Field        | Operators                         | Builder |
+//! |--------------|-----------------------------------|---------|
+//! | `mac`        | `=` string                        | [`Query::mac`] |
+//! | `zone`       | `=` string                        | [`Query::in_zone`] |
+//! | `tag`        | `=` string                        | [`Query::tagged`] |
+//! | `receiver`   | `=` integer                       | [`Query::receiver`] |
+//! | `floor`      | `=` integer                       | [`Query::on_floor`] |
+//! | `rssi`       | `=` `<` `<=` `>` `>=` `BETWEEN`   | [`Query::rssi_between`] |
+//! | `path_loss`  | `=` `<` `<=` `>` `>=` `BETWEEN`   | [`Query::path_loss_between`] |
+//! | `time`       | `=` `<` `<=` `>` `>=` `BETWEEN`   | [`Query::time_between`] |
+//! | `first_seen` | `>` `>=`                          | [`Query::new_since`] |
+//! | `last_seen`  | `<` `<=`                          | [`Query::not_seen_since`] |
+//!
+//! plus `WITHIN <distance>[m|km] OF (<lat>, <lon>)` for
+//! [`Query::within_radius`] (`AND` before it is optional). Several bounds on
+//! `rssi`, `path_loss` or `time` intersect. An empty string matches everything.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::mac::MacAddr;
+use crate::query::Query;
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+
+/// Error returned when a query string does not parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryParseError {
+    position: usize,
+    message: String,
+}
+
+impl QueryParseError {
+    /// Byte offset in the input where the problem was found
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for QueryParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Number(&'a str),
+    Str(&'a str),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 9] = [">=", "<=", "!=", "=", ">", "<", "(", ")", ","];
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token<'_>)>, QueryParseError> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let b = bytes[i];
+        if b.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if b.is_ascii_alphabetic() || b == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Token::Word(&input[start..i])
+        } else if b.is_ascii_digit()
+            || (b == b'-' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            Token::Number(&input[start..i])
+        } else if b == b'\'' || b == b'"' {
+            let Some(len) = input[i + 1..].find(b as char) else {
+                return Err(QueryParseError {
+                    position: start,
+                    message: "unterminated string".to_string(),
+                });
+            };
+            i += len + 2;
+            Token::Str(&input[start + 1..i - 1])
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| input[i..].starts_with(*s)) {
+            i += symbol.len();
+            Token::Symbol(symbol)
+        } else {
+            return Err(QueryParseError {
+                position: start,
+                message: format!(
+                    "unexpected character {:?}",
+                    input[i..].chars().next().unwrap_or('?')
+                ),
+            });
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Comparison in a `field <op> value` condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    next: usize,
+    end: usize,
+    query: Query,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, QueryParseError> {
+        Err(QueryParseError {
+            position: self.position(),
+            message: message.into(),
+        })
+    }
+
+    /// Offset of the next token (end of input if none)
+    fn position(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |&(at, _)| at)
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn bump(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.next).map(|(_, token)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), QueryParseError> {
+        if !self.peek_keyword(keyword) {
+            return self.error(format!("expected {}", keyword.to_ascii_uppercase()));
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Result<(), QueryParseError> {
+        if !matches!(self.peek(), Some(&Token::Symbol(s)) if s == symbol) {
+            return self.error(format!("expected '{symbol}'"));
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<f64, QueryParseError> {
+        match self.peek() {
+            Some(&Token::Number(text)) => match text.parse() {
+                Ok(value) => {
+                    self.next += 1;
+                    Ok(value)
+                }
+                Err(_) => self.error(format!("invalid number {text:?}")),
+            },
+            _ => self.error("expected a number"),
+        }
+    }
+
+    fn integer(&mut self) -> Result<i64, QueryParseError> {
+        match self.peek() {
+            Some(&Token::Number(text)) => match text.parse() {
+                Ok(value) => {
+                    self.next += 1;
+                    Ok(value)
+                }
+                Err(_) => self.error(format!("expected an integer, found {text:?}")),
+            },
+            _ => self.error("expected an integer"),
+        }
+    }
+
+    fn string(&mut self) -> Result<&'a str, QueryParseError> {
+        match self.peek() {
+            Some(&Token::Str(text)) => {
+                self.next += 1;
+                Ok(text)
+            }
+            _ => self.error("expected a quoted string"),
+        }
+    }
+
+    fn op(&mut self) -> Result<Op, QueryParseError> {
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => Op::Eq,
+            Some(Token::Symbol("<")) => Op::Lt,
+            Some(Token::Symbol("<=")) => Op::Le,
+            Some(Token::Symbol(">")) => Op::Gt,
+            Some(Token::Symbol(">=")) => Op::Ge,
+            _ => return self.error("expected a comparison (=, <, <=, >, >=)"),
+        };
+        self.next += 1;
+        Ok(op)
+    }
+
+    /// `= value` only
+    fn equals(&mut self) -> Result<(), QueryParseError> {
+        if self.op()? != Op::Eq {
+            self.next -= 1;
+            return self.error("only '=' is supported here");
+        }
+        Ok(())
+    }
+
+    /// Inclusive bounds from `<op> n` or `BETWEEN a AND b`
+    fn bounds(&mut self) -> Result<(i64, i64), QueryParseError> {
+        if self.peek_keyword("between") {
+            self.next += 1;
+            let low = self.integer()?;
+            self.keyword("and")?;
+            return Ok((low, self.integer()?));
+        }
+        let op = self.op()?;
+        let value = self.integer()?;
+        Ok(match op {
+            Op::Eq => (value, value),
+            Op::Lt => (i64::MIN, value.saturating_sub(1)),
+            Op::Le => (i64::MIN, value),
+            Op::Gt => (value.saturating_add(1), i64::MAX),
+            Op::Ge => (value, i64::MAX),
+        })
+    }
+
+    fn condition(&mut self) -> Result<(), QueryParseError> {
+        let at = self.position();
+        let Some(Token::Word(field)) = self.bump() else {
+            self.next -= 1;
+            return self.error("expected a field name or WITHIN");
+        };
+        match field.to_ascii_lowercase().as_str() {
+            "mac" => {
+                self.equals()?;
+                let position = self.position();
+                let text = self.string()?;
+                let mac: MacAddr = text.parse().map_err(|_| QueryParseError {
+                    position,
+                    message: format!("invalid MAC address {text:?}"),
+                })?;
+                self.query = core::mem::take(&mut self.query).mac(mac);
+            }
+            "zone" => {
+                self.equals()?;
+                let zone = self.string()?;
+                self.query = core::mem::take(&mut self.query).in_zone(zone);
+            }
+            "tag" => {
+                self.equals()?;
+                let tag = self.string()?;
+                self.query = core::mem::take(&mut self.query).tagged(tag);
+            }
+            "receiver" => {
+                self.equals()?;
+                let position = self.position();
+                let receiver = u16::try_from(self.integer()?).map_err(|_| QueryParseError {
+                    position,
+                    message: "receiver out of range".to_string(),
+                })?;
+                self.query = core::mem::take(&mut self.query).receiver(receiver);
+            }
+            "floor" => {
+                self.equals()?;
+                let position = self.position();
+                let floor = i16::try_from(self.integer()?).map_err(|_| QueryParseError {
+                    position,
+                    message: "floor out of range".to_string(),
+                })?;
+                self.query = core::mem::take(&mut self.query).on_floor(floor);
+            }
+            "rssi" => {
+                let (low, high) = self.bounds()?;
+                let (min, max) = self.query.rssi_range.unwrap_or((i8::MIN, i8::MAX));
+                let low = low.max(i64::from(min));
+                let high = high.min(i64::from(max));
+                if low > high {
+                    return Err(QueryParseError {
+                        position: at,
+                        message: "empty rssi range".to_string(),
+                    });
+                }
+                // Both bounds now lie within i8
+                self.query.rssi_range = Some((low as i8, high as i8));
+            }
+            "path_loss" => {
+                let (low, high) = self.bounds()?;
+                let (min, max) = self.query.path_loss_range.unwrap_or((i16::MIN, i16::MAX));
+                let low = low.max(i64::from(min));
+                let high = high.min(i64::from(max));
+                if low > high {
+                    return Err(QueryParseError {
+                        position: at,
+                        message: "empty path_loss range".to_string(),
+                    });
+                }
+                self.query.path_loss_range = Some((low as i16, high as i16));
+            }
+            "time" => {
+                let (low, high) = self.bounds()?;
+                let (start, end) = self.query.time_range.unwrap_or((i64::MIN, i64::MAX));
+                let (start, end) = (low.max(start), high.min(end));
+                if start > end {
+                    return Err(QueryParseError {
+                        position: at,
+                        message: "empty time range".to_string(),
+                    });
+                }
+                self.query.time_range = Some((start, end));
+            }
+            "first_seen" => {
+                let timestamp = match self.op()? {
+                    Op::Ge => self.integer()?,
+                    Op::Gt => self.integer()?.saturating_add(1),
+                    _ => {
+                        self.next -= 1;
+                        return self.error("first_seen supports only > and >=");
+                    }
+                };
+                self.query.new_since = Some(timestamp);
+            }
+            "last_seen" => {
+                let timestamp = match self.op()? {
+                    Op::Lt => self.integer()?,
+                    Op::Le => self.integer()?.saturating_add(1),
+                    _ => {
+                        self.next -= 1;
+                        return self.error("last_seen supports only < and <=");
+                    }
+                };
+                self.query.not_seen_since = Some(timestamp);
+            }
+            "within" => {
+                let mut radius_m = self.number()?;
+                if self.peek_keyword("km") {
+                    self.next += 1;
+                    radius_m *= 1000.0;
+                } else if self.peek_keyword("m") {
+                    self.next += 1;
+                }
+                self.keyword("of")?;
+                self.symbol("(")?;
+                let lat = self.number()?;
+                self.symbol(",")?;
+                let lon = self.number()?;
+                self.symbol(")")?;
+                self.query = core::mem::take(&mut self.query).within_radius(lat, lon, radius_m);
+            }
+            _ => {
+                return Err(QueryParseError {
+                    position: at,
+                    message: format!("unknown field {field:?}"),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(mut self) -> Result<Query, QueryParseError> {
+        if self.peek().is_none() {
+            return Ok(self.query);
+        }
+        self.condition()?;
+        while self.peek().is_some() {
+            if self.peek_keyword("and") {
+                self.next += 1;
+            } else if !self.peek_keyword("within") {
+                return self.error("expected AND");
+            }
+            self.condition()?;
+        }
+        Ok(self.query)
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryParseError;
+
+    /// Parse the query language described in the module docs
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser {
+            tokens: tokenize(s)?,
+            next: 0,
+            end: s.len(),
+            query: Query::new(),
+        }
+        .parse()
+    }
+}
+
+impl BleCube {
+    /// Parse a query string (see [`Query`]'s `FromStr`) and execute it
+    pub fn query_str(&self, query: &str) -> Result<Vec<&BleObservation>, QueryParseError> {
+        Ok(self.execute(&query.parse()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_compiles_to_builder() {
+        let parsed: Query = "mac = 'AA:BB:CC:DD:EE:FF' AND rssi >= -70 \
+             AND time BETWEEN 1700000000 AND 1700003600 WITHIN 500m OF (37.77, -122.41)"
+            .parse()
+            .unwrap();
+        let built = Query::new()
+            .mac([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])
+            .rssi_between(-70, i8::MAX)
+            .time_between(1_700_000_000, 1_700_003_600)
+            .within_radius(37.77, -122.41, 500.0);
+        assert_eq!(parsed, built);
+
+        let parsed: Query = "Rssi > -80 and rssi < -40 AND receiver = 3 AND floor = -1 \
+             and zone = \"lobby\" and tag = 'suspect' and first_seen >= 100 and last_seen <= 200 \
+             and within 1.5 km of (1, 2)"
+            .parse()
+            .unwrap();
+        let built = Query::new()
+            .rssi_between(-79, -41)
+            .receiver(3)
+            .on_floor(-1)
+            .in_zone("lobby")
+            .tagged("suspect")
+            .new_since(100)
+            .not_seen_since(201)
+            .within_radius(1.0, 2.0, 1500.0);
+        assert_eq!(parsed, built);
+        assert_eq!("".parse::<Query>().unwrap(), Query::new());
+        assert_eq!(
+            "path_loss BETWEEN 10 AND 300 AND path_loss < 40"
+                .parse::<Query>()
+                .unwrap(),
+            Query::new().path_loss_between(10, 39)
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_point_at_problem() {
+        for (input, position) in [
+            ("rssi >= -70 time = 5", 12),
+            ("mac = 'AA:BB'", 6),
+            ("mac >= 'AA:BB:CC:DD:EE:FF'", 4),
+            ("speed > 3", 0),
+            ("rssi > 10 AND rssi < 0", 14),
+            ("rssi >= 1.5", 8),
+            ("zone = 'lobby", 7),
+            ("time BETWEEN 1 5", 15),
+            ("WITHIN 5 OF (1, 2", 17),
+            ("receiver = 70000", 11),
+            ("rssi ! 3", 5),
+        ] {
+            let err = input.parse::<Query>().unwrap_err();
+            assert_eq!(err.position(), position, "{input}: {err}");
+        }
+    }
+
+    #[test]
+    fn test_query_str_executes() {
+        let mut cube = BleCube::new();
+        for i in 0..10u8 {
+            cube.insert(BleObservation {
+                mac: [0, 0, 0, 0, 0, i % 2],
+                rssi: -50 - i as i8,
+                timestamp: 100 + i as i64,
+                ..Default::default()
+            });
+        }
+        let hits = cube
+            .query_str("mac = '00:00:00:00:00:01' AND time <= 105")
+            .unwrap();
+        assert_eq!(hits.len(), 3);
+        assert_eq!(cube.query_str("").unwrap().len(), 10);
+        assert!(cube.query_str("rssi").is_err());
+    }
+}
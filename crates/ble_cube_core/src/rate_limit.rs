@@ -0,0 +1,411 @@
+//! Per-MAC insert rate limiting.
+//!
+//! A beacon advertising at 10 Hz next to a scanner produces more records
+//! than every other device combined while adding little information. With
+//! a limit set, [`BleCube::insert`] stores at most that many observations
+//! per MAC per second (of the cube's time unit, seconds if none is
+//! declared) and either drops the rest or keeps the strongest of them.
+//! Limits can differ per MAC prefix, e.g. by OUI. A dropped observation
+//! fails the insert with [`CubeError::RateLimited`]; a stronger one that
+//! takes the place of a stored record reaches sinks, subscribers and the
+//! write-ahead log like any insert.
+//!
+//! Limits apply to `insert` / `try_insert` and to inserts with an
+//! advertisement (`insert_advertisement`, HCI and capture imports) only:
+//! upserts, replicated deltas and write-ahead log replay store what they
+//! are given. Observations for an earlier second than the newest one seen
+//! for their MAC (late arrivals) are not limited. Per-MAC tracking is
+//! dropped once the newest limited observation is a minute past it, so
+//! memory stays bounded by the devices heard in the last few minutes.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::error::CubeError;
+use crate::time::TimeUnit;
+#[cfg(feature = "wal")]
+use crate::wal::WalEntry;
+
+/// Which observations a rate limit keeps once a MAC's second is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RateLimitKeep {
+    /// The first observations of the second; later ones are dropped
+    First,
+    /// The strongest observations of the second: a stronger arrival
+    /// replaces the weakest stored one
+    Strongest,
+}
+
+/// Suppression counters, see [`BleCube::rate_limit_stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimitStats {
+    /// Observations over a limit that were not kept (the incoming one, or
+    /// the stored one it replaced)
+    pub suppressed: u64,
+    /// Of those, stored records replaced by a stronger arrival
+    pub replaced: u64,
+}
+
+/// (max per second, keep policy)
+type Limit = (u32, RateLimitKeep);
+
+/// Seconds a MAC's tracking outlives its newest second
+const TRACKED_SECONDS: i64 = 60;
+
+/// Configured limits and the records each recently limited MAC kept in its
+/// newest second
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RateLimiter {
+    default: Option<Limit>,
+    // Longest matching prefix wins
+    prefixes: Vec<(Vec<u8>, Limit)>,
+    windows: HashMap<[u8; 6], (i64, Vec<usize>)>,
+    // Newest second of any limited observation, and the newest second at
+    // the last pruning of `windows`
+    newest: Option<i64>,
+    pruned: Option<i64>,
+    stats: RateLimitStats,
+}
+
+impl RateLimiter {
+    fn limit_for(&self, mac: &[u8; 6]) -> Option<Limit> {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| mac.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, limit)| limit)
+            .or(self.default)
+    }
+
+    /// Advance the newest second to `window`, dropping tracking that fell
+    /// out of the last minute (at most once a minute)
+    fn advance(&mut self, window: i64) {
+        let newest = self.newest.map_or(window, |newest| newest.max(window));
+        self.newest = Some(newest);
+        if self
+            .pruned
+            .is_none_or(|pruned| newest.saturating_sub(pruned) >= TRACKED_SECONDS)
+        {
+            let oldest = newest.saturating_sub(TRACKED_SECONDS);
+            self.windows.retain(|_, (tracked, _)| *tracked > oldest);
+            self.pruned = Some(newest);
+        }
+    }
+
+    /// Forget tracked record IDs, e.g. after compaction renumbered them
+    #[cfg(feature = "std")]
+    pub(crate) fn forget_windows(&mut self) {
+        self.windows.clear();
+    }
+}
+
+impl BleCube {
+    /// Store at most `max_observations_per_mac_per_second` observations per
+    /// MAC per second, keeping the ones `keep` picks. Prefix limits from
+    /// [`BleCube::set_prefix_rate_limit`] take precedence.
+    ///
+    /// # Panics
+    /// Panics if the maximum is zero.
+    pub fn set_rate_limit(
+        &mut self,
+        max_observations_per_mac_per_second: u32,
+        keep: RateLimitKeep,
+    ) {
+        assert!(
+            max_observations_per_mac_per_second > 0,
+            "rate limit must be positive"
+        );
+        self.rate_limiter.default = Some((max_observations_per_mac_per_second, keep));
+    }
+
+    /// Limit MACs starting with `prefix` (1 to 6 bytes, e.g. a 3-byte OUI)
+    /// instead of by the global limit; the longest matching prefix wins.
+    /// A limit of `u32::MAX` exempts the prefix.
+    ///
+    /// # Panics
+    /// Panics if the maximum is zero or the prefix is empty or longer than
+    /// a MAC.
+    pub fn set_prefix_rate_limit(
+        &mut self,
+        prefix: &[u8],
+        max_observations_per_mac_per_second: u32,
+        keep: RateLimitKeep,
+    ) {
+        assert!(
+            max_observations_per_mac_per_second > 0,
+            "rate limit must be positive"
+        );
+        assert!(
+            (1..=6).contains(&prefix.len()),
+            "MAC prefix must be 1 to 6 bytes"
+        );
+        let limit = (max_observations_per_mac_per_second, keep);
+        let prefixes = &mut self.rate_limiter.prefixes;
+        match prefixes.iter_mut().find(|(existing, _)| existing == prefix) {
+            Some((_, existing)) => *existing = limit,
+            None => prefixes.push((prefix.to_vec(), limit)),
+        }
+    }
+
+    /// Remove every rate limit (counters are kept)
+    pub fn clear_rate_limits(&mut self) {
+        let stats = self.rate_limiter.stats;
+        self.rate_limiter = RateLimiter {
+            stats,
+            ..Default::default()
+        };
+    }
+
+    /// How many observations the rate limits suppressed so far
+    pub fn rate_limit_stats(&self) -> RateLimitStats {
+        self.rate_limiter.stats
+    }
+
+    /// Second (in the cube's time unit) a timestamp falls into
+    fn rate_window(&self, timestamp: i64) -> i64 {
+        let per_second = self.time_unit().unwrap_or(TimeUnit::Seconds).per_second();
+        timestamp.div_euclid(per_second)
+    }
+
+    /// Apply the rate limit to an admitted observation: `None` to insert it,
+    /// `Some(record_id)` of the weaker record it replaces (see
+    /// [`BleCube::replace_rate_limited`]), or [`CubeError::RateLimited`] if
+    /// it must not be stored
+    pub(crate) fn rate_limit(&mut self, obs: &BleObservation) -> Result<Option<usize>, CubeError> {
+        let Some((max, keep)) = self.rate_limiter.limit_for(&obs.mac) else {
+            return Ok(None);
+        };
+        let window = self.rate_window(obs.timestamp);
+        self.rate_limiter.advance(window);
+        let (tracked, kept) = self
+            .rate_limiter
+            .windows
+            .entry(obs.mac)
+            .or_insert_with(|| (window, Vec::new()));
+        if window > *tracked {
+            *tracked = window;
+            kept.clear();
+        }
+        if window < *tracked || kept.len() < max as usize {
+            return Ok(None);
+        }
+
+        if keep == RateLimitKeep::Strongest {
+            let weakest = *kept
+                .iter()
+                .min_by_key(|&&record_id| self.records.rssi()[record_id])
+                .expect("a full window holds records");
+            if obs.rssi > self.records[weakest].rssi {
+                return Ok(Some(weakest));
+            }
+        }
+        self.rate_limiter.stats.suppressed += 1;
+        Err(CubeError::RateLimited)
+    }
+
+    /// Store `obs` and its advertisement (none if `payload` is empty) in
+    /// place of the weaker record `record_id`, teed, logged and announced
+    /// like an insert
+    pub(crate) fn replace_rate_limited(
+        &mut self,
+        record_id: usize,
+        obs: BleObservation,
+        payload: &[u8],
+    ) -> Result<usize, CubeError> {
+        #[cfg(feature = "std")]
+        self.write_sinks(&obs)?;
+
+        #[cfg(feature = "wal")]
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&WalEntry::ReplaceAdvertisement(
+                record_id,
+                obs,
+                payload.to_vec(),
+            ))?;
+        }
+
+        self.replace_record(record_id, obs);
+        self.beacons.replace(record_id, payload);
+        #[cfg(feature = "std")]
+        self.notify_subscribers(record_id);
+        self.rate_limiter.stats.suppressed += 1;
+        self.rate_limiter.stats.replaced += 1;
+        Ok(record_id)
+    }
+
+    /// Count a newly inserted record against its MAC's current second
+    pub(crate) fn track_rate(&mut self, obs: &BleObservation, record_id: usize) {
+        if self.rate_limiter.windows.is_empty() {
+            return;
+        }
+        let window = self.rate_window(obs.timestamp);
+        if let Some((tracked, kept)) = self.rate_limiter.windows.get_mut(&obs.mac) {
+            if *tracked == window {
+                kept.push(record_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(mac: [u8; 6], timestamp: i64, rssi: i8) -> BleObservation {
+        BleObservation {
+            mac,
+            rssi,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_keep_first_and_strongest() {
+        let chatty = [0xAC, 0x23, 0x3F, 0, 0, 1];
+        let quiet = [0x11, 0, 0, 0, 0, 2];
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Milliseconds, crate::time::UnitMismatch::Reject);
+        cube.set_rate_limit(2, RateLimitKeep::First);
+        cube.set_prefix_rate_limit(&chatty[..3], 1, RateLimitKeep::Strongest);
+
+        // 10 Hz for 2 seconds from both devices
+        let mut refused = 0;
+        for i in 0..20i64 {
+            let t = 1_700_000_000_000 + i * 100;
+            let rssi = -70 + (i % 10) as i8;
+            for mac in [chatty, quiet] {
+                match cube.try_insert(obs(mac, t, rssi)) {
+                    Ok(_) => {}
+                    Err(CubeError::RateLimited) => refused += 1,
+                    Err(e) => panic!("{e}"),
+                }
+            }
+        }
+        // Strongest: one record per second, the -61 dBm peak of each
+        let kept: Vec<i8> = cube.query_mac(chatty).iter().map(|o| o.rssi).collect();
+        assert_eq!(kept, vec![-61, -61]);
+        // First: the first two per second
+        let kept: Vec<i64> = cube
+            .query_mac(quiet)
+            .iter()
+            .map(|o| o.timestamp % 1000)
+            .collect();
+        assert_eq!(kept, vec![0, 100, 0, 100]);
+        assert_eq!(cube.len(), 6);
+        // Every stronger chatty arrival replaced the stored record instead
+        assert_eq!(refused, 16);
+        let stats = cube.rate_limit_stats();
+        assert_eq!(stats.suppressed, 34);
+        assert_eq!(stats.replaced, 18);
+
+        // Late arrivals and unlimited cubes store everything
+        cube.insert(obs(quiet, 1_699_999_999_000, -40));
+        cube.clear_rate_limits();
+        cube.insert(obs(chatty, 1_700_000_001_900, -90));
+        assert_eq!(cube.len(), 8);
+        assert_eq!(cube.rate_limit_stats().suppressed, 34);
+    }
+
+    #[test]
+    fn test_suppressed_insert_stores_nothing() {
+        let mac = [1; 6];
+        let mut cube = BleCube::new();
+        cube.set_rate_limit(1, RateLimitKeep::First);
+        cube.insert(obs(mac, 100, -70));
+
+        let err = cube.try_insert(obs(mac, 100, -40)).unwrap_err();
+        assert!(matches!(err, CubeError::RateLimited));
+        assert!(err.is_rejected());
+        assert_eq!(cube.len(), 1);
+        assert_eq!(cube.get(0).unwrap().rssi, -70);
+    }
+
+    #[test]
+    fn test_limits_apply_to_advertisements() {
+        let mac = [0xAC, 0x23, 0x3F, 0, 0, 1];
+        let uuid = [0x11; 16];
+        let ibeacon = |major: u16| {
+            let mut payload = vec![0x1A, 0xFF, 0x4C, 0x00, 0x02, 0x15];
+            payload.extend_from_slice(&uuid);
+            payload.extend_from_slice(&major.to_be_bytes());
+            payload.extend_from_slice(&[0, 1, 0xC5]);
+            payload
+        };
+        let mut cube = BleCube::new();
+        cube.set_rate_limit(1, RateLimitKeep::Strongest);
+
+        let first = cube.insert_advertisement(obs(mac, 100, -70), &ibeacon(1));
+        // Weaker: dropped with its payload
+        let err = cube
+            .try_insert_advertisement(obs(mac, 100, -80), &ibeacon(2))
+            .unwrap_err();
+        assert!(matches!(err, CubeError::RateLimited));
+        // Stronger: replaces the record and its advertisement
+        assert_eq!(
+            cube.insert_advertisement(obs(mac, 100, -50), &ibeacon(3)),
+            first
+        );
+        assert_eq!(cube.len(), 1);
+        assert_eq!(cube.get(first).unwrap().rssi, -50);
+        assert_eq!(cube.advertisement(first), Some(&ibeacon(3)[..]));
+        assert!(cube.query_ibeacon(&uuid, 1, 1).is_empty());
+        assert_eq!(cube.query_ibeacon(&uuid, 3, 1).len(), 1);
+        assert_eq!(cube.query_ibeacon_uuid(&uuid).len(), 1);
+        assert_eq!(cube.rate_limit_stats().suppressed, 2);
+
+        // A plain observation replacing it clears the advertisement
+        assert_eq!(cube.insert(obs(mac, 100, -40)), first);
+        assert_eq!(cube.advertisement(first), None);
+        assert!(cube.query_ibeacon_uuid(&uuid).is_empty());
+
+        // The next second is tracked from the advertisement just inserted
+        let second = cube.insert_advertisement(obs(mac, 101, -90), &[]);
+        assert!(cube.try_insert_advertisement(obs(mac, 101, -95), &[]).is_err());
+        assert_eq!(cube.get(second).unwrap().rssi, -90);
+        assert_eq!(cube.len(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_replacements_reach_sinks_and_subscribers() {
+        let mac = [1; 6];
+        let mut cube = BleCube::new();
+        cube.set_rate_limit(1, RateLimitKeep::Strongest);
+        let (tx, teed) = std::sync::mpsc::channel();
+        cube.add_sink(tx);
+        let feed = cube.subscribe(crate::query::Query::new());
+
+        cube.insert(obs(mac, 100, -70));
+        assert!(cube.try_insert(obs(mac, 100, -80)).is_err());
+        cube.insert(obs(mac, 100, -60));
+
+        let teed: Vec<i8> = teed.try_iter().map(|obs| obs.rssi).collect();
+        assert_eq!(teed, [-70, -60]);
+        let announced: Vec<i8> = feed.try_iter().map(|obs| obs.rssi).collect();
+        assert_eq!(announced, [-70, -60]);
+    }
+
+    #[test]
+    fn test_tracking_is_pruned() {
+        let mut cube = BleCube::new();
+        cube.set_rate_limit(1, RateLimitKeep::First);
+        for device in 0..100u8 {
+            cube.insert(obs([device; 6], 1_000, -70));
+        }
+        assert_eq!(cube.rate_limiter.windows.len(), 100);
+
+        // A minute later only the MAC just heard is still tracked
+        cube.insert(obs([1; 6], 1_000 + TRACKED_SECONDS, -70));
+        assert_eq!(cube.rate_limiter.windows.len(), 1);
+        // A MAC heard again is tracked afresh
+        assert!(cube.try_insert(obs([1; 6], 1_000 + TRACKED_SECONDS, -70)).is_err());
+        cube.insert(obs([2; 6], 1_000 + TRACKED_SECONDS, -70));
+        assert!(cube.try_insert(obs([2; 6], 1_000 + TRACKED_SECONDS, -70)).is_err());
+        assert_eq!(cube.rate_limiter.windows.len(), 2);
+    }
+}
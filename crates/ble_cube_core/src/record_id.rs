@@ -0,0 +1,157 @@
+//! Stable record identifiers.
+//!
+//! The `usize` record IDs used throughout the API are positions in the
+//! record store: cheap, dense, and valid until the store is compacted
+//! (`evict_partitions_before`). A [`RecordId`] is assigned once at insert
+//! and never changes or gets reused, so it is what external systems should
+//! store to refer back to an observation.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use core::fmt;
+
+/// Durable identifier of an observation.
+///
+/// Guarantees, including across write-ahead log recovery and checkpoints:
+/// - assigned in insertion order, strictly increasing;
+/// - never reassigned, even after the record is evicted;
+/// - unaffected by upserts that overwrite the record in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct RecordId(u64);
+
+impl RecordId {
+    pub const fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for RecordId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl BleCube {
+    /// Stable ID of the record currently at position `record_id`
+    pub fn stable_id(&self, record_id: usize) -> Option<RecordId> {
+        self.record_ids.get(record_id).copied()
+    }
+
+    /// Current position of a stable ID, `None` once the record is evicted.
+    /// O(log n).
+    pub fn resolve(&self, id: RecordId) -> Option<usize> {
+        self.record_ids.binary_search(&id).ok()
+    }
+
+    /// Look up an observation by stable ID
+    pub fn get_by_id(&self, id: RecordId) -> Option<&BleObservation> {
+        self.resolve(id).map(|record_id| &self.records[record_id])
+    }
+
+    /// Stable ID of every record, indexed by position (ascending, since
+    /// compaction preserves order); export it to translate positional
+    /// results for external systems
+    pub fn external_id_map(&self) -> &[RecordId] {
+        &self.record_ids
+    }
+
+    /// ID the next inserted record will get
+    pub fn next_record_id(&self) -> RecordId {
+        RecordId(self.next_record_id)
+    }
+
+    /// Observations inserted since the cube's [`next_record_id`] was `seq`,
+    /// in insertion order, whatever their timestamps. O(log n + k).
+    ///
+    /// Stable IDs double as an insertion sequence, so an incremental
+    /// consumer (exporter, replicator) saves `next_record_id()` as its
+    /// checkpoint after each pull and passes it back next time; starting
+    /// from `RecordId::default()` pulls everything. Evicted records are gone
+    /// and upserts that overwrite a record in place keep its old position in
+    /// the sequence, so neither shows up here.
+    ///
+    /// [`next_record_id`]: BleCube::next_record_id
+    pub fn query_since_seq(&self, seq: RecordId) -> Vec<&BleObservation> {
+        let start = self.record_ids.partition_point(|&id| id < seq);
+        self.records[start..].iter().collect()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_survive_compaction() {
+        let mut cube = BleCube::new();
+        for t in [0, 5000, 100, 7200] {
+            cube.insert(BleObservation {
+                timestamp: t,
+                ..Default::default()
+            });
+        }
+        let late = cube.stable_id(3).unwrap();
+        assert_eq!(late, RecordId::from_u64(3));
+
+        // Evicts the records at positions 0 and 2 (hour 0)
+        cube.evict_partitions_before(3600).unwrap();
+        assert_eq!(
+            cube.external_id_map(),
+            &[RecordId::from_u64(1), RecordId::from_u64(3)]
+        );
+        assert_eq!(cube.resolve(late), Some(1));
+        assert_eq!(cube.get_by_id(late).unwrap().timestamp, 7200);
+        assert_eq!(cube.resolve(RecordId::from_u64(0)), None);
+
+        // IDs are never reused
+        let id = cube.insert(BleObservation::default());
+        assert_eq!(cube.stable_id(id), Some(RecordId::from_u64(4)));
+        assert_eq!(cube.next_record_id(), RecordId::from_u64(5));
+    }
+
+    #[test]
+    fn test_query_since_seq_pulls_new_inserts() {
+        let mut cube = BleCube::new();
+        let mut checkpoint = RecordId::default();
+        let mut pull = |cube: &BleCube| {
+            let stamps: Vec<i64> = cube
+                .query_since_seq(checkpoint)
+                .iter()
+                .map(|obs| obs.timestamp)
+                .collect();
+            checkpoint = cube.next_record_id();
+            stamps
+        };
+        assert!(pull(&cube).is_empty());
+
+        // Insertion order, not timestamp order
+        for t in [5000, 100, 7200] {
+            cube.insert(BleObservation {
+                timestamp: t,
+                ..Default::default()
+            });
+        }
+        assert_eq!(pull(&cube), vec![5000, 100, 7200]);
+        assert!(pull(&cube).is_empty());
+
+        // A late, backdated insert is still new; evicted ones are skipped
+        cube.insert(BleObservation {
+            timestamp: 50,
+            ..Default::default()
+        });
+        cube.insert(BleObservation {
+            timestamp: 9000,
+            ..Default::default()
+        });
+        cube.evict_partitions_before(3600).unwrap();
+        assert_eq!(pull(&cube), vec![9000]);
+        assert_eq!(cube.query_since_seq(RecordId::from_u64(1)).len(), 2);
+        assert!(cube.query_since_seq(RecordId::from_u64(99)).is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+//! Replication of new observations between cubes.
+//!
+//! An edge collector cuts a [`Delta`] of everything inserted since the last
+//! checkpoint with [`BleCube::delta_since`], ships [`Delta::encode`]'s bytes
+//! over whatever transport it has (HTTP, MQTT, a serial link), and the
+//! aggregation cube applies them with [`BleCube::apply_delta`]. Checkpoints
+//! are insertion sequence numbers (stable record IDs), so backfilled or
+//! late-arriving observations replicate like any other.
+//!
+//! Wire format, little-endian: the 8-byte magic `BLEDLT01`, `since: u64`,
+//! `until: u64`, `count: u32`, `count` observations in the write-ahead log's
+//! observation encoding, then a CRC-32 of everything before it.
+
+use crate::ble_cube::{BleCube, BleObservation, DuplicatePolicy, UpsertOutcome};
+use crate::checksum::crc32;
+use crate::codec::{
+    decode_observation, encode_observation, MAX_OBSERVATION_LEN, MIN_OBSERVATION_LEN,
+};
+use crate::compat::prelude::*;
+use crate::error::CubeError;
+use crate::record_id::RecordId;
+use core::error::Error;
+use core::fmt;
+
+const DELTA_MAGIC: &[u8; 8] = b"BLEDLT01";
+/// Magic, since, until and count
+const DELTA_HEADER_LEN: usize = 8 + 8 + 8 + 4;
+const DELTA_TRAILER_LEN: usize = 4;
+
+/// Observations a cube gained between two checkpoints, see
+/// [`BleCube::delta_since`]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Delta {
+    /// Checkpoint the delta was cut from
+    pub since: RecordId,
+    /// The source's [`BleCube::next_record_id`] when the delta was cut;
+    /// the checkpoint for the next delta
+    pub until: RecordId,
+    /// New observations in insertion order
+    pub observations: Vec<BleObservation>,
+}
+
+/// Why [`Delta::decode`] rejected its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaDecodeError {
+    /// Not a delta, or a format version this build does not read
+    BadMagic,
+    /// Input ends before the announced observations and checksum
+    Truncated,
+    /// The checksum does not match (corrupted in transit)
+    ChecksumMismatch,
+    /// Checksum is fine but an observation or the length is invalid
+    Malformed,
+}
+
+impl fmt::Display for DeltaDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DeltaDecodeError::BadMagic => "not a replication delta",
+            DeltaDecodeError::Truncated => "truncated replication delta",
+            DeltaDecodeError::ChecksumMismatch => "replication delta checksum mismatch",
+            DeltaDecodeError::Malformed => "malformed replication delta",
+        })
+    }
+}
+
+impl Error for DeltaDecodeError {}
+
+impl Delta {
+    /// Wire encoding (see the module docs); about 32 bytes per observation
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            DELTA_HEADER_LEN + self.observations.len() * MAX_OBSERVATION_LEN + DELTA_TRAILER_LEN,
+        );
+        buf.extend_from_slice(DELTA_MAGIC);
+        buf.extend_from_slice(&self.since.as_u64().to_le_bytes());
+        buf.extend_from_slice(&self.until.as_u64().to_le_bytes());
+        buf.extend_from_slice(&(self.observations.len() as u32).to_le_bytes());
+        for obs in &self.observations {
+            encode_observation(obs, &mut buf);
+        }
+        let crc = crc32(&buf);
+        buf.extend_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Parse bytes produced by [`Delta::encode`]
+    pub fn decode(bytes: &[u8]) -> Result<Self, DeltaDecodeError> {
+        if bytes
+            .get(..DELTA_MAGIC.len())
+            .is_some_and(|magic| magic != DELTA_MAGIC)
+        {
+            return Err(DeltaDecodeError::BadMagic);
+        }
+        if bytes.len() < DELTA_HEADER_LEN + DELTA_TRAILER_LEN {
+            return Err(DeltaDecodeError::Truncated);
+        }
+        let (body, trailer) = bytes.split_at(bytes.len() - DELTA_TRAILER_LEN);
+        let u64_at = |offset: usize| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&body[offset..offset + 8]);
+            u64::from_le_bytes(word)
+        };
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&body[24..28]);
+        let count = u32::from_le_bytes(count) as usize;
+
+        if crc32(body).to_le_bytes() != trailer {
+            // A short read usually shows up as a bad checksum; say so when
+            // the body cannot hold the announced observations
+            return Err(
+                if body.len() < DELTA_HEADER_LEN + count.saturating_mul(MIN_OBSERVATION_LEN) {
+                    DeltaDecodeError::Truncated
+                } else {
+                    DeltaDecodeError::ChecksumMismatch
+                },
+            );
+        }
+
+        let mut observations = Vec::with_capacity(count.min(body.len() / MIN_OBSERVATION_LEN));
+        let mut rest = &body[DELTA_HEADER_LEN..];
+        for _ in 0..count {
+            let (obs, len) = decode_observation(rest).ok_or(DeltaDecodeError::Malformed)?;
+            observations.push(obs);
+            rest = &rest[len..];
+        }
+        if !rest.is_empty() {
+            return Err(DeltaDecodeError::Malformed);
+        }
+        Ok(Self {
+            since: RecordId::from_u64(u64_at(8)),
+            until: RecordId::from_u64(u64_at(16)),
+            observations,
+        })
+    }
+}
+
+impl BleCube {
+    /// Everything inserted since checkpoint `seq` (see
+    /// [`BleCube::query_since_seq`]), ready to ship to another cube. Start
+    /// from `RecordId::default()` and continue from each delta's `until`.
+    pub fn delta_since(&self, seq: RecordId) -> Delta {
+        Delta {
+            since: seq,
+            until: self.next_record_id(),
+            observations: self.query_since_seq(seq).into_iter().copied().collect(),
+        }
+    }
+
+    /// Insert a delta's observations through the usual admission path
+    /// (time-unit policy, validation, write-ahead log), returning how many
+    /// were new. RSSI offsets are not applied again: the edge calibrated
+    /// its observations when it stored them. Observations whose (mac, timestamp)
+    /// is already stored are skipped, so redelivering a delta after a lost
+    /// acknowledgement is harmless and several edges can feed one cube.
+    ///
+    /// # Panics
+    /// Panics if an observation is rejected or the write-ahead log append
+    /// fails; use [`BleCube::try_apply_delta`] to handle that case.
+    pub fn apply_delta(&mut self, delta: Delta) -> usize {
+        self.apply_delta_logged(delta).expect("insert failed")
+    }
+
+    /// Fallible form of [`BleCube::apply_delta`]; observations before the
+    /// failing one stay applied
+    pub fn try_apply_delta(&mut self, delta: Delta) -> Result<usize, CubeError> {
+        self.apply_delta_logged(delta)
+    }
+
+    fn apply_delta_logged(&mut self, delta: Delta) -> Result<usize, CubeError> {
+        let mut inserted = 0;
+        for obs in delta.observations {
+            let obs = self.admit_calibrated(obs)?;
+            if let UpsertOutcome::Inserted(_) =
+                self.upsert_admitted(obs, DuplicatePolicy::KeepExisting)?
+            {
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -60,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_edges_sync_into_aggregate() {
+        let mut edges = [BleCube::new(), BleCube::new()];
+        let mut checkpoints = [RecordId::default(); 2];
+        let mut central = BleCube::new();
+        let mut sync = |edges: &[BleCube; 2], central: &mut BleCube| {
+            let mut inserted = 0;
+            for (edge, checkpoint) in edges.iter().zip(&mut checkpoints) {
+                let wire = edge.delta_since(*checkpoint).encode();
+                let delta = Delta::decode(&wire).unwrap();
+                *checkpoint = delta.until;
+                inserted += central.apply_delta(delta);
+            }
+            inserted
+        };
+
+        edges[0].insert(obs(1, 100));
+        edges[0].insert(BleObservation {
+            receiver_id: Some(3),
+            floor: Some(-1),
+            tx_power: Some(-59),
+            ..obs(1, 200)
+        });
+        edges[1].insert(obs(2, 150));
+        assert_eq!(sync(&edges, &mut central), 3);
+        assert_eq!(central.len(), 3);
+        assert_eq!(central.query_receiver(3)[0].tx_power, Some(-59));
+        assert_eq!(sync(&edges, &mut central), 0);
+
+        // A backfilled observation is new to the sequence
+        edges[1].insert(obs(2, 50));
+        assert_eq!(sync(&edges, &mut central), 1);
+        assert_eq!(central.query_mac([2; 6]).len(), 2);
+
+        // Redelivery is idempotent
+        let delta = edges[0].delta_since(RecordId::default());
+        assert_eq!(delta.observations.len(), 2);
+        assert_eq!(central.apply_delta(delta), 0);
+        assert_eq!(central.len(), 4);
+    }
+
+    #[test]
+    fn test_deltas_are_not_calibrated_twice() {
+        let mut edge = BleCube::new();
+        let mut central = BleCube::new();
+        for cube in [&mut edge, &mut central] {
+            cube.set_rssi_offset(3, 5);
+        }
+        edge.insert(BleObservation {
+            receiver_id: Some(3),
+            ..obs(1, 100)
+        });
+        assert_eq!(edge.get(0).unwrap().rssi, -55);
+        central.apply_delta(edge.delta_since(RecordId::default()));
+        assert_eq!(central.get(0).unwrap().rssi, -55);
+    }
+
+    #[test]
+    fn test_decode_rejects_damaged_input() {
+        let mut cube = BleCube::new();
+        cube.insert(obs(1, 100));
+        cube.insert(obs(2, 200));
+        let wire = cube.delta_since(RecordId::from_u64(1)).encode();
+        let delta = Delta::decode(&wire).unwrap();
+        assert_eq!(
+            (delta.since, delta.until, delta.observations[0].mac),
+            (RecordId::from_u64(1), RecordId::from_u64(2), [2; 6])
+        );
+        assert_eq!(wire.len(), DELTA_HEADER_LEN + 32 + DELTA_TRAILER_LEN);
+
+        let mut flipped = wire.clone();
+        flipped[40] ^= 1;
+        assert_eq!(
+            Delta::decode(&flipped).unwrap_err(),
+            DeltaDecodeError::ChecksumMismatch
+        );
+        assert_eq!(
+            Delta::decode(&wire[..wire.len() - 10]).unwrap_err(),
+            DeltaDecodeError::Truncated
+        );
+        assert_eq!(
+            Delta::decode(&wire[..12]).unwrap_err(),
+            DeltaDecodeError::Truncated
+        );
+        assert_eq!(
+            Delta::decode(b"BLEWAL02 and more").unwrap_err(),
+            DeltaDecodeError::BadMagic
+        );
+        let empty = Delta::default().encode();
+        assert!(Delta::decode(&empty).unwrap().observations.is_empty());
+    }
+}
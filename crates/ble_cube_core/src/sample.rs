@@ -0,0 +1,154 @@
+//! Uniform random sampling of query results.
+//!
+//! Matches are streamed through a fixed-size reservoir (Algorithm R), so
+//! sampling `n` records out of tens of millions keeps only `n` IDs in memory.
+//! Randomness comes from a seeded SplitMix64 generator: the same seed over
+//! the same cube yields the same sample.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+
+/// SplitMix64: tiny, fast, and good enough for picking sample slots
+#[derive(Debug, Clone)]
+pub struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, bound) by widening multiply (bias at most bound / 2^64)
+    pub fn below(&mut self, bound: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(bound)) >> 64) as u64
+    }
+}
+
+/// Fixed-size uniform sample of a stream of record IDs
+#[derive(Debug, Clone)]
+pub struct Reservoir {
+    capacity: usize,
+    seen: u64,
+    ids: Vec<usize>,
+    rng: SplitMix64,
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Self {
+            capacity,
+            seen: 0,
+            ids: Vec::with_capacity(capacity.min(1 << 16)),
+            rng: SplitMix64(seed),
+        }
+    }
+
+    pub fn offer(&mut self, record_id: usize) {
+        self.seen += 1;
+        if self.ids.len() < self.capacity {
+            self.ids.push(record_id);
+        } else {
+            let slot = self.rng.below(self.seen) as usize;
+            if slot < self.capacity {
+                self.ids[slot] = record_id;
+            }
+        }
+    }
+
+    /// Sampled IDs in ascending order
+    pub fn into_sorted(mut self) -> Vec<usize> {
+        self.ids.sort_unstable();
+        self.ids
+    }
+}
+
+impl BleCube {
+    /// Up to `n` observations drawn uniformly without replacement from the
+    /// whole cube, in record ID order, using a fresh random seed
+    #[cfg(feature = "std")]
+    pub fn random_sample(&self, n: usize) -> Vec<&BleObservation> {
+        use std::hash::{BuildHasher, Hasher};
+        let seed = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        self.random_sample_seeded(n, seed)
+    }
+
+    /// Like [`BleCube::random_sample`], but reproducible: the same seed over
+    /// the same records picks the same sample
+    pub fn random_sample_seeded(&self, n: usize, seed: u64) -> Vec<&BleObservation> {
+        let mut reservoir = Reservoir::new(n, seed);
+        (0..self.records.len()).for_each(|id| reservoir.offer(id));
+        reservoir
+            .into_sorted()
+            .into_iter()
+            .map(|id| &self.records[id])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+
+    fn cube(n: usize) -> BleCube {
+        let records = (0..n)
+            .map(|i| BleObservation {
+                rssi: if i % 2 == 0 { -50 } else { -80 },
+                timestamp: i as i64,
+                ..Default::default()
+            })
+            .collect();
+        BleCube::bulk_load(records)
+    }
+
+    #[test]
+    fn test_reservoir_is_uniform() {
+        // Each of 10 IDs should land in a 3-slot sample ~30% of the time
+        let mut hits = [0u32; 10];
+        for seed in 0..10_000 {
+            let mut reservoir = Reservoir::new(3, seed);
+            (0..10).for_each(|id| reservoir.offer(id));
+            let sample = reservoir.into_sorted();
+            assert_eq!(sample.len(), 3);
+            assert!(sample.windows(2).all(|w| w[0] < w[1]));
+            sample.iter().for_each(|&id| hits[id] += 1);
+        }
+        assert!(
+            hits.iter().all(|&h| (2_700..3_300).contains(&h)),
+            "{hits:?}"
+        );
+    }
+
+    #[test]
+    fn test_query_sample_is_seeded_subset() {
+        let cube = cube(1_000);
+        let query = Query::new().rssi_between(-60, -40).sample(25, 7);
+        let ids = cube.execute_ids(&query);
+        assert_eq!(ids.len(), 25);
+        assert!(ids.iter().all(|&id| id % 2 == 0));
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(cube.execute_ids(&query), ids);
+        assert_ne!(cube.execute_ids(&query.clone().sample(25, 8)), ids);
+
+        // Fewer matches than requested: all of them
+        let few = Query::new().time_between(0, 9).sample(25, 7);
+        assert_eq!(cube.execute_ids(&few), (0..10).collect::<Vec<_>>());
+        assert!(cube.execute_ids(&Query::new().sample(0, 7)).is_empty());
+    }
+
+    #[test]
+    fn test_random_sample() {
+        let cube = cube(500);
+        let seeded = cube.random_sample_seeded(50, 1);
+        assert_eq!(seeded.len(), 50);
+        assert!(seeded.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+        #[cfg(feature = "std")]
+        assert_eq!(cube.random_sample(10).len(), 10);
+        assert_eq!(cube.random_sample_seeded(600, 1).len(), 500);
+    }
+}
@@ -0,0 +1,223 @@
+//! Scan schedules: when the collector was listening at all.
+//!
+//! A device missing from the data was either away or not looked for: a
+//! duty-cycled scanner, a survey van parked overnight, a gateway that was
+//! down. With a [`ScanSchedule`] registered through
+//! [`BleCube::set_scan_schedule`], presence analytics tell the two apart:
+//! - [`BleCube::presence`] reports a window as seen, absent or not scanned;
+//! - presence sessions and dwell times only count scanned time towards a
+//!   gap, so a scanner pause does not split a visit;
+//! - time-bucket groups carry the fraction of the bucket that was scanned
+//!   ([`Group::scan_coverage`](crate::Group::scan_coverage)).
+
+use crate::ble_cube::BleCube;
+use crate::compat::prelude::*;
+use crate::mac::MacAddr;
+
+/// Time windows during which the collector was scanning, in the cube's
+/// timestamp units. Windows are half-open, `[start, end)`; overlapping and
+/// touching windows are merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanSchedule {
+    /// Sorted, disjoint, non-empty
+    windows: Vec<(i64, i64)>,
+}
+
+impl ScanSchedule {
+    /// A schedule with no scanning at all
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the window `[start, end)`; empty windows are ignored
+    pub fn window(mut self, start: i64, end: i64) -> Self {
+        if start >= end {
+            return self;
+        }
+        let first = self.windows.partition_point(|&(_, e)| e < start);
+        let last = self.windows.partition_point(|&(s, _)| s <= end);
+        let (mut start, mut end) = (start, end);
+        if first < last {
+            start = start.min(self.windows[first].0);
+            end = end.max(self.windows[last - 1].1);
+        }
+        self.windows.splice(first..last, [(start, end)]);
+        self
+    }
+
+    /// Add a duty cycle between `start` and `end`: scanning for `on` units
+    /// at the start of every `period` (e.g. 10 s of every minute)
+    ///
+    /// # Panics
+    /// Panics if `period` is not positive.
+    pub fn periodic(mut self, start: i64, end: i64, period: i64, on: i64) -> Self {
+        assert!(period > 0, "scan period must be positive");
+        let mut cycle = start;
+        while cycle < end {
+            self = self.window(cycle, cycle.saturating_add(on).min(end));
+            let Some(next) = cycle.checked_add(period) else {
+                break;
+            };
+            cycle = next;
+        }
+        self
+    }
+
+    /// The merged windows, in ascending order
+    pub fn windows(&self) -> &[(i64, i64)] {
+        &self.windows
+    }
+
+    /// Whether the collector was scanning at `timestamp`
+    pub fn is_scanning(&self, timestamp: i64) -> bool {
+        let i = self.windows.partition_point(|&(_, end)| end <= timestamp);
+        self.windows
+            .get(i)
+            .is_some_and(|&(start, _)| start <= timestamp)
+    }
+
+    /// Scanned time within `[start, end)`
+    pub fn scanned(&self, start: i64, end: i64) -> i64 {
+        let first = self.windows.partition_point(|&(_, e)| e <= start);
+        self.windows[first..]
+            .iter()
+            .take_while(|&&(s, _)| s < end)
+            .map(|&(s, e)| e.min(end).saturating_sub(s.max(start)))
+            .fold(0, i64::saturating_add)
+    }
+
+    /// Fraction of `[start, end)` that was scanned, 0 for an empty interval
+    pub fn coverage(&self, start: i64, end: i64) -> f64 {
+        if start >= end {
+            return 0.0;
+        }
+        self.scanned(start, end) as f64 / end.abs_diff(start) as f64
+    }
+}
+
+/// Whether a device was seen during a window, see [`BleCube::presence`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Presence {
+    /// At least one observation in the window
+    Seen,
+    /// No observation although the collector was scanning for part of the
+    /// window (or no schedule is set, so it is assumed to be always on)
+    Absent,
+    /// No observation, and the collector was not scanning at all
+    NotScanned,
+}
+
+impl BleCube {
+    /// Register when the collector was scanning, replacing any earlier
+    /// schedule. Without one the collector is assumed to scan all the time.
+    pub fn set_scan_schedule(&mut self, schedule: ScanSchedule) {
+        self.scan_schedule = Some(schedule);
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+    }
+
+    /// Forget the scan schedule
+    pub fn clear_scan_schedule(&mut self) {
+        self.scan_schedule = None;
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+    }
+
+    /// The registered scan schedule
+    pub fn scan_schedule(&self) -> Option<&ScanSchedule> {
+        self.scan_schedule.as_ref()
+    }
+
+    /// Whether `mac` was seen in `[start, end]`, and if not, whether it
+    /// could have been
+    pub fn presence<M: Into<MacAddr>>(&self, mac: M, start: i64, end: i64) -> Presence {
+        let seen = self.mac_index.get(&mac.into().0).is_some_and(|ids| {
+            ids.iter()
+                .any(|&id| (start..=end).contains(&self.records[id].timestamp))
+        });
+        if seen {
+            Presence::Seen
+        } else if self.scanned(start, end.saturating_add(1)) > 0 {
+            Presence::Absent
+        } else {
+            Presence::NotScanned
+        }
+    }
+
+    /// Scanned time within `[start, end)`: all of it without a schedule
+    pub fn scanned(&self, start: i64, end: i64) -> i64 {
+        match &self.scan_schedule {
+            Some(schedule) => schedule.scanned(start, end),
+            None => end.saturating_sub(start).max(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+    use crate::group::GroupBy;
+    use crate::query::Query;
+
+    #[test]
+    fn test_schedule_windows() {
+        let schedule = ScanSchedule::new()
+            .window(100, 200)
+            .window(300, 400)
+            .window(150, 300)
+            .window(500, 500)
+            .window(600, 650);
+        assert_eq!(schedule.windows(), [(100, 400), (600, 650)]);
+        assert!(schedule.is_scanning(100));
+        assert!(!schedule.is_scanning(400));
+        assert!(!schedule.is_scanning(99));
+        assert_eq!(schedule.scanned(0, 1000), 350);
+        assert_eq!(schedule.scanned(350, 620), 70);
+        assert_eq!(schedule.coverage(0, 1000), 0.35);
+        assert_eq!(schedule.coverage(10, 10), 0.0);
+
+        let duty = ScanSchedule::new().periodic(0, 180, 60, 10);
+        assert_eq!(duty.windows(), [(0, 10), (60, 70), (120, 130)]);
+        assert_eq!(
+            ScanSchedule::new().periodic(0, 100, 10, 10).windows(),
+            [(0, 100)]
+        );
+    }
+
+    #[test]
+    fn test_schedule_aware_presence() {
+        let mut cube = BleCube::new();
+        for ts in [0, 100, 700, 800] {
+            cube.insert(BleObservation {
+                mac: [1; 6],
+                timestamp: ts,
+                ..Default::default()
+            });
+        }
+        // The scanner was off from 200 to 650
+        let schedule = ScanSchedule::new().window(0, 200).window(650, 1000);
+
+        assert_eq!(cube.presence([1; 6], 300, 400), Presence::Absent);
+        assert_eq!(cube.scanned(100, 700), 600);
+
+        cube.set_scan_schedule(schedule);
+        assert_eq!(cube.presence([1; 6], 300, 400), Presence::NotScanned);
+        assert_eq!(cube.presence([1; 6], 300, 660), Presence::Absent);
+        assert_eq!(cube.presence([1; 6], 690, 710), Presence::Seen);
+        assert_eq!(cube.scanned(100, 700), 150);
+
+        let groups = cube.execute_grouped(&Query::new().group_by(GroupBy::TimeBucket(500)));
+        let coverage: Vec<Option<f64>> = groups.iter().map(|g| g.scan_coverage).collect();
+        assert_eq!(coverage, [Some(0.4), Some(0.7)]);
+        assert_eq!(cube.execute_grouped(&Query::new())[0].scan_coverage, None);
+
+        cube.clear_scan_schedule();
+        assert_eq!(
+            cube.execute_grouped(&Query::new().group_by(GroupBy::TimeBucket(500)))[0].scan_coverage,
+            None
+        );
+    }
+}
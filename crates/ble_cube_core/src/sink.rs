@@ -0,0 +1,131 @@
+//! Insert tee: archive observations while they are indexed.
+//!
+//! [`BleCube::add_sink`] registers an [`ObservationSink`] that receives
+//! every observation the cube indexes, just before it is logged and
+//! indexed, so a live capture is archived without a second pass over the
+//! data. Built in: the `std::sync::mpsc` senders, which hand observations
+//! to another thread (a bounded `SyncSender` applies backpressure). File
+//! sinks (`CsvSink`, `JsonlSink`) are in `ble_cube_io`.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use std::io;
+use std::sync::mpsc::{Sender, SyncSender};
+
+/// Destination for observations as they are inserted, see
+/// [`BleCube::add_sink`]. `Send + Sync` so a cube holding sinks can still
+/// be shared between threads.
+pub trait ObservationSink: Send + Sync {
+    /// Take one observation. An error fails the insert that produced it.
+    fn write(&mut self, obs: &BleObservation) -> io::Result<()>;
+
+    /// Push buffered observations to their destination
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Fails with `BrokenPipe` once the receiver is gone
+impl ObservationSink for Sender<BleObservation> {
+    fn write(&mut self, obs: &BleObservation) -> io::Result<()> {
+        self.send(*obs).map_err(|_| disconnected())
+    }
+}
+
+/// Blocks while the channel is full; fails with `BrokenPipe` once the
+/// receiver is gone
+impl ObservationSink for SyncSender<BleObservation> {
+    fn write(&mut self, obs: &BleObservation) -> io::Result<()> {
+        self.send(*obs).map_err(|_| disconnected())
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "sink receiver disconnected")
+}
+
+impl BleCube {
+    /// Tee every subsequently indexed observation into `sink`. Sinks see
+    /// inserts (including upserts that insert) in order, after validation
+    /// and rate limiting and before the write-ahead log; records updated in
+    /// place are not re-sent. A sink error fails that insert like a WAL
+    /// error does ([`BleCube::try_insert`] returns it, `insert` panics), and
+    /// the observation is neither logged nor indexed.
+    pub fn add_sink<S: ObservationSink + 'static>(&mut self, sink: S) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    /// Number of registered sinks
+    pub fn sink_count(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Flush every sink
+    pub fn flush_sinks(&mut self) -> io::Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.flush())
+    }
+
+    /// Flush and detach every sink
+    pub fn remove_sinks(&mut self) -> io::Result<()> {
+        let result = self.flush_sinks();
+        self.sinks.clear();
+        result
+    }
+
+    /// Hand an admitted observation to every sink
+    pub(crate) fn write_sinks(&mut self, obs: &BleObservation) -> io::Result<()> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.write(obs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CubeError;
+    use std::sync::mpsc;
+
+    fn obs(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -60,
+            timestamp,
+            lat: 37.5,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sinks_receive_inserts() {
+        let mut cube = BleCube::new();
+        let (tx, rx) = mpsc::channel();
+        cube.add_sink(tx);
+        let (bounded, bounded_rx) = mpsc::sync_channel(4);
+        cube.add_sink(bounded);
+        assert_eq!(cube.sink_count(), 2);
+
+        cube.insert(obs(1, 100));
+        cube.insert(obs(2, 200));
+        cube.flush_sinks().unwrap();
+
+        let sent: Vec<i64> = rx.try_iter().map(|obs| obs.timestamp).collect();
+        assert_eq!(sent, [100, 200]);
+        assert_eq!(bounded_rx.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_sink_failure_fails_insert() {
+        let mut cube = BleCube::new();
+        let (tx, rx) = mpsc::channel();
+        cube.add_sink(tx);
+        drop(rx);
+
+        let err = cube.try_insert(obs(1, 100)).unwrap_err();
+        assert!(matches!(err, CubeError::Io(e) if e.kind() == io::ErrorKind::BrokenPipe));
+        assert!(cube.is_empty());
+
+        cube.remove_sinks().unwrap();
+        assert_eq!(cube.sink_count(), 0);
+        cube.insert(obs(1, 100));
+        assert_eq!(cube.len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+//! Change feed: push newly inserted observations that match a [`Query`]
+//! to channel subscribers, so alerting pipelines don't have to poll.
+
+use crate::alert::AlertSubscription;
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::query::Query;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+
+/// Sending half of a subscription channel
+#[derive(Debug)]
+enum Feed {
+    Unbounded(Sender<BleObservation>),
+    /// Notifications are dropped (never block ingest) while the buffer is full
+    Bounded(SyncSender<BleObservation>),
+}
+
+#[derive(Debug)]
+struct Subscription {
+    query: Query,
+    feed: Feed,
+}
+
+/// Registered subscriptions
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers {
+    subscriptions: Vec<Subscription>,
+    /// See [`BleCube::subscribe_alerts`]
+    pub(crate) alerts: Vec<AlertSubscription>,
+}
+
+impl Subscribers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty() && self.alerts.is_empty()
+    }
+}
+
+impl BleCube {
+    /// Receive every subsequently inserted observation matching `filter`.
+    /// Drop the receiver to unsubscribe.
+    pub fn subscribe(&mut self, filter: Query) -> Receiver<BleObservation> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.subscriptions.push(Subscription {
+            query: filter,
+            feed: Feed::Unbounded(tx),
+        });
+        rx
+    }
+
+    /// Like [`BleCube::subscribe`] with a buffer of `capacity` observations;
+    /// matches are dropped rather than blocking inserts while it is full
+    pub fn subscribe_bounded(
+        &mut self,
+        filter: Query,
+        capacity: usize,
+    ) -> Receiver<BleObservation> {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        self.subscribers.subscriptions.push(Subscription {
+            query: filter,
+            feed: Feed::Bounded(tx),
+        });
+        rx
+    }
+
+    /// Number of live subscriptions (disconnected ones are pruned on the next insert)
+    pub fn subscription_count(&self) -> usize {
+        self.subscribers.subscriptions.len()
+    }
+
+    /// Deliver a freshly indexed record to matching subscribers
+    pub(crate) fn notify_subscribers(&mut self, record_id: usize) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let obs = self.records[record_id];
+        let mut subscriptions = std::mem::take(&mut self.subscribers.subscriptions);
+        subscriptions.retain(|sub| {
+            if !self.matches(&sub.query, record_id) {
+                return true;
+            }
+            match &sub.feed {
+                Feed::Unbounded(tx) => tx.send(obs).is_ok(),
+                Feed::Bounded(tx) => {
+                    !matches!(tx.try_send(obs), Err(TrySendError::Disconnected(_)))
+                }
+            }
+        });
+        self.subscribers.subscriptions = subscriptions;
+        if !self.subscribers.alerts.is_empty() {
+            self.notify_alerts(record_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::Zone;
+
+    fn obs(mac: u8, lat: f64) -> BleObservation {
+        BleObservation {
+            rssi: -60,
+            mac: [0, 0, 0, 0, 0, mac],
+            timestamp: 0,
+            lat,
+            lon: 0.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_subscriber_receives_matching_inserts() {
+        let mut cube = BleCube::new();
+        cube.add_zone("gate", Zone::circle(0.0, 0.0, 100.0));
+        let rx = cube.subscribe(Query::new().mac([0, 0, 0, 0, 0, 1]).in_zone("gate"));
+
+        cube.insert(obs(1, 1.0)); // outside the zone
+        cube.insert(obs(2, 0.0)); // wrong MAC
+        cube.insert(obs(1, 0.0)); // match
+
+        let received: Vec<BleObservation> = rx.try_iter().collect();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].lat, 0.0);
+    }
+
+    #[test]
+    fn test_dropped_receivers_are_pruned() {
+        let mut cube = BleCube::new();
+        let rx = cube.subscribe(Query::new());
+        drop(cube.subscribe(Query::new()));
+        assert_eq!(cube.subscription_count(), 2);
+
+        cube.insert(obs(1, 0.0));
+        assert_eq!(cube.subscription_count(), 1);
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_bounded_subscription_drops_when_full() {
+        let mut cube = BleCube::new();
+        let rx = cube.subscribe_bounded(Query::new(), 2);
+        for _ in 0..5 {
+            cube.insert(obs(1, 0.0));
+        }
+        assert_eq!(rx.try_iter().count(), 2);
+        assert_eq!(cube.subscription_count(), 1);
+    }
+}
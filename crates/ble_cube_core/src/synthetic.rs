@@ -0,0 +1,282 @@
+//! Realistic synthetic workloads for benchmarks and load tests.
+//!
+//! Uniformly random observations flatter every index: each MAC has the same
+//! handful of records, every R-tree node is equally full and each hour holds
+//! the same traffic. Real captures are skewed, and the skew is what the
+//! planner and the indices have to cope with. [`SyntheticConfig::generate`]
+//! produces a seeded, reproducible stream shaped like a city deployment:
+//! - a device population where a few devices are seen far more often than
+//!   most;
+//! - phones that rotate resolvable private addresses every
+//!   `rotation_period`, next to beacons with fixed public addresses;
+//! - sightings clustered around hotspots, plus some roaming across the area;
+//! - more traffic by day than at night.
+
+use crate::ble_cube::BleObservation;
+use crate::compat::prelude::*;
+use crate::geo::destination_point;
+use crate::sample::SplitMix64;
+use core::f64::consts::PI;
+
+const DAY_SECS: i64 = 86_400;
+
+/// Attempts at drawing a daytime timestamp before taking whatever came up
+const MAX_TIME_DRAWS: usize = 64;
+
+/// Shape of a generated workload; timestamps are Unix seconds (UTC)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticConfig {
+    /// Same seed and config, same observations
+    pub seed: u64,
+    /// Distinct physical devices
+    pub devices: usize,
+    /// First timestamp
+    pub start: i64,
+    /// Seconds covered from `start`
+    pub duration: i64,
+    /// (lat, lon) of the middle of the area
+    pub center: (f64, f64),
+    /// Radius of the area roaming sightings fall in, in meters
+    pub area_radius_m: f64,
+    /// Spots devices gather around, each device belonging to one
+    pub hotspots: usize,
+    /// Spread of sightings around a hotspot, in meters; denser at the middle
+    pub hotspot_radius_m: f64,
+    /// Fraction of sightings placed anywhere in the area instead
+    pub roaming: f64,
+    /// Fraction of devices rotating their address (the rest are beacons
+    /// with a public address and a calibrated TX power)
+    pub rotating: f64,
+    /// Seconds between address rotations
+    pub rotation_period: i64,
+    /// Traffic at night relative to the midday peak, in [0, 1]
+    pub night_activity: f64,
+    /// Receivers, one per group of hotspots; 0 leaves `receiver_id` empty
+    pub receivers: u16,
+}
+
+impl Default for SyntheticConfig {
+    /// 10,000 devices around 20 hotspots in 5 km of central San Francisco
+    /// over a week from November 2023, 60% rotating every 15 minutes, 8
+    /// receivers
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            devices: 10_000,
+            start: 1_700_000_000,
+            duration: 7 * DAY_SECS,
+            center: (37.7749, -122.4194),
+            area_radius_m: 5_000.0,
+            hotspots: 20,
+            hotspot_radius_m: 150.0,
+            roaming: 0.1,
+            rotating: 0.6,
+            rotation_period: 900,
+            night_activity: 0.15,
+            receivers: 8,
+        }
+    }
+}
+
+impl SyntheticConfig {
+    /// (lat, lon) of each hotspot, as used by [`SyntheticConfig::generate`]
+    pub fn hotspot_centers(&self) -> Vec<(f64, f64)> {
+        let mut rng = SplitMix64(self.seed.wrapping_add(K2));
+        let (lat, lon) = self.center;
+        (0..self.hotspots.max(1))
+            .map(|_| {
+                let distance = self.area_radius_m * unit(&mut rng).sqrt();
+                destination_point(lat, lon, 360.0 * unit(&mut rng), distance)
+            })
+            .collect()
+    }
+
+    /// MAC `device` advertises at `timestamp`: fixed for beacons, a fresh
+    /// resolvable private address (top bits `01`) every rotation period for
+    /// rotating devices
+    pub fn device_mac(&self, device: usize, timestamp: i64) -> [u8; 6] {
+        let device = device as u64;
+        if !self.is_rotating(device) {
+            let id = device.to_be_bytes();
+            return [0x00, 0x1A, 0x7D, id[5], id[6], id[7]];
+        }
+        let epoch = timestamp.div_euclid(self.rotation_period.max(1));
+        let mut rng = SplitMix64(mix(self.seed, device) ^ (epoch as u64).wrapping_mul(K2));
+        let bits = rng.next_u64().to_be_bytes();
+        [
+            (bits[0] & 0x3F) | 0x40,
+            bits[1],
+            bits[2],
+            bits[3],
+            bits[4],
+            bits[5],
+        ]
+    }
+
+    /// `n` observations sorted by timestamp
+    pub fn generate(&self, n: usize) -> Vec<BleObservation> {
+        let hotspots = self.hotspot_centers();
+        let mut rng = SplitMix64(self.seed);
+        let devices = self.devices.max(1);
+        let mut records: Vec<BleObservation> = (0..n)
+            .map(|_| {
+                // Squaring skews the draw towards low device numbers
+                let u = unit(&mut rng);
+                let device = ((u * u * devices as f64) as usize).min(devices - 1);
+                self.observation(&mut rng, &hotspots, device)
+            })
+            .collect();
+        records.sort_by_key(|obs| obs.timestamp);
+        records
+    }
+
+    fn observation(
+        &self,
+        rng: &mut SplitMix64,
+        hotspots: &[(f64, f64)],
+        device: usize,
+    ) -> BleObservation {
+        let timestamp = self.draw_timestamp(rng);
+        let home = (mix(self.seed, device as u64) % hotspots.len() as u64) as usize;
+        let ((lat, lon), rssi, hotspot) = if unit(rng) < self.roaming {
+            let distance = self.area_radius_m * unit(rng).sqrt();
+            let position =
+                destination_point(self.center.0, self.center.1, 360.0 * unit(rng), distance);
+            let hotspot = rng.below(hotspots.len() as u64) as usize;
+            (position, -95 + rng.below(30) as i8, hotspot)
+        } else {
+            let (lat, lon) = hotspots[home];
+            let spread = unit(rng);
+            let position =
+                destination_point(lat, lon, 360.0 * unit(rng), self.hotspot_radius_m * spread);
+            // Stronger near the middle of the hotspot, plus a little noise
+            let rssi = -45.0 - 40.0 * spread + 12.0 * (unit(rng) - 0.5);
+            (position, rssi as i8, home)
+        };
+        let beacon = !self.is_rotating(device as u64);
+        BleObservation {
+            rssi,
+            mac: self.device_mac(device, timestamp),
+            timestamp,
+            lat,
+            lon,
+            receiver_id: (self.receivers > 0).then(|| (hotspot % self.receivers as usize) as u16),
+            floor: None,
+            tx_power: beacon.then_some(-59),
+            quality: None,
+        }
+    }
+
+    /// Uniform in time, thinned by the day/night cycle
+    fn draw_timestamp(&self, rng: &mut SplitMix64) -> i64 {
+        let span = self.duration.max(1) as u64;
+        let mut timestamp = self.start;
+        for _ in 0..MAX_TIME_DRAWS {
+            timestamp = self.start.saturating_add(rng.below(span) as i64);
+            if unit(rng) < self.activity(timestamp) {
+                break;
+            }
+        }
+        timestamp
+    }
+
+    /// Relative traffic at `timestamp`: a daylight arc from 06:00 to 22:00
+    /// UTC peaking at 1, over a floor of `night_activity`
+    fn activity(&self, timestamp: i64) -> f64 {
+        let hour = timestamp.rem_euclid(DAY_SECS) as f64 / 3600.0;
+        let daylight = if (6.0..22.0).contains(&hour) {
+            (PI * (hour - 6.0) / 16.0).sin()
+        } else {
+            0.0
+        };
+        let night = self.night_activity.clamp(0.0, 1.0);
+        night + (1.0 - night) * daylight
+    }
+
+    fn is_rotating(&self, device: u64) -> bool {
+        unit(&mut SplitMix64(mix(self.seed, device))) < self.rotating
+    }
+}
+
+const K1: u64 = 0x9E37_79B9_7F4A_7C15;
+const K2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+
+/// Per-device randomness that does not depend on draw order
+fn mix(seed: u64, device: u64) -> u64 {
+    SplitMix64(seed ^ device.wrapping_mul(K1)).next_u64()
+}
+
+/// Uniform in [0, 1)
+fn unit(rng: &mut SplitMix64) -> f64 {
+    (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::haversine_distance;
+    use crate::mac::{MacAddr, RandomAddressKind};
+
+    #[test]
+    fn test_synthetic_workload_shape() {
+        let config = SyntheticConfig {
+            devices: 500,
+            duration: 2 * DAY_SECS,
+            ..Default::default()
+        };
+        let records = config.generate(20_000);
+        assert_eq!(records.len(), 20_000);
+        assert!(records.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        let again = config.generate(20_000);
+        assert!(records
+            .iter()
+            .zip(&again)
+            .all(|(a, b)| a.mac == b.mac && a.timestamp == b.timestamp && a.lat == b.lat));
+
+        let end = config.start + config.duration;
+        assert!(records
+            .iter()
+            .all(|obs| (config.start..end).contains(&obs.timestamp)));
+        assert!(records
+            .iter()
+            .all(|obs| obs.receiver_id.is_some_and(|receiver| receiver < 8)));
+
+        // Midday (12:00-14:00 UTC) is far busier than the small hours
+        let in_hours = |from: i64, to: i64| {
+            records
+                .iter()
+                .filter(|obs| {
+                    (from * 3600..to * 3600).contains(&obs.timestamp.rem_euclid(DAY_SECS))
+                })
+                .count()
+        };
+        assert!(in_hours(12, 14) > 3 * in_hours(1, 3));
+
+        // Most sightings sit near a hotspot
+        let hotspots = config.hotspot_centers();
+        let near = records
+            .iter()
+            .filter(|obs| {
+                hotspots
+                    .iter()
+                    .any(|&(lat, lon)| haversine_distance(lat, lon, obs.lat, obs.lon) <= 150.0)
+            })
+            .count();
+        assert!(near > records.len() * 8 / 10);
+
+        // Rotating devices change address between periods, beacons never do
+        let kinds = |device: usize| {
+            (
+                config.device_mac(device, 0) != config.device_mac(device, config.rotation_period),
+                MacAddr(config.device_mac(device, 0)).random_address_kind(),
+            )
+        };
+        let rotating = (0..500).filter(|&d| kinds(d).0).count();
+        assert!((250..350).contains(&rotating), "{rotating}");
+        assert!((0..500)
+            .filter(|&d| kinds(d).0)
+            .all(|d| kinds(d).1 == RandomAddressKind::ResolvablePrivate));
+        let beacon = (0..500).find(|&d| !kinds(d).0).unwrap();
+        assert_eq!(config.device_mac(beacon, 123)[..3], [0x00, 0x1A, 0x7D]);
+    }
+}
@@ -0,0 +1,229 @@
+//! Analyst labels on individual records.
+//!
+//! During an investigation records get marked by hand ("suspect",
+//! "reviewed", ...) and pulled up again later with [`Query::tagged`]. Tag
+//! names are interned once and each keeps a sorted posting list of record
+//! IDs, so a tagged query is driven by that list like a zone query.
+//!
+//! Tags live in memory only: they are not written to the write-ahead log,
+//! snapshots or the cold tier, and frozen rows never match a tag.
+
+use crate::ble_cube::{insert_posting, remove_posting, BleCube};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::memory::{hash_table_bytes, posting_bytes, ComponentMemory};
+use crate::query::Query;
+use core::mem::size_of;
+
+/// Interned tag names with a posting list each
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TagIndex {
+    names: Vec<String>,
+    by_name: HashMap<String, usize>,
+    // Parallel to `names`; a tag stays interned once its list empties
+    members: Vec<Vec<usize>>,
+}
+
+impl TagIndex {
+    /// Tag count and estimated heap bytes (see `memory.rs`)
+    pub(crate) fn memory(&self) -> ComponentMemory {
+        ComponentMemory {
+            entries: self.names.len(),
+            bytes: self.names.capacity() * size_of::<String>()
+                + self.members.capacity() * size_of::<Vec<usize>>()
+                + self.members.iter().map(posting_bytes).sum::<usize>()
+                + hash_table_bytes(&self.by_name)
+                + self
+                    .names
+                    .iter()
+                    .chain(self.by_name.keys())
+                    .map(String::capacity)
+                    .sum::<usize>(),
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.members.iter_mut().for_each(Vec::shrink_to_fit);
+        self.members.shrink_to_fit();
+        self.names.shrink_to_fit();
+        self.by_name.shrink_to_fit();
+    }
+
+    /// Index of `name`, interning it on first use
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(&tag) = self.by_name.get(name) {
+            return tag;
+        }
+        self.names.push(name.to_string());
+        self.members.push(Vec::new());
+        self.by_name.insert(name.to_string(), self.names.len() - 1);
+        self.names.len() - 1
+    }
+
+    pub(crate) fn members(&self, name: &str) -> Option<&[usize]> {
+        self.by_name
+            .get(name)
+            .map(|&tag| self.members[tag].as_slice())
+    }
+
+    /// Renumber record IDs after compaction (`remap[old] = new`, `None` for
+    /// removed records); the mapping is monotone, so postings stay sorted
+    #[cfg(feature = "std")]
+    pub(crate) fn remap(&mut self, remap: &[Option<usize>]) {
+        for ids in &mut self.members {
+            *ids = ids.iter().filter_map(|&id| remap[id]).collect();
+        }
+    }
+}
+
+impl BleCube {
+    /// Label a record with `tag`, returning false if it already carried it
+    /// or does not exist
+    pub fn tag(&mut self, record_id: usize, tag: &str) -> bool {
+        if record_id >= self.records.len() {
+            return false;
+        }
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+        let tag = self.tags.intern(tag);
+        let ids = &mut self.tags.members[tag];
+        let before = ids.len();
+        insert_posting(ids, record_id);
+        ids.len() > before
+    }
+
+    /// Label every match of `query` with `tag`, returning how many records
+    /// were newly tagged
+    pub fn tag_matches(&mut self, query: &Query, tag: &str) -> usize {
+        let ids = self.execute_ids(query);
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+        let tag = self.tags.intern(tag);
+        let members = &mut self.tags.members[tag];
+        let before = members.len();
+        // One sort instead of a shifting insert per record
+        members.extend(ids);
+        members.sort_unstable();
+        members.dedup();
+        members.len() - before
+    }
+
+    /// Remove `tag` from a record, returning whether it carried it
+    pub fn untag(&mut self, record_id: usize, tag: &str) -> bool {
+        let Some(&tag) = self.tags.by_name.get(tag) else {
+            return false;
+        };
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+        let ids = &mut self.tags.members[tag];
+        let before = ids.len();
+        remove_posting(ids, record_id);
+        ids.len() < before
+    }
+
+    /// Remove `tag` from every record, returning how many carried it
+    pub fn clear_tag(&mut self, tag: &str) -> usize {
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+        self.tags
+            .by_name
+            .get(tag)
+            .map_or(0, |&tag| core::mem::take(&mut self.tags.members[tag]).len())
+    }
+
+    /// Tags a record carries, in the order they were first used
+    pub fn tags_of(&self, record_id: usize) -> Vec<&str> {
+        self.tags
+            .names
+            .iter()
+            .zip(&self.tags.members)
+            .filter(|(_, ids)| ids.binary_search(&record_id).is_ok())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Tags carried by at least one record, in the order they were first used
+    pub fn tag_names(&self) -> Vec<&str> {
+        self.tags
+            .names
+            .iter()
+            .zip(&self.tags.members)
+            .filter(|(_, ids)| !ids.is_empty())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_cube::BleObservation;
+
+    fn obs(mac: u8, timestamp: i64, rssi: i8) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tag_and_requery() {
+        let mut cube = BleCube::new();
+        for i in 0..10 {
+            cube.insert(obs(i % 3, i64::from(i), -50 - i as i8));
+        }
+
+        assert!(cube.tag(4, "suspect"));
+        assert!(!cube.tag(4, "suspect"));
+        assert!(!cube.tag(99, "suspect"));
+        assert_eq!(
+            cube.tag_matches(&Query::new().mac([1; 6]), "suspect"),
+            2 // record 4 is MAC 1 and already tagged
+        );
+        cube.tag(9, "reviewed");
+        cube.tag(4, "reviewed");
+
+        assert_eq!(
+            cube.execute_ids(&Query::new().tagged("suspect")),
+            vec![1, 4, 7]
+        );
+        let query = Query::new().tagged("suspect").rssi_between(-60, -52);
+        assert_eq!(cube.execute_ids(&query), vec![4, 7]);
+        assert_eq!(cube.explain(&query).driver, Some(crate::Dimension::Tag));
+        assert!(cube.execute(&Query::new().tagged("missing")).is_empty());
+        assert_eq!(cube.tags_of(4), vec!["suspect", "reviewed"]);
+        assert_eq!(cube.tag_names(), vec!["suspect", "reviewed"]);
+
+        assert!(cube.untag(4, "suspect"));
+        assert!(!cube.untag(4, "suspect"));
+        assert!(!cube.untag(4, "missing"));
+        assert!(cube.matches(&Query::new().tagged("reviewed"), 4));
+        assert!(!cube.matches(&Query::new().tagged("suspect"), 4));
+        assert_eq!(cube.clear_tag("reviewed"), 2);
+        assert_eq!(cube.tag_names(), vec!["suspect"]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_tags_follow_records_through_eviction() {
+        let mut cube = BleCube::new();
+        cube.set_time_partition_width(10);
+        for i in 0..30 {
+            cube.insert(obs(1, i, -60));
+        }
+        cube.tag(5, "early");
+        cube.tag(15, "late");
+        cube.tag(25, "late");
+        cube.evict_partitions_before(10).unwrap();
+
+        assert!(cube.execute(&Query::new().tagged("early")).is_empty());
+        let late = cube.execute(&Query::new().tagged("late"));
+        let timestamps: Vec<i64> = late.iter().map(|obs| obs.timestamp).collect();
+        assert_eq!(timestamps, vec![15, 25]);
+        assert_eq!(cube.tags_of(5), vec!["late"]);
+    }
+}
@@ -0,0 +1,273 @@
+//! Explicit timestamp units.
+//!
+//! `BleObservation::timestamp` is a raw `i64`. A cube can declare which unit
+//! it stores via [`BleCube::set_time_unit`]; inserts whose magnitude points to
+//! a different unit are then rejected or normalized instead of silently
+//! mixing seconds with microseconds.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::error::CubeError;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Resolution of a raw Unix timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl TimeUnit {
+    /// Number of this unit per second
+    pub fn per_second(self) -> i64 {
+        match self {
+            TimeUnit::Seconds => 1,
+            TimeUnit::Milliseconds => 1_000,
+            TimeUnit::Microseconds => 1_000_000,
+            TimeUnit::Nanoseconds => 1_000_000_000,
+        }
+    }
+
+    /// Best guess of the unit of a raw Unix timestamp from its magnitude.
+    /// Unambiguous for dates between 1973 and 5138.
+    pub fn guess(value: i64) -> TimeUnit {
+        match value.unsigned_abs() {
+            0..=99_999_999_999 => TimeUnit::Seconds,
+            100_000_000_000..=99_999_999_999_999 => TimeUnit::Milliseconds,
+            100_000_000_000_000..=99_999_999_999_999_999 => TimeUnit::Microseconds,
+            _ => TimeUnit::Nanoseconds,
+        }
+    }
+
+    /// Convert a raw value from this unit to `target`, truncating toward zero
+    /// and saturating on overflow
+    pub fn convert(self, value: i64, target: TimeUnit) -> i64 {
+        let (from, to) = (self.per_second(), target.per_second());
+        if from <= to {
+            value.saturating_mul(to / from)
+        } else {
+            value / (from / to)
+        }
+    }
+}
+
+/// What to do with an insert whose timestamp looks like the wrong unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnitMismatch {
+    /// Fail the insert ([`CubeError::TimeUnit`])
+    Reject,
+    /// Convert the timestamp into the cube's unit
+    Normalize,
+}
+
+/// Unix timestamp with an explicit unit, stored as microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Timestamp {
+    micros: i64,
+}
+
+impl Timestamp {
+    pub fn from_secs(secs: i64) -> Self {
+        Self::from_unit(secs, TimeUnit::Seconds)
+    }
+
+    pub fn from_millis(millis: i64) -> Self {
+        Self::from_unit(millis, TimeUnit::Milliseconds)
+    }
+
+    pub fn from_micros(micros: i64) -> Self {
+        Self { micros }
+    }
+
+    /// Sub-microsecond precision is truncated
+    pub fn from_nanos(nanos: i64) -> Self {
+        Self::from_unit(nanos, TimeUnit::Nanoseconds)
+    }
+
+    /// Interpret a raw value in the given unit
+    pub fn from_unit(value: i64, unit: TimeUnit) -> Self {
+        Self {
+            micros: unit.convert(value, TimeUnit::Microseconds),
+        }
+    }
+
+    /// Current wall-clock time
+    #[cfg(feature = "std")]
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    pub fn as_secs(self) -> i64 {
+        self.to_unit(TimeUnit::Seconds)
+    }
+
+    pub fn as_millis(self) -> i64 {
+        self.to_unit(TimeUnit::Milliseconds)
+    }
+
+    pub fn as_micros(self) -> i64 {
+        self.micros
+    }
+
+    /// Raw value in the given unit (truncating / saturating)
+    pub fn to_unit(self, unit: TimeUnit) -> i64 {
+        TimeUnit::Microseconds.convert(self.micros, unit)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        let micros = match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => i64::try_from(after.as_micros()).unwrap_or(i64::MAX),
+            Err(before) => -i64::try_from(before.duration().as_micros()).unwrap_or(i64::MAX),
+        };
+        Self { micros }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timestamp> for SystemTime {
+    fn from(ts: Timestamp) -> Self {
+        let offset = Duration::from_micros(ts.micros.unsigned_abs());
+        if ts.micros >= 0 {
+            UNIX_EPOCH + offset
+        } else {
+            UNIX_EPOCH - offset
+        }
+    }
+}
+
+impl BleObservation {
+    /// This observation's timestamp, interpreting the raw value in `unit`
+    pub fn timestamp_in(&self, unit: TimeUnit) -> Timestamp {
+        Timestamp::from_unit(self.timestamp, unit)
+    }
+}
+
+impl BleCube {
+    /// Declare the unit of stored timestamps and how inserts in another
+    /// unit (judged by magnitude, see [`TimeUnit::guess`]) are handled.
+    /// Records already in the cube are not rewritten.
+    pub fn set_time_unit(&mut self, unit: TimeUnit, on_mismatch: UnitMismatch) {
+        self.time_unit = Some((unit, on_mismatch));
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+    }
+
+    /// Declared timestamp unit, if any
+    pub fn time_unit(&self) -> Option<TimeUnit> {
+        self.time_unit.map(|(unit, _)| unit)
+    }
+
+    /// Query [start, end] inclusive using unit-aware timestamps.
+    /// Uses seconds if the cube has no declared unit.
+    pub fn query_time_range_ts(&self, start: Timestamp, end: Timestamp) -> Vec<&BleObservation> {
+        let unit = self.time_unit().unwrap_or(TimeUnit::Seconds);
+        self.query_time_range(start.to_unit(unit), end.to_unit(unit))
+    }
+
+    /// Apply the declared unit policy to an observation about to be inserted
+    pub(crate) fn check_time_unit(
+        &self,
+        mut obs: BleObservation,
+    ) -> Result<BleObservation, CubeError> {
+        let Some((unit, on_mismatch)) = self.time_unit else {
+            return Ok(obs);
+        };
+
+        let guessed = TimeUnit::guess(obs.timestamp);
+        if guessed == unit {
+            return Ok(obs);
+        }
+        match on_mismatch {
+            UnitMismatch::Normalize => {
+                obs.timestamp = guessed.convert(obs.timestamp, unit);
+                Ok(obs)
+            }
+            UnitMismatch::Reject => Err(CubeError::TimeUnit {
+                timestamp: obs.timestamp,
+                guessed,
+                unit,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(timestamp: i64) -> BleObservation {
+        BleObservation {
+            rssi: -60,
+            mac: [0; 6],
+            timestamp,
+            lat: 0.0,
+            lon: 0.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_unit_conversion_and_guess() {
+        let ts = Timestamp::from_secs(1_700_000_000);
+        assert_eq!(ts.as_millis(), 1_700_000_000_000);
+        assert_eq!(ts.to_unit(TimeUnit::Nanoseconds), 1_700_000_000_000_000_000);
+        assert_eq!(
+            Timestamp::from_millis(1_700_000_000_999).as_secs(),
+            1_700_000_000
+        );
+        #[cfg(feature = "std")]
+        assert_eq!(Timestamp::from(SystemTime::from(ts)), ts);
+
+        assert_eq!(TimeUnit::guess(1_700_000_000), TimeUnit::Seconds);
+        assert_eq!(TimeUnit::guess(1_700_000_000_000), TimeUnit::Milliseconds);
+        assert_eq!(
+            TimeUnit::guess(1_700_000_000_000_000),
+            TimeUnit::Microseconds
+        );
+        assert_eq!(
+            TimeUnit::guess(1_700_000_000_000_000_000),
+            TimeUnit::Nanoseconds
+        );
+    }
+
+    #[test]
+    fn test_mixed_units_are_normalized() {
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Seconds, UnitMismatch::Normalize);
+
+        cube.insert(obs(1_700_000_000));
+        cube.insert(obs(1_700_000_001_000_000)); // microseconds
+
+        assert_eq!(cube.get(1).unwrap().timestamp, 1_700_000_001);
+        let range = cube.query_time_range_ts(
+            Timestamp::from_secs(1_700_000_000),
+            Timestamp::from_millis(1_700_000_001_000),
+        );
+        assert_eq!(range.len(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_mixed_units_are_rejected() {
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Microseconds, UnitMismatch::Reject);
+
+        assert!(cube.try_insert(obs(1_700_000_000_000_000)).is_ok());
+        let err = cube.try_insert(obs(1_700_000_000)).unwrap_err();
+        assert!(matches!(err, CubeError::TimeUnit { .. }));
+        assert_eq!(cube.len(), 1);
+    }
+}
@@ -0,0 +1,361 @@
+//! Insert-time validation of observations.
+//!
+//! Bad fixes (a GPS reporting (0, 0) before it has a lock, out-of-range
+//! coordinates, sentinel RSSI values, a clock far in the future, an all-zero
+//! MAC) otherwise land in the indices and quietly skew every query that
+//! touches them. With rules set via [`BleCube::set_validation`], each insert
+//! is checked first and the [`ValidationPolicy`] decides what happens to
+//! failures.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::compat::prelude::*;
+use crate::error::CubeError;
+#[cfg(feature = "std")]
+use crate::time::{TimeUnit, Timestamp};
+use core::fmt;
+
+/// Which checks run on insert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ValidationRules {
+    /// Latitude in [-90, 90] and longitude in [-180, 180]. NaN coordinates
+    /// fail whatever the rules.
+    pub coordinate_ranges: bool,
+    /// Fail the exact (0, 0) position GPS modules report without a fix
+    pub reject_null_island: bool,
+    /// Plausible RSSI bounds (inclusive)
+    pub rssi_range: Option<(i8, i8)>,
+    /// Seconds a timestamp may run ahead of the wall clock, converted to the
+    /// cube's declared time unit (seconds if none). Ignored without `std`,
+    /// which has no wall clock.
+    pub max_future_secs: Option<i64>,
+    /// Fail the all-zero MAC address
+    pub reject_zero_mac: bool,
+}
+
+impl Default for ValidationRules {
+    /// Every check on: RSSI in [-120, 10] dBm, at most 5 minutes ahead
+    fn default() -> Self {
+        Self {
+            coordinate_ranges: true,
+            reject_null_island: true,
+            rssi_range: Some((-120, 10)),
+            max_future_secs: Some(300),
+            reject_zero_mac: true,
+        }
+    }
+}
+
+/// What to do with an observation that fails a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValidationPolicy {
+    /// Fail the insert ([`CubeError::Invalid`])
+    Reject,
+    /// Pull out-of-range coordinates, RSSI and future timestamps to the
+    /// nearest allowed value and insert. Null island and zero MACs cannot be
+    /// repaired and are rejected.
+    Clamp,
+    /// Fail the insert like `Reject`, but keep the observation in
+    /// [`BleCube::quarantine`] for inspection
+    Quarantine,
+}
+
+/// The rule an observation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Violation {
+    ZeroMac,
+    /// Latitude or longitude is NaN
+    NanCoordinate,
+    LatitudeOutOfRange,
+    LongitudeOutOfRange,
+    NullIsland,
+    RssiOutOfRange,
+    FutureTimestamp,
+}
+
+impl Violation {
+    /// Whether [`ValidationPolicy::Clamp`] can repair it
+    fn clampable(self) -> bool {
+        !matches!(
+            self,
+            Violation::ZeroMac | Violation::NanCoordinate | Violation::NullIsland
+        )
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Violation::ZeroMac => "all-zero MAC address",
+            Violation::NanCoordinate => "coordinate is NaN",
+            Violation::LatitudeOutOfRange => "latitude out of range",
+            Violation::LongitudeOutOfRange => "longitude out of range",
+            Violation::NullIsland => "position (0, 0)",
+            Violation::RssiOutOfRange => "implausible RSSI",
+            Violation::FutureTimestamp => "timestamp in the future",
+        })
+    }
+}
+
+/// An observation held back by [`ValidationPolicy::Quarantine`]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QuarantinedObservation {
+    /// As submitted (after time-unit normalization)
+    pub observation: BleObservation,
+    /// First rule it failed
+    pub violation: Violation,
+}
+
+impl ValidationRules {
+    /// Every rule `obs` fails, in check order; `latest` is the latest
+    /// allowed timestamp, if the future check is on
+    fn violations(&self, obs: &BleObservation, latest: Option<i64>) -> Vec<Violation> {
+        let mut found = Vec::new();
+        if self.reject_zero_mac && obs.mac == [0; 6] {
+            found.push(Violation::ZeroMac);
+        }
+        if obs.lat.is_nan() || obs.lon.is_nan() {
+            found.push(Violation::NanCoordinate);
+        } else if self.coordinate_ranges {
+            if !(-90.0..=90.0).contains(&obs.lat) {
+                found.push(Violation::LatitudeOutOfRange);
+            }
+            if !(-180.0..=180.0).contains(&obs.lon) {
+                found.push(Violation::LongitudeOutOfRange);
+            }
+        }
+        if self.reject_null_island && obs.lat == 0.0 && obs.lon == 0.0 {
+            found.push(Violation::NullIsland);
+        }
+        if self
+            .rssi_range
+            .is_some_and(|(min, max)| !(min..=max).contains(&obs.rssi))
+        {
+            found.push(Violation::RssiOutOfRange);
+        }
+        if latest.is_some_and(|latest| obs.timestamp > latest) {
+            found.push(Violation::FutureTimestamp);
+        }
+        found
+    }
+
+    /// Repair every clampable violation in place
+    fn clamp(&self, obs: &mut BleObservation, latest: Option<i64>) {
+        if self.coordinate_ranges {
+            obs.lat = obs.lat.clamp(-90.0, 90.0);
+            obs.lon = obs.lon.clamp(-180.0, 180.0);
+        }
+        if let Some((min, max)) = self.rssi_range {
+            obs.rssi = obs.rssi.clamp(min, max);
+        }
+        if let Some(latest) = latest {
+            obs.timestamp = obs.timestamp.min(latest);
+        }
+    }
+}
+
+impl BleCube {
+    /// Check every insert against `rules`, handling failures per `policy`.
+    /// Records already in the cube are not re-checked.
+    pub fn set_validation(&mut self, rules: ValidationRules, policy: ValidationPolicy) {
+        self.validation = Some((rules, policy));
+    }
+
+    /// Stop validating inserts
+    pub fn clear_validation(&mut self) {
+        self.validation = None;
+    }
+
+    /// Active rules and policy, if any
+    pub fn validation(&self) -> Option<(ValidationRules, ValidationPolicy)> {
+        self.validation
+    }
+
+    /// Observations held back by [`ValidationPolicy::Quarantine`], oldest
+    /// first. Kept in memory only (not in the write-ahead log).
+    pub fn quarantine(&self) -> &[QuarantinedObservation] {
+        &self.quarantine
+    }
+
+    /// Remove and return the quarantined observations
+    pub fn take_quarantine(&mut self) -> Vec<QuarantinedObservation> {
+        core::mem::take(&mut self.quarantine)
+    }
+
+    /// Apply the validation policy to an observation about to be inserted
+    pub(crate) fn validate(
+        &mut self,
+        mut obs: BleObservation,
+    ) -> Result<BleObservation, CubeError> {
+        let Some((rules, policy)) = self.validation else {
+            return Ok(obs);
+        };
+        let latest = self.latest_allowed_timestamp(&rules);
+        let violations = rules.violations(&obs, latest);
+        let Some(&first) = violations.first() else {
+            return Ok(obs);
+        };
+
+        match policy {
+            ValidationPolicy::Clamp if violations.iter().all(|v| v.clampable()) => {
+                rules.clamp(&mut obs, latest);
+                Ok(obs)
+            }
+            ValidationPolicy::Reject | ValidationPolicy::Clamp => Err(CubeError::Invalid {
+                violation: violations
+                    .into_iter()
+                    .find(|v| !v.clampable())
+                    .unwrap_or(first),
+                quarantined: false,
+            }),
+            ValidationPolicy::Quarantine => {
+                self.quarantine.push(QuarantinedObservation {
+                    observation: obs,
+                    violation: first,
+                });
+                Err(CubeError::Invalid {
+                    violation: first,
+                    quarantined: true,
+                })
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn latest_allowed_timestamp(&self, rules: &ValidationRules) -> Option<i64> {
+        let unit = self.time_unit().unwrap_or(TimeUnit::Seconds);
+        rules.max_future_secs.map(|secs| {
+            Timestamp::now()
+                .to_unit(unit)
+                .saturating_add(TimeUnit::Seconds.convert(secs, unit))
+        })
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn latest_allowed_timestamp(&self, _rules: &ValidationRules) -> Option<i64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(lat: f64, lon: f64, rssi: i8) -> BleObservation {
+        BleObservation {
+            rssi,
+            mac: [1; 6],
+            timestamp: 1_700_000_000,
+            lat,
+            lon,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_quarantine_keeps_bad_fixes_out_of_queries() {
+        let mut cube = BleCube::new();
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Quarantine);
+
+        cube.insert(obs(37.77, -122.42, -60));
+        for bad in [
+            obs(0.0, 0.0, -60),
+            obs(91.0, 0.5, -60),
+            obs(37.77, f64::NAN, -60),
+            obs(37.77, -122.42, -127),
+            BleObservation {
+                mac: [0; 6],
+                ..obs(37.77, -122.42, -60)
+            },
+        ] {
+            let err = cube.try_insert(bad).unwrap_err();
+            assert!(matches!(
+                err,
+                CubeError::Invalid {
+                    quarantined: true,
+                    ..
+                }
+            ));
+        }
+
+        assert_eq!(cube.len(), 1);
+        assert!(cube.query_geo_radius(0.0, 0.0, 1_000.0).is_empty());
+        let violations: Vec<Violation> = cube.quarantine().iter().map(|q| q.violation).collect();
+        assert_eq!(
+            violations,
+            vec![
+                Violation::NullIsland,
+                Violation::LatitudeOutOfRange,
+                Violation::NanCoordinate,
+                Violation::RssiOutOfRange,
+                Violation::ZeroMac,
+            ]
+        );
+        assert_eq!(cube.take_quarantine().len(), 5);
+        assert!(cube.quarantine().is_empty());
+    }
+
+    #[test]
+    fn test_clamp_repairs_what_it_can() {
+        let mut cube = BleCube::new();
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Clamp);
+
+        let id = cube.insert(obs(95.0, -200.0, 40));
+        let stored = cube.get(id).unwrap();
+        assert_eq!((stored.lat, stored.lon, stored.rssi), (90.0, -180.0, 10));
+
+        assert!(cube.validate(obs(0.0, 0.0, -60)).is_err());
+        assert!(cube.validate(obs(f64::NAN, 0.5, -60)).is_err());
+        assert!(cube.quarantine().is_empty());
+    }
+
+    #[cfg(not(feature = "std"))]
+    #[test]
+    fn test_future_check_ignored_without_std() {
+        let mut cube = BleCube::new();
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Reject);
+        let id = cube.insert(BleObservation {
+            timestamp: i64::MAX,
+            ..obs(1.0, 1.0, -60)
+        });
+        assert_eq!(cube.get(id).unwrap().timestamp, i64::MAX);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_future_timestamps() {
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Milliseconds, crate::time::UnitMismatch::Reject);
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Clamp);
+
+        let now = Timestamp::now().as_millis();
+        let id = cube.insert(BleObservation {
+            timestamp: now + 86_400_000,
+            ..obs(1.0, 1.0, -60)
+        });
+        let stored = cube.get(id).unwrap().timestamp;
+        assert!(stored >= now && stored <= now + 300_000 + 1_000);
+
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Reject);
+        let err = cube
+            .try_insert(BleObservation {
+                timestamp: now + 86_400_000,
+                ..obs(1.0, 1.0, -60)
+            })
+            .unwrap_err();
+        assert!(err.to_string().contains("timestamp in the future"));
+
+        // Within tolerance passes untouched
+        cube.insert(BleObservation {
+            timestamp: now + 60_000,
+            ..obs(1.0, 1.0, -60)
+        });
+        assert_eq!(cube.len(), 2);
+    }
+}
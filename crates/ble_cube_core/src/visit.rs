@@ -0,0 +1,240 @@
+//! Allocation-free query execution.
+//!
+//! `execute` and the `query_*` lookups return a fresh `Vec` per call. The
+//! visitor variants here hand each match to a callback straight from the
+//! index instead, and [`BleCube::query_into`] refills a caller-owned buffer,
+//! so a polling loop (or a C caller) running the same query every tick does
+//! not allocate every iteration.
+
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::cancel::Interrupt;
+use crate::compat::prelude::*;
+use crate::mac::MacAddr;
+use crate::query::Query;
+use crate::record_id::RecordId;
+
+impl BleCube {
+    /// Call `visit` with every match of `query` without collecting them.
+    /// Matches arrive in driving-index order, which is record ID order only
+    /// when a MAC, zone, receiver or floor filter drives the query. Sampled
+    /// and per-device ([`Query::distinct_by_mac`]) queries collect their
+    /// matches first, so they still allocate.
+    ///
+    /// Fails if the query's deadline passes or it is cancelled; the matches
+    /// visited until then stand.
+    pub fn query_visit<'a>(
+        &'a self,
+        query: &Query,
+        mut visit: impl FnMut(&'a BleObservation),
+    ) -> Result<(), Interrupt> {
+        if query.post_processed() {
+            let (ids, result) = match self.try_execute_ids(query) {
+                Ok(ids) => (ids, Ok(())),
+                Err(interrupted) => (interrupted.partial, Err(interrupted.reason)),
+            };
+            ids.into_iter().for_each(|id| visit(&self.records[id]));
+            return result;
+        }
+        self.scan(query, |id| visit(&self.records[id]))
+    }
+
+    /// Replace the contents of `out` with the stable IDs of the matches of
+    /// `query`, ascending. The buffer's capacity is kept, so reusing it
+    /// across calls allocates only when a result outgrows every earlier one.
+    ///
+    /// Fails if the query is interrupted, leaving the matches found until
+    /// then in `out`.
+    pub fn query_into(&self, query: &Query, out: &mut Vec<RecordId>) -> Result<(), Interrupt> {
+        out.clear();
+        let result = if query.post_processed() {
+            let (ids, result) = match self.try_execute_ids(query) {
+                Ok(ids) => (ids, Ok(())),
+                Err(interrupted) => (interrupted.partial, Err(interrupted.reason)),
+            };
+            out.extend(ids.into_iter().map(|id| self.record_ids[id]));
+            result
+        } else {
+            self.scan(query, |id| out.push(self.record_ids[id]))
+        };
+        // Stable IDs ascend with record position
+        out.sort_unstable();
+        result
+    }
+
+    /// [`BleCube::query_mac`] calling `visit` per observation, in record ID
+    /// order
+    pub fn query_mac_visit<'a, M: Into<MacAddr>>(
+        &'a self,
+        mac: M,
+        mut visit: impl FnMut(&'a BleObservation),
+    ) {
+        if let Some(ids) = self.mac_index.get(&mac.into().0) {
+            ids.iter().for_each(|&id| visit(&self.records[id]));
+        }
+    }
+
+    /// [`BleCube::query_time_range`] calling `visit` per observation, in
+    /// timestamp order
+    pub fn query_time_range_visit<'a>(
+        &'a self,
+        start: i64,
+        end: i64,
+        mut visit: impl FnMut(&'a BleObservation),
+    ) {
+        if start > end {
+            return;
+        }
+        for (_, id) in self.time_index.range(start..=end) {
+            visit(&self.records[id]);
+        }
+    }
+
+    /// [`BleCube::query_geo_radius`] calling `visit` per observation, in no
+    /// particular order
+    pub fn query_geo_radius_visit<'a>(
+        &'a self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+        mut visit: impl FnMut(&'a BleObservation),
+    ) {
+        let envelope = self.crs.radius_envelope(lat, lon, radius_m);
+        for point in self.locate_in_envelope(envelope) {
+            if self
+                .crs
+                .distance(lat, lon, point.coords[0], point.coords[1])
+                <= radius_m
+            {
+                visit(&self.records[point.record_id]);
+            }
+        }
+    }
+
+    /// [`BleCube::query_geo_bbox`] calling `visit` per observation, in no
+    /// particular order
+    pub fn query_geo_bbox_visit<'a>(
+        &'a self,
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+        mut visit: impl FnMut(&'a BleObservation),
+    ) {
+        let envelope = self.crs.bbox_envelope(min_lat, min_lon, max_lat, max_lon);
+        for point in self.locate_in_envelope(envelope) {
+            visit(&self.records[point.record_id]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube() -> BleCube {
+        let mut cube = BleCube::new();
+        for t in 0..100 {
+            cube.insert(BleObservation {
+                mac: [(t % 4) as u8; 6],
+                rssi: -40 - (t % 50) as i8,
+                timestamp: 1000 - t,
+                lat: 37.0 + t as f64 * 1e-5,
+                lon: -122.0,
+                ..Default::default()
+            });
+        }
+        cube
+    }
+
+    fn stamps(observations: &[&BleObservation]) -> Vec<i64> {
+        let mut stamps: Vec<i64> = observations.iter().map(|obs| obs.timestamp).collect();
+        stamps.sort_unstable();
+        stamps
+    }
+
+    #[test]
+    fn test_visitors_match_collecting_queries() {
+        let cube = cube();
+        let query = Query::new().time_between(920, 980).rssi_between(-70, -50);
+        let mut visited = Vec::new();
+        cube.query_visit(&query, |obs| visited.push(obs)).unwrap();
+        assert_eq!(stamps(&visited), stamps(&cube.execute(&query)));
+        assert!(!visited.is_empty());
+
+        let mut visited = Vec::new();
+        cube.query_mac_visit([1; 6], |obs| visited.push(obs));
+        assert_eq!(stamps(&visited), stamps(&cube.query_mac([1; 6])));
+
+        let mut visited = Vec::new();
+        cube.query_time_range_visit(950, 960, |obs| visited.push(obs));
+        assert_eq!(stamps(&visited), (950..=960).collect::<Vec<_>>());
+        cube.query_time_range_visit(960, 950, |_| panic!("empty range"));
+
+        let mut visited = Vec::new();
+        cube.query_geo_radius_visit(37.0, -122.0, 50.0, |obs| visited.push(obs));
+        assert_eq!(
+            stamps(&visited),
+            stamps(&cube.query_geo_radius(37.0, -122.0, 50.0))
+        );
+
+        let mut visited = Vec::new();
+        cube.query_geo_bbox_visit(37.0, -122.1, 37.0001, -121.9, |obs| visited.push(obs));
+        assert_eq!(
+            stamps(&visited),
+            stamps(&cube.query_geo_bbox(37.0, -122.1, 37.0001, -121.9))
+        );
+
+        // Sampled queries visit exactly the sample
+        let sampled = Query::new().sample(5, 7);
+        let mut visited = Vec::new();
+        cube.query_visit(&sampled, |obs| visited.push(obs)).unwrap();
+        assert_eq!(stamps(&visited), stamps(&cube.execute(&sampled)));
+    }
+
+    #[test]
+    fn test_query_into_reuses_buffer() {
+        let cube = cube();
+        let mut out = Vec::new();
+        cube.query_into(&Query::new().mac([2; 6]), &mut out)
+            .unwrap();
+        let expected: Vec<RecordId> = cube
+            .execute_ids(&Query::new().mac([2; 6]))
+            .into_iter()
+            .filter_map(|id| cube.stable_id(id))
+            .collect();
+        assert_eq!(out, expected);
+
+        // The time index yields these in descending record order, but the
+        // buffer comes back sorted; refilling it keeps the allocation
+        let capacity = out.capacity();
+        let buffer = out.as_ptr();
+        cube.query_into(&Query::new().time_between(990, 1000), &mut out)
+            .unwrap();
+        assert_eq!(out.len(), 11);
+        assert!(out.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!((out.capacity(), out.as_ptr()), (capacity, buffer));
+
+        cube.query_into(&Query::new().mac([9; 6]), &mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_interrupted_visit_reports_reason() {
+        use crate::cancel::CancelToken;
+
+        let cube = cube();
+        let token = CancelToken::new();
+        token.cancel();
+        let query = Query::new().with_cancel_token(token);
+        let mut visited = 0;
+        assert_eq!(
+            cube.query_visit(&query, |_| visited += 1),
+            Err(Interrupt::Cancelled)
+        );
+        assert_eq!(visited, 0);
+        let mut out = vec![RecordId::from_u64(1)];
+        assert_eq!(cube.query_into(&query, &mut out), Err(Interrupt::Cancelled));
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,848 @@
+//! Write-ahead log for crash-safe live ingestion.
+//!
+//! A WAL directory holds an optional `snapshot.bin` written by
+//! [`BleCube::checkpoint`] plus numbered log segments (`wal-00000001.log`, ...).
+//! Every frame is `[len: u32][crc32: u32][payload]`, little-endian, where the
+//! payload is a tagged [`WalEntry`]. Replay stops at the first torn or corrupt
+//! frame and truncates the segment there.
+//!
+//! The 8-byte file magic carries the format version. Version 2 follows the
+//! fixed observation fields with a flags byte announcing optional fields;
+//! version 1 files (no optional fields) are still read. Snapshots since
+//! version 3 follow the magic with the number of the last segment they
+//! cover: a checkpoint renames the snapshot into place before deleting the
+//! old segments, and a crash in between must not replay them on top of it.
+
+use crate::beacon::MAX_ADVERTISEMENT_LEN;
+use crate::ble_cube::{BleCube, BleObservation};
+use crate::builder::CubeBuilder;
+use crate::checksum::crc32;
+use crate::codec::{
+    self, decode_core, encode_observation, CORE_OBSERVATION_LEN, MAX_OBSERVATION_LEN,
+};
+use crate::compat::sync_dir;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENT_MAGIC: &[u8; 8] = b"BLEWAL02";
+const SNAPSHOT_MAGIC: &[u8; 8] = b"BLESNP03";
+const LEGACY_SEGMENT_MAGIC: &[u8; 8] = b"BLEWAL01";
+/// Snapshot formats without the covered-segment header
+const LEGACY_SNAPSHOT_MAGIC_V2: &[u8; 8] = b"BLESNP02";
+const LEGACY_SNAPSHOT_MAGIC: &[u8; 8] = b"BLESNP01";
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.bin.tmp";
+const FRAME_HEADER_LEN: usize = 8;
+
+const ENTRY_INSERT: u8 = 0;
+const ENTRY_REPLACE: u8 = 1;
+const ENTRY_INSERT_ADVERTISEMENT: u8 = 2;
+const ENTRY_ADVANCE_RECORD_ID: u8 = 3;
+const ENTRY_FROZEN_THROUGH: u8 = 4;
+const ENTRY_REPLACE_ADVERTISEMENT: u8 = 5;
+
+const MAX_ENTRY_LEN: usize = 1 + 8 + MAX_OBSERVATION_LEN + MAX_ADVERTISEMENT_LEN;
+
+/// On-disk format revision, identified by the file magic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Fixed 31-byte observations
+    V1,
+    /// Fixed fields + flags byte + optional fields
+    V2,
+}
+
+/// One logged mutation
+#[derive(Debug, Clone)]
+pub(crate) enum WalEntry {
+    /// Append a new record
+    Insert(BleObservation),
+    /// Overwrite an existing record in place (upserts)
+    Replace(usize, BleObservation),
+    /// Append a new record with its raw advertisement payload
+    InsertAdvertisement(BleObservation, Vec<u8>),
+    /// Overwrite an existing record and its advertisement (none if the
+    /// payload is empty), for rate-limit replacements
+    ReplaceAdvertisement(usize, BleObservation, Vec<u8>),
+    /// Skip ahead to this stable record ID for the next insert (snapshots
+    /// of compacted cubes, where evicted IDs leave gaps)
+    AdvanceRecordId(u64),
+    /// Cold segments up to this number hold records the snapshot no longer
+    /// does (snapshots only, see [`BleCube::attach_cold_tier`])
+    FrozenThrough(u64),
+}
+
+/// Write-ahead log tuning options
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WalConfig {
+    /// Rotate to a new segment once the current one reaches this many bytes
+    pub max_segment_bytes: u64,
+    /// fsync after every append (survives power loss, much slower)
+    pub sync_on_append: bool,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            max_segment_bytes: 64 * 1024 * 1024,
+            sync_on_append: false,
+        }
+    }
+}
+
+/// Open append-only log attached to a cube
+pub(crate) struct Wal {
+    dir: PathBuf,
+    config: WalConfig,
+    writer: BufWriter<File>,
+    segment: u64,
+    segment_bytes: u64,
+    /// Last cold segment whose eviction the latest snapshot includes
+    pub(crate) frozen_through: u64,
+}
+
+impl Wal {
+    fn create(dir: &Path, segment: u64, config: WalConfig) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(segment_path(dir, segment))?;
+        file.write_all(SEGMENT_MAGIC)?;
+        file.sync_data()?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            config,
+            writer: BufWriter::new(file),
+            segment,
+            segment_bytes: SEGMENT_MAGIC.len() as u64,
+            frozen_through: 0,
+        })
+    }
+
+    /// Append one entry, rotating first if the segment is full
+    pub(crate) fn append(&mut self, entry: &WalEntry) -> io::Result<()> {
+        if self.segment_bytes >= self.config.max_segment_bytes {
+            self.rotate()?;
+        }
+
+        let frame = encode_frame(&encode_entry(entry));
+        self.writer.write_all(&frame)?;
+        self.writer.flush()?;
+        if self.config.sync_on_append {
+            self.writer.get_ref().sync_data()?;
+        }
+        self.segment_bytes += frame.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        *self = Self::create(&self.dir, self.segment + 1, self.config)?;
+        Ok(())
+    }
+
+    /// Drop every existing segment and start a fresh one
+    fn truncate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let next = self.segment + 1;
+        let mut fresh = Self::create(&self.dir, next, self.config)?;
+        fresh.frozen_through = self.frozen_through;
+        remove_segments_through(&self.dir, self.segment)?;
+        *self = fresh;
+        Ok(())
+    }
+}
+
+impl CubeBuilder {
+    /// Like [`BleCube::recover_with`], replaying into a cube built with
+    /// these options
+    pub fn recover<P: AsRef<Path>>(self, dir: P, config: WalConfig) -> io::Result<BleCube> {
+        BleCube::replay_into(self.build(), dir.as_ref(), config)
+    }
+}
+
+impl BleCube {
+    /// Open (or create) a WAL directory with default options, replay it,
+    /// and return a cube that logs every subsequent insert
+    pub fn recover<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        Self::recover_with(dir, WalConfig::default())
+    }
+
+    /// Like [`BleCube::recover`] with explicit WAL options
+    pub fn recover_with<P: AsRef<Path>>(dir: P, config: WalConfig) -> io::Result<Self> {
+        Self::replay_into(BleCube::new(), dir.as_ref(), config)
+    }
+
+    /// Replay a WAL directory into `cube` and attach the log to it
+    fn replay_into(mut cube: BleCube, dir: &Path, config: WalConfig) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let snapshot = dir.join(SNAPSHOT_FILE);
+        let mut covered = 0;
+        let mut frozen_through = 0;
+        if snapshot.exists() {
+            covered = read_snapshot(&snapshot, |entry| match entry {
+                WalEntry::FrozenThrough(segment) => frozen_through = segment,
+                entry => cube.apply_wal_entry(entry),
+            })?;
+            // Left behind by a checkpoint interrupted after the rename
+            remove_segments_through(dir, covered)?;
+        }
+
+        let segments = list_segments(dir)?;
+        for (_, path) in &segments {
+            replay_segment(path, |entry| cube.apply_wal_entry(entry))?;
+        }
+
+        let next = segments
+            .last()
+            .map_or(covered, |(segment, _)| *segment)
+            .max(covered)
+            + 1;
+        let mut wal = Wal::create(dir, next, config)?;
+        wal.frozen_through = frozen_through;
+        cube.wal = Some(wal);
+        Ok(cube)
+    }
+
+    /// Snapshot the cube next to the log and truncate the log.
+    /// Fails if the cube was not opened with [`BleCube::recover`].
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        #[cfg(feature = "cold")]
+        let frozen = self.cold.as_ref().map_or(0, |cold| cold.last_segment());
+        #[cfg(not(feature = "cold"))]
+        let frozen = 0;
+        let (dir, covered, frozen_through) = match self.wal.as_mut() {
+            Some(wal) => {
+                wal.writer.flush()?;
+                (wal.dir.clone(), wal.segment, wal.frozen_through.max(frozen))
+            }
+            None => return Err(io::Error::other("cube has no write-ahead log attached")),
+        };
+
+        write_snapshot(&dir, self, covered, frozen_through)?;
+
+        if let Some(wal) = self.wal.as_mut() {
+            wal.frozen_through = frozen_through;
+            wal.truncate()?;
+        }
+        Ok(())
+    }
+
+    /// Re-apply a logged mutation during recovery (the WAL is not attached yet)
+    fn apply_wal_entry(&mut self, entry: WalEntry) {
+        match entry {
+            WalEntry::Insert(obs) => {
+                self.insert(obs);
+            }
+            WalEntry::Replace(record_id, obs) => {
+                if record_id < self.len() {
+                    self.replace_record(record_id, obs);
+                }
+            }
+            WalEntry::InsertAdvertisement(obs, payload) => {
+                self.insert_advertisement(obs, &payload);
+            }
+            WalEntry::ReplaceAdvertisement(record_id, obs, payload) => {
+                if record_id < self.len() {
+                    self.replace_record(record_id, obs);
+                    self.beacons.replace(record_id, &payload);
+                }
+            }
+            WalEntry::AdvanceRecordId(next) => {
+                self.next_record_id = self.next_record_id.max(next);
+            }
+            // Read by `replay_into` before entries reach the cube
+            WalEntry::FrozenThrough(_) => {}
+        }
+    }
+}
+
+// ========== ENCODING ==========
+
+/// An observation at the start of `buf` in the given format and the number
+/// of bytes it occupied, `None` if truncated or malformed
+fn decode_observation(buf: &[u8], format: Format) -> Option<(BleObservation, usize)> {
+    match format {
+        Format::V1 => Some((decode_core(buf)?, CORE_OBSERVATION_LEN)),
+        Format::V2 => codec::decode_observation(buf),
+    }
+}
+
+fn encode_entry(entry: &WalEntry) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MAX_ENTRY_LEN);
+    match entry {
+        WalEntry::Insert(obs) => {
+            buf.push(ENTRY_INSERT);
+            encode_observation(obs, &mut buf);
+        }
+        WalEntry::Replace(record_id, obs) => {
+            buf.push(ENTRY_REPLACE);
+            buf.extend_from_slice(&(*record_id as u64).to_le_bytes());
+            encode_observation(obs, &mut buf);
+        }
+        WalEntry::InsertAdvertisement(obs, payload) => {
+            buf.push(ENTRY_INSERT_ADVERTISEMENT);
+            encode_observation(obs, &mut buf);
+            buf.extend_from_slice(&payload[..payload.len().min(MAX_ADVERTISEMENT_LEN)]);
+        }
+        WalEntry::ReplaceAdvertisement(record_id, obs, payload) => {
+            buf.push(ENTRY_REPLACE_ADVERTISEMENT);
+            buf.extend_from_slice(&(*record_id as u64).to_le_bytes());
+            encode_observation(obs, &mut buf);
+            buf.extend_from_slice(&payload[..payload.len().min(MAX_ADVERTISEMENT_LEN)]);
+        }
+        WalEntry::AdvanceRecordId(next) => {
+            buf.push(ENTRY_ADVANCE_RECORD_ID);
+            buf.extend_from_slice(&next.to_le_bytes());
+        }
+        WalEntry::FrozenThrough(segment) => {
+            buf.push(ENTRY_FROZEN_THROUGH);
+            buf.extend_from_slice(&segment.to_le_bytes());
+        }
+    }
+    buf
+}
+
+fn decode_entry(buf: &[u8], format: Format) -> Option<WalEntry> {
+    let body = buf.get(1..)?;
+    match buf[0] {
+        ENTRY_INSERT => {
+            let (obs, len) = decode_observation(body, format)?;
+            (len == body.len()).then_some(WalEntry::Insert(obs))
+        }
+        ENTRY_REPLACE => {
+            let record_id = u64::from_le_bytes(body.get(..8)?.try_into().ok()?);
+            let (obs, len) = decode_observation(&body[8..], format)?;
+            (len == body.len() - 8)
+                .then_some(WalEntry::Replace(usize::try_from(record_id).ok()?, obs))
+        }
+        ENTRY_INSERT_ADVERTISEMENT => {
+            let (obs, len) = decode_observation(body, format)?;
+            (len < body.len()).then(|| WalEntry::InsertAdvertisement(obs, body[len..].to_vec()))
+        }
+        ENTRY_REPLACE_ADVERTISEMENT => {
+            let record_id = u64::from_le_bytes(body.get(..8)?.try_into().ok()?);
+            let (obs, len) = decode_observation(&body[8..], format)?;
+            Some(WalEntry::ReplaceAdvertisement(
+                usize::try_from(record_id).ok()?,
+                obs,
+                body[8 + len..].to_vec(),
+            ))
+        }
+        ENTRY_ADVANCE_RECORD_ID => Some(WalEntry::AdvanceRecordId(u64::from_le_bytes(
+            body.try_into().ok()?,
+        ))),
+        ENTRY_FROZEN_THROUGH => Some(WalEntry::FrozenThrough(u64::from_le_bytes(
+            body.try_into().ok()?,
+        ))),
+        _ => None,
+    }
+}
+
+fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Outcome of reading a single frame
+enum Frame {
+    Entry(WalEntry, usize),
+    Eof,
+    Corrupt,
+}
+
+fn read_frame<R: Read>(reader: &mut R, format: Format) -> io::Result<Frame> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(Frame::Eof),
+        FRAME_HEADER_LEN => {}
+        _ => return Ok(Frame::Corrupt),
+    }
+
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if len > MAX_ENTRY_LEN {
+        return Ok(Frame::Corrupt);
+    }
+
+    let mut payload = [0u8; MAX_ENTRY_LEN];
+    let payload = &mut payload[..len];
+    if read_full(reader, payload)? != len || crc32(payload) != crc {
+        return Ok(Frame::Corrupt);
+    }
+
+    Ok(
+        decode_entry(payload, format).map_or(Frame::Corrupt, |entry| {
+            Frame::Entry(entry, FRAME_HEADER_LEN + len)
+        }),
+    )
+}
+
+/// Read until `buf` is full or EOF, returning the number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// ========== FILE HANDLING ==========
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("wal-{segment:08}.log"))
+}
+
+/// All log segments in `dir`, sorted by segment number
+fn list_segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("wal-"))
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|digits| digits.parse::<u64>().ok());
+        if let Some(number) = number {
+            segments.push((number, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Delete every segment numbered `last` or lower
+fn remove_segments_through(dir: &Path, last: u64) -> io::Result<()> {
+    let mut removed = false;
+    for (segment, path) in list_segments(dir)? {
+        if segment <= last {
+            fs::remove_file(path)?;
+            removed = true;
+        }
+    }
+    if removed {
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Replay a segment, truncating it at the first torn or corrupt frame
+fn replay_segment<F: FnMut(WalEntry)>(path: &Path, mut apply: F) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    let format = match read_full(&mut reader, &mut magic)? {
+        8 if &magic == SEGMENT_MAGIC => Format::V2,
+        8 if &magic == LEGACY_SEGMENT_MAGIC => Format::V1,
+        8 if magic.starts_with(b"BLEWAL") => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported WAL segment version in {}", path.display()),
+            ))
+        }
+        // Crashed before the header hit disk; nothing in here is recoverable
+        _ => return OpenOptions::new().write(true).open(path)?.set_len(0),
+    };
+
+    let mut valid_len = magic.len() as u64;
+    loop {
+        match read_frame(&mut reader, format)? {
+            Frame::Entry(entry, frame_len) => {
+                apply(entry);
+                valid_len += frame_len as u64;
+            }
+            Frame::Eof => return Ok(()),
+            Frame::Corrupt => {
+                return OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(valid_len);
+            }
+        }
+    }
+}
+
+/// Apply a snapshot's entries, returning the last log segment it covers
+/// (0 for snapshots written before that was recorded)
+fn read_snapshot<F: FnMut(WalEntry)>(path: &Path, mut apply: F) -> io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let bad_header = || io::Error::new(io::ErrorKind::InvalidData, "bad snapshot header");
+
+    let mut magic = [0u8; 8];
+    let (format, covered) = match read_full(&mut reader, &mut magic)? {
+        8 if &magic == SNAPSHOT_MAGIC => {
+            let mut covered = [0u8; 8];
+            if read_full(&mut reader, &mut covered)? != covered.len() {
+                return Err(bad_header());
+            }
+            (Format::V2, u64::from_le_bytes(covered))
+        }
+        8 if &magic == LEGACY_SNAPSHOT_MAGIC_V2 => (Format::V2, 0),
+        8 if &magic == LEGACY_SNAPSHOT_MAGIC => (Format::V1, 0),
+        _ => return Err(bad_header()),
+    };
+
+    loop {
+        match read_frame(&mut reader, format)? {
+            Frame::Entry(WalEntry::Replace(..) | WalEntry::ReplaceAdvertisement(..), _)
+            | Frame::Corrupt => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "corrupt snapshot frame",
+                ))
+            }
+            Frame::Entry(entry, _) => apply(entry),
+            Frame::Eof => return Ok(covered),
+        }
+    }
+}
+
+/// Write a snapshot covering log segments up to `covered` and cold segments
+/// up to `frozen_through` atomically (temp file + rename, then a directory
+/// sync)
+fn write_snapshot(dir: &Path, cube: &BleCube, covered: u64, frozen_through: u64) -> io::Result<()> {
+    let tmp = dir.join(SNAPSHOT_TMP_FILE);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_all(&covered.to_le_bytes())?;
+    let mut write_entry = |entry: &WalEntry| writer.write_all(&encode_frame(&encode_entry(entry)));
+
+    // Replay assigns stable IDs sequentially; mark the gaps evictions left
+    let mut next_id = 0;
+    for (record_id, obs) in cube.records.iter().enumerate() {
+        let id = cube.record_ids[record_id].as_u64();
+        if id != next_id {
+            write_entry(&WalEntry::AdvanceRecordId(id))?;
+        }
+        next_id = id + 1;
+        write_entry(&match cube.advertisement(record_id) {
+            Some(payload) => WalEntry::InsertAdvertisement(*obs, payload.to_vec()),
+            None => WalEntry::Insert(*obs),
+        })?;
+    }
+    if cube.next_record_id != next_id {
+        write_entry(&WalEntry::AdvanceRecordId(cube.next_record_id))?;
+    }
+    if frozen_through > 0 {
+        write_entry(&WalEntry::FrozenThrough(frozen_through))?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    fs::rename(tmp, dir.join(SNAPSHOT_FILE))?;
+    sync_dir(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DuplicatePolicy, RateLimitKeep, RecordId};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "ble_cube_wal_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn obs(i: u8) -> BleObservation {
+        BleObservation {
+            rssi: -(i as i8),
+            mac: [0, 0, 0, 0, 0, i],
+            timestamp: 1700000000 + i as i64,
+            lat: 37.0 + i as f64 * 0.001,
+            lon: -122.0,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_recover_replays_inserts() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            for i in 0..10 {
+                cube.try_insert(obs(i)).unwrap();
+            }
+        }
+
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 10);
+        assert_eq!(cube.get(3).unwrap().mac, [0, 0, 0, 0, 0, 3]);
+        assert_eq!(cube.query_mac([0, 0, 0, 0, 0, 7]).len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_is_truncated() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            for i in 0..3 {
+                cube.insert(obs(i));
+            }
+        }
+
+        // Simulate a crash halfway through a frame
+        let (_, path) = list_segments(&dir).unwrap().pop().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&encode_frame(&encode_entry(&WalEntry::Insert(obs(9))))[..20])
+            .unwrap();
+        drop(file);
+
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 3);
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            (8 + 3 * (FRAME_HEADER_LEN + 1 + CORE_OBSERVATION_LEN + 1)) as u64
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_and_rotation() {
+        let dir = temp_dir();
+        let config = WalConfig {
+            max_segment_bytes: 100,
+            sync_on_append: false,
+        };
+        {
+            let mut cube = BleCube::recover_with(&dir, config).unwrap();
+            for i in 0..10 {
+                cube.insert(obs(i));
+            }
+            assert!(list_segments(&dir).unwrap().len() > 1);
+
+            cube.checkpoint().unwrap();
+            assert_eq!(list_segments(&dir).unwrap().len(), 1);
+
+            cube.insert(obs(10));
+        }
+
+        let cube = BleCube::recover_with(&dir, config).unwrap();
+        assert_eq!(cube.len(), 11);
+        assert_eq!(cube.get(10).unwrap().timestamp, 1700000010);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_interrupted_before_truncate() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            for i in 0..5 {
+                cube.insert(obs(i));
+            }
+            // Crash after the snapshot was renamed into place, before the
+            // old segments were deleted
+            let covered = cube.wal.as_ref().unwrap().segment;
+            write_snapshot(&dir, &cube, covered, 0).unwrap();
+        }
+
+        let mut cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 5);
+        assert!(list_segments(&dir)
+            .unwrap()
+            .iter()
+            .all(|&(segment, _)| segment > 1));
+        cube.insert(obs(5));
+        drop(cube);
+
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 6);
+        assert_eq!(cube.query_mac([0, 0, 0, 0, 0, 2]).len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_upserts_are_replayed() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            cube.insert(obs(1));
+            let stronger = BleObservation { rssi: 0, ..obs(1) };
+            cube.try_upsert_by_key(stronger, DuplicatePolicy::KeepStrongest)
+                .unwrap();
+        }
+
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 1);
+        assert_eq!(cube.get(0).unwrap().rssi, 0);
+        assert_eq!(cube.query_rssi(-1).len(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_advertisements_survive_recovery_and_checkpoint() {
+        let dir = temp_dir();
+        let payload = [0x02, 0x01, 0x06];
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            cube.insert_advertisement(obs(1), &payload);
+            cube.insert(obs(2));
+        }
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            assert_eq!(cube.advertisement(0), Some(&payload[..]));
+            cube.checkpoint().unwrap();
+        }
+
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 2);
+        assert_eq!(cube.advertisement(0), Some(&payload[..]));
+        assert_eq!(cube.advertisement(1), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_optional_fields_round_trip_and_legacy_segments() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            cube.insert(BleObservation {
+                receiver_id: Some(7),
+                floor: Some(-2),
+                tx_power: Some(-59),
+                ..obs(1)
+            });
+            cube.insert(obs(2));
+        }
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.get(0).unwrap().tx_power, Some(-59));
+        assert_eq!(cube.get(1).unwrap().tx_power, None);
+        assert_eq!(cube.get(0).unwrap().receiver_id, Some(7));
+        assert_eq!(cube.get(1).unwrap().receiver_id, None);
+        assert_eq!(cube.get(0).unwrap().floor, Some(-2));
+        assert_eq!(cube.get(1).unwrap().floor, None);
+        assert_eq!(cube.query_receiver(7).len(), 1);
+        assert_eq!(cube.query_floor(-2).len(), 1);
+        drop(cube);
+
+        // A version 1 segment (fixed 31-byte observations) written before
+        // optional fields existed
+        let mut legacy = LEGACY_SEGMENT_MAGIC.to_vec();
+        let mut entry = vec![ENTRY_INSERT];
+        encode_observation(&obs(3), &mut entry);
+        entry.pop(); // v1 has no flags byte
+        legacy.extend_from_slice(&encode_frame(&entry));
+        fs::write(segment_path(&dir, 99), legacy).unwrap();
+
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 3);
+        assert_eq!(cube.get(2).unwrap().mac, [0, 0, 0, 0, 0, 3]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_builder_options_apply_to_recovered_cube() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            cube.insert(obs(1));
+            cube.insert(obs(2));
+        }
+        let cube = BleCube::builder()
+            .without_rssi_index()
+            .recover(&dir, WalConfig::default())
+            .unwrap();
+        assert_eq!(cube.len(), 2);
+        assert!(!cube.is_indexed(crate::Dimension::Rssi));
+        assert_eq!(cube.index_stats().rssi.entries, 0);
+        assert_eq!(cube.query_rssi(-2).len(), 1);
+        drop(cube);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_without_wal_fails() {
+        let mut cube = BleCube::new();
+        assert!(cube.checkpoint().is_err());
+    }
+
+    #[test]
+    fn test_eviction_survives_recovery() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            cube.set_time_partition_width(10);
+            for i in 0..30 {
+                cube.insert(obs(i));
+            }
+            // 1700000000 is a multiple of 10: the first two partitions end by +20
+            assert_eq!(cube.evict_partitions_before(1700000020).unwrap().len(), 20);
+            cube.insert(obs(40));
+        }
+        let mut cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 11);
+        assert_eq!(cube.get(0).unwrap().mac, [0, 0, 0, 0, 0, 20]);
+        // Stable IDs come back from the snapshot, gaps included
+        assert_eq!(cube.stable_id(0), Some(RecordId::from_u64(20)));
+        assert_eq!(cube.stable_id(10), Some(RecordId::from_u64(30)));
+
+        // Trailing evictions still advance the ID counter
+        cube.evict_partitions_before(i64::MAX).unwrap();
+        drop(cube);
+        let mut cube = BleCube::recover(&dir).unwrap();
+        assert!(cube.is_empty());
+        let id = cube.insert(obs(1));
+        assert_eq!(cube.stable_id(id), Some(RecordId::from_u64(31)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rate_limit_replacements_survive_recovery() {
+        let dir = temp_dir();
+        {
+            let mut cube = BleCube::recover(&dir).unwrap();
+            cube.set_rate_limit(1, RateLimitKeep::Strongest);
+            for rssi in [80, 60, 70] {
+                cube.insert(obs(rssi));
+                let limited = BleObservation {
+                    mac: [1; 6],
+                    timestamp: 1700000000,
+                    ..obs(rssi)
+                };
+                // The -60 dBm arrival replaces the record, -70 is dropped
+                let _ = cube.try_insert_advertisement(limited, &[0x02, 0x01, rssi]);
+            }
+        }
+        let cube = BleCube::recover(&dir).unwrap();
+        assert_eq!(cube.len(), 4);
+        let id = cube.mac_record_ids([1; 6])[0];
+        assert_eq!(cube.get(id).unwrap().rssi, -60);
+        assert_eq!(cube.advertisement(id), Some(&[0x02, 0x01, 60][..]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,446 @@
+//! Named geofences whose membership is maintained on insert, so zone queries
+//! don't re-run point-in-polygon over the geo index every time.
+
+use crate::ble_cube::{insert_posting, remove_posting, BleCube, BleObservation, DistanceMetric};
+use crate::compat::prelude::*;
+use crate::compat::HashMap;
+use crate::crs::CoordinateSystem;
+use crate::geo::{point_in_polygon, point_in_rings, vertices_envelope};
+use crate::memory::{hash_table_bytes, posting_bytes, ComponentMemory};
+use core::mem::size_of;
+use rstar::AABB;
+
+/// Region covered by a named zone
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Zone {
+    /// Simple polygon, vertices as [(lat, lon), ...]
+    Polygon(Vec<(f64, f64)>),
+    /// Polygons with holes, or several polygons: rings combined by the
+    /// even-odd rule (see [`point_in_rings`](crate::geo::point_in_rings))
+    MultiPolygon(Vec<Vec<(f64, f64)>>),
+    /// Circle around a point
+    Circle {
+        /// Center latitude
+        lat: f64,
+        /// Center longitude
+        lon: f64,
+        /// Radius in meters (the cube's distance metric, or Euclidean in a
+        /// projected CRS)
+        radius_m: f64,
+    },
+}
+
+impl Zone {
+    /// Circle of `radius_m` meters around (lat, lon)
+    pub fn circle(lat: f64, lon: f64, radius_m: f64) -> Self {
+        Zone::Circle { lat, lon, radius_m }
+    }
+
+    /// `exterior` with the `holes` cut out (e.g. a site boundary minus
+    /// building footprints), rings as for [`Zone::Polygon`]
+    pub fn with_holes(exterior: Vec<(f64, f64)>, holes: Vec<Vec<(f64, f64)>>) -> Self {
+        let mut rings = Vec::with_capacity(holes.len() + 1);
+        rings.push(exterior);
+        rings.extend(holes);
+        Zone::MultiPolygon(rings)
+    }
+
+    /// Whether WGS84 (lat, lon) lies inside the zone
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        self.contains_in(CoordinateSystem::Wgs84, lat, lon)
+    }
+
+    /// Whether (lat, lon) lies inside the zone, with coordinates and circle
+    /// radii interpreted in `crs`
+    pub fn contains_in(&self, crs: CoordinateSystem, lat: f64, lon: f64) -> bool {
+        self.contains_with(crs, DistanceMetric::Haversine, lat, lon)
+    }
+
+    /// Like [`Zone::contains_in`], measuring circle radii with `metric`
+    pub fn contains_with(
+        &self,
+        crs: CoordinateSystem,
+        metric: DistanceMetric,
+        lat: f64,
+        lon: f64,
+    ) -> bool {
+        match self {
+            Zone::Polygon(polygon) => polygon.len() >= 3 && point_in_polygon(lat, lon, polygon),
+            Zone::MultiPolygon(rings) => point_in_rings(lat, lon, rings),
+            Zone::Circle {
+                lat: c_lat,
+                lon: c_lon,
+                radius_m,
+            } => crs.distance_with(metric, *c_lat, *c_lon, lat, lon) <= *radius_m,
+        }
+    }
+
+    /// Envelope enclosing the zone, used as a cheap pre-filter
+    #[doc(hidden)]
+    pub fn envelope(&self, crs: CoordinateSystem) -> AABB<[f64; 2]> {
+        match self {
+            Zone::Polygon(polygon) => vertices_envelope(polygon),
+            Zone::MultiPolygon(rings) => vertices_envelope(rings.iter().flatten()),
+            Zone::Circle { lat, lon, radius_m } => crs.radius_envelope(*lat, *lon, *radius_m),
+        }
+    }
+}
+
+impl From<Vec<(f64, f64)>> for Zone {
+    fn from(polygon: Vec<(f64, f64)>) -> Self {
+        Zone::Polygon(polygon)
+    }
+}
+
+impl From<&[(f64, f64)]> for Zone {
+    fn from(polygon: &[(f64, f64)]) -> Self {
+        Zone::Polygon(polygon.to_vec())
+    }
+}
+
+/// A registered zone and the sorted IDs of records inside it
+#[derive(Debug, Clone)]
+struct RegisteredZone {
+    name: String,
+    zone: Zone,
+    envelope: AABB<[f64; 2]>,
+    members: Vec<usize>,
+}
+
+impl RegisteredZone {
+    fn covers(&self, obs: &BleObservation, crs: CoordinateSystem, metric: DistanceMetric) -> bool {
+        crs.envelope_contains(&self.envelope, obs.lat, obs.lon)
+            && self.zone.contains_with(crs, metric, obs.lat, obs.lon)
+    }
+}
+
+/// (zone position, record ID) pair of a membership posting
+type ZoneEntry = (usize, usize);
+
+/// Zone registry with per-zone membership postings
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Geofence {
+    zones: Vec<RegisteredZone>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Geofence {
+    /// Zone count and estimated heap bytes (see `memory.rs`)
+    pub(crate) fn memory(&self) -> ComponentMemory {
+        let zone_bytes: usize = self
+            .zones
+            .iter()
+            .map(|z| {
+                let vertices = match &z.zone {
+                    Zone::Polygon(vertices) => vertices.capacity() * size_of::<(f64, f64)>(),
+                    Zone::MultiPolygon(rings) => {
+                        rings.capacity() * size_of::<Vec<(f64, f64)>>()
+                            + rings
+                                .iter()
+                                .map(|ring| ring.capacity() * size_of::<(f64, f64)>())
+                                .sum::<usize>()
+                    }
+                    Zone::Circle { .. } => 0,
+                };
+                z.name.capacity() + vertices + posting_bytes(&z.members)
+            })
+            .sum();
+        ComponentMemory {
+            entries: self.zones.len(),
+            bytes: self.zones.capacity() * size_of::<RegisteredZone>()
+                + zone_bytes
+                + hash_table_bytes(&self.by_name)
+                + self.by_name.keys().map(String::capacity).sum::<usize>(),
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        for zone in &mut self.zones {
+            zone.members.shrink_to_fit();
+        }
+        self.zones.shrink_to_fit();
+        self.by_name.shrink_to_fit();
+    }
+
+    /// Record a newly inserted observation in every zone it falls in
+    pub(crate) fn tag(
+        &mut self,
+        record_id: usize,
+        obs: &BleObservation,
+        crs: CoordinateSystem,
+        metric: DistanceMetric,
+    ) {
+        for zone in &mut self.zones {
+            if zone.covers(obs, crs, metric) {
+                insert_posting(&mut zone.members, record_id);
+            }
+        }
+    }
+
+    /// Re-evaluate membership after a record's coordinates changed
+    pub(crate) fn retag(
+        &mut self,
+        record_id: usize,
+        obs: &BleObservation,
+        crs: CoordinateSystem,
+        metric: DistanceMetric,
+    ) {
+        for zone in &mut self.zones {
+            if zone.covers(obs, crs, metric) {
+                insert_posting(&mut zone.members, record_id);
+            } else {
+                remove_posting(&mut zone.members, record_id);
+            }
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&RegisteredZone> {
+        self.by_name.get(name).map(|&i| &self.zones[i])
+    }
+
+    pub(crate) fn members(&self, name: &str) -> Option<&[usize]> {
+        self.get(name).map(|zone| zone.members.as_slice())
+    }
+
+    /// (zone, record ID) membership entries as stored, and as the zone
+    /// shapes imply for `records`, for integrity checks
+    pub(crate) fn audit(
+        &self,
+        records: &[BleObservation],
+        crs: CoordinateSystem,
+        metric: DistanceMetric,
+    ) -> (Vec<ZoneEntry>, Vec<ZoneEntry>) {
+        let mut stored = Vec::new();
+        let mut implied = Vec::new();
+        for (i, zone) in self.zones.iter().enumerate() {
+            stored.extend(zone.members.iter().map(|&record_id| (i, record_id)));
+            implied.extend(
+                records
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, obs)| zone.covers(obs, crs, metric))
+                    .map(|(record_id, _)| (i, record_id)),
+            );
+        }
+        (stored, implied)
+    }
+}
+
+impl BleCube {
+    /// Register (or replace) a named zone; existing records are tagged immediately
+    pub fn add_zone<Z: Into<Zone>>(&mut self, name: &str, zone: Z) {
+        let zone = zone.into();
+        let envelope = zone.envelope(self.crs);
+        let members = self.zone_members(&zone, &envelope);
+
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+
+        let registered = RegisteredZone {
+            name: name.to_string(),
+            zone,
+            envelope,
+            members,
+        };
+
+        match self.geofence.by_name.get(name) {
+            Some(&i) => self.geofence.zones[i] = registered,
+            None => {
+                self.geofence
+                    .by_name
+                    .insert(name.to_string(), self.geofence.zones.len());
+                self.geofence.zones.push(registered);
+            }
+        }
+    }
+
+    /// Recompute every zone's envelope and membership (after a CRS or
+    /// distance metric change)
+    pub(crate) fn reindex_zones(&mut self) {
+        let mut zones = core::mem::take(&mut self.geofence.zones);
+        for registered in &mut zones {
+            registered.envelope = registered.zone.envelope(self.crs);
+            registered.members = self.zone_members(&registered.zone, &registered.envelope);
+        }
+        self.geofence.zones = zones;
+    }
+
+    /// Sorted IDs of the records inside `zone`
+    fn zone_members(&self, zone: &Zone, envelope: &AABB<[f64; 2]>) -> Vec<usize> {
+        let mut members: Vec<usize> = self
+            .locate_in_envelope(*envelope)
+            .filter(|point| {
+                zone.contains_with(
+                    self.crs,
+                    self.distance_metric,
+                    point.coords[0],
+                    point.coords[1],
+                )
+            })
+            .map(|point| point.record_id)
+            .collect();
+        members.sort_unstable();
+        members
+    }
+
+    /// Unregister a zone, returning whether it existed
+    pub fn remove_zone(&mut self, name: &str) -> bool {
+        let Some(i) = self.geofence.by_name.remove(name) else {
+            return false;
+        };
+        self.geofence.zones.remove(i);
+        #[cfg(feature = "std")]
+        self.clear_query_cache();
+        for index in self.geofence.by_name.values_mut() {
+            if *index > i {
+                *index -= 1;
+            }
+        }
+        true
+    }
+
+    /// Look up a registered zone by name
+    pub fn zone(&self, name: &str) -> Option<&Zone> {
+        self.geofence.get(name).map(|zone| &zone.zone)
+    }
+
+    /// Names of all registered zones, in registration order
+    pub fn zone_names(&self) -> Vec<&str> {
+        self.geofence
+            .zones
+            .iter()
+            .map(|zone| zone.name.as_str())
+            .collect()
+    }
+
+    /// Observations inside a zone, optionally restricted to [start, end] inclusive.
+    /// Unknown zones yield no results.
+    pub fn query_zone(&self, name: &str, time_range: Option<(i64, i64)>) -> Vec<&BleObservation> {
+        self.geofence
+            .members(name)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|&id| self.records.get(id))
+            .filter(|obs| {
+                time_range.is_none_or(|(start, end)| (start..=end).contains(&obs.timestamp))
+            })
+            .collect()
+    }
+
+    /// Record IDs inside a zone, ascending; `None` for an unknown zone
+    pub fn zone_record_ids(&self, name: &str) -> Option<&[usize]> {
+        self.geofence.members(name)
+    }
+
+    /// Names of the zones a record fell in
+    pub fn record_zones(&self, record_id: usize) -> Vec<&str> {
+        self.geofence
+            .zones
+            .iter()
+            .filter(|zone| zone.members.binary_search(&record_id).is_ok())
+            .map(|zone| zone.name.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs_at(lat: f64, lon: f64, timestamp: i64) -> BleObservation {
+        BleObservation {
+            rssi: -60,
+            mac: [0; 6],
+            timestamp,
+            lat,
+            lon,
+            receiver_id: None,
+            floor: None,
+            tx_power: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn test_zone_membership_on_insert_and_backfill() {
+        let mut cube = BleCube::new();
+
+        // Inserted before the zone exists: picked up by the backfill
+        cube.insert(obs_at(37.5, -122.5, 100));
+
+        cube.add_zone(
+            "dock",
+            vec![
+                (37.0, -123.0),
+                (38.0, -123.0),
+                (38.0, -122.0),
+                (37.0, -122.0),
+            ],
+        );
+        cube.add_zone("gate", Zone::circle(40.0, -100.0, 500.0));
+
+        cube.insert(obs_at(37.6, -122.4, 200));
+        cube.insert(obs_at(40.001, -100.0, 300)); // ~110m from gate center
+        cube.insert(obs_at(10.0, 10.0, 400));
+
+        assert_eq!(cube.query_zone("dock", None).len(), 2);
+        assert_eq!(cube.query_zone("dock", Some((150, 250))).len(), 1);
+        assert_eq!(cube.query_zone("gate", None).len(), 1);
+        assert!(cube.query_zone("nowhere", None).is_empty());
+
+        assert_eq!(cube.record_zones(1), vec!["dock"]);
+        assert_eq!(cube.record_zones(2), vec!["gate"]);
+        assert!(cube.record_zones(3).is_empty());
+    }
+
+    #[test]
+    fn test_replace_and_remove_zone() {
+        let mut cube = BleCube::new();
+        cube.add_zone("a", Zone::circle(0.0, 0.0, 1000.0));
+        cube.add_zone("b", Zone::circle(1.0, 1.0, 1000.0));
+        cube.insert(obs_at(0.0, 0.0, 0));
+
+        // Re-registering under the same name replaces the shape
+        cube.add_zone("a", Zone::circle(1.0, 1.0, 1000.0));
+        assert!(cube.query_zone("a", None).is_empty());
+
+        assert!(cube.remove_zone("a"));
+        assert!(!cube.remove_zone("a"));
+        assert_eq!(cube.zone_names(), vec!["b"]);
+        assert_eq!(cube.zone("b"), Some(&Zone::circle(1.0, 1.0, 1000.0)));
+    }
+
+    #[test]
+    fn test_zone_with_holes() {
+        let campus = vec![(0.0, 0.0), (0.0, 0.01), (0.01, 0.01), (0.01, 0.0)];
+        let building = vec![
+            (0.002, 0.002),
+            (0.002, 0.004),
+            (0.004, 0.004),
+            (0.004, 0.002),
+        ];
+        let mut cube = BleCube::new();
+        cube.insert(obs_at(0.001, 0.001, 0)); // outdoors
+        cube.insert(obs_at(0.003, 0.003, 1)); // indoors
+        cube.insert(obs_at(0.02, 0.02, 2)); // off campus
+
+        cube.add_zone(
+            "grounds",
+            Zone::with_holes(campus.clone(), vec![building.clone()]),
+        );
+        let stamps: Vec<i64> = cube
+            .query_zone("grounds", None)
+            .iter()
+            .map(|obs| obs.timestamp)
+            .collect();
+        assert_eq!(stamps, [0]);
+        assert!(Zone::with_holes(campus.clone(), vec![]).contains(0.003, 0.003));
+
+        let ad_hoc = cube.query_geo_multipolygon(&[campus, building]);
+        assert_eq!(ad_hoc.len(), 1);
+        assert!(cube
+            .query_geo_multipolygon(&[] as &[Vec<(f64, f64)>])
+            .is_empty());
+    }
+}
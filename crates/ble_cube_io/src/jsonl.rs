@@ -0,0 +1,184 @@
+//! Newline-delimited JSON import/export.
+//!
+//! One [`BleObservation`] per line, in the same JSON shape the `serde`
+//! feature produces. Both directions stream: import reads a line at a time
+//! and export writes straight from the record store.
+
+use ble_cube_core::{BleCube, BleObservation, CubeError};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Per-line errors kept in a [`JsonlImport`]; later ones are only counted
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// A line that could not be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonlLineError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonlLineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Outcome of [`Jsonl::import_jsonl`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonlImport {
+    /// Observations inserted (observations a rate limit suppressed count as
+    /// neither inserted nor rejected)
+    pub inserted: usize,
+    /// Non-blank lines that were skipped
+    pub rejected: usize,
+    /// Details of the first rejected lines (at most 100)
+    pub errors: Vec<JsonlLineError>,
+}
+
+impl JsonlImport {
+    fn reject(&mut self, line: usize, message: String) {
+        self.rejected += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(JsonlLineError { line, message });
+        }
+    }
+}
+
+/// Newline-delimited JSON import/export of a [`BleCube`]
+pub trait Jsonl {
+    /// Insert one observation per line of `reader`. Blank lines are ignored;
+    /// malformed lines and observations rejected by the time-unit or validation
+    /// policy are skipped and reported. Fails only on read errors or a failed WAL append.
+    fn import_jsonl<R: BufRead>(&mut self, reader: R) -> io::Result<JsonlImport>;
+
+    /// Write every record as one JSON line, in record ID order. Returns the
+    /// number of lines written.
+    fn export_jsonl<W: Write>(&self, writer: W) -> io::Result<usize>;
+}
+
+impl Jsonl for BleCube {
+    fn import_jsonl<R: BufRead>(&mut self, mut reader: R) -> io::Result<JsonlImport> {
+        let mut report = JsonlImport::default();
+        let mut buf = Vec::new();
+        let mut line = 0;
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                return Ok(report);
+            }
+            line += 1;
+            if buf.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let obs: BleObservation = match serde_json::from_slice(&buf) {
+                Ok(obs) => obs,
+                Err(e) => {
+                    report.reject(line, e.to_string());
+                    continue;
+                }
+            };
+            match self.try_insert(obs) {
+                Ok(_) => report.inserted += 1,
+                Err(e @ (CubeError::TimeUnit { .. } | CubeError::Invalid { .. })) => {
+                    report.reject(line, e.to_string())
+                }
+                Err(CubeError::RateLimited) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn export_jsonl<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        for obs in self.records() {
+            serde_json::to_writer(&mut writer, obs)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(self.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::{TimeUnit, UnitMismatch, ValidationPolicy, ValidationRules};
+
+    #[test]
+    fn test_import_reports_bad_lines() {
+        let input = concat!(
+            r#"{"rssi":-60,"mac":"AA:BB:CC:DD:EE:01","timestamp":100,"lat":1.0,"lon":2.0}"#,
+            "\n\n",
+            r#"{"rssi":-60,"mac":"not a mac","timestamp":100,"lat":1.0,"lon":2.0}"#,
+            "\n",
+            "{\"rssi\":-70\n",
+            r#"{"rssi":-70,"mac":[0,0,0,0,0,2],"timestamp":100000000000,"lat":0,"lon":0}"#,
+            "\n",
+            r#"{"rssi":-75,"mac":"aabbccddee03","timestamp":101,"lat":1.0,"lon":2.0,"floor":3}"#,
+        );
+
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Seconds, UnitMismatch::Reject);
+        let report = cube.import_jsonl(input.as_bytes()).unwrap();
+        assert_eq!(report.inserted, 2);
+        assert_eq!(report.rejected, 3);
+        let lines: Vec<usize> = report.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![3, 4, 5]);
+        assert_eq!(cube.get(1).unwrap().floor, Some(3));
+    }
+
+    #[test]
+    fn test_import_skips_invalid_observations() {
+        let input = concat!(
+            r#"{"rssi":-60,"mac":"AA:BB:CC:DD:EE:01","timestamp":100,"lat":1.0,"lon":2.0}"#,
+            "\n",
+            r#"{"rssi":-60,"mac":"00:00:00:00:00:00","timestamp":101,"lat":1.0,"lon":2.0}"#,
+            "\n",
+            r#"{"rssi":-65,"mac":"AA:BB:CC:DD:EE:02","timestamp":102,"lat":1.0,"lon":2.0}"#,
+        );
+
+        let mut cube = BleCube::new();
+        cube.set_validation(ValidationRules::default(), ValidationPolicy::Reject);
+        let report = cube.import_jsonl(input.as_bytes()).unwrap();
+        assert_eq!((report.inserted, report.rejected), (2, 1));
+        assert_eq!(report.errors[0].line, 2);
+        assert!(report.errors[0].message.contains("all-zero MAC"));
+        assert_eq!(cube.get(1).unwrap().mac, [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 2]);
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut cube = BleCube::new();
+        for i in 0..5u8 {
+            cube.insert(BleObservation {
+                rssi: -50 - i as i8,
+                mac: [0, 0, 0, 0, 0, i],
+                timestamp: i64::from(i),
+                lat: 37.0 + f64::from(i) * 0.001,
+                lon: -122.0,
+                receiver_id: Some(u16::from(i)),
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+
+        let mut out = Vec::new();
+        assert_eq!(cube.export_jsonl(&mut out).unwrap(), 5);
+        assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 5);
+
+        let mut copy = BleCube::new();
+        let report = copy.import_jsonl(&out[..]).unwrap();
+        assert_eq!(report.inserted, 5);
+        assert!(report.errors.is_empty());
+        for id in 0..5 {
+            let (a, b) = (cube.get(id).unwrap(), copy.get(id).unwrap());
+            assert_eq!(
+                (a.mac, a.rssi, a.lat, a.receiver_id),
+                (b.mac, b.rssi, b.lat, b.receiver_id)
+            );
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! File formats for `ble-cube`: CSV / JSON Lines insert sinks, BTSnoop
+//! capture import, and behind features newline-delimited JSON
+//! import/export and pcap / pcapng sniffer captures.
+//!
+//! Imports are extension traits on [`BleCube`](ble_cube_core::BleCube);
+//! bring them into scope (or use the `ble-cube` prelude) to call them.
+//!
+//! | Feature | Default | Module |
+//! |---------|---------|--------|
+//! | `jsonl` | no      | newline-delimited JSON import/export and `JsonlSink` |
+//! | `pcap`  | no      | pcap / pcapng import of BLE sniffer captures (`import_pcap`) |
+
+mod btsnoop;
+#[cfg(feature = "jsonl")]
+mod jsonl;
+#[cfg(feature = "pcap")]
+mod pcap;
+mod sink;
+
+pub use btsnoop::{Btsnoop, BtsnoopImport};
+#[cfg(feature = "jsonl")]
+pub use jsonl::{Jsonl, JsonlImport, JsonlLineError};
+#[cfg(feature = "pcap")]
+pub use pcap::{LinkLayerInfo, Pcap, PcapImport};
+#[cfg(feature = "jsonl")]
+pub use sink::JsonlSink;
+pub use sink::{write_csv_row, CsvSink, CSV_HEADER};
@@ -0,0 +1,695 @@
+//! PCAP / PCAPNG import of BLE sniffer captures.
+//!
+//! Sniffers (Nordic nRF Sniffer, Ubertooth, btlejack) capture link-layer
+//! packets off the air rather than HCI events, so a capture also records
+//! which advertising channel a packet was heard on and whether its CRC
+//! checked out. [`Pcap::import_pcap`] reads classic pcap and pcapng
+//! files with the `LINKTYPE_NORDIC_BLE` (272) or
+//! `LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR` (256) link types, inserts every
+//! advertising PDU that carries an RSSI, and returns the channel and CRC
+//! result per record, keyed by stable ID ([`PcapImport::link_layer`]), so
+//! it stays valid across compaction.
+//!
+//! Packets that fail their CRC are kept (flagged) since captures are often
+//! analysed for interference; their address and payload may be corrupt.
+//! The cube itself does not store link-layer metadata.
+
+use crate::btsnoop::read_full;
+use ble_cube_core::{
+    AdvertisingReport, BleCube, BleObservation, CubeError, HciReceiver, RecordId, TimeUnit,
+    Timestamp,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+
+/// Access address of every advertising-channel packet
+const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8E89_BED6;
+const LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR: u32 = 256;
+const LINKTYPE_NORDIC_BLE: u32 = 272;
+/// Bytes before the access address in each link type
+const PHDR_LEN: usize = 10;
+const NORDIC_HEADER_LEN: usize = 17;
+/// `LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR` flags
+const PHDR_SIGNAL_VALID: u16 = 0x0002;
+const PHDR_CRC_CHECKED: u16 = 0x0400;
+const PHDR_CRC_VALID: u16 = 0x0800;
+/// nRF Sniffer flags
+const NORDIC_CRC_OK: u8 = 0x01;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+/// Largest packet or block accepted; sniffer packets are under 300 bytes
+const MAX_CAPTURE_RECORD: usize = 1 << 18;
+
+/// Radio metadata of a record imported from a sniffer capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkLayerInfo {
+    /// Channel index the packet was heard on (37, 38 or 39 for primary
+    /// advertising, 0..=36 for secondary)
+    pub channel: u8,
+    /// Whether the sniffer found the CRC valid; `None` if it did not check
+    pub crc_valid: Option<bool>,
+}
+
+/// Outcome of [`Pcap::import_pcap`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PcapImport {
+    /// Captured packets read
+    pub packets: usize,
+    /// Advertising PDUs decoded (data-channel and scanner packets are not)
+    pub advertisements: usize,
+    /// Observations inserted (observations a rate limit suppressed count as
+    /// neither inserted nor rejected, see [`BleCube::rate_limit_stats`])
+    pub inserted: usize,
+    /// Inserted observations whose packet failed its CRC
+    pub crc_errors: usize,
+    /// Packets of an unsupported link type, too short for their header, or
+    /// observations the time-unit or validation policy rejected
+    pub rejected: usize,
+    /// Whether the file ended in the middle of a record
+    pub truncated: bool,
+    /// Channel and CRC result of each inserted record
+    pub links: BTreeMap<RecordId, LinkLayerInfo>,
+}
+
+impl PcapImport {
+    /// Channel and CRC result of an imported record
+    pub fn link_layer(&self, id: RecordId) -> Option<LinkLayerInfo> {
+        self.links.get(&id).copied()
+    }
+}
+
+/// One decoded sniffer packet
+struct SnifferPacket {
+    report: AdvertisingReport,
+    link: LinkLayerInfo,
+}
+
+/// Decode a captured packet; `Err` for one too short for its link type,
+/// `Ok(None)` for a well-formed packet that is not an advertisement
+fn decode_packet(link_type: u32, bytes: &[u8]) -> Result<Option<SnifferPacket>, ()> {
+    let (channel, rssi, crc_valid, link_layer) = match link_type {
+        LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR if bytes.len() >= PHDR_LEN => {
+            let flags = u16::from_le_bytes([bytes[8], bytes[9]]);
+            (
+                rf_channel_index(bytes[0]),
+                (flags & PHDR_SIGNAL_VALID != 0).then_some(bytes[1] as i8),
+                (flags & PHDR_CRC_CHECKED != 0).then_some(flags & PHDR_CRC_VALID != 0),
+                &bytes[PHDR_LEN..],
+            )
+        }
+        LINKTYPE_NORDIC_BLE if bytes.len() >= NORDIC_HEADER_LEN => (
+            bytes[9],
+            // Sent as the magnitude of a negative dBm value
+            Some(i8::try_from(-i16::from(bytes[10])).unwrap_or(i8::MIN)),
+            Some(bytes[8] & NORDIC_CRC_OK != 0),
+            &bytes[NORDIC_HEADER_LEN..],
+        ),
+        _ => return Err(()),
+    };
+    Ok(
+        advertising_pdu(link_layer).map(|(event_type, address_type, mac, tx_power, data)| {
+            SnifferPacket {
+                report: AdvertisingReport {
+                    event_type: u16::from(event_type),
+                    address_type,
+                    mac,
+                    rssi,
+                    tx_power,
+                    data: data.to_vec(),
+                },
+                link: LinkLayerInfo { channel, crc_valid },
+            }
+        }),
+    )
+}
+
+/// RF channel (0..=39, by frequency) to channel index
+fn rf_channel_index(rf: u8) -> u8 {
+    match rf {
+        0 => 37,
+        12 => 38,
+        39 => 39,
+        1..=11 => rf - 1,
+        _ => rf.saturating_sub(2),
+    }
+}
+
+/// (PDU type, TxAdd, advertiser address, TX power, AdvData) of an
+/// advertising-channel packet that carries its advertiser's address
+#[allow(clippy::type_complexity)]
+fn advertising_pdu(packet: &[u8]) -> Option<(u8, u8, [u8; 6], Option<i8>, &[u8])> {
+    let access_address = u32::from_le_bytes(packet.get(..4)?.try_into().ok()?);
+    if access_address != ADVERTISING_ACCESS_ADDRESS {
+        return None;
+    }
+    let (header, length) = (*packet.get(4)?, usize::from(*packet.get(5)?));
+    let payload = packet.get(6..6 + length)?;
+    let (pdu_type, tx_add) = (header & 0x0F, (header >> 6) & 1);
+    let address = |bytes: &[u8]| -> Option<[u8; 6]> {
+        let mut mac: [u8; 6] = bytes.get(..6)?.try_into().ok()?;
+        mac.reverse();
+        Some(mac)
+    };
+    match pdu_type {
+        // ADV_IND, ADV_NONCONN_IND, SCAN_RSP, ADV_SCAN_IND
+        0 | 2 | 4 | 6 => Some((pdu_type, tx_add, address(payload)?, None, &payload[6..])),
+        // ADV_DIRECT_IND: the rest is the target address, not AdvData
+        1 => Some((pdu_type, tx_add, address(payload)?, None, &[])),
+        // Extended advertising: AdvA and TX power sit in the extended header
+        7 => {
+            let header_len = usize::from(*payload.first()? & 0x3F);
+            let extended = payload.get(1..1 + header_len)?;
+            let flags = *extended.first()?;
+            let mut at = 1;
+            let mac = if flags & 0x01 != 0 {
+                at += 6;
+                address(extended.get(1..)?)?
+            } else {
+                return None;
+            };
+            // TargetA, CTEInfo, ADI, AuxPtr, SyncInfo
+            for (flag, len) in [(0x02, 6), (0x04, 1), (0x08, 2), (0x10, 3), (0x20, 18)] {
+                if flags & flag != 0 {
+                    at += len;
+                }
+            }
+            let tx_power = if flags & 0x40 != 0 {
+                Some(*extended.get(at)? as i8)
+            } else {
+                None
+            };
+            Some((pdu_type, tx_add, mac, tx_power, &payload[1 + header_len..]))
+        }
+        // Scan and connection requests come from the scanner, not the advertiser
+        _ => None,
+    }
+}
+
+/// Byte order of a capture's integer fields
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.big {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+/// A pcapng interface: link type and timestamp ticks per second
+struct Interface {
+    link_type: u32,
+    ticks_per_second: u64,
+}
+
+impl Interface {
+    fn from_description(endian: Endian, body: &[u8]) -> Option<Self> {
+        let mut interface = Interface {
+            link_type: u32::from(endian.u16(body.get(..2)?)),
+            ticks_per_second: 1_000_000,
+        };
+        let mut options = body.get(8..)?;
+        while options.len() >= 4 {
+            let (code, len) = (endian.u16(options), usize::from(endian.u16(&options[2..])));
+            let value = options.get(4..4 + len)?;
+            if code == PCAPNG_OPTION_TSRESOL && len == 1 {
+                let exponent = u32::from(value[0] & 0x7F);
+                let base: u64 = if value[0] & 0x80 != 0 { 2 } else { 10 };
+                interface.ticks_per_second = base.checked_pow(exponent)?;
+            }
+            if code == 0 {
+                break;
+            }
+            options = options
+                .get(4 + len.next_multiple_of(4)..)
+                .unwrap_or_default();
+        }
+        Some(interface)
+    }
+
+    fn micros(&self, ticks: u64) -> i64 {
+        let micros = u128::from(ticks) * 1_000_000 / u128::from(self.ticks_per_second);
+        i64::try_from(micros).unwrap_or(i64::MAX)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Sniffer capture import into a [`BleCube`]
+pub trait Pcap {
+    /// Insert every advertising PDU with an RSSI in a pcap or pcapng capture
+    /// from a BLE sniffer (nRF Sniffer for Bluetooth LE, Ubertooth,
+    /// btlejack), heard at `receiver`'s position, keeping each record's
+    /// channel and CRC result ([`PcapImport::link_layer`]). Packets failing
+    /// their CRC are inserted and flagged; their address and payload may be
+    /// corrupt.
+    /// Timestamps come from the capture, converted to the cube's time unit
+    /// (seconds if none is declared).
+    ///
+    /// Fails on read errors, a bad file header or an implausibly long
+    /// record, or a failed write-ahead log append.
+    fn import_pcap<R: Read>(&mut self, reader: R, receiver: &HciReceiver)
+        -> io::Result<PcapImport>;
+}
+
+impl Pcap for BleCube {
+    fn import_pcap<R: Read>(
+        &mut self,
+        mut reader: R,
+        receiver: &HciReceiver,
+    ) -> io::Result<PcapImport> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if u32::from_le_bytes(magic) == PCAPNG_SECTION_HEADER {
+            import_pcapng(self, reader, receiver)
+        } else {
+            import_classic_pcap(self, reader, magic, receiver)
+        }
+    }
+}
+
+fn import_classic_pcap<R: Read>(
+    cube: &mut BleCube,
+    mut reader: R,
+    magic: [u8; 4],
+    receiver: &HciReceiver,
+) -> io::Result<PcapImport> {
+    let (endian, nanos) = match u32::from_le_bytes(magic) {
+        0xA1B2_C3D4 => (Endian { big: false }, false),
+        0xA1B2_3C4D => (Endian { big: false }, true),
+        0xD4C3_B2A1 => (Endian { big: true }, false),
+        0x4D3C_B2A1 => (Endian { big: true }, true),
+        _ => return Err(invalid("not a pcap or pcapng capture")),
+    };
+    let mut header = [0u8; 20];
+    reader.read_exact(&mut header)?;
+    let link_type = endian.u32(&header[16..]) & 0x0FFF_FFFF;
+
+    let mut report = PcapImport::default();
+    let mut packet = Vec::new();
+    loop {
+        let mut record = [0u8; 16];
+        match read_full(&mut reader, &mut record)? {
+            0 => return Ok(report),
+            16 => {}
+            _ => {
+                report.truncated = true;
+                return Ok(report);
+            }
+        }
+        let len = endian.u32(&record[8..]) as usize;
+        if len > MAX_CAPTURE_RECORD {
+            return Err(invalid("pcap record too long"));
+        }
+        packet.resize(len, 0);
+        if read_full(&mut reader, &mut packet)? < len {
+            report.truncated = true;
+            return Ok(report);
+        }
+        let fraction = i64::from(endian.u32(&record[4..]));
+        let micros = i64::from(endian.u32(&record[..4])) * 1_000_000
+            + if nanos { fraction / 1_000 } else { fraction };
+        import_sniffer_packet(cube, link_type, &packet, micros, receiver, &mut report)?;
+    }
+}
+
+fn import_pcapng<R: Read>(
+    cube: &mut BleCube,
+    mut reader: R,
+    receiver: &HciReceiver,
+) -> io::Result<PcapImport> {
+    let mut report = PcapImport::default();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    // The section header's type was read by `import_pcap`
+    let mut block_type = PCAPNG_SECTION_HEADER;
+    let mut endian = Endian { big: false };
+    let mut block = Vec::new();
+    loop {
+        let mut length = [0u8; 4];
+        if read_full(&mut reader, &mut length)? < 4 {
+            report.truncated = true;
+            return Ok(report);
+        }
+        if block_type == PCAPNG_SECTION_HEADER {
+            // The byte-order magic decides how to read the block length
+            let mut order = [0u8; 4];
+            reader.read_exact(&mut order)?;
+            endian = match u32::from_le_bytes(order) {
+                PCAPNG_BYTE_ORDER_MAGIC => Endian { big: false },
+                magic if magic.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => Endian { big: true },
+                _ => return Err(invalid("bad pcapng byte-order magic")),
+            };
+            interfaces.clear();
+        }
+        let total = endian.u32(&length) as usize;
+        let consumed = if block_type == PCAPNG_SECTION_HEADER {
+            12
+        } else {
+            8
+        };
+        if total > MAX_CAPTURE_RECORD || total < consumed + 4 || !total.is_multiple_of(4) {
+            return Err(invalid("bad pcapng block length"));
+        }
+        // Body plus trailing length copy
+        block.resize(total - consumed, 0);
+        if read_full(&mut reader, &mut block)? < block.len() {
+            report.truncated = true;
+            return Ok(report);
+        }
+        let body = &block[..block.len() - 4];
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => match Interface::from_description(endian, body) {
+                Some(interface) => interfaces.push(interface),
+                None => return Err(invalid("bad pcapng interface description")),
+            },
+            PCAPNG_ENHANCED_PACKET => {
+                let packet = (body.len() >= 20)
+                    .then(|| {
+                        let interface = interfaces.get(endian.u32(body) as usize)?;
+                        let len = endian.u32(&body[12..]) as usize;
+                        let ticks = u64::from(endian.u32(&body[4..])) << 32
+                            | u64::from(endian.u32(&body[8..]));
+                        Some((interface, body.get(20..20 + len)?, ticks))
+                    })
+                    .flatten();
+                match packet {
+                    Some((interface, packet, ticks)) => {
+                        let (link_type, micros) = (interface.link_type, interface.micros(ticks));
+                        import_sniffer_packet(
+                            cube,
+                            link_type,
+                            packet,
+                            micros,
+                            receiver,
+                            &mut report,
+                        )?;
+                    }
+                    None => {
+                        report.packets += 1;
+                        report.rejected += 1;
+                    }
+                }
+            }
+            // Statistics, name resolution, simple packets (no timestamp), ...
+            _ => {}
+        }
+
+        let mut next = [0u8; 4];
+        match read_full(&mut reader, &mut next)? {
+            0 => return Ok(report),
+            4 => block_type = endian.u32(&next),
+            _ => {
+                report.truncated = true;
+                return Ok(report);
+            }
+        }
+        // A new section may switch byte order; its type reads the same
+        if u32::from_le_bytes(next) == PCAPNG_SECTION_HEADER {
+            block_type = PCAPNG_SECTION_HEADER;
+        }
+    }
+}
+
+fn import_sniffer_packet(
+    cube: &mut BleCube,
+    link_type: u32,
+    packet: &[u8],
+    micros: i64,
+    receiver: &HciReceiver,
+    report: &mut PcapImport,
+) -> io::Result<()> {
+    report.packets += 1;
+    let sniffed = match decode_packet(link_type, packet) {
+        Ok(Some(sniffed)) => sniffed,
+        Ok(None) => return Ok(()),
+        Err(()) => {
+            report.rejected += 1;
+            return Ok(());
+        }
+    };
+    report.advertisements += 1;
+    let unit = cube.time_unit().unwrap_or(TimeUnit::Seconds);
+    let timestamp = Timestamp::from_micros(micros).to_unit(unit);
+    let Some(mut obs) = receiver.observation(&sniffed.report, timestamp) else {
+        return Ok(());
+    };
+    obs.quality = sniffed.link.crc_valid.map(BleObservation::quality_from_crc);
+    match cube.try_insert_advertisement(obs, &sniffed.report.data) {
+        Ok(record_id) => {
+            report.inserted += 1;
+            if sniffed.link.crc_valid == Some(false) {
+                report.crc_errors += 1;
+            }
+            if let Some(id) = cube.stable_id(record_id) {
+                report.links.insert(id, sniffed.link);
+            }
+            Ok(())
+        }
+        Err(CubeError::TimeUnit { .. } | CubeError::Invalid { .. }) => {
+            report.rejected += 1;
+            Ok(())
+        }
+        Err(CubeError::RateLimited) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::internal::SplitMix64;
+    use ble_cube_core::{UnitMismatch, ValidationPolicy, ValidationRules};
+
+    /// ADV_IND from random address C1:22:33:44:55:66 with flags and a name
+    fn adv_ind() -> Vec<u8> {
+        let mut pdu = ADVERTISING_ACCESS_ADDRESS.to_le_bytes().to_vec();
+        let data = [0x02, 0x01, 0x06, 0x04, 0x09, b'T', b'a', b'g'];
+        pdu.extend([0x40, (6 + data.len()) as u8]);
+        pdu.extend([0x66, 0x55, 0x44, 0x33, 0x22, 0xC1]);
+        pdu.extend(data);
+        pdu.extend([0xAA, 0xBB, 0xCC]); // CRC
+        pdu
+    }
+
+    fn nordic(channel: u8, rssi_magnitude: u8, crc_ok: bool, pdu: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0, pdu.len() as u8 + 10, 0, 3, 0, 0, 2, 10];
+        packet.extend([u8::from(crc_ok), channel, rssi_magnitude, 0, 0, 0, 0, 0, 0]);
+        packet.extend(pdu);
+        packet
+    }
+
+    fn phdr(rf_channel: u8, rssi: i8, flags: u16, pdu: &[u8]) -> Vec<u8> {
+        let mut packet = vec![rf_channel, rssi as u8, 0, 0, 0, 0, 0, 0];
+        packet.extend(flags.to_le_bytes());
+        packet.extend(pdu);
+        packet
+    }
+
+    fn classic_pcap(link_type: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = 0xA1B2_C3D4u32.to_le_bytes().to_vec();
+        file.extend([2, 0, 4, 0]);
+        file.extend([0; 12]);
+        file.extend(link_type.to_le_bytes());
+        for (i, packet) in packets.iter().enumerate() {
+            file.extend((1_700_000_000 + i as u32).to_le_bytes());
+            file.extend(250_000u32.to_le_bytes());
+            file.extend((packet.len() as u32).to_le_bytes());
+            file.extend((packet.len() as u32).to_le_bytes());
+            file.extend(packet);
+        }
+        file
+    }
+
+    /// Big-endian pcapng, nanosecond interface, one enhanced packet block
+    fn pcapng(link_type: u16, packet: &[u8]) -> Vec<u8> {
+        let block = |block_type: u32, body: &[u8]| {
+            let total = (12 + body.len().next_multiple_of(4)) as u32;
+            let mut block = block_type.to_be_bytes().to_vec();
+            block.extend(total.to_be_bytes());
+            block.extend(body);
+            block.resize(total as usize - 4, 0);
+            block.extend(total.to_be_bytes());
+            block
+        };
+        let mut section = PCAPNG_BYTE_ORDER_MAGIC.to_be_bytes().to_vec();
+        section.extend([0, 1, 0, 0]);
+        section.extend(u64::MAX.to_be_bytes());
+        let mut interface = link_type.to_be_bytes().to_vec();
+        interface.extend([0, 0, 0, 0, 0, 0]);
+        interface.extend(PCAPNG_OPTION_TSRESOL.to_be_bytes());
+        interface.extend([0, 1, 9, 0, 0, 0, 0, 0, 0, 0]);
+        let ticks: u64 = 1_700_000_000 * 1_000_000_000;
+        let mut enhanced = 0u32.to_be_bytes().to_vec();
+        enhanced.extend(((ticks >> 32) as u32).to_be_bytes());
+        enhanced.extend((ticks as u32).to_be_bytes());
+        enhanced.extend((packet.len() as u32).to_be_bytes());
+        enhanced.extend((packet.len() as u32).to_be_bytes());
+        enhanced.extend(packet);
+
+        let mut file = block(PCAPNG_SECTION_HEADER, &section);
+        file.extend(block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+        file.extend(block(PCAPNG_ENHANCED_PACKET, &enhanced));
+        file
+    }
+
+    #[test]
+    fn test_import_nrf_sniffer_pcap() {
+        let receiver = HciReceiver::at(37.0, -122.0);
+        let mut scan_request = adv_ind();
+        scan_request[4] = 0x43;
+        let mut data_channel = adv_ind();
+        data_channel[0] = 0x01;
+        let file = classic_pcap(
+            LINKTYPE_NORDIC_BLE,
+            &[
+                nordic(37, 58, true, &adv_ind()),
+                nordic(39, 80, false, &adv_ind()),
+                nordic(38, 60, true, &scan_request),
+                nordic(5, 60, true, &data_channel),
+                vec![0; 4],
+            ],
+        );
+        let mut cube = BleCube::new();
+        let report = cube.import_pcap(&file[..], &receiver).unwrap();
+        assert_eq!(
+            (
+                report.packets,
+                report.advertisements,
+                report.inserted,
+                report.crc_errors,
+                report.rejected,
+                report.truncated
+            ),
+            (5, 2, 2, 1, 1, false)
+        );
+        let obs = cube.get(0).unwrap();
+        assert_eq!(obs.mac, [0xC1, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        assert_eq!((obs.rssi, obs.timestamp), (-58, 1_700_000_000));
+        assert_eq!(cube.advertisement(0).map(<[u8]>::len), Some(8));
+        assert_eq!(
+            report.link_layer(RecordId::from_u64(0)),
+            Some(LinkLayerInfo {
+                channel: 37,
+                crc_valid: Some(true),
+            })
+        );
+        let link = report.link_layer(RecordId::from_u64(1)).unwrap();
+        assert_eq!(link.crc_valid, Some(false));
+        assert_eq!(cube.get(1).unwrap().quality, Some(0));
+        assert_eq!(report.links.len(), 2);
+
+        // An observation the validation policy refuses is counted, and the
+        // import carries on
+        let mut cube = BleCube::new();
+        let rules = ValidationRules {
+            rssi_range: Some((-70, 10)),
+            ..Default::default()
+        };
+        cube.set_validation(rules, ValidationPolicy::Reject);
+        let report = cube.import_pcap(&file[..], &receiver).unwrap();
+        assert_eq!((report.inserted, report.rejected), (1, 2));
+        assert_eq!(cube.get(0).unwrap().rssi, -58);
+    }
+
+    #[test]
+    fn test_import_ubertooth_pcapng() {
+        let receiver = HciReceiver::at(37.0, -122.0);
+        // RF channel 12 is advertising channel 38; signal valid, CRC unchecked
+        let file = pcapng(
+            LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR as u16,
+            &phdr(12, -71, PHDR_SIGNAL_VALID, &adv_ind()),
+        );
+        let mut cube = BleCube::new();
+        cube.set_time_unit(TimeUnit::Milliseconds, UnitMismatch::Reject);
+        let report = cube.import_pcap(&file[..], &receiver).unwrap();
+        assert_eq!((report.inserted, report.truncated), (1, false));
+        assert_eq!(cube.get(0).unwrap().timestamp, 1_700_000_000_000);
+        assert_eq!(
+            report.link_layer(cube.stable_id(0).unwrap()),
+            Some(LinkLayerInfo {
+                channel: 38,
+                crc_valid: None,
+            })
+        );
+
+        // Without a valid signal reading there is nothing to insert
+        let file = pcapng(
+            LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR as u16,
+            &phdr(0, 0, PHDR_CRC_CHECKED | PHDR_CRC_VALID, &adv_ind()),
+        );
+        let report = cube.import_pcap(&file[..], &receiver).unwrap();
+        assert_eq!((report.advertisements, report.inserted), (1, 0));
+
+        let cut = &file[..file.len() - 6];
+        assert!(cube.import_pcap(cut, &receiver).unwrap().truncated);
+        assert!(cube.import_pcap(&b"btsnoop\0"[..], &receiver).is_err());
+    }
+
+    #[test]
+    fn test_link_layer_follows_compaction() {
+        let receiver = HciReceiver::at(37.0, -122.0);
+        let packets: Vec<Vec<u8>> = (0..3)
+            .map(|i| nordic(37 + i, 60, i != 1, &adv_ind()))
+            .collect();
+        let mut cube = BleCube::new();
+        cube.set_time_partition_width(1);
+        let report = cube
+            .import_pcap(&classic_pcap(LINKTYPE_NORDIC_BLE, &packets)[..], &receiver)
+            .unwrap();
+        cube.evict_partitions_before(1_700_000_001).unwrap();
+        let channel = |record_id| {
+            let id = cube.stable_id(record_id)?;
+            Some(report.link_layer(id)?.channel)
+        };
+        assert_eq!(
+            (channel(0), channel(1), channel(2)),
+            (Some(38), Some(39), None)
+        );
+    }
+
+    #[test]
+    fn test_random_captures_never_panic() {
+        let receiver = HciReceiver::at(37.0, -122.0);
+        let valid = [
+            classic_pcap(LINKTYPE_NORDIC_BLE, &[nordic(37, 60, true, &adv_ind())]),
+            pcapng(
+                LINKTYPE_BLUETOOTH_LE_LL_WITH_PHDR as u16,
+                &phdr(0, -60, PHDR_SIGNAL_VALID, &adv_ind()),
+            ),
+        ];
+        let mut rng = SplitMix64(3);
+        let mut cube = BleCube::new();
+        for round in 0..5_000 {
+            let mut file = valid[round % 2].clone();
+            for _ in 0..1 + round % 4 {
+                let at = rng.next_u64() as usize % file.len();
+                file[at] = rng.next_u64() as u8;
+            }
+            if round % 5 == 0 {
+                file.truncate(rng.next_u64() as usize % file.len());
+            }
+            let _ = cube.import_pcap(&file[..], &receiver);
+        }
+    }
+}
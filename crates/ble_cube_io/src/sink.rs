@@ -0,0 +1,172 @@
+//! File sinks for the insert tee.
+//!
+//! [`CsvSink`] and [`JsonlSink`] implement [`ObservationSink`], so
+//! [`BleCube::add_sink`](ble_cube_core::BleCube::add_sink) archives a live capture to disk while it is
+//! indexed.
+
+use ble_cube_core::{BleObservation, MacAddr, ObservationSink};
+use std::io::{self, Write};
+
+/// Header row of the observation CSV format
+pub const CSV_HEADER: &str = "mac,rssi,timestamp,lat,lon,receiver_id,floor,tx_power,quality";
+
+/// Writes observations as CSV rows (the format the `ble_cube` CLI loads)
+#[derive(Debug)]
+pub struct CsvSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Start a CSV stream on `writer`, writing the header row. Wrap files in
+    /// a `BufWriter`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{CSV_HEADER}")?;
+        Ok(Self { writer })
+    }
+
+    /// The underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send + Sync> ObservationSink for CsvSink<W> {
+    fn write(&mut self, obs: &BleObservation) -> io::Result<()> {
+        write_csv_row(&mut self.writer, obs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes observations as JSON Lines (see [`Jsonl::export_jsonl`](crate::Jsonl::export_jsonl))
+#[cfg(feature = "jsonl")]
+#[derive(Debug)]
+pub struct JsonlSink<W: Write> {
+    writer: W,
+}
+
+#[cfg(feature = "jsonl")]
+impl<W: Write> JsonlSink<W> {
+    /// Stream JSON lines to `writer`; wrap files in a `BufWriter`
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// The underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(feature = "jsonl")]
+impl<W: Write + Send + Sync> ObservationSink for JsonlSink<W> {
+    fn write(&mut self, obs: &BleObservation) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, obs)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// One CSV row of `obs`, columns as in [`CSV_HEADER`], absent optional
+/// fields empty
+pub fn write_csv_row<W: Write>(writer: &mut W, obs: &BleObservation) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{},{},{},{},{},{},{},{},{}",
+        MacAddr(obs.mac),
+        obs.rssi,
+        obs.timestamp,
+        obs.lat,
+        obs.lon,
+        optional(obs.receiver_id),
+        optional(obs.floor),
+        optional(obs.tx_power),
+        optional(obs.quality)
+    )
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "jsonl")]
+    use crate::Jsonl;
+    use ble_cube_core::BleCube;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    fn obs(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -60,
+            timestamp,
+            lat: 37.5,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    /// Shared buffer so the test can read what a boxed sink wrote
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sinks_receive_inserts() {
+        let mut cube = BleCube::new();
+        let csv = Shared::default();
+        cube.add_sink(CsvSink::new(csv.clone()).unwrap());
+        let (tx, rx) = mpsc::channel();
+        cube.add_sink(tx);
+
+        cube.insert(obs(1, 100));
+        cube.insert(BleObservation {
+            floor: Some(2),
+            ..obs(2, 200)
+        });
+        cube.flush_sinks().unwrap();
+
+        let text = String::from_utf8(csv.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                CSV_HEADER,
+                "01:01:01:01:01:01,-60,100,37.5,-122,,,,",
+                "02:02:02:02:02:02,-60,200,37.5,-122,,2,,",
+            ]
+        );
+        let sent: Vec<i64> = rx.try_iter().map(|obs| obs.timestamp).collect();
+        assert_eq!(sent, [100, 200]);
+    }
+
+    #[cfg(feature = "jsonl")]
+    #[test]
+    fn test_jsonl_sink_round_trips() {
+        let mut cube = BleCube::new();
+        let jsonl = Shared::default();
+        cube.add_sink(JsonlSink::new(jsonl.clone()));
+        cube.insert(obs(1, 100));
+        cube.insert(obs(2, 200));
+
+        let mut copy = BleCube::new();
+        let bytes = jsonl.0.lock().unwrap().clone();
+        assert_eq!(copy.import_jsonl(&bytes[..]).unwrap().inserted, 2);
+        assert_eq!(copy.get(1).unwrap().mac, [2; 6]);
+    }
+}
@@ -0,0 +1,21 @@
+//! Long-running services around a shared `ble-cube`: a background
+//! maintenance runner, a MAC-sharded cube for multi-threaded ingest, field
+//! survey sessions, and behind a feature an MQTT ingestion bridge.
+//!
+//! | Feature | Default | Module |
+//! |---------|---------|--------|
+//! | `wal`   | no      | survey sessions logged to a write-ahead log (`SurveyConfig::wal_dir`) |
+//! | `jsonl` | no      | JSON Lines export of finished surveys |
+//! | `mqtt`  | no      | MQTT subscriber feeding a shared cube (`MqttBridge`) |
+
+mod maintenance;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod shard;
+mod survey;
+
+pub use maintenance::{Maintenance, MaintenanceStats};
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttBridge, MqttConfig, MqttStats, PayloadFormat};
+pub use shard::ShardedCube;
+pub use survey::{FinishedSurvey, Survey, SurveyConfig, SurveySummary};
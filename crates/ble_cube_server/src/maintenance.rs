@@ -0,0 +1,166 @@
+//! Background maintenance for a cube shared between threads.
+//!
+//! [`Maintenance::start`] runs [`BleCube::run_maintenance`] passes
+//! (retention eviction, R-tree rebuilds, time index packing) on a thread of
+//! its own, every [`MaintenanceConfig::interval`].
+
+use ble_cube_core::{BleCube, MaintenanceConfig};
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+
+/// Runner counters, see [`Maintenance::stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MaintenanceStats {
+    pub passes: u64,
+    pub evicted: u64,
+    pub geo_rebuilds: u64,
+    /// Passes whose eviction checkpoint failed (the runner keeps going)
+    pub errors: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    passes: AtomicU64,
+    evicted: AtomicU64,
+    geo_rebuilds: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> MaintenanceStats {
+        MaintenanceStats {
+            passes: self.passes.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            geo_rebuilds: self.geo_rebuilds.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Background thread running [`BleCube::run_maintenance`] on a shared cube
+/// every interval, holding the write lock for the length of a pass; stops
+/// on [`Maintenance::shutdown`] or when dropped
+#[derive(Debug)]
+pub struct Maintenance {
+    stop: Sender<()>,
+    counters: Arc<Counters>,
+    handle: JoinHandle<()>,
+}
+
+impl Maintenance {
+    /// Start the runner; the first pass happens one interval from now
+    pub fn start(cube: Arc<RwLock<BleCube>>, config: MaintenanceConfig) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let counters = Arc::new(Counters::default());
+        let handle = {
+            let counters = counters.clone();
+            thread::spawn(move || {
+                // Wakes early, and exits, when the sender is used or dropped
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(config.interval) {
+                    let Ok(mut cube) = cube.write() else {
+                        return;
+                    };
+                    match cube.run_maintenance(&config) {
+                        Ok(report) => {
+                            counters
+                                .evicted
+                                .fetch_add(report.evicted as u64, Ordering::Relaxed);
+                            if report.geo_rebuilt {
+                                counters.geo_rebuilds.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        Err(_) => {
+                            counters.errors.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    counters.passes.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        };
+        Self {
+            stop,
+            counters,
+            handle,
+        }
+    }
+
+    /// Counters so far
+    pub fn stats(&self) -> MaintenanceStats {
+        self.counters.snapshot()
+    }
+
+    /// Whether the thread is still running (it exits if the cube's lock is
+    /// poisoned)
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+
+    /// Stop after the pass in progress, if any, and return the final counters
+    pub fn shutdown(self) -> io::Result<MaintenanceStats> {
+        let _ = self.stop.send(());
+        self.handle
+            .join()
+            .map_err(|_| io::Error::other("maintenance thread panicked"))?;
+        Ok(self.counters.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ble_cube_core::BleObservation;
+    use std::time::{Duration, Instant};
+
+    fn obs(i: i64) -> BleObservation {
+        BleObservation {
+            mac: [(i % 5) as u8; 6],
+            rssi: -60,
+            timestamp: i * 60,
+            lat: 37.0 + (i % 40) as f64 * 1e-4,
+            lon: -122.0 + (i / 40) as f64 * 1e-4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_runner_maintains_shared_cube() {
+        let cube = Arc::new(RwLock::new(BleCube::new()));
+        for i in 0..100 {
+            cube.write().unwrap().insert(obs(i));
+        }
+        let runner = Maintenance::start(
+            cube.clone(),
+            MaintenanceConfig::new(Duration::from_millis(5)),
+        );
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while runner.stats().geo_rebuilds == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(runner.is_running());
+        // The rebuild left no churn behind
+        let eager = MaintenanceConfig {
+            geo_rebuild_ratio: 0.0,
+            ..MaintenanceConfig::new(Duration::from_secs(60))
+        };
+        assert!(
+            !cube
+                .write()
+                .unwrap()
+                .run_maintenance(&eager)
+                .unwrap()
+                .geo_rebuilt
+        );
+        let stats = runner.shutdown().unwrap();
+        assert!(stats.passes >= 1);
+        assert_eq!((stats.geo_rebuilds, stats.errors), (1, 0));
+
+        // Shutdown does not wait out a long interval
+        let started = Instant::now();
+        let runner = Maintenance::start(cube, MaintenanceConfig::new(Duration::from_secs(3600)));
+        assert_eq!(runner.shutdown().unwrap().passes, 0);
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+}
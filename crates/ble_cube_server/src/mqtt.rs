@@ -0,0 +1,617 @@
+//! MQTT ingestion bridge.
+//!
+//! Subscribes to a broker topic (MQTT 3.1.1 over plain TCP, QoS 0/1) and
+//! inserts decoded observations into a shared cube — the usual layout where
+//! ESP32 scanners publish to a gateway-side broker. A minimal client is
+//! implemented here rather than pulling in an async MQTT stack.
+//!
+//! Two threads per bridge: a network thread that reads packets, answers
+//! keep-alives and decodes payloads, and an ingest thread that batches
+//! observations and takes the cube's write lock once per batch. They are
+//! connected by a bounded queue; when the cube cannot keep up, the network
+//! thread blocks on it and stops reading the socket, so TCP flow control
+//! pushes back on the broker instead of buffering without bound here.
+
+use ble_cube_core::{BleCube, BleObservation};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+/// Largest remaining length MQTT can encode (4 length bytes)
+const MAX_PACKET_LEN: usize = 268_435_455;
+/// How often blocked reads wake up to check for shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Encoding of message payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadFormat {
+    /// JSON if the payload starts with `{` or `[`, CBOR otherwise
+    #[default]
+    Auto,
+    Json,
+    /// CBOR; MACs as a 6-byte byte string or an array of 6 integers
+    Cbor,
+}
+
+/// Broker connection and batching options
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    /// Topic filter, wildcards allowed (`scanners/+/ble`)
+    pub topic: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Ping interval the broker uses to detect a dead client
+    pub keep_alive: Duration,
+    pub format: PayloadFormat,
+    /// Insert once this many observations are queued...
+    pub batch_size: usize,
+    /// ...or once the oldest queued observation is this old
+    pub flush_interval: Duration,
+    /// Decoded observations buffered between the network and ingest threads
+    pub queue_capacity: usize,
+}
+
+impl MqttConfig {
+    /// Defaults: port 1883, 30s keep-alive, batches of 256 or every 100ms
+    pub fn new(host: &str, topic: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port: 1883,
+            topic: topic.to_string(),
+            client_id: format!("ble-cube-{}", std::process::id()),
+            username: None,
+            password: None,
+            keep_alive: Duration::from_secs(30),
+            format: PayloadFormat::Auto,
+            batch_size: 256,
+            flush_interval: Duration::from_millis(100),
+            queue_capacity: 8192,
+        }
+    }
+}
+
+/// Bridge counters, see [`MqttBridge::stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MqttStats {
+    /// PUBLISH packets received
+    pub messages: u64,
+    /// Payloads that failed to decode
+    pub decode_errors: u64,
+    pub inserted: u64,
+    /// Observations the cube refused (e.g. time-unit policy or a rate limit)
+    pub insert_errors: u64,
+    /// Write-lock acquisitions by the ingest thread
+    pub batches: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    messages: AtomicU64,
+    decode_errors: AtomicU64,
+    inserted: AtomicU64,
+    insert_errors: AtomicU64,
+    batches: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> MqttStats {
+        MqttStats {
+            messages: self.messages.load(Ordering::Relaxed),
+            decode_errors: self.decode_errors.load(Ordering::Relaxed),
+            inserted: self.inserted.load(Ordering::Relaxed),
+            insert_errors: self.insert_errors.load(Ordering::Relaxed),
+            batches: self.batches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Running subscription feeding a shared cube; stops on [`MqttBridge::shutdown`]
+/// or when the broker closes the connection
+#[derive(Debug)]
+pub struct MqttBridge {
+    stream: TcpStream,
+    stop: Arc<AtomicBool>,
+    counters: Arc<Counters>,
+    network: JoinHandle<io::Result<()>>,
+    ingest: JoinHandle<()>,
+}
+
+impl MqttBridge {
+    /// Connect, subscribe, and start ingesting into `cube`. Returns once the
+    /// broker has acknowledged the subscription.
+    pub fn connect(cube: Arc<RwLock<BleCube>>, config: MqttConfig) -> io::Result<Self> {
+        let mut stream = TcpStream::connect((config.host.as_str(), config.port))?;
+        stream.set_nodelay(true)?;
+        let mut conn = Connection::new(stream.try_clone()?);
+
+        stream.write_all(&connect_packet(&config))?;
+        let (header, body) = conn.expect_packet(Instant::now() + handshake_timeout(&config))?;
+        if header & 0xF0 != CONNACK || body.len() < 2 {
+            return Err(protocol_error("expected CONNACK"));
+        }
+        if body[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("broker refused connection (return code {})", body[1]),
+            ));
+        }
+
+        stream.write_all(&subscribe_packet(&config.topic))?;
+        let (header, body) = conn.expect_packet(Instant::now() + handshake_timeout(&config))?;
+        if header != SUBACK || body.len() < 3 || body[2] == 0x80 {
+            return Err(protocol_error("subscription rejected"));
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(Counters::default());
+        let (tx, rx) = mpsc::sync_channel(config.queue_capacity.max(1));
+
+        let network = {
+            let (stop, counters, config) = (stop.clone(), counters.clone(), config.clone());
+            thread::spawn(move || conn.run(&config, &tx, &stop, &counters))
+        };
+        let ingest = {
+            let counters = counters.clone();
+            thread::spawn(move || ingest(&cube, &rx, &config, &counters))
+        };
+
+        Ok(Self {
+            stream,
+            stop,
+            counters,
+            network,
+            ingest,
+        })
+    }
+
+    /// Counters so far
+    pub fn stats(&self) -> MqttStats {
+        self.counters.snapshot()
+    }
+
+    /// Whether the connection is still up
+    pub fn is_running(&self) -> bool {
+        !self.network.is_finished()
+    }
+
+    /// Disconnect, flush queued observations into the cube, and return the
+    /// final counters, or the error that ended the connection early
+    pub fn shutdown(mut self) -> io::Result<MqttStats> {
+        self.stop.store(true, Ordering::Relaxed);
+        // Best effort: the broker may already be gone
+        let _ = self.stream.write_all(&[DISCONNECT, 0]);
+        let _ = self.stream.shutdown(Shutdown::Both);
+
+        let network = self
+            .network
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("MQTT network thread panicked")));
+        self.ingest
+            .join()
+            .map_err(|_| io::Error::other("MQTT ingest thread panicked"))?;
+        network.map(|()| self.counters.snapshot())
+    }
+}
+
+fn handshake_timeout(config: &MqttConfig) -> Duration {
+    config.keep_alive.max(Duration::from_secs(10))
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("MQTT: {msg}"))
+}
+
+// ========== NETWORK THREAD ==========
+
+/// Socket plus a buffer of bytes not yet forming a complete packet, so a read
+/// timeout in the middle of a packet never loses framing
+struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Pop one complete packet off the buffer: (fixed header byte, body)
+    fn take_packet(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let Some(&header) = self.buf.first() else {
+            return Ok(None);
+        };
+        let Some((len, len_bytes)) = decode_remaining_length(&self.buf[1..])? else {
+            return Ok(None);
+        };
+        let start = 1 + len_bytes;
+        if self.buf.len() < start + len {
+            return Ok(None);
+        }
+        let body = self.buf[start..start + len].to_vec();
+        self.buf.drain(..start + len);
+        Ok(Some((header, body)))
+    }
+
+    /// Next packet, reading from the socket as needed; `Ok(None)` when the
+    /// read timed out first
+    fn next_packet(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        if let Some(packet) = self.take_packet()? {
+            return Ok(Some(packet));
+        }
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                self.take_packet()
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Block until a packet arrives or `deadline` passes
+    fn expect_packet(&mut self, deadline: Instant) -> io::Result<(u8, Vec<u8>)> {
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        loop {
+            if let Some(packet) = self.next_packet()? {
+                return Ok(packet);
+            }
+            if Instant::now() >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+        }
+    }
+
+    fn run(
+        mut self,
+        config: &MqttConfig,
+        tx: &SyncSender<BleObservation>,
+        stop: &AtomicBool,
+        counters: &Counters,
+    ) -> io::Result<()> {
+        let ping_every = (config.keep_alive / 2).max(POLL_INTERVAL);
+        self.stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        let mut last_ping = Instant::now();
+
+        while !stop.load(Ordering::Relaxed) {
+            let packet = match self.next_packet() {
+                Ok(packet) => packet,
+                // Our own shutdown closes the socket under us
+                Err(_) if stop.load(Ordering::Relaxed) => break,
+                Err(e) => return Err(e),
+            };
+            if !config.keep_alive.is_zero() && last_ping.elapsed() >= ping_every {
+                self.stream.write_all(&[PINGREQ, 0])?;
+                last_ping = Instant::now();
+            }
+            let Some((header, body)) = packet else {
+                continue;
+            };
+
+            match header & 0xF0 {
+                PUBLISH => {
+                    let (packet_id, payload) = parse_publish(header, &body)?;
+                    if let Some(packet_id) = packet_id {
+                        let [hi, lo] = packet_id.to_be_bytes();
+                        self.stream.write_all(&[PUBACK, 2, hi, lo])?;
+                    }
+                    counters.messages.fetch_add(1, Ordering::Relaxed);
+                    match decode_payload(payload, config.format) {
+                        Ok(observations) => {
+                            for obs in observations {
+                                if tx.send(obs).is_err() {
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        Err(_) => {
+                            counters.decode_errors.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+                PINGRESP | SUBACK => {}
+                _ => return Err(protocol_error("unexpected packet")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// (packet ID if QoS > 0, payload) of a PUBLISH body
+fn parse_publish(header: u8, body: &[u8]) -> io::Result<(Option<u16>, &[u8])> {
+    let malformed = || protocol_error("malformed PUBLISH");
+    let topic_len = usize::from(u16::from_be_bytes([
+        *body.first().ok_or_else(malformed)?,
+        *body.get(1).ok_or_else(malformed)?,
+    ]));
+    let mut pos = 2 + topic_len;
+    let qos = (header >> 1) & 0x03;
+    let packet_id = if qos > 0 {
+        let id = body.get(pos..pos + 2).ok_or_else(malformed)?;
+        pos += 2;
+        Some(u16::from_be_bytes([id[0], id[1]]))
+    } else {
+        None
+    };
+    if qos > 1 {
+        return Err(protocol_error("QoS 2 is not supported"));
+    }
+    Ok((packet_id, body.get(pos..).ok_or_else(malformed)?))
+}
+
+/// A payload holds one observation or an array of them
+fn decode_payload(payload: &[u8], format: PayloadFormat) -> Result<Vec<BleObservation>, String> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(BleObservation),
+        Many(Vec<BleObservation>),
+    }
+
+    let json = match format {
+        PayloadFormat::Json => true,
+        PayloadFormat::Cbor => false,
+        PayloadFormat::Auto => matches!(
+            payload.iter().find(|b| !b.is_ascii_whitespace()),
+            Some(b'{' | b'[')
+        ),
+    };
+    let decoded: OneOrMany = if json {
+        serde_json::from_slice(payload).map_err(|e| e.to_string())?
+    } else {
+        ciborium::from_reader(payload).map_err(|e| e.to_string())?
+    };
+    Ok(match decoded {
+        OneOrMany::One(obs) => vec![obs],
+        OneOrMany::Many(observations) => observations,
+    })
+}
+
+// ========== INGEST THREAD ==========
+
+fn ingest(
+    cube: &RwLock<BleCube>,
+    rx: &Receiver<BleObservation>,
+    config: &MqttConfig,
+    counters: &Counters,
+) {
+    let batch_size = config.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut deadline = None;
+    loop {
+        let timeout = deadline.map_or(config.flush_interval, |d: Instant| {
+            d.saturating_duration_since(Instant::now())
+        });
+        let disconnected = match rx.recv_timeout(timeout) {
+            Ok(obs) => {
+                deadline.get_or_insert_with(|| Instant::now() + config.flush_interval);
+                batch.push(obs);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        let due = deadline.is_some_and(|d| Instant::now() >= d);
+        if !batch.is_empty() && (batch.len() >= batch_size || due || disconnected) {
+            let Ok(mut cube) = cube.write() else {
+                return;
+            };
+            for obs in batch.drain(..) {
+                match cube.try_insert(obs) {
+                    Ok(_) => counters.inserted.fetch_add(1, Ordering::Relaxed),
+                    Err(_) => counters.insert_errors.fetch_add(1, Ordering::Relaxed),
+                };
+            }
+            counters.batches.fetch_add(1, Ordering::Relaxed);
+            deadline = None;
+        }
+        if disconnected {
+            return;
+        }
+    }
+}
+
+// ========== PACKET ENCODING ==========
+
+fn encode_remaining_length(mut len: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            return;
+        }
+    }
+}
+
+/// (length, bytes used), `None` if more bytes are needed
+fn decode_remaining_length(buf: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let mut len = 0;
+    for (i, &byte) in buf.iter().enumerate().take(4) {
+        len |= usize::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((len, i + 1)));
+        }
+    }
+    if buf.len() >= 4 {
+        return Err(protocol_error("remaining length exceeds 4 bytes"));
+    }
+    Ok(None)
+}
+
+fn push_str(s: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    debug_assert!(body.len() <= MAX_PACKET_LEN);
+    let mut out = vec![header];
+    encode_remaining_length(body.len(), &mut out);
+    out.extend_from_slice(body);
+    out
+}
+
+fn connect_packet(config: &MqttConfig) -> Vec<u8> {
+    let mut flags = 0x02; // clean session
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+    let keep_alive = config.keep_alive.as_secs().min(u64::from(u16::MAX)) as u16;
+
+    let mut body = Vec::new();
+    push_str("MQTT", &mut body);
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&keep_alive.to_be_bytes());
+    push_str(&config.client_id, &mut body);
+    for field in [&config.username, &config.password].into_iter().flatten() {
+        push_str(field, &mut body);
+    }
+    packet(CONNECT, &body)
+}
+
+fn subscribe_packet(topic: &str) -> Vec<u8> {
+    let mut body = SUBSCRIBE_PACKET_ID.to_be_bytes().to_vec();
+    push_str(topic, &mut body);
+    body.push(1); // max QoS 1
+    packet(SUBSCRIBE, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn publish(topic: &str, payload: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_str(topic, &mut body);
+        body.extend_from_slice(payload);
+        packet(PUBLISH, &body)
+    }
+
+    #[test]
+    fn test_remaining_length_round_trip() {
+        for len in [0, 127, 128, 16_383, 16_384, MAX_PACKET_LEN] {
+            let mut buf = Vec::new();
+            encode_remaining_length(len, &mut buf);
+            assert_eq!(
+                decode_remaining_length(&buf).unwrap(),
+                Some((len, buf.len()))
+            );
+        }
+        assert_eq!(decode_remaining_length(&[0x80]).unwrap(), None);
+        assert!(decode_remaining_length(&[0xFF; 4]).is_err());
+    }
+
+    #[test]
+    fn test_bridge_ingests_json_and_cbor() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Minimal broker: acknowledge, publish, then wait for DISCONNECT
+        let broker = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = Connection::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            let deadline = Instant::now() + Duration::from_secs(5);
+
+            let (header, _) = conn.expect_packet(deadline).unwrap();
+            assert_eq!(header, CONNECT);
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+            let (header, body) = conn.expect_packet(deadline).unwrap();
+            assert_eq!(header, SUBSCRIBE);
+            stream
+                .write_all(&packet(SUBACK, &[body[0], body[1], 1]))
+                .unwrap();
+
+            let one = br#"{"rssi":-60,"mac":"AA:BB:CC:DD:EE:01","timestamp":1,"lat":0,"lon":0}"#;
+            let many = br#"[{"rssi":-61,"mac":"AA:BB:CC:DD:EE:02","timestamp":2,"lat":0,"lon":0},
+                {"rssi":-62,"mac":"AA:BB:CC:DD:EE:03","timestamp":3,"lat":0,"lon":0}]"#;
+            let mut cbor = Vec::new();
+            ciborium::into_writer(
+                &BleObservation {
+                    rssi: -63,
+                    mac: [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x04],
+                    timestamp: 4,
+                    ..Default::default()
+                },
+                &mut cbor,
+            )
+            .unwrap();
+            for payload in [&one[..], &many[..], b"garbage", &cbor] {
+                stream
+                    .write_all(&publish("scanners/1/ble", payload))
+                    .unwrap();
+            }
+
+            loop {
+                match conn.expect_packet(deadline) {
+                    Ok((DISCONNECT, _)) | Err(_) => return,
+                    Ok(_) => {}
+                }
+            }
+        });
+
+        let cube = Arc::new(RwLock::new(BleCube::new()));
+        let mut config = MqttConfig::new("127.0.0.1", "scanners/+/ble");
+        config.port = port;
+        config.batch_size = 2;
+        config.flush_interval = Duration::from_millis(20);
+        let bridge = MqttBridge::connect(cube.clone(), config).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while bridge.stats().inserted < 4 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(bridge.is_running());
+        let stats = bridge.shutdown().unwrap();
+        broker.join().unwrap();
+
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.decode_errors, 1);
+        assert_eq!(stats.inserted, 4);
+        let cube = cube.read().unwrap();
+        assert_eq!(cube.len(), 4);
+        assert_eq!(
+            cube.query_mac([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0x04]).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! MAC-sharded cube for multi-threaded ingest.
+//!
+//! A single [`BleCube`] behind one lock serializes every insert. A
+//! [`ShardedCube`] keeps N cubes, each behind its own lock, and routes every
+//! observation to one of them by a consistent hash of its MAC, so threads
+//! inserting different devices rarely contend and all of a device's records
+//! live in one shard. Queries fan out to every shard (only one for a MAC
+//! query) and merge the matches by timestamp.
+//!
+//! Routing uses jump consistent hashing (Lamping & Veach): building the same
+//! data with one more shard moves only about 1/N of the MACs, so a
+//! re-sharded rebuild mostly leaves devices where they were.
+
+use ble_cube_core::internal::{hash_mac, Reservoir};
+use ble_cube_core::{BleCube, BleObservation, CubeBuilder, MacAddr, Query};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Cubes partitioned by MAC, each behind its own lock, see the
+/// [module docs](self). Share it between ingest threads with an `Arc`.
+///
+/// Methods panic if a shard's lock was poisoned by a panic while writing.
+pub struct ShardedCube {
+    shards: Vec<RwLock<BleCube>>,
+}
+
+impl ShardedCube {
+    /// `shards` empty cubes
+    ///
+    /// # Panics
+    /// Panics if `shards` is zero.
+    pub fn new(shards: usize) -> Self {
+        Self::with_builder(shards, CubeBuilder::default())
+    }
+
+    /// `shards` empty cubes built with the same options
+    ///
+    /// # Panics
+    /// Panics if `shards` is zero.
+    pub fn with_builder(shards: usize, builder: CubeBuilder) -> Self {
+        assert!(shards > 0, "a sharded cube needs at least one shard");
+        Self {
+            shards: (0..shards)
+                .map(|_| RwLock::new(builder.clone().build()))
+                .collect(),
+        }
+    }
+
+    /// Number of shards
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Shard that holds (or will hold) the records of `mac`
+    pub fn shard_for<M: Into<MacAddr>>(&self, mac: M) -> usize {
+        jump_hash(hash_mac(&mac.into().0), self.shards.len())
+    }
+
+    /// Insert into the MAC's shard, locking only that shard. Returns
+    /// (shard, record ID within the shard).
+    ///
+    /// # Panics
+    /// Panics where [`BleCube::insert`] does.
+    pub fn insert(&self, obs: BleObservation) -> (usize, usize) {
+        let shard = self.shard_for(obs.mac);
+        (shard, self.write_shard(shard).insert(obs))
+    }
+
+    /// Insert a batch, taking each shard's lock once rather than once per
+    /// observation; observations keep their relative order within a shard
+    ///
+    /// # Panics
+    /// Panics where [`BleCube::insert`] does.
+    pub fn insert_batch(&self, observations: Vec<BleObservation>) {
+        let mut routed: Vec<Vec<BleObservation>> = vec![Vec::new(); self.shards.len()];
+        for obs in observations {
+            routed[self.shard_for(obs.mac)].push(obs);
+        }
+        for (shard, batch) in routed.into_iter().enumerate() {
+            if !batch.is_empty() {
+                let mut cube = self.write_shard(shard);
+                batch.into_iter().for_each(|obs| {
+                    cube.insert(obs);
+                });
+            }
+        }
+    }
+
+    /// Observations across all shards
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|shard| self.read_shard(shard).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Run `query` on every shard it can match in (just the MAC's shard
+    /// when it constrains the MAC), returning copies of the matches
+    /// ascending by timestamp (ties in shard order). A sampled query draws
+    /// its sample from the merged matches.
+    pub fn execute(&self, query: &Query) -> Vec<BleObservation> {
+        let unsampled = query.unsampled();
+        let mut merged = Vec::new();
+        for shard in self.shards_for(query) {
+            merged.extend(
+                self.read_shard(shard)
+                    .execute(&unsampled)
+                    .into_iter()
+                    .copied(),
+            );
+        }
+        // Stable: ties stay in shard order
+        merged.sort_by_key(|obs| obs.timestamp);
+        match query.sampling() {
+            Some((n, seed)) => {
+                let mut reservoir = Reservoir::new(n, seed);
+                (0..merged.len()).for_each(|index| reservoir.offer(index));
+                reservoir
+                    .into_sorted()
+                    .into_iter()
+                    .map(|index| merged[index])
+                    .collect()
+            }
+            None => merged,
+        }
+    }
+
+    /// Number of matches of `query` across the shards
+    pub fn count(&self, query: &Query) -> usize {
+        let unsampled = query.unsampled();
+        let total: usize = self
+            .shards_for(query)
+            .map(|shard| self.read_shard(shard).execute_ids(&unsampled).len())
+            .sum();
+        query.sampling().map_or(total, |(n, _)| total.min(n))
+    }
+
+    /// Every observation of one MAC, from its shard alone, in insertion order
+    pub fn query_mac<M: Into<MacAddr>>(&self, mac: M) -> Vec<BleObservation> {
+        let mac = mac.into();
+        self.read_shard(self.shard_for(mac))
+            .query_mac(mac)
+            .into_iter()
+            .copied()
+            .collect()
+    }
+
+    /// Read access to one shard, for the rest of the [`BleCube`] API
+    ///
+    /// # Panics
+    /// Panics if `shard` is out of range.
+    pub fn read_shard(&self, shard: usize) -> RwLockReadGuard<'_, BleCube> {
+        self.shards[shard].read().expect("shard lock poisoned")
+    }
+
+    /// Write access to one shard. Insert through [`ShardedCube::insert`] so
+    /// records land in their MAC's shard.
+    ///
+    /// # Panics
+    /// Panics if `shard` is out of range.
+    pub fn write_shard(&self, shard: usize) -> RwLockWriteGuard<'_, BleCube> {
+        self.shards[shard].write().expect("shard lock poisoned")
+    }
+
+    /// The shards as plain cubes, e.g. to register them in a
+    /// [`CubeSet`](ble_cube_core::CubeSet)
+    pub fn into_cubes(self) -> Vec<BleCube> {
+        self.shards
+            .into_iter()
+            .map(|shard| shard.into_inner().expect("shard lock poisoned"))
+            .collect()
+    }
+
+    /// Shards that can hold matches of `query`
+    fn shards_for(&self, query: &Query) -> impl Iterator<Item = usize> {
+        match query.mac_filter() {
+            Some(mac) => {
+                let shard = self.shard_for(mac);
+                shard..shard + 1
+            }
+            None => 0..self.shards.len(),
+        }
+    }
+}
+
+/// Jump consistent hash: a bucket in `0..buckets` for `key`
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut bucket, mut next) = (0u64, 0u64);
+    while next < buckets as u64 {
+        bucket = next;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as u64;
+    }
+    bucket as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn obs(mac: u16, timestamp: i64) -> BleObservation {
+        let [a, b] = mac.to_be_bytes();
+        BleObservation {
+            mac: [0x02, 0, 0, 0, a, b],
+            rssi: -60,
+            timestamp,
+            lat: 37.0,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parallel_ingest_and_merged_queries() {
+        let cube = Arc::new(ShardedCube::new(4));
+        let writers: Vec<_> = (0..4u16)
+            .map(|thread_id| {
+                let cube = cube.clone();
+                thread::spawn(move || {
+                    for i in 0..250u16 {
+                        let mac = thread_id * 250 + i;
+                        cube.insert(obs(mac % 100, i64::from(mac)));
+                    }
+                })
+            })
+            .collect();
+        writers
+            .into_iter()
+            .for_each(|writer| writer.join().unwrap());
+        assert_eq!(cube.len(), 1000);
+
+        // Every shard got some devices, and each device lives in one shard
+        for shard in 0..4 {
+            let cube_shard = cube.read_shard(shard);
+            assert!(!cube_shard.is_empty());
+            assert!(cube_shard
+                .get_all_macs()
+                .iter()
+                .all(|&mac| cube.shard_for(mac) == shard));
+        }
+
+        let all = cube.execute(&Query::new());
+        assert!(all
+            .windows(2)
+            .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+        let device = obs(7, 0).mac;
+        let mut timestamps: Vec<i64> = cube.query_mac(device).iter().map(|o| o.timestamp).collect();
+        // Insertion order depends on thread scheduling
+        timestamps.sort_unstable();
+        assert_eq!(timestamps, (0..10).map(|i| i * 100 + 7).collect::<Vec<_>>());
+        let query = Query::new().mac(device).time_between(200, 500);
+        assert_eq!(cube.count(&query), 3);
+        assert_eq!(cube.execute(&query.clone().sample(2, 1)).len(), 2);
+        assert_eq!(cube.count(&Query::new().sample(5, 1)), 5);
+
+        cube.insert_batch((0..100).map(|mac| obs(mac, 5_000)).collect());
+        assert_eq!(cube.count(&Query::new().time_between(5_000, 5_000)), 100);
+        let Ok(cube) = Arc::try_unwrap(cube) else {
+            panic!("writers still hold the cube");
+        };
+        let cubes = cube.into_cubes();
+        assert_eq!(cubes.iter().map(BleCube::len).sum::<usize>(), 1100);
+    }
+
+    #[test]
+    fn test_jump_hash_moves_few_keys() {
+        let keys: Vec<u64> = (0..10_000u16).map(|i| hash_mac(&obs(i, 0).mac)).collect();
+        let moved = keys
+            .iter()
+            .filter(|&&key| jump_hash(key, 8) != jump_hash(key, 9))
+            .count();
+        // Ideal: 1/9 of the keys, all of them to the new shard
+        assert!((900..1_350).contains(&moved), "{moved} keys moved");
+        assert!(keys
+            .iter()
+            .filter(|&&key| jump_hash(key, 8) != jump_hash(key, 9))
+            .all(|&key| jump_hash(key, 9) == 8));
+        assert!(keys.iter().all(|&key| jump_hash(key, 1) == 0));
+    }
+}
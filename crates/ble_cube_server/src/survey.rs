@@ -0,0 +1,333 @@
+//! Field survey sessions.
+//!
+//! A survey walk or drive is the same routine every time: set up a cube
+//! for one receiver, stamp its ID and floor on every observation, apply its
+//! RSSI calibration, keep a long session within a retention window, and
+//! write the result out when the walk ends. [`Survey`] packages that
+//! routine: [`Survey::start`], [`Survey::ingest`] per observation,
+//! [`Survey::finish`], then export from the [`FinishedSurvey`].
+
+#[cfg(feature = "wal")]
+use ble_cube_core::WalConfig;
+use ble_cube_core::{BleCube, BleObservation, CubeBuilder, MaintenanceConfig};
+#[cfg(feature = "jsonl")]
+use ble_cube_io::Jsonl;
+use ble_cube_io::{write_csv_row, CSV_HEADER};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Ingests between retention passes
+const RETENTION_EVERY: u64 = 4096;
+
+/// How a [`Survey`] is set up
+#[derive(Debug, Clone, Default)]
+pub struct SurveyConfig {
+    /// Label carried into the [`SurveySummary`]
+    pub name: String,
+    /// Stamped on observations that carry no receiver ID
+    pub receiver_id: Option<u16>,
+    /// Stamped on observations that carry no floor
+    pub floor: Option<i16>,
+    /// Calibration offset (dB) for `receiver_id`, see
+    /// [`BleCube::set_rssi_offset`]
+    pub rssi_offset: i8,
+    /// Keep only partitions ending within this many timestamp units of the
+    /// newest observation (checked every few thousand ingests and when the
+    /// survey finishes); `None` keeps everything
+    pub retention: Option<i64>,
+    /// Log every ingest to this write-ahead-log directory, resuming the
+    /// survey recorded there if there is one. Needs the `wal` feature;
+    /// without it [`Survey::start`] fails with `Unsupported`.
+    pub wal_dir: Option<PathBuf>,
+    /// Cube options, e.g. indices a survey never queries
+    pub builder: CubeBuilder,
+}
+
+impl SurveyConfig {
+    /// Defaults for a survey called `name`: no receiver metadata, no
+    /// retention, all indices, in memory only
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Overview of a finished survey, see [`FinishedSurvey::summary`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurveySummary {
+    pub name: String,
+    /// Wall-clock length of the session
+    pub duration: Duration,
+    /// Observations ingested, including ones retention later dropped
+    pub ingested: u64,
+    /// Observations kept
+    pub observations: usize,
+    pub devices: usize,
+    /// (first, last) observation timestamp, `None` when empty
+    pub time_span: Option<(i64, i64)>,
+    /// (min_lat, min_lon, max_lat, max_lon) of the kept observations
+    pub bbox: Option<(f64, f64, f64, f64)>,
+}
+
+/// A survey in progress, see the [module docs](self)
+pub struct Survey {
+    config: SurveyConfig,
+    cube: BleCube,
+    started_at: SystemTime,
+    ingested: u64,
+}
+
+impl Survey {
+    /// Set up the cube and start the clock. Fails only if the write-ahead
+    /// log cannot be opened or replayed, or is asked for without the `wal`
+    /// feature.
+    pub fn start(config: SurveyConfig) -> io::Result<Self> {
+        let mut cube = match &config.wal_dir {
+            #[cfg(feature = "wal")]
+            Some(dir) => config.builder.clone().recover(dir, WalConfig::default())?,
+            #[cfg(not(feature = "wal"))]
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "survey write-ahead log needs the `wal` feature",
+                ))
+            }
+            None => config.builder.clone().build(),
+        };
+        if let Some(receiver_id) = config.receiver_id {
+            cube.set_rssi_offset(receiver_id, config.rssi_offset);
+        }
+        Ok(Self {
+            config,
+            cube,
+            started_at: SystemTime::now(),
+            ingested: 0,
+        })
+    }
+
+    /// Stamp the survey's receiver metadata on `obs` and insert it,
+    /// returning its record ID (record IDs shift down when retention
+    /// evicts). Fails where [`BleCube::try_insert`] does, or if a retention
+    /// pass cannot checkpoint the log.
+    pub fn ingest(&mut self, mut obs: BleObservation) -> io::Result<usize> {
+        self.ingested += 1;
+        // Before the insert, so the returned ID stays valid until the next pass
+        if self.ingested.is_multiple_of(RETENTION_EVERY) {
+            self.apply_retention()?;
+        }
+        obs.receiver_id = obs.receiver_id.or(self.config.receiver_id);
+        obs.floor = obs.floor.or(self.config.floor);
+        Ok(self.cube.try_insert(obs)?)
+    }
+
+    /// The cube so far, e.g. for live queries during the walk
+    pub fn cube(&self) -> &BleCube {
+        &self.cube
+    }
+
+    /// Observations ingested so far (including ones retention dropped)
+    pub fn ingested(&self) -> u64 {
+        self.ingested
+    }
+
+    /// Stop the survey: a last retention pass and, with a write-ahead log,
+    /// a checkpoint so the directory holds one compact snapshot
+    pub fn finish(mut self) -> io::Result<FinishedSurvey> {
+        self.apply_retention()?;
+        #[cfg(feature = "wal")]
+        if self.config.wal_dir.is_some() {
+            self.cube.checkpoint()?;
+        }
+        Ok(FinishedSurvey {
+            duration: self.started_at.elapsed().unwrap_or_default(),
+            name: self.config.name,
+            ingested: self.ingested,
+            cube: self.cube,
+        })
+    }
+
+    /// Evict outside the retention window, returning how many records went
+    fn apply_retention(&mut self) -> io::Result<usize> {
+        let Some(retention) = self.config.retention else {
+            return Ok(0);
+        };
+        let config = MaintenanceConfig {
+            retention: Some(retention),
+            geo_rebuild_ratio: f64::INFINITY,
+            ..MaintenanceConfig::new(Duration::ZERO)
+        };
+        Ok(self.cube.run_maintenance(&config)?.evicted)
+    }
+}
+
+/// The result of [`Survey::finish`]
+pub struct FinishedSurvey {
+    name: String,
+    duration: Duration,
+    ingested: u64,
+    cube: BleCube,
+}
+
+impl FinishedSurvey {
+    pub fn summary(&self) -> SurveySummary {
+        let stats = self.cube.index_stats();
+        let timestamps = self.cube.timestamps();
+        let time_span = timestamps
+            .iter()
+            .min()
+            .zip(timestamps.iter().max())
+            .map(|(&first, &last)| (first, last));
+        SurveySummary {
+            name: self.name.clone(),
+            duration: self.duration,
+            ingested: self.ingested,
+            observations: self.cube.len(),
+            devices: stats.mac.keys,
+            time_span,
+            bbox: stats.geo_bounds,
+        }
+    }
+
+    pub fn cube(&self) -> &BleCube {
+        &self.cube
+    }
+
+    pub fn into_cube(self) -> BleCube {
+        self.cube
+    }
+
+    /// Write every observation as CSV with a header row (`mac`, `rssi`,
+    /// `timestamp`, `lat`, `lon`, `receiver_id`, `floor`, `tx_power`,
+    /// `quality`; absent optional fields empty), the format the `ble_cube` CLI loads.
+    /// Returns the number of rows.
+    pub fn export_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        self.write_csv(&mut BufWriter::new(File::create(path)?))?;
+        Ok(self.cube.len())
+    }
+
+    /// Write every observation as JSON Lines, see [`Jsonl::export_jsonl`]
+    #[cfg(feature = "jsonl")]
+    pub fn export_jsonl<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        self.cube.export_jsonl(BufWriter::new(File::create(path)?))
+    }
+
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{CSV_HEADER}")?;
+        for obs in self.cube.records() {
+            write_csv_row(writer, obs)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ble_cube_survey_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn heard(mac: u8, timestamp: i64) -> BleObservation {
+        BleObservation {
+            mac: [mac; 6],
+            rssi: -70,
+            timestamp,
+            lat: 37.0 + f64::from(mac) * 1e-4,
+            lon: -122.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_survey_stamps_retains_and_exports() {
+        let mut config = SurveyConfig::new("level 2 walk");
+        config.receiver_id = Some(7);
+        config.floor = Some(2);
+        config.rssi_offset = 3;
+        config.retention = Some(3 * 3600);
+        let mut survey = Survey::start(config).unwrap();
+
+        survey
+            .ingest(BleObservation {
+                floor: Some(1),
+                ..heard(1, 0)
+            })
+            .unwrap();
+        for i in 0..10i64 {
+            survey.ingest(heard((i % 3) as u8, i * 3600)).unwrap();
+        }
+        let first = survey.cube().get(0).unwrap();
+        assert_eq!(
+            (first.receiver_id, first.floor, first.rssi),
+            (Some(7), Some(1), -67)
+        );
+        assert_eq!(survey.ingested(), 11);
+
+        let finished = survey.finish().unwrap();
+        let summary = finished.summary();
+        assert_eq!(summary.name, "level 2 walk");
+        // Partitions ending at or before 3h before the newest observation go
+        assert_eq!((summary.ingested, summary.observations), (11, 4));
+        assert_eq!(summary.time_span, Some((6 * 3600, 9 * 3600)));
+        assert_eq!(summary.devices, 3);
+        let (min_lat, _, max_lat, _) = summary.bbox.unwrap();
+        assert!(min_lat < max_lat);
+
+        let dir = temp_dir("export");
+        let path = dir.join("walk.csv");
+        assert_eq!(finished.export_csv(&path).unwrap(), 4);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("mac,rssi,timestamp,lat,lon,receiver_id,floor,tx_power,quality")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("00:00:00:00:00:00,-67,21600,37,-122,7,2,,")
+        );
+        assert_eq!(finished.into_cube().len(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(not(feature = "wal"))]
+    #[test]
+    fn test_survey_log_needs_wal_feature() {
+        let dir = temp_dir("no_wal");
+        let mut config = SurveyConfig::new("unlogged");
+        config.wal_dir = Some(dir.clone());
+        let err = Survey::start(config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "wal")]
+    #[test]
+    fn test_survey_resumes_from_its_log() {
+        let dir = temp_dir("wal");
+        let mut config = SurveyConfig::new("resumable");
+        config.wal_dir = Some(dir.clone());
+        let mut survey = Survey::start(config.clone()).unwrap();
+        survey.ingest(heard(1, 10)).unwrap();
+        survey.ingest(heard(2, 20)).unwrap();
+        // Crash: the survey is dropped without finishing
+        drop(survey);
+
+        let mut survey = Survey::start(config).unwrap();
+        assert_eq!(survey.cube().len(), 2);
+        survey.ingest(heard(3, 30)).unwrap();
+        let finished = survey.finish().unwrap();
+        assert_eq!(finished.summary().observations, 3);
+        assert_eq!(BleCube::recover(&dir).unwrap().len(), 3);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
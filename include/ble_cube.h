@@ -0,0 +1,72 @@
+/*
+ * C interface to ble-cube (cargo feature `ffi`).
+ *
+ * Build the library with `cargo build --release --features ffi` and link
+ * against target/release/libble_cube.a (or the shared library). Keep in sync
+ * with src/ffi.rs; `cargo test --features ffi` checks every export is
+ * declared here.
+ */
+#ifndef BLE_CUBE_H
+#define BLE_CUBE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque cube handle */
+typedef struct BleCube ble_cube_t;
+
+/* Opaque snapshot of query matches with an iteration cursor */
+typedef struct FfiResults ble_results_t;
+
+typedef struct {
+    int8_t rssi;
+    uint8_t mac[6];
+    int64_t timestamp;
+    double lat;
+    double lon;
+    bool has_receiver_id;
+    uint16_t receiver_id;
+    bool has_floor;
+    int16_t floor;
+    bool has_tx_power;
+    int8_t tx_power;
+    bool has_quality;
+    uint8_t quality;
+} ble_observation_t;
+
+/* Called once per match; `obs` is only valid during the call */
+typedef void (*ble_visit_fn)(const ble_observation_t *obs, void *user_data);
+
+ble_cube_t *ble_cube_new(void);
+void ble_cube_free(ble_cube_t *cube);
+size_t ble_cube_len(const ble_cube_t *cube);
+
+/* Returns the record ID, or -1 on NULL arguments or a rejected insert */
+int64_t ble_cube_insert(ble_cube_t *cube, const ble_observation_t *obs);
+
+/* Queries return NULL on a NULL cube; free results with ble_results_free */
+ble_results_t *ble_cube_query_mac(const ble_cube_t *cube, const uint8_t *mac);
+ble_results_t *ble_cube_query_time_range(const ble_cube_t *cube, int64_t start, int64_t end);
+ble_results_t *ble_cube_query_radius(const ble_cube_t *cube, double lat, double lon, double radius_m);
+
+/* Visit matches without allocating a result set; return the match count */
+size_t ble_cube_visit_time_range(const ble_cube_t *cube, int64_t start, int64_t end,
+                                 ble_visit_fn visit, void *user_data);
+size_t ble_cube_visit_radius(const ble_cube_t *cube, double lat, double lon, double radius_m,
+                             ble_visit_fn visit, void *user_data);
+
+size_t ble_results_len(const ble_results_t *results);
+bool ble_results_next(ble_results_t *results, ble_observation_t *out);
+bool ble_results_get(const ble_results_t *results, size_t index, ble_observation_t *out);
+void ble_results_free(ble_results_t *results);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BLE_CUBE_H */
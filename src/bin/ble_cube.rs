@@ -0,0 +1,411 @@
+//! `ble_cube`: explore a cube from the command line (feature `cli`).
+//!
+//! Loads a write-ahead-log directory (opened like `BleCube::recover`) or a
+//! CSV file, then runs the command given after the input path, or reads
+//! commands from stdin when there is none:
+//!
+//! ```text
+//! ble_cube captures.csv macs
+//! ble_cube /var/lib/ble-cube near 37.7749 -122.4194 50
+//! ble_cube captures.csv
+//! > query rssi >= -60 AND time BETWEEN 1700000000 AND 1700003600
+//! > export geojson hits.geojson
+//! ```
+//!
+//! CSV files need a header naming the columns `mac`, `rssi`, `timestamp`,
+//! `lat` and `lon` (any order); `receiver_id`, `floor`, `tx_power` and
+//! `quality` are optional and may be left empty.
+
+use ble_cube::prelude::*;
+use ble_cube::{BleCube, BleObservation, MacAddr, Query};
+use clap::{Arg, Command};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+/// Rows printed per result before eliding the rest
+const MAX_ROWS: usize = 20;
+
+const COMMANDS: &str = "\
+commands:
+  info                      record, device and time-span summary
+  macs                      per-device counts, peak RSSI, first/last seen
+  stats <mac>               one device's summary and RSSI histogram
+  near <lat> <lon> <m>      records within a radius
+  query <query string>      e.g. query mac = 'AA:BB:CC:DD:EE:FF' AND rssi >= -70
+  export geojson [file]     last result (or every record) as GeoJSON
+  help                      this list
+  quit                      leave";
+
+fn main() -> ExitCode {
+    let matches = Command::new("ble_cube")
+        .about("Explore a BLE observation cube")
+        .after_help(COMMANDS)
+        .arg(
+            Arg::new("input")
+                .required(true)
+                .help("Write-ahead-log directory or CSV file"),
+        )
+        .arg(
+            Arg::new("command")
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true)
+                .help("Command to run; reads commands from stdin if omitted"),
+        )
+        .get_matches();
+
+    let input = matches.get_one::<String>("input").expect("required");
+    let cube = match load(Path::new(input)) {
+        Ok(cube) => cube,
+        Err(err) => {
+            eprintln!("error: cannot load {input}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut session = Session {
+        cube,
+        last: Vec::new(),
+    };
+    let mut stdout = io::stdout().lock();
+
+    if let Some(words) = matches.get_many::<String>("command") {
+        let line = words.map(String::as_str).collect::<Vec<_>>().join(" ");
+        return match session.run(&line, &mut stdout) {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("error: {err}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        let _ = writeln!(
+            stdout,
+            "{} records loaded; type 'help' for commands",
+            session.cube.len()
+        );
+    }
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            let _ = write!(stdout, "> ");
+            let _ = stdout.flush();
+        }
+        let Some(Ok(line)) = lines.next() else {
+            return ExitCode::SUCCESS;
+        };
+        match session.run(&line, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::SUCCESS,
+            Err(err) => eprintln!("error: {err}"),
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<BleCube> {
+    if path.is_dir() {
+        BleCube::recover(path)
+    } else {
+        load_csv(BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid(line: usize, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {line}: {message}"),
+    )
+}
+
+/// Observations from CSV with a header row, see the module docs
+fn load_csv<R: BufRead>(reader: R) -> io::Result<BleCube> {
+    let mut lines = reader.lines();
+    let header = lines.next().ok_or_else(|| invalid(1, "missing header"))??;
+    let columns: Vec<String> = header
+        .split(',')
+        .map(|c| c.trim().to_ascii_lowercase())
+        .collect();
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let required = |name: &str| column(name).ok_or_else(|| invalid(1, format!("no {name} column")));
+    let (mac, rssi, timestamp, lat, lon) = (
+        required("mac")?,
+        required("rssi")?,
+        required("timestamp")?,
+        required("lat")?,
+        required("lon")?,
+    );
+    let (receiver_id, floor, tx_power, quality) = (
+        column("receiver_id"),
+        column("floor"),
+        column("tx_power"),
+        column("quality"),
+    );
+
+    let mut records = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let (line, number) = (line?, i + 2);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let row = Row {
+            fields: &fields,
+            number,
+        };
+        records.push(BleObservation {
+            mac: row.parse::<MacAddr>(mac, "mac")?.0,
+            rssi: row.parse(rssi, "rssi")?,
+            timestamp: row.parse(timestamp, "timestamp")?,
+            lat: row.parse(lat, "lat")?,
+            lon: row.parse(lon, "lon")?,
+            receiver_id: row.optional(receiver_id, "receiver_id")?,
+            floor: row.optional(floor, "floor")?,
+            tx_power: row.optional(tx_power, "tx_power")?,
+            quality: row.optional(quality, "quality")?,
+        });
+    }
+    Ok(BleCube::bulk_load(records))
+}
+
+/// Fields of one CSV data line
+struct Row<'a> {
+    fields: &'a [&'a str],
+    number: usize,
+}
+
+impl Row<'_> {
+    fn parse<T: FromStr>(&self, index: usize, name: &str) -> io::Result<T> {
+        let value = self.fields.get(index).copied().unwrap_or("");
+        value
+            .parse()
+            .map_err(|_| invalid(self.number, format!("bad {name} {value:?}")))
+    }
+
+    /// Empty or missing column: `None`
+    fn optional<T: FromStr>(&self, index: Option<usize>, name: &str) -> io::Result<Option<T>> {
+        match index.and_then(|index| self.fields.get(index)) {
+            None | Some(&"") => Ok(None),
+            Some(_) => self.parse(index.expect("checked above"), name).map(Some),
+        }
+    }
+}
+
+/// Loaded cube plus the record IDs of the last result, for `export`
+struct Session {
+    cube: BleCube,
+    last: Vec<usize>,
+}
+
+impl Session {
+    /// Run one command line; `Ok(false)` means quit
+    fn run<W: Write>(&mut self, line: &str, out: &mut W) -> Result<bool, String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let output = match command {
+            "" => String::new(),
+            "info" => self.info(),
+            "macs" => self.macs(),
+            "stats" => match args.as_slice() {
+                [mac] => self.stats(mac.parse().map_err(|err| format!("{err}"))?),
+                _ => return Err("usage: stats <mac>".to_string()),
+            },
+            "near" => {
+                let [lat, lon, radius_m] = args.as_slice() else {
+                    return Err("usage: near <lat> <lon> <m>".to_string());
+                };
+                let number = |s: &str| s.parse::<f64>().map_err(|_| format!("not a number: {s}"));
+                let query =
+                    Query::new().within_radius(number(lat)?, number(lon)?, number(radius_m)?);
+                self.results(&query)
+            }
+            "query" => {
+                let query: Query = rest.parse().map_err(|err| format!("{err}"))?;
+                self.results(&query)
+            }
+            "export" => match args.as_slice() {
+                ["geojson"] => self.geojson(),
+                ["geojson", path] => {
+                    let mut file =
+                        BufWriter::new(File::create(path).map_err(|err| format!("{path}: {err}"))?);
+                    file.write_all(self.geojson().as_bytes())
+                        .and_then(|()| file.flush())
+                        .map_err(|err| format!("{path}: {err}"))?;
+                    format!("wrote {path}\n")
+                }
+                _ => return Err("usage: export geojson [file]".to_string()),
+            },
+            "help" => format!("{COMMANDS}\n"),
+            "quit" | "exit" => return Ok(false),
+            other => return Err(format!("unknown command {other:?} (try 'help')")),
+        };
+        out.write_all(output.as_bytes())
+            .map_err(|err| err.to_string())?;
+        Ok(true)
+    }
+
+    fn info(&self) -> String {
+        let stats = self.cube.mac_stats(&Query::new());
+        let first = stats.iter().map(|s| s.first_seen).min();
+        let last = stats.iter().map(|s| s.last_seen).max();
+        let mut out = format!("records: {}\ndevices: {}\n", self.cube.len(), stats.len());
+        if let (Some(first), Some(last)) = (first, last) {
+            let _ = writeln!(out, "time: {first} .. {last}");
+        }
+        let _ = writeln!(
+            out,
+            "memory: {} bytes",
+            self.cube.memory_footprint().total_bytes()
+        );
+        out
+    }
+
+    fn macs(&self) -> String {
+        let mut out = String::new();
+        for s in self.cube.mac_stats(&Query::new()) {
+            let _ = writeln!(
+                out,
+                "{}  {:>8} obs  peak {:>4} dBm  {} .. {}",
+                MacAddr(s.mac),
+                s.observations,
+                s.max_rssi,
+                s.first_seen,
+                s.last_seen
+            );
+        }
+        out
+    }
+
+    fn stats(&self, mac: MacAddr) -> String {
+        let query = Query::new().mac(mac);
+        let Some(s) = self.cube.mac_stats(&query).pop() else {
+            return format!("{mac}: not seen\n");
+        };
+        let mut out = format!(
+            "{mac}\nobservations: {}\npeak rssi: {} dBm\nseen: {} .. {}\nrssi histogram:\n",
+            s.observations, s.max_rssi, s.first_seen, s.last_seen
+        );
+        for bin in self.cube.rssi_histogram(&query, 10) {
+            let _ = writeln!(out, "  {:>4} .. {:>4}  {}", bin.start, bin.end, bin.count);
+        }
+        out
+    }
+
+    /// Run `query`, remember its IDs and list the first rows
+    fn results(&mut self, query: &Query) -> String {
+        self.last = self.cube.execute_ids(query);
+        let mut out = String::new();
+        for &id in self.last.iter().take(MAX_ROWS) {
+            let obs = self.cube.get(id).expect("matched record exists");
+            let _ = writeln!(
+                out,
+                "{id:>8}  {}  {:>4} dBm  t={}  ({:.6}, {:.6})",
+                MacAddr(obs.mac),
+                obs.rssi,
+                obs.timestamp,
+                obs.lat,
+                obs.lon
+            );
+        }
+        if self.last.len() > MAX_ROWS {
+            let _ = writeln!(out, "... {} more", self.last.len() - MAX_ROWS);
+        }
+        let _ = writeln!(out, "{} matches", self.last.len());
+        out
+    }
+
+    /// Last result (every record if there is none) as a GeoJSON
+    /// `FeatureCollection` of points
+    fn geojson(&self) -> String {
+        let ids: Box<dyn Iterator<Item = usize>> = if self.last.is_empty() {
+            Box::new(0..self.cube.len())
+        } else {
+            Box::new(self.last.iter().copied())
+        };
+        let mut out = String::from(r#"{"type":"FeatureCollection","features":["#);
+        for id in ids {
+            let Some(obs) = self.cube.get(id) else {
+                continue;
+            };
+            if !out.ends_with('[') {
+                out.push(',');
+            }
+            // GeoJSON positions are [lon, lat]
+            let _ = write!(
+                out,
+                r#"{{"type":"Feature","geometry":{{"type":"Point","coordinates":[{},{}]}},"properties":{{"record_id":{},"mac":"{}","rssi":{},"timestamp":{}}}}}"#,
+                obs.lon,
+                obs.lat,
+                id,
+                MacAddr(obs.mac),
+                obs.rssi,
+                obs.timestamp
+            );
+        }
+        out.push_str("]}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+timestamp,mac,rssi,lat,lon,floor
+100,AA:BB:CC:DD:EE:01,-60,37.0,-122.0,
+110,AA:BB:CC:DD:EE:01,-70,37.0001,-122.0,2
+120,AA:BB:CC:DD:EE:02,-80,38.0,-122.0,
+";
+
+    fn run(session: &mut Session, line: &str) -> String {
+        let mut out = Vec::new();
+        session.run(line, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_load_csv_and_run_commands() {
+        let cube = load_csv(CSV.as_bytes()).unwrap();
+        assert_eq!(cube.len(), 3);
+        assert_eq!(cube.get(1).unwrap().floor, Some(2));
+        let mut session = Session {
+            cube,
+            last: Vec::new(),
+        };
+
+        assert!(run(&mut session, "info").contains("devices: 2"));
+        assert_eq!(run(&mut session, "macs").lines().count(), 2);
+        assert!(run(&mut session, "stats aa:bb:cc:dd:ee:01").contains("observations: 2"));
+        assert!(run(&mut session, "near 37.0 -122.0 50").ends_with("2 matches\n"));
+        assert_eq!(
+            run(&mut session, "export geojson")
+                .matches("\"Feature\"")
+                .count(),
+            2
+        );
+        assert!(run(&mut session, "query rssi < -75").ends_with("1 matches\n"));
+
+        let mut out = Vec::new();
+        assert!(session.run("near 1 2", &mut out).is_err());
+        assert!(session.run("bogus", &mut out).is_err());
+        assert_eq!(session.run("quit", &mut out), Ok(false));
+    }
+
+    #[test]
+    fn test_csv_errors_name_the_line() {
+        let err = load_csv("mac,rssi,timestamp,lat\n".as_bytes())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no lon column"), "{err}");
+        let bad = "mac,rssi,timestamp,lat,lon\nAA:BB:CC:DD:EE:FF,loud,1,0,0\n";
+        let err = load_csv(bad.as_bytes()).err().unwrap();
+        assert!(err.to_string().starts_with("line 2: bad rssi"), "{err}");
+    }
+}
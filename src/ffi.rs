@@ -0,0 +1,403 @@
+//! C ABI for embedding a cube in C/C++ tools.
+//!
+//! The cube is an opaque `ble_cube_t *` owned by the caller: create it with
+//! [`ble_cube_new`] and release it with [`ble_cube_free`]. Queries return a
+//! `ble_results_t *` holding copies of the matching observations, so a result
+//! set stays valid while the cube is modified; walk it with
+//! [`ble_results_next`] (or index it with [`ble_results_get`]) and release it
+//! with [`ble_results_free`]. Polling loops that would rather not allocate
+//! a result set per call can pass a `ble_visit_fn` callback to the
+//! `ble_cube_visit_*` functions instead. The declarations are in
+//! `include/ble_cube.h`.
+//!
+//! No function unwinds into C: failures are reported as `-1`, `NULL` or
+//! `false`, and null handles are accepted everywhere.
+
+use crate::{BleCube, BleObservation, Query};
+use core::ffi::c_void;
+use core::ptr;
+
+/// `ble_visit_fn`: called once per match with a pointer valid only for the
+/// duration of the call and the caller's `user_data`
+pub type FfiVisit = unsafe extern "C" fn(obs: *const FfiObservation, user_data: *mut c_void);
+
+/// `ble_observation_t`: a [`BleObservation`] with C-compatible optionals
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FfiObservation {
+    pub rssi: i8,
+    pub mac: [u8; 6],
+    pub timestamp: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub has_receiver_id: bool,
+    pub receiver_id: u16,
+    pub has_floor: bool,
+    pub floor: i16,
+    pub has_tx_power: bool,
+    pub tx_power: i8,
+    pub has_quality: bool,
+    pub quality: u8,
+}
+
+impl From<FfiObservation> for BleObservation {
+    fn from(obs: FfiObservation) -> Self {
+        BleObservation {
+            rssi: obs.rssi,
+            mac: obs.mac,
+            timestamp: obs.timestamp,
+            lat: obs.lat,
+            lon: obs.lon,
+            receiver_id: obs.has_receiver_id.then_some(obs.receiver_id),
+            floor: obs.has_floor.then_some(obs.floor),
+            tx_power: obs.has_tx_power.then_some(obs.tx_power),
+            quality: obs.has_quality.then_some(obs.quality),
+        }
+    }
+}
+
+impl From<&BleObservation> for FfiObservation {
+    fn from(obs: &BleObservation) -> Self {
+        FfiObservation {
+            rssi: obs.rssi,
+            mac: obs.mac,
+            timestamp: obs.timestamp,
+            lat: obs.lat,
+            lon: obs.lon,
+            has_receiver_id: obs.receiver_id.is_some(),
+            receiver_id: obs.receiver_id.unwrap_or(0),
+            has_floor: obs.floor.is_some(),
+            floor: obs.floor.unwrap_or(0),
+            has_tx_power: obs.tx_power.is_some(),
+            tx_power: obs.tx_power.unwrap_or(0),
+            has_quality: obs.quality.is_some(),
+            quality: obs.quality.unwrap_or(0),
+        }
+    }
+}
+
+/// `ble_results_t`: an owned snapshot of query matches with a cursor
+pub struct FfiResults {
+    records: Vec<FfiObservation>,
+    cursor: usize,
+}
+
+impl FfiResults {
+    fn boxed(records: Vec<&BleObservation>) -> *mut FfiResults {
+        Box::into_raw(Box::new(FfiResults {
+            records: records.into_iter().map(FfiObservation::from).collect(),
+            cursor: 0,
+        }))
+    }
+}
+
+/// Allocate an empty cube
+#[no_mangle]
+pub extern "C" fn ble_cube_new() -> *mut BleCube {
+    Box::into_raw(Box::new(BleCube::new()))
+}
+
+/// Release a cube; `NULL` is ignored
+///
+/// # Safety
+/// `cube` must come from [`ble_cube_new`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_free(cube: *mut BleCube) {
+    if !cube.is_null() {
+        drop(Box::from_raw(cube));
+    }
+}
+
+/// Number of observations, 0 for `NULL`
+///
+/// # Safety
+/// `cube` must be `NULL` or a live cube.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_len(cube: *const BleCube) -> usize {
+    cube.as_ref().map_or(0, BleCube::len)
+}
+
+/// Insert an observation; returns its record ID, or -1 if either pointer is
+/// `NULL` or the insert fails (time-unit policy, rate limit, write-ahead log)
+///
+/// # Safety
+/// `cube` must be `NULL` or a live cube not used concurrently; `obs` must be
+/// `NULL` or point to a valid `ble_observation_t`.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_insert(cube: *mut BleCube, obs: *const FfiObservation) -> i64 {
+    let (Some(cube), Some(obs)) = (cube.as_mut(), obs.as_ref()) else {
+        return -1;
+    };
+    match cube.try_insert((*obs).into()) {
+        Ok(record_id) => record_id as i64,
+        Err(_) => -1,
+    }
+}
+
+/// Observations from the 6-byte MAC at `mac`, in record ID order
+///
+/// # Safety
+/// `cube` must be `NULL` or a live cube; `mac` must be `NULL` or point to 6
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_query_mac(
+    cube: *const BleCube,
+    mac: *const u8,
+) -> *mut FfiResults {
+    let Some(cube) = cube.as_ref() else {
+        return ptr::null_mut();
+    };
+    if mac.is_null() {
+        return ptr::null_mut();
+    }
+    let mac: [u8; 6] = ptr::read_unaligned(mac.cast());
+    FfiResults::boxed(cube.query_mac(mac))
+}
+
+/// Observations with timestamps in [start, end] inclusive
+///
+/// # Safety
+/// `cube` must be `NULL` or a live cube.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_query_time_range(
+    cube: *const BleCube,
+    start: i64,
+    end: i64,
+) -> *mut FfiResults {
+    match cube.as_ref() {
+        Some(cube) => FfiResults::boxed(cube.query_time_range(start, end)),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Observations within `radius_m` meters of (lat, lon), in record ID order
+///
+/// # Safety
+/// `cube` must be `NULL` or a live cube.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_query_radius(
+    cube: *const BleCube,
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+) -> *mut FfiResults {
+    match cube.as_ref() {
+        Some(cube) => {
+            FfiResults::boxed(cube.execute(&Query::new().within_radius(lat, lon, radius_m)))
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Call `visit` for each observation with a timestamp in [start, end],
+/// in timestamp order, without building a result set; returns the number
+/// of matches (0 for a `NULL` cube or callback)
+///
+/// # Safety
+/// `cube` must be `NULL` or a live cube not modified during the call;
+/// `visit` must not unwind and `user_data` is passed to it untouched.
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_visit_time_range(
+    cube: *const BleCube,
+    start: i64,
+    end: i64,
+    visit: Option<FfiVisit>,
+    user_data: *mut c_void,
+) -> usize {
+    let (Some(cube), Some(visit)) = (cube.as_ref(), visit) else {
+        return 0;
+    };
+    let mut count = 0;
+    cube.query_time_range_visit(start, end, |obs| {
+        visit(&FfiObservation::from(obs), user_data);
+        count += 1;
+    });
+    count
+}
+
+/// Call `visit` for each observation within `radius_m` meters of
+/// (lat, lon), in no particular order; returns the number of matches
+///
+/// # Safety
+/// Same as [`ble_cube_visit_time_range`].
+#[no_mangle]
+pub unsafe extern "C" fn ble_cube_visit_radius(
+    cube: *const BleCube,
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+    visit: Option<FfiVisit>,
+    user_data: *mut c_void,
+) -> usize {
+    let (Some(cube), Some(visit)) = (cube.as_ref(), visit) else {
+        return 0;
+    };
+    let mut count = 0;
+    cube.query_geo_radius_visit(lat, lon, radius_m, |obs| {
+        visit(&FfiObservation::from(obs), user_data);
+        count += 1;
+    });
+    count
+}
+
+/// Number of observations in a result set, 0 for `NULL`
+///
+/// # Safety
+/// `results` must be `NULL` or a live result set.
+#[no_mangle]
+pub unsafe extern "C" fn ble_results_len(results: *const FfiResults) -> usize {
+    results.as_ref().map_or(0, |results| results.records.len())
+}
+
+/// Copy the next observation into `out` and advance; false once exhausted
+///
+/// # Safety
+/// `results` must be `NULL` or a live result set; `out` must be `NULL` or
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn ble_results_next(
+    results: *mut FfiResults,
+    out: *mut FfiObservation,
+) -> bool {
+    let (Some(results), false) = (results.as_mut(), out.is_null()) else {
+        return false;
+    };
+    let Some(&obs) = results.records.get(results.cursor) else {
+        return false;
+    };
+    results.cursor += 1;
+    out.write(obs);
+    true
+}
+
+/// Copy the observation at `index` into `out`; false if out of range.
+/// Does not move the cursor.
+///
+/// # Safety
+/// `results` must be `NULL` or a live result set; `out` must be `NULL` or
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn ble_results_get(
+    results: *const FfiResults,
+    index: usize,
+    out: *mut FfiObservation,
+) -> bool {
+    match (results.as_ref(), out.is_null()) {
+        (Some(results), false) => match results.records.get(index) {
+            Some(&obs) => {
+                out.write(obs);
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Release a result set; `NULL` is ignored
+///
+/// # Safety
+/// `results` must come from a `ble_cube_query_*` function and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ble_results_free(results: *mut FfiResults) {
+    if !results.is_null() {
+        drop(Box::from_raw(results));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obs(mac: u8, timestamp: i64, lat: f64) -> FfiObservation {
+        FfiObservation {
+            rssi: -60,
+            mac: [mac; 6],
+            timestamp,
+            lat,
+            lon: -74.0,
+            has_receiver_id: mac == 1,
+            receiver_id: 7,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip_through_c_abi() {
+        unsafe {
+            let cube = ble_cube_new();
+            assert_eq!(ble_cube_insert(cube, &obs(1, 100, 40.0)), 0);
+            assert_eq!(ble_cube_insert(cube, &obs(2, 200, 40.0001)), 1);
+            assert_eq!(ble_cube_insert(cube, &obs(1, 300, 41.0)), 2);
+            assert_eq!(ble_cube_insert(cube, ptr::null()), -1);
+            assert_eq!(ble_cube_len(cube), 3);
+
+            let results = ble_cube_query_mac(cube, [1u8; 6].as_ptr());
+            assert_eq!(ble_results_len(results), 2);
+            // Results are snapshots: later inserts do not affect them
+            ble_cube_insert(cube, &obs(1, 400, 42.0));
+            let mut out = FfiObservation::default();
+            let mut seen = Vec::new();
+            while ble_results_next(results, &mut out) {
+                seen.push(out.timestamp);
+            }
+            assert_eq!(seen, vec![100, 300]);
+            assert!(out.has_receiver_id && out.receiver_id == 7 && !out.has_floor);
+            ble_results_free(results);
+
+            let results = ble_cube_query_radius(cube, 40.0, -74.0, 50.0);
+            assert_eq!(ble_results_len(results), 2);
+            assert!(ble_results_get(results, 1, &mut out));
+            assert_eq!(out.mac, [2; 6]);
+            assert!(!ble_results_get(results, 2, &mut out));
+            ble_results_free(results);
+
+            let results = ble_cube_query_time_range(cube, 150, 350);
+            assert_eq!(ble_results_len(results), 2);
+            ble_results_free(results);
+
+            // Visitors sum timestamps into the caller's state
+            unsafe extern "C" fn sum(obs: *const FfiObservation, user_data: *mut c_void) {
+                *user_data.cast::<i64>() += (*obs).timestamp;
+            }
+            let mut total = 0i64;
+            let user_data = ptr::addr_of_mut!(total).cast();
+            assert_eq!(
+                ble_cube_visit_time_range(cube, 150, 450, Some(sum), user_data),
+                3
+            );
+            assert_eq!(total, 900);
+            total = 0;
+            assert_eq!(
+                ble_cube_visit_radius(cube, 40.0, -74.0, 50.0, Some(sum), user_data),
+                2
+            );
+            assert_eq!(total, 300);
+            assert_eq!(
+                ble_cube_visit_radius(cube, 40.0, -74.0, 50.0, None, user_data),
+                0
+            );
+
+            ble_cube_free(cube);
+            assert!(ble_cube_query_time_range(ptr::null(), 0, 1).is_null());
+            assert_eq!(ble_results_len(ptr::null()), 0);
+        }
+    }
+
+    #[test]
+    fn test_header_declares_every_export() {
+        let header = include_str!("../include/ble_cube.h");
+        let exports = include_str!("ffi.rs")
+            .lines()
+            .filter_map(|line| line.split_once("extern \"C\" fn ")?.1.split_once('('))
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with("ble_"));
+        let mut count = 0;
+        for name in exports {
+            assert!(header.contains(&format!("{name}(")), "{name} missing");
+            count += 1;
+        }
+        assert_eq!(count, 13);
+    }
+}
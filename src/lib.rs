@@ -1,2 +1,65 @@
-mod ble_cube;
-pub use ble_cube::{BleCube, BleObservation};
+//! In-memory 4-dimensional index for BLE scanner observations.
+//!
+//! `ble-cube` is a facade over the workspace crates, so applications take
+//! one dependency and pick what they need with features:
+//!
+//! - `ble-cube-core`: `BleCube`, `BleObservation`, queries, geo math, zones,
+//!   tags and the HCI report parser. Always re-exported; `no_std` + `alloc`
+//!   without `std`, so embedded users can depend on it alone.
+//! - `ble-cube-analytics` (`analytics`): dwell time, positioning,
+//!   clustering, coverage, encounters, rasters and anonymized export.
+//! - `ble-cube-io` (`io`, `jsonl`, `pcap`): CSV / JSON Lines sinks, JSON
+//!   Lines import/export, BTSnoop and pcap capture import.
+//! - `ble-cube-server` (`server`, `mqtt`): background maintenance,
+//!   `ShardedCube`, `Survey` and the MQTT bridge.
+//!
+//! Analytics and imports are extension traits on `BleCube`; bring them into
+//! scope with `use ble_cube::prelude::*`.
+//!
+//! | Feature     | Default | Module |
+//! |-------------|---------|--------|
+//! | `std`       | yes     | standard library support; off = `no_std` + `alloc` core |
+//! | `wal`       | yes     | write-ahead log (`BleCube::recover`, `checkpoint`, implies `std`) |
+//! | `analytics` | yes     | `ble-cube-analytics` (`Analytics`, `Positioning`, `Rasterize`, ...) |
+//! | `image`     | no      | PNG export of heatmaps (`Raster::to_png`, `write_png`, implies `analytics`) |
+//! | `serde`     | no      | `Serialize`/`Deserialize` for observations, queries and results |
+//! | `io`        | no      | `ble-cube-io`: `CsvSink` and BTSnoop import (implies `std`) |
+//! | `jsonl`     | no      | newline-delimited JSON import/export and `JsonlSink` (implies `io`, `serde`) |
+//! | `pcap`      | no      | pcap / pcapng import of BLE sniffer captures (`import_pcap`, implies `io`) |
+//! | `server`    | no      | `ble-cube-server`: `Maintenance`, `ShardedCube`, `Survey` (implies `io`) |
+//! | `mqtt`      | no      | MQTT subscriber feeding a shared cube (`MqttBridge`, implies `server`, `serde`) |
+//! | `ffi`       | no      | C ABI in the `ffi` module, with header `include/ble_cube.h` (implies `std`) |
+//! | `wasm`      | no      | `wasm-bindgen` wrapper for the browser (`WasmCube`, implies `std`) |
+//! | `cold`      | no      | memory-mapped segments for frozen partitions (`freeze_partitions_before`, implies `std`) |
+//! | `cli`       | no      | `ble_cube` command-line explorer binary (implies `std`, `wal`, `analytics`) |
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "analytics")]
+pub use ble_cube_analytics::*;
+pub use ble_cube_core::*;
+#[cfg(feature = "io")]
+pub use ble_cube_io::*;
+#[cfg(feature = "server")]
+pub use ble_cube_server::*;
+#[cfg(feature = "wasm")]
+pub use wasm::WasmCube;
+
+/// The extension traits of the enabled crates, for `use ble_cube::prelude::*`
+pub mod prelude {
+    #[cfg(feature = "analytics")]
+    pub use ble_cube_analytics::{
+        Analytics, Anonymize, Clustering, Coverage, Encounters, Positioning, Proximity, Rasterize,
+    };
+    #[cfg(feature = "io")]
+    pub use ble_cube_io::Btsnoop;
+    #[cfg(feature = "jsonl")]
+    pub use ble_cube_io::Jsonl;
+    #[cfg(feature = "pcap")]
+    pub use ble_cube_io::Pcap;
+}
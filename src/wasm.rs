@@ -0,0 +1,275 @@
+//! `wasm-bindgen` wrapper for running a cube in the browser.
+//!
+//! JS talks to [`WasmCube`] in columns: inserts take parallel typed arrays
+//! (one entry per observation, MACs packed 6 bytes each), queries return
+//! record IDs as a `Uint32Array`, and the column getters / GeoJSON export
+//! turn those IDs back into data for plotting. Timestamps cross the
+//! boundary as JS numbers, which are exact up to 2^53.
+//!
+//! Build with `cargo rustc --release --lib --target wasm32-unknown-unknown
+//! --features wasm --crate-type cdylib`, then run `wasm-bindgen --target web`
+//! on the resulting `.wasm`.
+
+use crate::{BleCube, BleObservation, MacAddr, Query};
+use std::fmt::Write;
+use wasm_bindgen::prelude::*;
+
+/// A [`BleCube`] exported to JavaScript
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmCube {
+    cube: BleCube,
+}
+
+#[wasm_bindgen]
+impl WasmCube {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of observations
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.cube.len()
+    }
+
+    /// Insert one observation per array index; `macs` holds 6 bytes per
+    /// observation. Timestamps are truncated to integers. Returns the record
+    /// ID of the first inserted observation (IDs are consecutive).
+    #[wasm_bindgen(js_name = insertBatch)]
+    pub fn insert_batch(
+        &mut self,
+        macs: &[u8],
+        rssi: &[i8],
+        timestamps: &[f64],
+        lats: &[f64],
+        lons: &[f64],
+    ) -> Result<usize, JsError> {
+        let count = batch_len(macs, rssi, timestamps, lats, lons).map_err(|e| JsError::new(&e))?;
+        let first = self.cube.len();
+        for i in 0..count {
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&macs[i * 6..i * 6 + 6]);
+            self.cube.insert(BleObservation {
+                rssi: rssi[i],
+                mac,
+                timestamp: timestamps[i] as i64,
+                lat: lats[i],
+                lon: lons[i],
+                receiver_id: None,
+                floor: None,
+                tx_power: None,
+                quality: None,
+            });
+        }
+        Ok(first)
+    }
+
+    /// Record IDs within `radius_m` meters of (lat, lon)
+    #[wasm_bindgen(js_name = queryRadius)]
+    pub fn query_radius(&self, lat: f64, lon: f64, radius_m: f64) -> Vec<u32> {
+        to_u32(
+            self.cube
+                .execute_ids(&Query::new().within_radius(lat, lon, radius_m)),
+        )
+    }
+
+    /// Record IDs inside the bounding box, ascending
+    #[wasm_bindgen(js_name = queryBbox)]
+    pub fn query_bbox(&self, min_lat: f64, min_lon: f64, max_lat: f64, max_lon: f64) -> Vec<u32> {
+        let envelope = self
+            .cube
+            .coordinate_system()
+            .bbox_envelope(min_lat, min_lon, max_lat, max_lon);
+        let mut ids: Vec<usize> = self
+            .cube
+            .locate_in_envelope(envelope)
+            .map(|point| point.record_id)
+            .collect();
+        ids.sort_unstable();
+        to_u32(ids)
+    }
+
+    /// Record IDs with timestamps in [start, end] inclusive
+    #[wasm_bindgen(js_name = queryTimeRange)]
+    pub fn query_time_range(&self, start: f64, end: f64) -> Vec<u32> {
+        to_u32(
+            self.cube
+                .execute_ids(&Query::new().time_between(start as i64, end as i64)),
+        )
+    }
+
+    /// Record IDs within the radius and the time range
+    #[wasm_bindgen(js_name = queryRadiusBetween)]
+    pub fn query_radius_between(
+        &self,
+        lat: f64,
+        lon: f64,
+        radius_m: f64,
+        start: f64,
+        end: f64,
+    ) -> Vec<u32> {
+        let query = Query::new()
+            .within_radius(lat, lon, radius_m)
+            .time_between(start as i64, end as i64);
+        to_u32(self.cube.execute_ids(&query))
+    }
+
+    /// Latitudes of the given records (unknown IDs are skipped)
+    pub fn lats(&self, ids: &[u32]) -> Vec<f64> {
+        self.column(ids, |obs| obs.lat)
+    }
+
+    pub fn lons(&self, ids: &[u32]) -> Vec<f64> {
+        self.column(ids, |obs| obs.lon)
+    }
+
+    pub fn rssi(&self, ids: &[u32]) -> Vec<i8> {
+        self.column(ids, |obs| obs.rssi)
+    }
+
+    pub fn timestamps(&self, ids: &[u32]) -> Vec<f64> {
+        self.column(ids, |obs| obs.timestamp as f64)
+    }
+
+    /// MACs of the given records, 6 bytes each
+    pub fn macs(&self, ids: &[u32]) -> Vec<u8> {
+        self.records(ids).flat_map(|obs| obs.mac).collect()
+    }
+
+    /// The given records as a GeoJSON `FeatureCollection` of points with
+    /// `record_id`, `mac`, `rssi` and `timestamp` properties
+    #[wasm_bindgen(js_name = toGeoJson)]
+    pub fn to_geojson(&self, ids: &[u32]) -> String {
+        let mut out = String::from(r#"{"type":"FeatureCollection","features":["#);
+        for &id in ids {
+            let Some(obs) = self.cube.get(id as usize) else {
+                continue;
+            };
+            if !out.ends_with('[') {
+                out.push(',');
+            }
+            // GeoJSON positions are [lon, lat]
+            let _ = write!(
+                out,
+                r#"{{"type":"Feature","geometry":{{"type":"Point","coordinates":[{},{}]}},"properties":{{"record_id":{},"mac":"{}","rssi":{},"timestamp":{}}}}}"#,
+                obs.lon,
+                obs.lat,
+                id,
+                MacAddr::from(obs.mac),
+                obs.rssi,
+                obs.timestamp
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+impl WasmCube {
+    /// Wrap an existing cube
+    pub fn from_cube(cube: BleCube) -> Self {
+        Self { cube }
+    }
+
+    pub fn cube(&self) -> &BleCube {
+        &self.cube
+    }
+
+    fn records<'a>(&'a self, ids: &'a [u32]) -> impl Iterator<Item = &'a BleObservation> + 'a {
+        ids.iter().filter_map(|&id| self.cube.get(id as usize))
+    }
+
+    fn column<T>(&self, ids: &[u32], f: impl Fn(&BleObservation) -> T) -> Vec<T> {
+        self.records(ids).map(f).collect()
+    }
+}
+
+/// Number of observations in a columnar batch, if the columns agree
+fn batch_len(
+    macs: &[u8],
+    rssi: &[i8],
+    timestamps: &[f64],
+    lats: &[f64],
+    lons: &[f64],
+) -> Result<usize, String> {
+    let count = rssi.len();
+    if macs.len() != count * 6 {
+        return Err(format!(
+            "expected {} MAC bytes for {} observations, got {}",
+            count * 6,
+            count,
+            macs.len()
+        ));
+    }
+    for (name, len) in [
+        ("timestamps", timestamps.len()),
+        ("lats", lats.len()),
+        ("lons", lons.len()),
+    ] {
+        if len != count {
+            return Err(format!("expected {count} {name}, got {len}"));
+        }
+    }
+    Ok(count)
+}
+
+fn to_u32(ids: Vec<usize>) -> Vec<u32> {
+    ids.into_iter().map(|id| id as u32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columnar_insert_and_queries() {
+        let mut cube = WasmCube::new();
+        let macs = [[1, 2, 3, 4, 5, 6], [1, 2, 3, 4, 5, 6], [0xAA; 6]].concat();
+        let first = cube
+            .insert_batch(
+                &macs,
+                &[-50, -60, -70],
+                &[100.0, 200.0, 300.0],
+                &[40.0, 40.0001, 41.0],
+                &[-74.0, -74.0, -74.0],
+            )
+            .unwrap();
+        assert_eq!(first, 0);
+        assert_eq!(cube.length(), 3);
+
+        assert_eq!(cube.query_radius(40.0, -74.0, 50.0), vec![0, 1]);
+        assert_eq!(cube.query_bbox(40.5, -75.0, 41.5, -73.0), vec![2]);
+        assert_eq!(cube.query_time_range(150.0, 300.0), vec![1, 2]);
+        assert_eq!(
+            cube.query_radius_between(40.0, -74.0, 50.0, 150.0, 300.0),
+            vec![1]
+        );
+
+        assert_eq!(cube.rssi(&[2, 0, 9]), vec![-70, -50]);
+        assert_eq!(cube.timestamps(&[1]), vec![200.0]);
+        assert_eq!(cube.macs(&[2]), vec![0xAA; 6]);
+
+        let geojson: serde_json::Value = serde_json::from_str(&cube.to_geojson(&[0, 9])).unwrap();
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        let position = &features[0]["geometry"]["coordinates"];
+        assert_eq!(position[0].as_f64(), Some(-74.0));
+        assert_eq!(position[1].as_f64(), Some(40.0));
+        assert_eq!(features[0]["properties"]["mac"], "01:02:03:04:05:06");
+    }
+
+    #[test]
+    fn test_mismatched_columns_are_rejected() {
+        assert_eq!(
+            batch_len(&[0; 12], &[-50, -60], &[0.0; 2], &[0.0; 2], &[0.0; 2]),
+            Ok(2)
+        );
+        assert!(batch_len(&[0; 6], &[-50, -60], &[0.0; 2], &[0.0; 2], &[0.0; 2]).is_err());
+        assert_eq!(
+            batch_len(&[0; 6], &[-50], &[0.0], &[], &[0.0]),
+            Err("expected 1 lats, got 0".to_string())
+        );
+    }
+}